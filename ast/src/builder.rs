@@ -0,0 +1,170 @@
+use std::rc::Rc;
+
+use token::{Token, TokenType};
+
+use crate::*;
+
+pub fn program(statements: Vec<Rc<dyn Statement>>) -> Program {
+    Program { statements }
+}
+
+pub fn let_stmt(name: &str, value: Rc<dyn Expression>) -> Rc<dyn Statement> {
+    Rc::new(LetStatement {
+        token: token(TokenType::LET, "let"),
+        name: ident(name),
+        type_annotation: None,
+        value: Some(value),
+    })
+}
+
+pub fn return_stmt(value: Rc<dyn Expression>) -> Rc<dyn Statement> {
+    Rc::new(ReturnStatement {
+        token: token(TokenType::RETURN, "return"),
+        return_value: Some(value),
+    })
+}
+
+pub fn expr_stmt(expression: Rc<dyn Expression>) -> Rc<dyn Statement> {
+    let literal = expression.token_literal();
+    Rc::new(ExpressionStatement {
+        token: token(token::lookup_ident(&literal), &literal),
+        expression: Some(expression),
+    })
+}
+
+pub fn block(statements: Vec<Rc<dyn Statement>>) -> Rc<dyn Statement> {
+    Rc::new(BlockStatement {
+        token: token(TokenType::LBRACE, "{"),
+        statements,
+        end: Span::default(),
+    })
+}
+
+pub fn ident(name: &str) -> Rc<Identifier> {
+    Rc::new(Identifier {
+        token: token(TokenType::IDENT, name),
+        value: name.to_string(),
+    })
+}
+
+pub fn int(value: i64) -> Rc<dyn Expression> {
+    Rc::new(IntegerLiteral {
+        token: token(TokenType::INT, &value.to_string()),
+        value,
+    })
+}
+
+pub fn float(value: f64) -> Rc<dyn Expression> {
+    Rc::new(FloatLiteral {
+        token: token(TokenType::FLOAT, &format!("{:?}", value)),
+        value,
+    })
+}
+
+pub fn string(value: &str) -> Rc<dyn Expression> {
+    Rc::new(StringLiteral {
+        token: token(TokenType::STRING, value),
+        value: value.into(),
+    })
+}
+
+pub fn boolean(value: bool) -> Rc<dyn Expression> {
+    let token_type = if value { TokenType::TRUE } else { TokenType::FALSE };
+    Rc::new(Boolean {
+        token: token(token_type, &value.to_string()),
+        value,
+    })
+}
+
+pub fn null() -> Rc<dyn Expression> {
+    Rc::new(NullLiteral {
+        token: token(TokenType::NULL, "null"),
+    })
+}
+
+pub fn prefix(operator: &str, right: Rc<dyn Expression>) -> Rc<dyn Expression> {
+    Rc::new(PrefixExpression {
+        token: operator_token(operator),
+        operator: operator.to_string(),
+        right,
+    })
+}
+
+pub fn infix(left: Rc<dyn Expression>, operator: &str, right: Rc<dyn Expression>) -> Rc<dyn Expression> {
+    Rc::new(InfixExpression {
+        token: operator_token(operator),
+        left,
+        operator: operator.to_string(),
+        right,
+    })
+}
+
+pub fn assign(name: &str, value: Rc<dyn Expression>) -> Rc<dyn Expression> {
+    Rc::new(AssignExpression {
+        token: operator_token("="),
+        name: ident(name),
+        operator: "=".to_string(),
+        value,
+    })
+}
+
+pub fn if_expr(condition: Rc<dyn Expression>, consequence: Vec<Rc<dyn Statement>>, alternative: Option<Vec<Rc<dyn Statement>>>) -> Rc<dyn Expression> {
+    Rc::new(IfExpression {
+        token: token(TokenType::IF, "if"),
+        condition,
+        consequence: block(consequence),
+        alternative: alternative.map(block),
+    })
+}
+
+pub fn while_loop(condition: Rc<dyn Expression>, body: Vec<Rc<dyn Statement>>) -> Rc<dyn Expression> {
+    Rc::new(WhileExpression {
+        token: token(TokenType::WHILE, "while"),
+        condition,
+        body: block(body),
+    })
+}
+
+pub fn function(parameters: &[&str], body: Vec<Rc<dyn Statement>>) -> Rc<dyn Expression> {
+    Rc::new(FunctionLiteral {
+        token: token(TokenType::FUNCTION, "fn"),
+        parameters: parameters.iter().map(|p| ident(p)).collect(),
+        parameter_types: vec![None; parameters.len()],
+        return_type: None,
+        body: block(body),
+    })
+}
+
+pub fn call(function: Rc<dyn Expression>, arguments: Vec<Rc<dyn Expression>>) -> Rc<dyn Expression> {
+    Rc::new(CallExpression {
+        token: token(TokenType::LPAREN, "("),
+        function,
+        arguments,
+        end: Span::default(),
+    })
+}
+
+fn token(token_type: TokenType, literal: &str) -> Rc<Token> {
+    Rc::new(Token::new(token_type, literal.to_string()))
+}
+
+fn operator_token(operator: &str) -> Rc<Token> {
+    let token_type = match operator {
+        "=" => TokenType::ASSIGN,
+        "+" => TokenType::PLUS,
+        "-" => TokenType::MINUS,
+        "*" => TokenType::ASTERISK,
+        "**" => TokenType::POWER,
+        "/" => TokenType::SLASH,
+        "%" => TokenType::MODULO,
+        "!" => TokenType::BANG,
+        "<" => TokenType::LT,
+        ">" => TokenType::RT,
+        "<=" => TokenType::LT_EQ,
+        ">=" => TokenType::GT_EQ,
+        "==" => TokenType::EQ,
+        "!=" => TokenType::NOT_EQ,
+        _ => TokenType::ILLEGAL,
+    };
+    token(token_type, operator)
+}
@@ -0,0 +1,416 @@
+use std::rc::Rc;
+
+use serde_json::{json, Value};
+use token::Token;
+
+use crate::*;
+
+impl Program {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "node": "PROGRAM",
+            "statements": self.statements.iter().map(statement_to_json).collect::<Vec<_>>(),
+        })
+    }
+
+    pub fn from_json(value: &Value) -> Result<Program, String> {
+        expect_node(value, "PROGRAM")?;
+        Ok(Program {
+            statements: array(value, "statements")?.iter().map(statement_from_json).collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+fn statement_to_json(statement: &Rc<dyn Statement>) -> Value {
+    let any = statement.as_any();
+    match statement.node_type() {
+        NodeType::LET_STATEMENT => {
+            let stmt = any.downcast_ref::<LetStatement>().unwrap();
+            json!({
+                "node": "LET_STATEMENT",
+                "token": token_to_json(&stmt.token),
+                "name": identifier_to_json(&stmt.name),
+                "type": stmt.type_annotation.as_ref().map(annotation_to_json),
+                "value": stmt.value.as_ref().map(expression_to_json),
+            })
+        },
+        NodeType::RETURN_STATEMENT => {
+            let stmt = any.downcast_ref::<ReturnStatement>().unwrap();
+            json!({
+                "node": "RETURN_STATEMENT",
+                "token": token_to_json(&stmt.token),
+                "value": stmt.return_value.as_ref().map(expression_to_json),
+            })
+        },
+        NodeType::EXPRESSION_STATEMENT => {
+            let stmt = any.downcast_ref::<ExpressionStatement>().unwrap();
+            json!({
+                "node": "EXPRESSION_STATEMENT",
+                "token": token_to_json(&stmt.token),
+                "expression": stmt.expression.as_ref().map(expression_to_json),
+            })
+        },
+        NodeType::BLOCK_STATEMENT => {
+            let block = any.downcast_ref::<BlockStatement>().unwrap();
+            json!({
+                "node": "BLOCK_STATEMENT",
+                "token": token_to_json(&block.token),
+                "statements": block.statements.iter().map(statement_to_json).collect::<Vec<_>>(),
+            })
+        },
+        node_type => json!({ "node": format!("{:?}", node_type) }),
+    }
+}
+
+fn expression_to_json(exp: &Rc<dyn Expression>) -> Value {
+    let any = exp.as_any();
+    match exp.node_type() {
+        NodeType::IDENTIFIER => identifier_to_json(any.downcast_ref::<Identifier>().unwrap()),
+        NodeType::INTEGER_LITERAL => {
+            let integer = any.downcast_ref::<IntegerLiteral>().unwrap();
+            json!({ "node": "INTEGER_LITERAL", "token": token_to_json(&integer.token), "value": integer.value })
+        },
+        NodeType::FLOAT_LITERAL => {
+            let float = any.downcast_ref::<FloatLiteral>().unwrap();
+            json!({ "node": "FLOAT_LITERAL", "token": token_to_json(&float.token), "value": float.value })
+        },
+        NodeType::STRING_LITERAL => {
+            let string = any.downcast_ref::<StringLiteral>().unwrap();
+            json!({ "node": "STRING_LITERAL", "token": token_to_json(&string.token), "value": &*string.value })
+        },
+        NodeType::INTERPOLATED_STRING => {
+            let interpolated = any.downcast_ref::<InterpolatedString>().unwrap();
+            json!({
+                "node": "INTERPOLATED_STRING",
+                "token": token_to_json(&interpolated.token),
+                "parts": interpolated.parts.iter().map(expression_to_json).collect::<Vec<_>>(),
+            })
+        },
+        NodeType::BOOLEAN => {
+            let boolean = any.downcast_ref::<Boolean>().unwrap();
+            json!({ "node": "BOOLEAN", "token": token_to_json(&boolean.token), "value": boolean.value })
+        },
+        NodeType::NULL_LITERAL => {
+            let null = any.downcast_ref::<NullLiteral>().unwrap();
+            json!({ "node": "NULL_LITERAL", "token": token_to_json(&null.token) })
+        },
+        NodeType::PREFIX_EXPRESSION => {
+            let prefix = any.downcast_ref::<PrefixExpression>().unwrap();
+            json!({
+                "node": "PREFIX_EXPRESSION",
+                "token": token_to_json(&prefix.token),
+                "operator": prefix.operator,
+                "right": expression_to_json(&prefix.right),
+            })
+        },
+        NodeType::INFIX_EXPRESSION => {
+            let infix = any.downcast_ref::<InfixExpression>().unwrap();
+            json!({
+                "node": "INFIX_EXPRESSION",
+                "token": token_to_json(&infix.token),
+                "left": expression_to_json(&infix.left),
+                "operator": infix.operator,
+                "right": expression_to_json(&infix.right),
+            })
+        },
+        NodeType::ASSIGN_EXPRESSION => {
+            let assign = any.downcast_ref::<AssignExpression>().unwrap();
+            json!({
+                "node": "ASSIGN_EXPRESSION",
+                "token": token_to_json(&assign.token),
+                "name": identifier_to_json(&assign.name),
+                "operator": assign.operator,
+                "value": expression_to_json(&assign.value),
+            })
+        },
+        NodeType::IF_EXPRESSION => {
+            let if_expression = any.downcast_ref::<IfExpression>().unwrap();
+            json!({
+                "node": "IF_EXPRESSION",
+                "token": token_to_json(&if_expression.token),
+                "condition": expression_to_json(&if_expression.condition),
+                "consequence": statement_to_json(&if_expression.consequence),
+                "alternative": if_expression.alternative.as_ref().map(statement_to_json),
+            })
+        },
+        NodeType::WHILE_EXPRESSION => {
+            let while_expression = any.downcast_ref::<WhileExpression>().unwrap();
+            json!({
+                "node": "WHILE_EXPRESSION",
+                "token": token_to_json(&while_expression.token),
+                "condition": expression_to_json(&while_expression.condition),
+                "body": statement_to_json(&while_expression.body),
+            })
+        },
+        NodeType::FUNCTION_LITERAL => {
+            let function = any.downcast_ref::<FunctionLiteral>().unwrap();
+            json!({
+                "node": "FUNCTION_LITERAL",
+                "token": token_to_json(&function.token),
+                "parameters": function.parameters.iter().map(|p| identifier_to_json(p)).collect::<Vec<_>>(),
+                "parameter_types": function.parameter_types.iter().map(|t| t.as_ref().map(annotation_to_json)).collect::<Vec<_>>(),
+                "return_type": function.return_type.as_ref().map(annotation_to_json),
+                "body": statement_to_json(&function.body),
+            })
+        },
+        NodeType::MACRO_LITERAL => {
+            let macro_literal = any.downcast_ref::<MacroLiteral>().unwrap();
+            json!({
+                "node": "MACRO_LITERAL",
+                "token": token_to_json(&macro_literal.token),
+                "parameters": macro_literal.parameters.iter().map(|p| identifier_to_json(p)).collect::<Vec<_>>(),
+                "body": statement_to_json(&macro_literal.body),
+            })
+        },
+        NodeType::CALL_EXPRESSION => {
+            let call = any.downcast_ref::<CallExpression>().unwrap();
+            json!({
+                "node": "CALL_EXPRESSION",
+                "token": token_to_json(&call.token),
+                "function": expression_to_json(&call.function),
+                "arguments": call.arguments.iter().map(expression_to_json).collect::<Vec<_>>(),
+            })
+        },
+        NodeType::IMPORT_EXPRESSION => {
+            let import = any.downcast_ref::<ImportExpression>().unwrap();
+            json!({
+                "node": "IMPORT_EXPRESSION",
+                "token": token_to_json(&import.token),
+                "path": { "node": "STRING_LITERAL", "token": token_to_json(&import.path.token), "value": &*import.path.value },
+            })
+        },
+        NodeType::ARRAY_LITERAL => {
+            let array = any.downcast_ref::<ArrayLiteral>().unwrap();
+            json!({
+                "node": "ARRAY_LITERAL",
+                "token": token_to_json(&array.token),
+                "elements": array.elements.iter().map(expression_to_json).collect::<Vec<_>>(),
+            })
+        },
+        NodeType::INDEX_EXPRESSION => {
+            let index = any.downcast_ref::<IndexExpression>().unwrap();
+            json!({
+                "node": "INDEX_EXPRESSION",
+                "token": token_to_json(&index.token),
+                "left": expression_to_json(&index.left),
+                "index": expression_to_json(&index.index),
+            })
+        },
+        node_type => json!({ "node": format!("{:?}", node_type) }),
+    }
+}
+
+fn identifier_to_json(identifier: &Identifier) -> Value {
+    json!({ "node": "IDENTIFIER", "token": token_to_json(&identifier.token), "value": identifier.value })
+}
+
+fn annotation_to_json(annotation: &TypeAnnotation) -> Value {
+    json!({ "token": token_to_json(&annotation.token), "name": annotation.name })
+}
+
+fn token_to_json(token: &Token) -> Value {
+    json!({
+        "type": token.token_type.to_string(),
+        "literal": token.literal,
+        "line": token.line,
+        "column": token.column,
+    })
+}
+
+fn statement_from_json(value: &Value) -> Result<Rc<dyn Statement>, String> {
+    let statement: Rc<dyn Statement> = match node(value)? {
+        "LET_STATEMENT" => Rc::new(LetStatement {
+            token: token_from_json(value)?,
+            name: Rc::new(identifier_from_json(field(value, "name")?)?),
+            type_annotation: optional(value, "type").map(annotation_from_json).transpose()?,
+            value: optional(value, "value").map(expression_from_json).transpose()?,
+        }),
+        "RETURN_STATEMENT" => Rc::new(ReturnStatement {
+            token: token_from_json(value)?,
+            return_value: optional(value, "value").map(expression_from_json).transpose()?,
+        }),
+        "EXPRESSION_STATEMENT" => Rc::new(ExpressionStatement {
+            token: token_from_json(value)?,
+            expression: optional(value, "expression").map(expression_from_json).transpose()?,
+        }),
+        "BLOCK_STATEMENT" => Rc::new(BlockStatement {
+            token: token_from_json(value)?,
+            statements: array(value, "statements")?.iter().map(statement_from_json).collect::<Result<_, _>>()?,
+            end: token::Span::default(),
+        }),
+        other => return Err(format!("unknown statement node: {}", other)),
+    };
+    Ok(statement)
+}
+
+fn expression_from_json(value: &Value) -> Result<Rc<dyn Expression>, String> {
+    let expression: Rc<dyn Expression> = match node(value)? {
+        "IDENTIFIER" => Rc::new(identifier_from_json(value)?),
+        "INTEGER_LITERAL" => Rc::new(IntegerLiteral {
+            token: token_from_json(value)?,
+            value: field(value, "value")?.as_i64().ok_or("INTEGER_LITERAL value must be an integer")?,
+        }),
+        "FLOAT_LITERAL" => Rc::new(FloatLiteral {
+            token: token_from_json(value)?,
+            value: field(value, "value")?.as_f64().ok_or("FLOAT_LITERAL value must be a number")?,
+        }),
+        "STRING_LITERAL" => Rc::new(StringLiteral {
+            token: token_from_json(value)?,
+            value: string(value, "value")?.into(),
+        }),
+        "INTERPOLATED_STRING" => Rc::new(InterpolatedString {
+            token: token_from_json(value)?,
+            parts: array(value, "parts")?.iter().map(expression_from_json).collect::<Result<_, _>>()?,
+        }),
+        "BOOLEAN" => Rc::new(Boolean {
+            token: token_from_json(value)?,
+            value: field(value, "value")?.as_bool().ok_or("BOOLEAN value must be a boolean")?,
+        }),
+        "NULL_LITERAL" => Rc::new(NullLiteral {
+            token: token_from_json(value)?,
+        }),
+        "PREFIX_EXPRESSION" => Rc::new(PrefixExpression {
+            token: token_from_json(value)?,
+            operator: string(value, "operator")?,
+            right: expression_from_json(field(value, "right")?)?,
+        }),
+        "INFIX_EXPRESSION" => Rc::new(InfixExpression {
+            token: token_from_json(value)?,
+            left: expression_from_json(field(value, "left")?)?,
+            operator: string(value, "operator")?,
+            right: expression_from_json(field(value, "right")?)?,
+        }),
+        "ASSIGN_EXPRESSION" => Rc::new(AssignExpression {
+            token: token_from_json(value)?,
+            name: Rc::new(identifier_from_json(field(value, "name")?)?),
+            operator: string(value, "operator")?,
+            value: expression_from_json(field(value, "value")?)?,
+        }),
+        "IF_EXPRESSION" => Rc::new(IfExpression {
+            token: token_from_json(value)?,
+            condition: expression_from_json(field(value, "condition")?)?,
+            consequence: statement_from_json(field(value, "consequence")?)?,
+            alternative: optional(value, "alternative").map(statement_from_json).transpose()?,
+        }),
+        "WHILE_EXPRESSION" => Rc::new(WhileExpression {
+            token: token_from_json(value)?,
+            condition: expression_from_json(field(value, "condition")?)?,
+            body: statement_from_json(field(value, "body")?)?,
+        }),
+        "FUNCTION_LITERAL" => {
+            let parameters: Vec<Rc<Identifier>> = array(value, "parameters")?.iter()
+                .map(|p| identifier_from_json(p).map(Rc::new))
+                .collect::<Result<_, _>>()?;
+            // Annotations are optional, so JSON written without them still reads.
+            let parameter_types = match optional(value, "parameter_types") {
+                Some(_) => array(value, "parameter_types")?.iter()
+                    .map(|t| if t.is_null() { Ok(None) } else { annotation_from_json(t).map(Some) })
+                    .collect::<Result<Vec<_>, _>>()?,
+                None => vec![None; parameters.len()],
+            };
+            if parameter_types.len() != parameters.len() {
+                return Err("parameter_types must have one entry per parameter".to_string());
+            }
+            Rc::new(FunctionLiteral {
+                token: token_from_json(value)?,
+                parameters,
+                parameter_types,
+                return_type: optional(value, "return_type").map(annotation_from_json).transpose()?,
+                body: statement_from_json(field(value, "body")?)?,
+            })
+        },
+        "MACRO_LITERAL" => Rc::new(MacroLiteral {
+            token: token_from_json(value)?,
+            parameters: array(value, "parameters")?.iter()
+                .map(|p| identifier_from_json(p).map(Rc::new))
+                .collect::<Result<_, _>>()?,
+            body: statement_from_json(field(value, "body")?)?,
+        }),
+        "CALL_EXPRESSION" => Rc::new(CallExpression {
+            token: token_from_json(value)?,
+            function: expression_from_json(field(value, "function")?)?,
+            arguments: array(value, "arguments")?.iter().map(expression_from_json).collect::<Result<_, _>>()?,
+            end: token::Span::default(),
+        }),
+        "IMPORT_EXPRESSION" => {
+            let path = field(value, "path")?;
+            expect_node(path, "STRING_LITERAL")?;
+            Rc::new(ImportExpression {
+                token: token_from_json(value)?,
+                path: Rc::new(StringLiteral {
+                    token: token_from_json(path)?,
+                    value: string(path, "value")?.into(),
+                }),
+            })
+        },
+        "ARRAY_LITERAL" => Rc::new(ArrayLiteral {
+            token: token_from_json(value)?,
+            elements: array(value, "elements")?.iter().map(expression_from_json).collect::<Result<_, _>>()?,
+            end: token::Span::default(),
+        }),
+        "INDEX_EXPRESSION" => Rc::new(IndexExpression {
+            token: token_from_json(value)?,
+            left: expression_from_json(field(value, "left")?)?,
+            index: expression_from_json(field(value, "index")?)?,
+            end: token::Span::default(),
+        }),
+        other => return Err(format!("unknown expression node: {}", other)),
+    };
+    Ok(expression)
+}
+
+fn identifier_from_json(value: &Value) -> Result<Identifier, String> {
+    expect_node(value, "IDENTIFIER")?;
+    Ok(Identifier {
+        token: token_from_json(value)?,
+        value: string(value, "value")?,
+    })
+}
+
+fn annotation_from_json(value: &Value) -> Result<TypeAnnotation, String> {
+    Ok(TypeAnnotation {
+        token: token_from_json(value)?,
+        name: string(value, "name")?,
+    })
+}
+
+fn token_from_json(value: &Value) -> Result<Rc<Token>, String> {
+    let token = field(value, "token")?;
+    Ok(Rc::new(Token {
+        token_type: string(token, "type")?.parse()?,
+        literal: string(token, "literal")?,
+        line: field(token, "line")?.as_u64().ok_or("token line must be an integer")? as usize,
+        column: field(token, "column")?.as_u64().ok_or("token column must be an integer")? as usize,
+        // Spans are not part of the JSON form.
+        span: token::Span::default(),
+        // File ids only mean something within the session that assigned them.
+        file: None,
+    }))
+}
+
+fn node(value: &Value) -> Result<&str, String> {
+    field(value, "node")?.as_str().ok_or_else(|| "node must be a string".to_string())
+}
+
+fn expect_node(value: &Value, expected: &str) -> Result<(), String> {
+    match node(value)? {
+        actual if actual == expected => Ok(()),
+        actual => Err(format!("expected {} node, got {}", expected, actual)),
+    }
+}
+
+fn field<'a>(value: &'a Value, name: &str) -> Result<&'a Value, String> {
+    value.get(name).ok_or_else(|| format!("missing field: {}", name))
+}
+
+fn optional<'a>(value: &'a Value, name: &str) -> Option<&'a Value> {
+    value.get(name).filter(|v| !v.is_null())
+}
+
+fn string(value: &Value, name: &str) -> Result<String, String> {
+    field(value, name)?.as_str().map(String::from).ok_or_else(|| format!("{} must be a string", name))
+}
+
+fn array<'a>(value: &'a Value, name: &str) -> Result<&'a Vec<Value>, String> {
+    field(value, name)?.as_array().ok_or_else(|| format!("{} must be an array", name))
+}
@@ -1,6 +1,15 @@
-use token::Token;
+#![allow(non_camel_case_types)]
+
+use token::{Span, Token};
 use std::{fmt::Debug, rc::Rc};
 
+pub mod builder;
+mod json;
+pub mod modify;
+pub mod visit;
+
+pub use visit::{walk_expression, walk_program, walk_statement, Visitor};
+
 #[derive(Debug)]
 pub enum NodeType {
     PROGRAM,
@@ -8,18 +17,30 @@ pub enum NodeType {
     RETURN_STATEMENT,
     EXPRESSION_STATEMENT,
     INTEGER_LITERAL,
+    FLOAT_LITERAL,
     STRING_LITERAL,
+    INTERPOLATED_STRING,
     PREFIX_EXPRESSION,
     INFIX_EXPRESSION,
+    ASSIGN_EXPRESSION,
     BOOLEAN,
+    NULL_LITERAL,
     IF_EXPRESSION,
+    WHILE_EXPRESSION,
     BLOCK_STATEMENT,
     FUNCTION_LITERAL,
+    MACRO_LITERAL,
     CALL_EXPRESSION,
+    IMPORT_EXPRESSION,
+    ARRAY_LITERAL,
+    INDEX_EXPRESSION,
     IDENTIFIER,
 }
 
 pub trait Node {
+    // The bytes of the source the node was parsed from. Nodes built in code
+    // have the empty spans of their tokens.
+    fn span(&self) -> Span;
     fn node_type(&self) -> NodeType;
     fn token_literal(&self) -> String;
     fn to_string(&self) -> String;
@@ -27,11 +48,41 @@ pub trait Node {
 }
 
 pub trait Statement: Node + Debug {
-    fn statement_node(&self);
+    fn kind(&self) -> StatementKind<'_>;
 }
 
 pub trait Expression: Node + Debug {
-    fn expression_node(&self);
+    fn kind(&self) -> ExpressionKind<'_>;
+}
+
+// A typed view of a node, so passes over the tree can match exhaustively
+// instead of downcasting through as_any().
+pub enum StatementKind<'a> {
+    Let(&'a LetStatement),
+    Return(&'a ReturnStatement),
+    Expression(&'a ExpressionStatement),
+    Block(&'a BlockStatement),
+}
+
+pub enum ExpressionKind<'a> {
+    Identifier(&'a Identifier),
+    IntegerLiteral(&'a IntegerLiteral),
+    FloatLiteral(&'a FloatLiteral),
+    StringLiteral(&'a StringLiteral),
+    InterpolatedString(&'a InterpolatedString),
+    Boolean(&'a Boolean),
+    NullLiteral(&'a NullLiteral),
+    Prefix(&'a PrefixExpression),
+    Infix(&'a InfixExpression),
+    Assign(&'a AssignExpression),
+    If(&'a IfExpression),
+    While(&'a WhileExpression),
+    FunctionLiteral(&'a FunctionLiteral),
+    MacroLiteral(&'a MacroLiteral),
+    Import(&'a ImportExpression),
+    Call(&'a CallExpression),
+    Array(&'a ArrayLiteral),
+    Index(&'a IndexExpression),
 }
 
 pub struct Program {
@@ -39,8 +90,15 @@ pub struct Program {
 }
 
 impl Node for Program {
+    fn span(&self) -> Span {
+        match (self.statements.first(), self.statements.last()) {
+            (Some(first), Some(last)) => cover(first.span(), last.span()),
+            _ => Span::default(),
+        }
+    }
+
     fn token_literal(&self) -> String {
-        if self.statements.len() > 0 {
+        if !self.statements.is_empty() {
             self.statements[0].token_literal()
         } else {
             String::from("")
@@ -71,6 +129,10 @@ pub struct Identifier {
 }
 
 impl Node for Identifier {
+    fn span(&self) -> Span {
+        self.token.span
+    }
+
     fn token_literal(&self) -> String {
         self.token.literal.clone()
     }
@@ -89,7 +151,18 @@ impl Node for Identifier {
 }
 
 impl Expression for Identifier {
-    fn expression_node(&self) {}
+    fn kind(&self) -> ExpressionKind<'_> {
+        ExpressionKind::Identifier(self)
+    }
+}
+
+// The type written after a let name or a parameter, or after the arrow of a
+// function. The name is kept as written; the type checker decides what it
+// means, and the evaluator ignores it.
+#[derive(Debug, Clone)]
+pub struct TypeAnnotation {
+    pub token: Rc<Token>,
+    pub name: String,
 }
 
 #[derive(Debug)]
@@ -99,6 +172,13 @@ pub struct ExpressionStatement {
 }
 
 impl Node for ExpressionStatement {
+    fn span(&self) -> Span {
+        match &self.expression {
+            Some(expression) => expression.span(),
+            None => self.token.span,
+        }
+    }
+
     fn token_literal(&self) -> String {
         self.token.literal.clone()
     }
@@ -121,17 +201,27 @@ impl Node for ExpressionStatement {
 }
 
 impl Statement for ExpressionStatement {
-    fn statement_node(&self) {}
+    fn kind(&self) -> StatementKind<'_> {
+        StatementKind::Expression(self)
+    }
 }
 
 #[derive(Debug)]
 pub struct LetStatement {
     pub token: Rc<Token>,
     pub name: Rc<Identifier>,
+    pub type_annotation: Option<TypeAnnotation>,
     pub value: Option<Rc<dyn Expression>>,
 }
 
 impl Node for LetStatement {
+    fn span(&self) -> Span {
+        match &self.value {
+            Some(value) => cover(self.token.span, value.span()),
+            None => cover(self.token.span, self.name.span()),
+        }
+    }
+
     fn token_literal(&self) -> String {
         self.token.literal.clone()
     }
@@ -139,13 +229,17 @@ impl Node for LetStatement {
     fn to_string(&self) -> String {
         let mut out = String::new();
         out.push_str(&self.token_literal());
-        out.push_str(" ");
+        out.push(' ');
         out.push_str(&self.name.to_string());
-        out.push_str(" = ");
+        if let Some(annotation) = &self.type_annotation {
+            out.push_str(": ");
+            out.push_str(&annotation.name);
+        }
         if let Some(expr) = &self.value {
+            out.push_str(" = ");
             out.push_str(&expr.to_string());
         }
-        out.push_str(";");
+        out.push(';');
         out
     }
 
@@ -159,26 +253,28 @@ impl Node for LetStatement {
 }
 
 impl Statement for LetStatement {
-    fn statement_node(&self) {}
+    fn kind(&self) -> StatementKind<'_> {
+        StatementKind::Let(self)
+    }
 }
 
 #[derive(Debug)]
 pub struct StringLiteral {
     pub token: Rc<Token>,
-    pub value: String,
+    pub value: Rc<str>,
 }
 
 impl Node for StringLiteral {
+    fn span(&self) -> Span {
+        self.token.span
+    }
+
     fn token_literal(&self) -> String {
         self.token.literal.clone()
     }
 
     fn to_string(&self) -> String {
-        let mut out = String::new();
-        out.push_str("\"");
-        out.push_str(&self.token.literal);
-        out.push_str("\"");
-        out
+        format!("\"{}\"", escape_string(&self.value))
     }
 
     fn node_type(&self) -> NodeType {
@@ -191,8 +287,111 @@ impl Node for StringLiteral {
 }
 
 impl Expression for StringLiteral {
-    
-    fn expression_node(&self) {}
+    fn kind(&self) -> ExpressionKind<'_> {
+        ExpressionKind::StringLiteral(self)
+    }
+}
+
+// Escapes a string value so that it lexes back to the same text inside
+// double quotes.
+pub fn escape_string(value: &str) -> String {
+    let mut out = String::new();
+    for ch in value.chars() {
+        match ch {
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '{' => out.push_str("\\{"),
+            _ => out.push(ch),
+        }
+    }
+    out
+}
+
+// The span from the start of one to the end of the other. Spans of nodes
+// built in code are empty, so the end is kept from going before the start.
+fn cover(start: Span, end: Span) -> Span {
+    Span {
+        start: start.start,
+        end: end.end.max(start.start),
+    }
+}
+
+// The token an expression starts with in the source. Infix, call and index
+// expressions keep the token of their operator, which is not where they
+// begin.
+pub fn first_token(exp: &dyn Expression) -> Rc<Token> {
+    match exp.kind() {
+        ExpressionKind::Infix(infix) => first_token(infix.left.as_ref()),
+        ExpressionKind::Call(call) => first_token(call.function.as_ref()),
+        ExpressionKind::Index(index) => first_token(index.left.as_ref()),
+        ExpressionKind::Assign(assign) => assign.name.token.clone(),
+        ExpressionKind::Identifier(node) => node.token.clone(),
+        ExpressionKind::IntegerLiteral(node) => node.token.clone(),
+        ExpressionKind::FloatLiteral(node) => node.token.clone(),
+        ExpressionKind::StringLiteral(node) => node.token.clone(),
+        ExpressionKind::InterpolatedString(node) => node.token.clone(),
+        ExpressionKind::Boolean(node) => node.token.clone(),
+        ExpressionKind::NullLiteral(node) => node.token.clone(),
+        ExpressionKind::Prefix(node) => node.token.clone(),
+        ExpressionKind::If(node) => node.token.clone(),
+        ExpressionKind::While(node) => node.token.clone(),
+        ExpressionKind::FunctionLiteral(node) => node.token.clone(),
+        ExpressionKind::MacroLiteral(node) => node.token.clone(),
+        ExpressionKind::Import(node) => node.token.clone(),
+        ExpressionKind::Array(node) => node.token.clone(),
+    }
+}
+
+// Text parts are StringLiterals; every other part is an interpolated
+// expression.
+#[derive(Debug)]
+pub struct InterpolatedString {
+    pub token: Rc<Token>,
+    pub parts: Vec<Rc<dyn Expression>>,
+}
+
+impl Node for InterpolatedString {
+    fn span(&self) -> Span {
+        self.token.span
+    }
+
+    fn token_literal(&self) -> String {
+        self.token.literal.clone()
+    }
+
+    fn to_string(&self) -> String {
+        let mut out = String::new();
+        out.push('"');
+        for part in &self.parts {
+            match part.kind() {
+                ExpressionKind::StringLiteral(text) => out.push_str(&escape_string(&text.value)),
+                _ => {
+                    out.push('{');
+                    out.push_str(&part.to_string());
+                    out.push('}');
+                }
+            }
+        }
+        out.push('"');
+        out
+    }
+
+    fn node_type(&self) -> NodeType {
+        NodeType::INTERPOLATED_STRING
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
+impl Expression for InterpolatedString {
+    fn kind(&self) -> ExpressionKind<'_> {
+        ExpressionKind::InterpolatedString(self)
+    }
 }
 
 #[derive(Debug)]
@@ -202,6 +401,10 @@ pub struct IntegerLiteral {
 }
 
 impl Node for IntegerLiteral {
+    fn span(&self) -> Span {
+        self.token.span
+    }
+
     fn token_literal(&self) -> String {
         self.token.literal.clone()
     }
@@ -220,7 +423,43 @@ impl Node for IntegerLiteral {
 }
 
 impl Expression for IntegerLiteral {
-    fn expression_node(&self) {}
+    fn kind(&self) -> ExpressionKind<'_> {
+        ExpressionKind::IntegerLiteral(self)
+    }
+}
+
+#[derive(Debug)]
+pub struct FloatLiteral {
+    pub token: Rc<Token>,
+    pub value: f64,
+}
+
+impl Node for FloatLiteral {
+    fn span(&self) -> Span {
+        self.token.span
+    }
+
+    fn token_literal(&self) -> String {
+        self.token.literal.clone()
+    }
+
+    fn to_string(&self) -> String {
+        self.token.literal.clone()
+    }
+
+    fn node_type(&self) -> NodeType {
+        NodeType::FLOAT_LITERAL
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
+impl Expression for FloatLiteral {
+    fn kind(&self) -> ExpressionKind<'_> {
+        ExpressionKind::FloatLiteral(self)
+    }
 }
 
 #[derive(Debug)]
@@ -231,16 +470,20 @@ pub struct PrefixExpression {
 }
 
 impl Node for PrefixExpression {
+    fn span(&self) -> Span {
+        cover(self.token.span, self.right.span())
+    }
+
     fn token_literal(&self) -> String {
         self.token.literal.clone()
     }
 
     fn to_string(&self) -> String {
         let mut out = String::new();
-        out.push_str("(");
+        out.push('(');
         out.push_str(&self.operator);
         out.push_str(&self.right.to_string());
-        out.push_str(")");
+        out.push(')');
         out
     }
 
@@ -254,7 +497,9 @@ impl Node for PrefixExpression {
 }
 
 impl Expression for PrefixExpression {
-    fn expression_node(&self) {}
+    fn kind(&self) -> ExpressionKind<'_> {
+        ExpressionKind::Prefix(self)
+    }
 }
 
 #[derive(Debug)]
@@ -264,6 +509,13 @@ pub struct ReturnStatement {
 }
 
 impl Node for ReturnStatement {
+    fn span(&self) -> Span {
+        match &self.return_value {
+            Some(value) => cover(self.token.span, value.span()),
+            None => self.token.span,
+        }
+    }
+
     fn token_literal(&self) -> String {
         self.token.literal.clone()
     }
@@ -271,11 +523,11 @@ impl Node for ReturnStatement {
     fn to_string(&self) -> String {
         let mut out = String::new();
         out.push_str(&self.token_literal());
-        out.push_str(" ");
+        out.push(' ');
         if let Some(expr) = &self.return_value {
             out.push_str(&expr.to_string());
         }
-        out.push_str(";");
+        out.push(';');
         out
     }
     
@@ -289,7 +541,9 @@ impl Node for ReturnStatement {
 }
 
 impl Statement for ReturnStatement {
-    fn statement_node(&self) {}
+    fn kind(&self) -> StatementKind<'_> {
+        StatementKind::Return(self)
+    }
 }
 
 #[derive(Debug)]
@@ -299,6 +553,10 @@ pub struct Boolean {
 }
 
 impl Node for Boolean {
+    fn span(&self) -> Span {
+        self.token.span
+    }
+
     fn token_literal(&self) -> String {
         self.token.literal.clone()
     }
@@ -317,7 +575,42 @@ impl Node for Boolean {
 }
 
 impl Expression for Boolean {
-    fn expression_node(&self) {}
+    fn kind(&self) -> ExpressionKind<'_> {
+        ExpressionKind::Boolean(self)
+    }
+}
+
+#[derive(Debug)]
+pub struct NullLiteral {
+    pub token: Rc<Token>,
+}
+
+impl Node for NullLiteral {
+    fn span(&self) -> Span {
+        self.token.span
+    }
+
+    fn token_literal(&self) -> String {
+        self.token.literal.clone()
+    }
+
+    fn to_string(&self) -> String {
+        self.token.literal.clone()
+    }
+
+    fn node_type(&self) -> NodeType {
+        NodeType::NULL_LITERAL
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
+impl Expression for NullLiteral {
+    fn kind(&self) -> ExpressionKind<'_> {
+        ExpressionKind::NullLiteral(self)
+    }
 }
 
 #[derive(Debug)]
@@ -329,6 +622,10 @@ pub struct InfixExpression {
 }
 
 impl Node for InfixExpression {
+    fn span(&self) -> Span {
+        cover(self.left.span(), self.right.span())
+    }
+
     fn token_literal(&self) -> String {
         self.token.literal.clone()
     }
@@ -352,7 +649,50 @@ impl Node for InfixExpression {
 }
 
 impl Expression for InfixExpression {
-    fn expression_node(&self) {}
+    fn kind(&self) -> ExpressionKind<'_> {
+        ExpressionKind::Infix(self)
+    }
+}
+
+#[derive(Debug)]
+pub struct AssignExpression {
+    pub token: Rc<Token>,
+    pub name: Rc<Identifier>,
+    pub operator: String,
+    pub value: Rc<dyn Expression>,
+}
+
+impl Node for AssignExpression {
+    fn span(&self) -> Span {
+        cover(self.name.span(), self.value.span())
+    }
+
+    fn token_literal(&self) -> String {
+        self.token.literal.clone()
+    }
+
+    fn to_string(&self) -> String {
+        format!(
+            "{} {} {}",
+            self.name.to_string(),
+            self.operator,
+            self.value.to_string(),
+        )
+    }
+
+    fn node_type(&self) -> NodeType {
+        NodeType::ASSIGN_EXPRESSION
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
+impl Expression for AssignExpression {
+    fn kind(&self) -> ExpressionKind<'_> {
+        ExpressionKind::Assign(self)
+    }
 }
 
 #[derive(Debug)]
@@ -364,6 +704,13 @@ pub struct IfExpression {
 }
 
 impl Node for IfExpression {
+    fn span(&self) -> Span {
+        match &self.alternative {
+            Some(alternative) => cover(self.token.span, alternative.span()),
+            None => cover(self.token.span, self.consequence.span()),
+        }
+    }
+
     fn token_literal(&self) -> String {
         self.token.literal.clone()
     }
@@ -372,7 +719,7 @@ impl Node for IfExpression {
         let mut out = String::new();
         out.push_str("if");
         out.push_str(&self.condition.to_string());
-        out.push_str(" ");
+        out.push(' ');
         out.push_str(&self.consequence.to_string());
         if let Some(alt) = &self.alternative {
             out.push_str(" else ");
@@ -391,27 +738,75 @@ impl Node for IfExpression {
 }
 
 impl Expression for IfExpression {
-    fn expression_node(&self) {}
+    fn kind(&self) -> ExpressionKind<'_> {
+        ExpressionKind::If(self)
+    }
+}
+
+#[derive(Debug)]
+pub struct WhileExpression {
+    pub token: Rc<Token>,
+    pub condition: Rc<dyn Expression>,
+    pub body: Rc<dyn Statement>,
+}
+
+impl Node for WhileExpression {
+    fn span(&self) -> Span {
+        cover(self.token.span, self.body.span())
+    }
+
+    fn token_literal(&self) -> String {
+        self.token.literal.clone()
+    }
+
+    fn to_string(&self) -> String {
+        let mut out = String::new();
+        out.push_str("while");
+        out.push_str(&self.condition.to_string());
+        out.push(' ');
+        out.push_str(&self.body.to_string());
+        out
+    }
+
+    fn node_type(&self) -> NodeType {
+        NodeType::WHILE_EXPRESSION
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
+impl Expression for WhileExpression {
+    fn kind(&self) -> ExpressionKind<'_> {
+        ExpressionKind::While(self)
+    }
 }
 
 #[derive(Debug)]
 pub struct BlockStatement {
     pub token: Rc<Token>,
     pub statements: Vec<Rc<dyn Statement>>,
+    // The span of the closing brace.
+    pub end: Span,
 }
 
 impl Node for BlockStatement {
+    fn span(&self) -> Span {
+        cover(self.token.span, self.end)
+    }
+
     fn token_literal(&self) -> String {
         self.token.literal.clone()
     }
 
     fn to_string(&self) -> String {
         let mut out = String::new();
-        out.push_str("{");
+        out.push('{');
         for s in &self.statements {
             out.push_str(&s.to_string());
         }
-        out.push_str("}");
+        out.push('}');
         out
     }
 
@@ -425,7 +820,9 @@ impl Node for BlockStatement {
 }
 
 impl Statement for BlockStatement {
-    fn statement_node(&self) {}
+    fn kind(&self) -> StatementKind<'_> {
+        StatementKind::Block(self)
+    }
 }
 
 impl Clone for BlockStatement {
@@ -433,6 +830,7 @@ impl Clone for BlockStatement {
         BlockStatement {
             token: self.token.clone(),
             statements: self.statements.clone(),
+            end: self.end,
         }
     }
 }
@@ -441,10 +839,17 @@ impl Clone for BlockStatement {
 pub struct FunctionLiteral {
     pub token: Rc<Token>,
     pub parameters: Vec<Rc<Identifier>>,
+    // One entry per parameter.
+    pub parameter_types: Vec<Option<TypeAnnotation>>,
+    pub return_type: Option<TypeAnnotation>,
     pub body: Rc<dyn Statement>,
 }
 
 impl Node for FunctionLiteral {
+    fn span(&self) -> Span {
+        cover(self.token.span, self.body.span())
+    }
+
     fn token_literal(&self) -> String {
         self.token.literal.clone()
     }
@@ -452,14 +857,23 @@ impl Node for FunctionLiteral {
     fn to_string(&self) -> String {
         let mut out = String::new();
         out.push_str(&self.token_literal());
-        out.push_str("(");
+        out.push('(');
         for (i, p) in self.parameters.iter().enumerate() {
             out.push_str(&p.to_string());
+            if let Some(Some(annotation)) = self.parameter_types.get(i) {
+                out.push_str(": ");
+                out.push_str(&annotation.name);
+            }
             if i != self.parameters.len() - 1 {
                 out.push_str(", ");
             }
         }
         out.push_str(") ");
+        if let Some(annotation) = &self.return_type {
+            out.push_str("-> ");
+            out.push_str(&annotation.name);
+            out.push(' ');
+        }
         out.push_str(&self.body.to_string());
         out
     }
@@ -474,7 +888,55 @@ impl Node for FunctionLiteral {
 }
 
 impl Expression for FunctionLiteral {
-    fn expression_node(&self) {}
+    fn kind(&self) -> ExpressionKind<'_> {
+        ExpressionKind::FunctionLiteral(self)
+    }
+}
+
+#[derive(Debug)]
+pub struct MacroLiteral {
+    pub token: Rc<Token>,
+    pub parameters: Vec<Rc<Identifier>>,
+    pub body: Rc<dyn Statement>,
+}
+
+impl Node for MacroLiteral {
+    fn span(&self) -> Span {
+        cover(self.token.span, self.body.span())
+    }
+
+    fn token_literal(&self) -> String {
+        self.token.literal.clone()
+    }
+
+    fn to_string(&self) -> String {
+        let mut out = String::new();
+        out.push_str(&self.token_literal());
+        out.push('(');
+        for (i, p) in self.parameters.iter().enumerate() {
+            out.push_str(&p.to_string());
+            if i != self.parameters.len() - 1 {
+                out.push_str(", ");
+            }
+        }
+        out.push_str(") ");
+        out.push_str(&self.body.to_string());
+        out
+    }
+
+    fn node_type(&self) -> NodeType {
+        NodeType::MACRO_LITERAL
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
+impl Expression for MacroLiteral {
+    fn kind(&self) -> ExpressionKind<'_> {
+        ExpressionKind::MacroLiteral(self)
+    }
 }
 
 #[derive(Debug)]
@@ -482,9 +944,15 @@ pub struct CallExpression {
     pub token: Rc<Token>,
     pub function: Rc<dyn Expression>,
     pub arguments: Vec<Rc<dyn Expression>>,
+    // The span of the closing parenthesis.
+    pub end: Span,
 }
 
 impl Node for CallExpression {
+    fn span(&self) -> Span {
+        cover(self.function.span(), self.end)
+    }
+
     fn token_literal(&self) -> String {
         self.token.literal.clone()
     }
@@ -492,14 +960,14 @@ impl Node for CallExpression {
     fn to_string(&self) -> String {
         let mut out = String::new();
         out.push_str(&self.function.to_string());
-        out.push_str("(");
+        out.push('(');
         for (i, arg) in self.arguments.iter().enumerate() {
             out.push_str(&arg.to_string());
             if i != self.arguments.len() - 1 {
                 out.push_str(", ");
             }
         }
-        out.push_str(")");
+        out.push(')');
         out
     }
 
@@ -513,5 +981,115 @@ impl Node for CallExpression {
 }
 
 impl Expression for CallExpression {
-    fn expression_node(&self) {}
+    fn kind(&self) -> ExpressionKind<'_> {
+        ExpressionKind::Call(self)
+    }
+}
+
+#[derive(Debug)]
+pub struct ImportExpression {
+    pub token: Rc<Token>,
+    pub path: Rc<StringLiteral>,
+}
+
+impl Node for ImportExpression {
+    fn span(&self) -> Span {
+        cover(self.token.span, self.path.span())
+    }
+
+    fn token_literal(&self) -> String {
+        self.token.literal.clone()
+    }
+
+    fn to_string(&self) -> String {
+        format!("{} {}", self.token_literal(), self.path.to_string())
+    }
+
+    fn node_type(&self) -> NodeType {
+        NodeType::IMPORT_EXPRESSION
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
+impl Expression for ImportExpression {
+    fn kind(&self) -> ExpressionKind<'_> {
+        ExpressionKind::Import(self)
+    }
+}
+
+#[derive(Debug)]
+pub struct ArrayLiteral {
+    pub token: Rc<Token>,
+    pub elements: Vec<Rc<dyn Expression>>,
+    // The span of the closing bracket.
+    pub end: Span,
+}
+
+impl Node for ArrayLiteral {
+    fn span(&self) -> Span {
+        cover(self.token.span, self.end)
+    }
+
+    fn token_literal(&self) -> String {
+        self.token.literal.clone()
+    }
+
+    fn to_string(&self) -> String {
+        let elements: Vec<String> = self.elements.iter().map(|e| e.to_string()).collect();
+        format!("[{}]", elements.join(", "))
+    }
+
+    fn node_type(&self) -> NodeType {
+        NodeType::ARRAY_LITERAL
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
+impl Expression for ArrayLiteral {
+    fn kind(&self) -> ExpressionKind<'_> {
+        ExpressionKind::Array(self)
+    }
+}
+
+#[derive(Debug)]
+pub struct IndexExpression {
+    pub token: Rc<Token>,
+    pub left: Rc<dyn Expression>,
+    pub index: Rc<dyn Expression>,
+    // The span of the closing bracket.
+    pub end: Span,
+}
+
+impl Node for IndexExpression {
+    fn span(&self) -> Span {
+        cover(self.left.span(), self.end)
+    }
+
+    fn token_literal(&self) -> String {
+        self.token.literal.clone()
+    }
+
+    fn to_string(&self) -> String {
+        format!("({}[{}])", self.left.to_string(), self.index.to_string())
+    }
+
+    fn node_type(&self) -> NodeType {
+        NodeType::INDEX_EXPRESSION
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
+impl Expression for IndexExpression {
+    fn kind(&self) -> ExpressionKind<'_> {
+        ExpressionKind::Index(self)
+    }
 }
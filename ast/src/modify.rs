@@ -0,0 +1,142 @@
+use std::rc::Rc;
+
+use crate::*;
+
+pub type Modifier<'a> = dyn FnMut(Rc<dyn Expression>) -> Rc<dyn Expression> + 'a;
+
+// Children are rewritten before their parent is handed to the modifier, so
+// the modifier always sees a node whose subtrees are already final.
+pub fn modify_program(program: Program, modifier: &mut Modifier) -> Program {
+    Program {
+        statements: program.statements.into_iter().map(|s| modify_statement(s, modifier)).collect(),
+    }
+}
+
+pub fn modify_statement(statement: Rc<dyn Statement>, modifier: &mut Modifier) -> Rc<dyn Statement> {
+    match statement.kind() {
+        StatementKind::Let(let_statement) => Rc::new(LetStatement {
+            token: let_statement.token.clone(),
+            name: let_statement.name.clone(),
+            type_annotation: let_statement.type_annotation.clone(),
+            value: let_statement.value.clone().map(|v| modify_expression(v, modifier)),
+        }),
+        StatementKind::Return(return_statement) => Rc::new(ReturnStatement {
+            token: return_statement.token.clone(),
+            return_value: return_statement.return_value.clone().map(|v| modify_expression(v, modifier)),
+        }),
+        StatementKind::Expression(expression_statement) => Rc::new(ExpressionStatement {
+            token: expression_statement.token.clone(),
+            expression: expression_statement.expression.clone().map(|e| modify_expression(e, modifier)),
+        }),
+        StatementKind::Block(block) => Rc::new(BlockStatement {
+            token: block.token.clone(),
+            statements: block.statements.iter().map(|s| modify_statement(s.clone(), modifier)).collect(),
+            end: block.end,
+        }),
+    }
+}
+
+pub fn modify_expression(exp: Rc<dyn Expression>, modifier: &mut Modifier) -> Rc<dyn Expression> {
+    let modified: Rc<dyn Expression> = match exp.kind() {
+        ExpressionKind::InterpolatedString(interpolated) => Rc::new(InterpolatedString {
+            token: interpolated.token.clone(),
+            parts: interpolated.parts.iter().map(|p| modify_expression(p.clone(), modifier)).collect(),
+        }),
+        ExpressionKind::Prefix(prefix) => Rc::new(PrefixExpression {
+            token: prefix.token.clone(),
+            operator: prefix.operator.clone(),
+            right: modify_expression(prefix.right.clone(), modifier),
+        }),
+        ExpressionKind::Infix(infix) => Rc::new(InfixExpression {
+            token: infix.token.clone(),
+            left: modify_expression(infix.left.clone(), modifier),
+            operator: infix.operator.clone(),
+            right: modify_expression(infix.right.clone(), modifier),
+        }),
+        ExpressionKind::Assign(assign) => Rc::new(AssignExpression {
+            token: assign.token.clone(),
+            name: assign.name.clone(),
+            operator: assign.operator.clone(),
+            value: modify_expression(assign.value.clone(), modifier),
+        }),
+        ExpressionKind::If(if_expression) => Rc::new(IfExpression {
+            token: if_expression.token.clone(),
+            condition: modify_expression(if_expression.condition.clone(), modifier),
+            consequence: modify_statement(if_expression.consequence.clone(), modifier),
+            alternative: if_expression.alternative.clone().map(|a| modify_statement(a, modifier)),
+        }),
+        ExpressionKind::While(while_expression) => Rc::new(WhileExpression {
+            token: while_expression.token.clone(),
+            condition: modify_expression(while_expression.condition.clone(), modifier),
+            body: modify_statement(while_expression.body.clone(), modifier),
+        }),
+        ExpressionKind::FunctionLiteral(function) => Rc::new(FunctionLiteral {
+            token: function.token.clone(),
+            parameters: function.parameters.clone(),
+            parameter_types: function.parameter_types.clone(),
+            return_type: function.return_type.clone(),
+            body: modify_statement(function.body.clone(), modifier),
+        }),
+        ExpressionKind::MacroLiteral(macro_literal) => Rc::new(MacroLiteral {
+            token: macro_literal.token.clone(),
+            parameters: macro_literal.parameters.clone(),
+            body: modify_statement(macro_literal.body.clone(), modifier),
+        }),
+        ExpressionKind::Call(call) => Rc::new(CallExpression {
+            token: call.token.clone(),
+            function: modify_expression(call.function.clone(), modifier),
+            arguments: call.arguments.iter().map(|a| modify_expression(a.clone(), modifier)).collect(),
+            end: call.end,
+        }),
+        ExpressionKind::Array(array) => Rc::new(ArrayLiteral {
+            token: array.token.clone(),
+            elements: array.elements.iter().map(|e| modify_expression(e.clone(), modifier)).collect(),
+            end: array.end,
+        }),
+        ExpressionKind::Index(index) => Rc::new(IndexExpression {
+            token: index.token.clone(),
+            left: modify_expression(index.left.clone(), modifier),
+            index: modify_expression(index.index.clone(), modifier),
+            end: index.end,
+        }),
+        ExpressionKind::Identifier(_)
+        | ExpressionKind::IntegerLiteral(_)
+        | ExpressionKind::FloatLiteral(_)
+        | ExpressionKind::StringLiteral(_)
+        | ExpressionKind::Boolean(_)
+        | ExpressionKind::NullLiteral(_)
+        | ExpressionKind::Import(_) => exp.clone(),
+    };
+    modifier(modified)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::*;
+
+    fn one_to_two(exp: Rc<dyn Expression>) -> Rc<dyn Expression> {
+        match exp.kind() {
+            ExpressionKind::IntegerLiteral(integer) if integer.value == 1 => int(2),
+            _ => exp,
+        }
+    }
+
+    #[test]
+    fn test_modify() {
+        let tests: Vec<(Rc<dyn Expression>, &str)> = vec![
+            (int(1), "2"),
+            (infix(int(1), "+", int(2)), "(2 + 2)"),
+            (prefix("-", int(1)), "(-2)"),
+            (call(ident("f"), vec![int(1), infix(int(1), "*", int(1))]), "f(2, (2 * 2))"),
+            (if_expr(int(1), vec![expr_stmt(int(1))], Some(vec![expr_stmt(int(3))])), "if2 {2} else {3}"),
+            (function(&["x"], vec![return_stmt(int(1))]), "fn(x) {return 2;}"),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(modify_expression(input, &mut one_to_two).to_string(), expected);
+        }
+
+        let program = program(vec![let_stmt("a", int(1)), expr_stmt(int(1))]);
+        assert_eq!(modify_program(program, &mut one_to_two).to_string(), "let a = 2;2");
+    }
+}
@@ -0,0 +1,153 @@
+use std::rc::Rc;
+
+use crate::*;
+
+// A read-only pass over the tree. Each method defaults to walking into the
+// node's children, so a pass overrides only the nodes it cares about and
+// calls the matching walk_ function to keep descending past them.
+pub trait Visitor {
+    fn visit_statement(&mut self, statement: &Rc<dyn Statement>) {
+        walk_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, expression: &Rc<dyn Expression>) {
+        walk_expression(self, expression);
+    }
+
+    // Names being bound: let names, and the parameters of functions and
+    // macros. Names used or assigned to reach visit_expression instead.
+    fn visit_binding(&mut self, _name: &Rc<Identifier>) {}
+}
+
+pub fn walk_program<V: Visitor + ?Sized>(visitor: &mut V, program: &Program) {
+    for statement in &program.statements {
+        visitor.visit_statement(statement);
+    }
+}
+
+// Children are visited in source order.
+pub fn walk_statement<V: Visitor + ?Sized>(visitor: &mut V, statement: &Rc<dyn Statement>) {
+    match statement.kind() {
+        StatementKind::Let(let_statement) => {
+            visitor.visit_binding(&let_statement.name);
+            if let Some(value) = &let_statement.value {
+                visitor.visit_expression(value);
+            }
+        },
+        StatementKind::Return(return_statement) => {
+            if let Some(value) = &return_statement.return_value {
+                visitor.visit_expression(value);
+            }
+        },
+        StatementKind::Expression(expression_statement) => {
+            if let Some(expression) = &expression_statement.expression {
+                visitor.visit_expression(expression);
+            }
+        },
+        StatementKind::Block(block) => {
+            for statement in &block.statements {
+                visitor.visit_statement(statement);
+            }
+        },
+    }
+}
+
+pub fn walk_expression<V: Visitor + ?Sized>(visitor: &mut V, expression: &Rc<dyn Expression>) {
+    match expression.kind() {
+        ExpressionKind::InterpolatedString(interpolated) => interpolated.parts.iter().for_each(|p| visitor.visit_expression(p)),
+        ExpressionKind::Prefix(prefix) => visitor.visit_expression(&prefix.right),
+        ExpressionKind::Infix(infix) => {
+            visitor.visit_expression(&infix.left);
+            visitor.visit_expression(&infix.right);
+        },
+        ExpressionKind::Assign(assign) => visitor.visit_expression(&assign.value),
+        ExpressionKind::If(if_expression) => {
+            visitor.visit_expression(&if_expression.condition);
+            visitor.visit_statement(&if_expression.consequence);
+            if let Some(alternative) = &if_expression.alternative {
+                visitor.visit_statement(alternative);
+            }
+        },
+        ExpressionKind::While(while_expression) => {
+            visitor.visit_expression(&while_expression.condition);
+            visitor.visit_statement(&while_expression.body);
+        },
+        ExpressionKind::FunctionLiteral(function) => {
+            function.parameters.iter().for_each(|p| visitor.visit_binding(p));
+            visitor.visit_statement(&function.body);
+        },
+        ExpressionKind::MacroLiteral(macro_literal) => {
+            macro_literal.parameters.iter().for_each(|p| visitor.visit_binding(p));
+            visitor.visit_statement(&macro_literal.body);
+        },
+        ExpressionKind::Call(call) => {
+            visitor.visit_expression(&call.function);
+            call.arguments.iter().for_each(|a| visitor.visit_expression(a));
+        },
+        ExpressionKind::Array(array) => array.elements.iter().for_each(|e| visitor.visit_expression(e)),
+        ExpressionKind::Index(index) => {
+            visitor.visit_expression(&index.left);
+            visitor.visit_expression(&index.index);
+        },
+        ExpressionKind::Identifier(_)
+        | ExpressionKind::IntegerLiteral(_)
+        | ExpressionKind::FloatLiteral(_)
+        | ExpressionKind::StringLiteral(_)
+        | ExpressionKind::Boolean(_)
+        | ExpressionKind::NullLiteral(_)
+        | ExpressionKind::Import(_) => {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::*;
+
+    // Records every node it reaches as its type and its text.
+    #[derive(Default)]
+    struct Recorder {
+        visited: Vec<String>,
+    }
+
+    impl Visitor for Recorder {
+        fn visit_statement(&mut self, statement: &Rc<dyn Statement>) {
+            self.visited.push(format!("{:?}", statement.node_type()));
+            walk_statement(self, statement);
+        }
+
+        fn visit_expression(&mut self, expression: &Rc<dyn Expression>) {
+            self.visited.push(expression.to_string());
+            walk_expression(self, expression);
+        }
+
+        fn visit_binding(&mut self, name: &Rc<Identifier>) {
+            self.visited.push(format!("bind {}", name.value));
+        }
+    }
+
+    #[test]
+    fn test_walk_program() {
+        let program = program(vec![
+            let_stmt("f", function(&["x"], vec![expr_stmt(infix(ident("x"), "+", int(1)))])),
+            expr_stmt(call(ident("f"), vec![int(2)])),
+        ]);
+        let mut recorder = Recorder::default();
+        walk_program(&mut recorder, &program);
+        assert_eq!(recorder.visited, vec![
+            "LET_STATEMENT",
+            "bind f",
+            "fn(x) {(x + 1)}",
+            "bind x",
+            "BLOCK_STATEMENT",
+            "EXPRESSION_STATEMENT",
+            "(x + 1)",
+            "x",
+            "1",
+            "EXPRESSION_STATEMENT",
+            "f(2)",
+            "f",
+            "2",
+        ]);
+    }
+}
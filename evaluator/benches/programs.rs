@@ -0,0 +1,39 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use evaluator::Evaluator;
+use lexer::Lexer;
+use parser::Parser;
+
+// Each program is parsed once; only evaluation is measured.
+const PROGRAMS: &[(&str, &str)] = &[
+    ("fib", "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(18)"),
+    ("string_concatenation", "let s = \"\"; let i = 0; while (i < 500) { s += \"ab\"; i += 1 }; len(s)"),
+    ("string_builder", "let sb = string_builder(); let i = 0; while (i < 500) { append(sb, \"ab\", i); i += 1 }; len(build(sb))"),
+    ("deep_recursion", "let down = fn(n) { if (n > 0) { down(n - 1) } else { 0 } }; down(500)"),
+    ("nested_closures", "let add = fn(a) { fn(b) { fn(c) { fn(d) { a + b + c + d } } } }; let i = 0; let total = 0; while (i < 200) { total += add(i)(1)(2)(3); i += 1 }; total"),
+    ("nested_blocks", "let i = 0; let n = 0; while (i < 300) { if (i % 2 == 0) { if (i % 3 == 0) { if (i % 5 == 0) { n += 1 } } }; i += 1 }; n"),
+];
+
+fn bench_programs(c: &mut Criterion) {
+    for (name, source) in PROGRAMS {
+        let mut parser = Parser::new(Lexer::new(source));
+        let statements = parser.parse_program().statements;
+        assert!(parser.errors().is_empty(), "{}: {:?}", name, parser.errors());
+
+        c.bench_function(name, |b| {
+            let mut evaluator = Evaluator::new();
+            b.iter(|| {
+                let env = Rc::new(RefCell::new(object::Environment::new()));
+                let program = ast::Program { statements: statements.clone() };
+                let result = evaluator.evaluate_program(program, env).unwrap();
+                assert_ne!(result.object_type(), object::ObjectType::ERROR, "{}: {}", name, result.inspect());
+                result
+            })
+        });
+    }
+}
+
+criterion_group!(benches, bench_programs);
+criterion_main!(benches);
@@ -0,0 +1,626 @@
+#[cfg(feature = "stdlib")]
+use std::cell::RefCell;
+use std::io::Write;
+#[cfg(feature = "stdlib")]
+use std::rc::Rc;
+
+use object::Object;
+
+use crate::Evaluator;
+
+pub type BuiltinFunction = fn(&mut Evaluator, Vec<Object>) -> Object;
+
+pub fn lookup(name: &str) -> Option<BuiltinFunction> {
+    match name {
+        "puts" => Some(puts),
+        "len" => Some(len),
+        #[cfg(feature = "stdlib")]
+        "string_builder" => Some(string_builder),
+        #[cfg(feature = "stdlib")]
+        "append" => Some(append),
+        #[cfg(feature = "stdlib")]
+        "build" => Some(build),
+        #[cfg(feature = "stdlib")]
+        "call" => Some(call),
+        #[cfg(all(feature = "stdlib", feature = "clock"))]
+        "with_timeout" => Some(with_timeout),
+        #[cfg(feature = "stdlib")]
+        "map" => Some(map),
+        #[cfg(feature = "stdlib")]
+        "filter" => Some(filter),
+        #[cfg(feature = "stdlib")]
+        "reduce" => Some(reduce),
+        #[cfg(feature = "stdlib")]
+        "each" => Some(each),
+        #[cfg(feature = "stdlib")]
+        "push" => Some(push),
+        #[cfg(feature = "stdlib")]
+        "pop" => Some(pop),
+        #[cfg(feature = "stdlib")]
+        "insert" => Some(insert),
+        #[cfg(feature = "stdlib")]
+        "remove" => Some(remove),
+        #[cfg(feature = "stdlib")]
+        "split" => Some(split),
+        #[cfg(feature = "stdlib")]
+        "join" => Some(join),
+        #[cfg(feature = "stdlib")]
+        "upper" => Some(upper),
+        #[cfg(feature = "stdlib")]
+        "lower" => Some(lower),
+        #[cfg(feature = "stdlib")]
+        "trim" => Some(trim),
+        #[cfg(feature = "stdlib")]
+        "replace" => Some(replace),
+        #[cfg(feature = "stdlib")]
+        "contains" => Some(contains),
+        #[cfg(feature = "stdlib")]
+        "substr" => Some(substr),
+        #[cfg(feature = "stdlib")]
+        "inspect_pretty" => Some(inspect_pretty),
+        #[cfg(feature = "stdlib")]
+        "type" => Some(type_name),
+        #[cfg(feature = "stdlib")]
+        "arity" => Some(arity),
+        #[cfg(feature = "stdlib")]
+        "params" => Some(params),
+        #[cfg(feature = "stdlib")]
+        "assert" => Some(assert),
+        #[cfg(feature = "stdlib")]
+        "assert_eq" => Some(assert_eq),
+        #[cfg(feature = "stdlib")]
+        "args" => Some(args),
+        #[cfg(feature = "fs")]
+        "env" => Some(env),
+        #[cfg(feature = "fs")]
+        "read_file" => Some(read_file),
+        #[cfg(feature = "fs")]
+        "write_file" => Some(write_file),
+        #[cfg(feature = "fs")]
+        "append_file" => Some(append_file),
+        #[cfg(feature = "fs")]
+        "file_exists" => Some(file_exists),
+        _ => None,
+    }
+}
+
+fn puts(evaluator: &mut Evaluator, args: Vec<Object>) -> Object {
+    for arg in args {
+        let _ = writeln!(evaluator.output, "{}", arg.inspect());
+    }
+    let _ = evaluator.output.flush();
+    Object::Null
+}
+
+fn len(_: &mut Evaluator, args: Vec<Object>) -> Object {
+    match args.as_slice() {
+        [Object::String(value)] => Object::Integer(value.chars().count() as i64),
+        [Object::Array(elements)] => Object::Integer(elements.len() as i64),
+        [arg] => Object::Error(format!("argument to len not supported, got {:?}", arg.object_type())),
+        _ => Object::Error(format!("wrong number of arguments to len: got {}, want 1", args.len())),
+    }
+}
+
+#[cfg(feature = "stdlib")]
+fn string_builder(_: &mut Evaluator, args: Vec<Object>) -> Object {
+    if !args.is_empty() {
+        return Object::Error(format!("wrong number of arguments to string_builder: got {}, want 0", args.len()));
+    }
+    Object::StringBuilder(Rc::new(RefCell::new(String::new())))
+}
+
+// Appends to the builder in place, so building a string in a loop does not
+// copy everything built so far on each iteration.
+#[cfg(feature = "stdlib")]
+fn append(evaluator: &mut Evaluator, args: Vec<Object>) -> Object {
+    let buffer = match args.first() {
+        Some(Object::StringBuilder(buffer)) => buffer,
+        _ => return Object::Error("first argument to append must be a STRING_BUILDER".to_string()),
+    };
+    for arg in &args[1..] {
+        let text = arg.inspect();
+        if let Some(error) = evaluator.check_string_length(buffer.borrow().len() + text.len()) {
+            return error;
+        }
+        buffer.borrow_mut().push_str(&text);
+    }
+    args[0].clone()
+}
+
+#[cfg(feature = "stdlib")]
+fn build(_: &mut Evaluator, args: Vec<Object>) -> Object {
+    match args.as_slice() {
+        [Object::StringBuilder(buffer)] => Object::String(buffer.borrow().as_str().into()),
+        [arg] => Object::Error(format!("argument to build must be a STRING_BUILDER, got {:?}", arg.object_type())),
+        _ => Object::Error(format!("wrong number of arguments to build: got {}, want 1", args.len())),
+    }
+}
+
+#[cfg(feature = "stdlib")]
+fn call(evaluator: &mut Evaluator, mut args: Vec<Object>) -> Object {
+    if args.is_empty() {
+        return Object::Error("wrong number of arguments to call: got 0, want at least 1".to_string());
+    }
+    let function = args.remove(0);
+    evaluator.call_function(function, args)
+}
+
+#[cfg(all(feature = "stdlib", feature = "clock"))]
+fn with_timeout(evaluator: &mut Evaluator, args: Vec<Object>) -> Object {
+    match args.as_slice() {
+        [Object::Integer(ms), function] if *ms >= 0 => evaluator.call_with_timeout(function.clone(), *ms as u64),
+        [Object::Integer(ms), _] => Object::Error(format!("timeout passed to with_timeout must not be negative, got {}", ms)),
+        [other, _] => Object::Error(format!("first argument to with_timeout must be an INTEGER, got {:?}", other.object_type())),
+        _ => Object::Error(format!("wrong number of arguments to with_timeout: got {}, want 2", args.len())),
+    }
+}
+
+// The iteration builtins take the array first and the function last, and
+// stop at the first error the function returns.
+#[cfg(feature = "stdlib")]
+fn array_and_function<'a>(name: &str, args: &'a [Object], want: usize) -> Result<(&'a object::Array, &'a Object), Object> {
+    if args.len() != want {
+        return Err(Object::Error(format!("wrong number of arguments to {}: got {}, want {}", name, args.len(), want)));
+    }
+    match &args[0] {
+        Object::Array(elements) => Ok((elements, &args[want - 1])),
+        other => Err(Object::Error(format!("first argument to {} must be an ARRAY, got {:?}", name, other.object_type()))),
+    }
+}
+
+#[cfg(feature = "stdlib")]
+fn map(evaluator: &mut Evaluator, args: Vec<Object>) -> Object {
+    let (elements, function) = match array_and_function("map", &args, 2) {
+        Ok(found) => found,
+        Err(error) => return error,
+    };
+    if let Some(error) = evaluator.check_array_length(elements.len()) {
+        return error;
+    }
+    let mut mapped = Vec::with_capacity(elements.len());
+    for element in elements.iter() {
+        let result = evaluator.call_function(function.clone(), vec![element.clone()]);
+        if result.object_type() == object::ObjectType::ERROR {
+            return result;
+        }
+        mapped.push(result);
+    }
+    Object::Array(mapped.into())
+}
+
+#[cfg(feature = "stdlib")]
+fn filter(evaluator: &mut Evaluator, args: Vec<Object>) -> Object {
+    let (elements, function) = match array_and_function("filter", &args, 2) {
+        Ok(found) => found,
+        Err(error) => return error,
+    };
+    let mut kept = vec![];
+    for element in elements.iter() {
+        let result = evaluator.call_function(function.clone(), vec![element.clone()]);
+        if result.object_type() == object::ObjectType::ERROR {
+            return result;
+        }
+        if crate::is_truthy(result) {
+            kept.push(element.clone());
+        }
+    }
+    Object::Array(kept.into())
+}
+
+// reduce(arr, init, fn(acc, x) { ... })
+#[cfg(feature = "stdlib")]
+fn reduce(evaluator: &mut Evaluator, args: Vec<Object>) -> Object {
+    let (elements, function) = match array_and_function("reduce", &args, 3) {
+        Ok(found) => found,
+        Err(error) => return error,
+    };
+    let mut accumulator = args[1].clone();
+    for element in elements.iter() {
+        accumulator = evaluator.call_function(function.clone(), vec![accumulator, element.clone()]);
+        if accumulator.object_type() == object::ObjectType::ERROR {
+            return accumulator;
+        }
+    }
+    accumulator
+}
+
+#[cfg(feature = "stdlib")]
+fn each(evaluator: &mut Evaluator, args: Vec<Object>) -> Object {
+    let (elements, function) = match array_and_function("each", &args, 2) {
+        Ok(found) => found,
+        Err(error) => return error,
+    };
+    for element in elements.iter() {
+        let result = evaluator.call_function(function.clone(), vec![element.clone()]);
+        if result.object_type() == object::ObjectType::ERROR {
+            return result;
+        }
+    }
+    Object::Null
+}
+
+// The array builtins return a new array and leave the one they are given
+// as it was. The two share what elements they can, so building an array
+// with push in a loop copies nothing; object::Array has the details.
+#[cfg(feature = "stdlib")]
+fn array_arg<'a>(name: &str, args: &'a [Object], want: usize) -> Result<&'a object::Array, Object> {
+    if args.len() != want {
+        return Err(Object::Error(format!("wrong number of arguments to {}: got {}, want {}", name, args.len(), want)));
+    }
+    match &args[0] {
+        Object::Array(elements) => Ok(elements),
+        other => Err(Object::Error(format!("first argument to {} must be an ARRAY, got {:?}", name, other.object_type()))),
+    }
+}
+
+// The index argument of insert and remove, which must be within bounds.
+#[cfg(feature = "stdlib")]
+fn index_arg(name: &str, index: &Object, len: usize, bound: usize) -> Result<usize, Object> {
+    match index {
+        Object::Integer(i) => usize::try_from(*i).ok().filter(|i| *i < bound).ok_or_else(|| {
+            Object::Error(format!("index {} out of range for {} on an array of length {}", i, name, len))
+        }),
+        other => Err(Object::Error(format!("second argument to {} must be an INTEGER, got {:?}", name, other.object_type()))),
+    }
+}
+
+#[cfg(feature = "stdlib")]
+fn push(evaluator: &mut Evaluator, args: Vec<Object>) -> Object {
+    let elements = match array_arg("push", &args, 2) {
+        Ok(elements) => elements,
+        Err(error) => return error,
+    };
+    if let Some(error) = evaluator.check_array_length(elements.len() + 1) {
+        return error;
+    }
+    Object::Array(elements.push(args[1].clone()))
+}
+
+// All but the last element; last gives that one.
+#[cfg(feature = "stdlib")]
+fn pop(_: &mut Evaluator, args: Vec<Object>) -> Object {
+    match array_arg("pop", &args, 1) {
+        Ok(elements) => match elements.pop() {
+            Some(popped) => Object::Array(popped),
+            None => Object::Error("cannot pop from an empty array".to_string()),
+        },
+        Err(error) => error,
+    }
+}
+
+// insert(arr, i, value) puts value before the element at i, or at the end
+// when i is the length.
+#[cfg(feature = "stdlib")]
+fn insert(evaluator: &mut Evaluator, args: Vec<Object>) -> Object {
+    let elements = match array_arg("insert", &args, 3) {
+        Ok(elements) => elements,
+        Err(error) => return error,
+    };
+    if let Some(error) = evaluator.check_array_length(elements.len() + 1) {
+        return error;
+    }
+    match index_arg("insert", &args[1], elements.len(), elements.len() + 1) {
+        Ok(index) => Object::Array(elements.insert(index, args[2].clone()).unwrap()),
+        Err(error) => error,
+    }
+}
+
+#[cfg(feature = "stdlib")]
+fn remove(_: &mut Evaluator, args: Vec<Object>) -> Object {
+    let elements = match array_arg("remove", &args, 2) {
+        Ok(elements) => elements,
+        Err(error) => return error,
+    };
+    match index_arg("remove", &args[1], elements.len(), elements.len()) {
+        Ok(index) => Object::Array(elements.remove(index).unwrap()),
+        Err(error) => error,
+    }
+}
+
+// Checks the argument count and that every argument is a STRING, so the
+// string builtins below can destructure their arguments directly.
+#[cfg(any(feature = "stdlib", feature = "fs"))]
+fn string_args<'a>(name: &str, args: &'a [Object], want: usize) -> Result<Vec<&'a str>, Object> {
+    if args.len() != want {
+        return Err(Object::Error(format!("wrong number of arguments to {}: got {}, want {}", name, args.len(), want)));
+    }
+    args.iter().map(|arg| match arg {
+        Object::String(value) => Ok(&**value),
+        _ => Err(Object::Error(format!("argument to {} must be a STRING, got {:?}", name, arg.object_type()))),
+    }).collect()
+}
+
+// An empty separator splits the string into its characters.
+#[cfg(feature = "stdlib")]
+fn split(evaluator: &mut Evaluator, args: Vec<Object>) -> Object {
+    let values = match string_args("split", &args, 2) {
+        Ok(values) => values,
+        Err(error) => return error,
+    };
+    let (value, separator) = (values[0], values[1]);
+    let parts: Vec<Object> = if separator.is_empty() {
+        value.chars().map(|ch| Object::String(ch.to_string().into())).collect()
+    } else {
+        value.split(separator).map(|part| Object::String(part.into())).collect()
+    };
+    if let Some(error) = evaluator.check_array_length(parts.len()) {
+        return error;
+    }
+    Object::Array(parts.into())
+}
+
+#[cfg(feature = "stdlib")]
+fn join(evaluator: &mut Evaluator, args: Vec<Object>) -> Object {
+    let (elements, separator) = match args.as_slice() {
+        [Object::Array(elements), Object::String(separator)] => (elements, separator),
+        [Object::Array(_), separator] => {
+            return Object::Error(format!("second argument to join must be a STRING, got {:?}", separator.object_type()))
+        },
+        [array, _] => return Object::Error(format!("first argument to join must be an ARRAY, got {:?}", array.object_type())),
+        _ => return Object::Error(format!("wrong number of arguments to join: got {}, want 2", args.len())),
+    };
+    let parts: Vec<String> = elements.iter().map(|e| e.inspect()).collect();
+    let joined = parts.join(separator);
+    if let Some(error) = evaluator.check_string_length(joined.len()) {
+        return error;
+    }
+    Object::String(joined.into())
+}
+
+#[cfg(feature = "stdlib")]
+fn upper(_: &mut Evaluator, args: Vec<Object>) -> Object {
+    match string_args("upper", &args, 1) {
+        Ok(values) => Object::String(values[0].to_uppercase().into()),
+        Err(error) => error,
+    }
+}
+
+#[cfg(feature = "stdlib")]
+fn lower(_: &mut Evaluator, args: Vec<Object>) -> Object {
+    match string_args("lower", &args, 1) {
+        Ok(values) => Object::String(values[0].to_lowercase().into()),
+        Err(error) => error,
+    }
+}
+
+#[cfg(feature = "stdlib")]
+fn trim(_: &mut Evaluator, args: Vec<Object>) -> Object {
+    match string_args("trim", &args, 1) {
+        Ok(values) => Object::String(values[0].trim().into()),
+        Err(error) => error,
+    }
+}
+
+// Replaces every occurrence of the pattern.
+#[cfg(feature = "stdlib")]
+fn replace(evaluator: &mut Evaluator, args: Vec<Object>) -> Object {
+    let values = match string_args("replace", &args, 3) {
+        Ok(values) => values,
+        Err(error) => return error,
+    };
+    if values[1].is_empty() {
+        return Object::Error("pattern passed to replace must not be empty".to_string());
+    }
+    let replaced = values[0].replace(values[1], values[2]);
+    if let Some(error) = evaluator.check_string_length(replaced.len()) {
+        return error;
+    }
+    Object::String(replaced.into())
+}
+
+#[cfg(feature = "stdlib")]
+fn contains(_: &mut Evaluator, args: Vec<Object>) -> Object {
+    match string_args("contains", &args, 2) {
+        Ok(values) => Object::Boolean(values[0].contains(values[1])),
+        Err(error) => error,
+    }
+}
+
+// substr(s, start) or substr(s, start, length), counted in characters. A
+// range running past the end of the string is cut short.
+#[cfg(feature = "stdlib")]
+fn substr(_: &mut Evaluator, args: Vec<Object>) -> Object {
+    let (value, start, length) = match args.as_slice() {
+        [Object::String(value), Object::Integer(start)] => (value, *start, None),
+        [Object::String(value), Object::Integer(start), Object::Integer(length)] => (value, *start, Some(*length)),
+        [_, _] | [_, _, _] => {
+            let types: Vec<String> = args.iter().map(|arg| format!("{:?}", arg.object_type())).collect();
+            return Object::Error(format!("arguments to substr must be (STRING, INTEGER[, INTEGER]), got ({})", types.join(", ")));
+        },
+        _ => return Object::Error(format!("wrong number of arguments to substr: got {}, want 2 or 3", args.len())),
+    };
+    if start < 0 || length.is_some_and(|length| length < 0) {
+        return Object::Error("start and length passed to substr must not be negative".to_string());
+    }
+    let chars = value.chars().skip(start as usize);
+    let result: String = match length {
+        Some(length) => chars.take(length as usize).collect(),
+        None => chars.collect(),
+    };
+    Object::String(result.into())
+}
+
+// The file builtins are always defined in builds with the fs feature, so a
+// script gets a clear error rather than an unknown identifier when the
+// embedder has not allowed file access.
+#[cfg(feature = "fs")]
+fn file_args<'a>(evaluator: &Evaluator, name: &str, args: &'a [Object], want: usize) -> Result<Vec<&'a str>, Object> {
+    if !evaluator.limits.allow_fs {
+        return Err(Object::Error(format!("cannot call {}: file access is disabled", name)));
+    }
+    string_args(name, args, want)
+}
+
+// inspect_pretty(x) or inspect_pretty(x, separator): x as the REPL shows
+// it, always with the digits of numbers grouped.
+#[cfg(feature = "stdlib")]
+fn inspect_pretty(evaluator: &mut Evaluator, args: Vec<Object>) -> Object {
+    let separator = match args.as_slice() {
+        [_] => evaluator.pretty.digit_grouping.unwrap_or(','),
+        [_, Object::String(separator)] => {
+            let mut chars = separator.chars();
+            match (chars.next(), chars.next()) {
+                (Some(separator), None) => separator,
+                _ => return Object::Error(format!("separator passed to inspect_pretty must be a single character, got {:?}", separator.to_string())),
+            }
+        },
+        [_, other] => return Object::Error(format!("second argument to inspect_pretty must be a STRING, got {:?}", other.object_type())),
+        _ => return Object::Error(format!("wrong number of arguments to inspect_pretty: got {}, want 1 or 2", args.len())),
+    };
+    let config = object::PrettyConfig { digit_grouping: Some(separator), ..evaluator.pretty.clone() };
+    Object::String(args[0].inspect_pretty(&config).into())
+}
+
+#[cfg(feature = "stdlib")]
+fn type_name(_: &mut Evaluator, args: Vec<Object>) -> Object {
+    match args.as_slice() {
+        [arg] => Object::String(format!("{:?}", arg.object_type()).into()),
+        _ => Object::Error(format!("wrong number of arguments to type: got {}, want 1", args.len())),
+    }
+}
+
+#[cfg(feature = "stdlib")]
+fn function_arg<'a>(name: &str, args: &'a [Object]) -> Result<&'a object::Function, Object> {
+    match args {
+        [Object::Function(function)] => Ok(function),
+        [arg] => Err(Object::Error(format!("argument to {} must be a FUNCTION, got {:?}", name, arg.object_type()))),
+        _ => Err(Object::Error(format!("wrong number of arguments to {}: got {}, want 1", name, args.len()))),
+    }
+}
+
+#[cfg(feature = "stdlib")]
+fn arity(_: &mut Evaluator, args: Vec<Object>) -> Object {
+    match function_arg("arity", &args) {
+        Ok(function) => Object::Integer(function.parameters.len() as i64),
+        Err(error) => error,
+    }
+}
+
+#[cfg(feature = "stdlib")]
+fn params(_: &mut Evaluator, args: Vec<Object>) -> Object {
+    match function_arg("params", &args) {
+        Ok(function) => Object::Array(function.parameters.iter().map(|p| Object::String(p.value.as_str().into())).collect()),
+        Err(error) => error,
+    }
+}
+
+// assert(condition) or assert(condition, message): fails unless the
+// condition is truthy, as it would be for if.
+#[cfg(feature = "stdlib")]
+fn assert(evaluator: &mut Evaluator, args: Vec<Object>) -> Object {
+    let message = match args.as_slice() {
+        [condition] | [condition, _] if crate::is_truthy(condition.clone()) => return Object::Null,
+        [_] => "assertion failed".to_string(),
+        [_, Object::String(message)] => format!("assertion failed: {}", message),
+        [_, message] => format!("assertion failed: {}", message.inspect()),
+        _ => return Object::Error(format!("wrong number of arguments to assert: got {}, want 1 or 2", args.len())),
+    };
+    assertion_failed(evaluator, message)
+}
+
+// Fails unless the two are equal by ==. Both are shown as the REPL would
+// show them, so "1" and 1 can be told apart.
+#[cfg(feature = "stdlib")]
+fn assert_eq(evaluator: &mut Evaluator, args: Vec<Object>) -> Object {
+    let (actual, expected) = match args.as_slice() {
+        [actual, expected] => (actual, expected),
+        _ => return Object::Error(format!("wrong number of arguments to assert_eq: got {}, want 2", args.len())),
+    };
+    if actual.equals(expected) {
+        return Object::Null;
+    }
+    let config = object::PrettyConfig::default();
+    let message = format!("assertion failed: {} != {}", actual.inspect_pretty(&config), expected.inspect_pretty(&config));
+    assertion_failed(evaluator, message)
+}
+
+#[cfg(feature = "stdlib")]
+fn assertion_failed(evaluator: &mut Evaluator, message: String) -> Object {
+    match evaluator.assertion_failures.as_mut() {
+        Some(failures) => {
+            failures.push(message);
+            Object::Null
+        },
+        None => Object::Error(message),
+    }
+}
+
+#[cfg(feature = "stdlib")]
+fn args(evaluator: &mut Evaluator, args: Vec<Object>) -> Object {
+    if let Err(error) = string_args("args", &args, 0) {
+        return error;
+    }
+    Object::Array(evaluator.script_args.iter().map(|arg| Object::String(arg.as_str().into())).collect())
+}
+
+// Null for a variable that is not set. Reading the environment is allowed
+// along with file access.
+#[cfg(feature = "fs")]
+fn env(evaluator: &mut Evaluator, args: Vec<Object>) -> Object {
+    if !evaluator.limits.allow_fs {
+        return Object::Error("cannot call env: environment access is disabled".to_string());
+    }
+    let name = match string_args("env", &args, 1) {
+        Ok(values) => values[0],
+        Err(error) => return error,
+    };
+    match std::env::var(name) {
+        Ok(value) => Object::String(value.into()),
+        Err(_) => Object::Null,
+    }
+}
+
+#[cfg(feature = "fs")]
+fn read_file(evaluator: &mut Evaluator, args: Vec<Object>) -> Object {
+    let path = match file_args(evaluator, "read_file", &args, 1) {
+        Ok(values) => values[0],
+        Err(error) => return error,
+    };
+    match std::fs::read_to_string(path) {
+        Ok(contents) => {
+            if let Some(error) = evaluator.check_string_length(contents.len()) {
+                return error;
+            }
+            Object::String(contents.into())
+        },
+        Err(err) => Object::Error(format!("cannot read {}: {}", path, err)),
+    }
+}
+
+#[cfg(feature = "fs")]
+fn write_file(evaluator: &mut Evaluator, args: Vec<Object>) -> Object {
+    let values = match file_args(evaluator, "write_file", &args, 2) {
+        Ok(values) => values,
+        Err(error) => return error,
+    };
+    match std::fs::write(values[0], values[1]) {
+        Ok(()) => Object::Null,
+        Err(err) => Object::Error(format!("cannot write {}: {}", values[0], err)),
+    }
+}
+
+// Creates the file when it does not exist yet.
+#[cfg(feature = "fs")]
+fn append_file(evaluator: &mut Evaluator, args: Vec<Object>) -> Object {
+    let values = match file_args(evaluator, "append_file", &args, 2) {
+        Ok(values) => values,
+        Err(error) => return error,
+    };
+    let appended = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(values[0])
+        .and_then(|mut file| file.write_all(values[1].as_bytes()));
+    match appended {
+        Ok(()) => Object::Null,
+        Err(err) => Object::Error(format!("cannot write {}: {}", values[0], err)),
+    }
+}
+
+#[cfg(feature = "fs")]
+fn file_exists(evaluator: &mut Evaluator, args: Vec<Object>) -> Object {
+    match file_args(evaluator, "file_exists", &args, 1) {
+        Ok(values) => Object::Boolean(std::path::Path::new(values[0]).exists()),
+        Err(error) => error,
+    }
+}
@@ -0,0 +1,66 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[derive(Clone)]
+pub struct Frame {
+    pub function: String,
+    // Where the call was made.
+    pub file: Option<token::FileId>,
+    pub line: usize,
+    pub column: usize,
+    pub environment: Rc<RefCell<object::Environment>>,
+}
+
+impl Frame {
+    pub fn get(&self, name: &str) -> Option<object::Object> {
+        self.environment.borrow().get(name)
+    }
+
+    pub fn locals(&self) -> Vec<(String, object::Object)> {
+        let mut locals: Vec<_> = self.environment.borrow().scope.iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        locals.sort_by(|a, b| a.0.cmp(&b.0));
+        locals
+    }
+}
+
+// Frames are ordered from the outermost call to the innermost one.
+#[derive(Clone, Default)]
+pub struct CallStack {
+    frames: Vec<Frame>,
+}
+
+impl CallStack {
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn innermost(&self) -> Option<&Frame> {
+        self.frames.last()
+    }
+
+    pub fn get(&self, frame: usize, name: &str) -> Option<object::Object> {
+        self.frames.get(frame).and_then(|frame| frame.get(name))
+    }
+
+    pub(crate) fn push(&mut self, frame: Frame) {
+        self.frames.push(frame);
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<Frame> {
+        self.frames.pop()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.frames.clear();
+    }
+}
@@ -0,0 +1,202 @@
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+use ast::{Statement, StatementKind};
+use token::{FileId, Token};
+
+use crate::CallStack;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Breakpoint {
+    // The first statement starting on a line. Positions in code without a
+    // file, as evaluated by evaluate_program from a plain Lexer, match a
+    // file of None.
+    Line { file: Option<FileId>, line: usize },
+    // The first statement of every call made through this name.
+    Function(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resume {
+    // Pause again at the next statement, inside calls too.
+    Step,
+    // Pause again at the next statement outside the calls the current one
+    // makes.
+    Next,
+    // Run until a breakpoint is reached.
+    Continue,
+    // Abandon the evaluation, which fails as interrupted.
+    Stop,
+}
+
+// Where the evaluator stopped, for a console to show.
+pub struct Pause<'a> {
+    pub statement: &'a dyn Statement,
+    pub token: &'a Token,
+    pub environment: &'a Rc<RefCell<object::Environment>>,
+    pub call_stack: &'a CallStack,
+    // The breakpoint that was hit, or None when stepping got here.
+    pub breakpoint: Option<&'a Breakpoint>,
+}
+
+// Talks to whoever is debugging while the evaluator is paused. output is
+// the evaluator's output sink.
+pub trait DebugConsole {
+    fn pause(&mut self, pause: &Pause, output: &mut dyn Write) -> Resume;
+}
+
+// Decides before each statement whether the evaluator pauses there.
+pub struct Debugger {
+    pub breakpoints: Vec<Breakpoint>,
+    console: Box<dyn DebugConsole>,
+    resume: Resume,
+    // The call depth at the last pause, for Next.
+    paused_depth: usize,
+    // Where the statement before this one was, so a line with several
+    // statements on it, or a loop written on one line, breaks once.
+    last_line: Option<(Option<FileId>, usize)>,
+    last_depth: usize,
+}
+
+impl Debugger {
+    pub fn new(console: Box<dyn DebugConsole>) -> Debugger {
+        Debugger {
+            breakpoints: vec![],
+            console,
+            resume: Resume::Continue,
+            paused_depth: 0,
+            last_line: None,
+            last_depth: 0,
+        }
+    }
+
+    // Evaluations start out running to the first breakpoint.
+    pub(crate) fn reset(&mut self) {
+        self.resume = Resume::Continue;
+        self.last_line = None;
+        self.last_depth = 0;
+    }
+
+    // Returns the resumption the console chose, or None when the statement
+    // runs without pausing. Blocks are not stopped at; the statements in
+    // them are.
+    pub(crate) fn before(
+        &mut self,
+        statement: &dyn Statement,
+        environment: &Rc<RefCell<object::Environment>>,
+        call_stack: &CallStack,
+        output: &mut dyn Write,
+    ) -> Option<Resume> {
+        let token = statement_token(statement)?;
+        let depth = call_stack.depth();
+        let line = (token.file, token.line);
+        let new_line = self.last_line != Some(line) || depth != self.last_depth;
+        let entered = depth > self.last_depth;
+        self.last_line = Some(line);
+        self.last_depth = depth;
+
+        let breakpoint = self.breakpoints.iter().find(|breakpoint| match breakpoint {
+            Breakpoint::Line { file, line } => new_line && token.file == *file && token.line == *line,
+            Breakpoint::Function(name) => entered && call_stack.innermost().is_some_and(|frame| frame.function == *name),
+        });
+        let stepping = match self.resume {
+            Resume::Step => true,
+            Resume::Next => depth <= self.paused_depth,
+            Resume::Continue | Resume::Stop => false,
+        };
+        if breakpoint.is_none() && !stepping {
+            return None;
+        }
+        let pause = Pause {
+            statement,
+            token,
+            environment,
+            call_stack,
+            breakpoint,
+        };
+        self.resume = self.console.pause(&pause, output);
+        self.paused_depth = depth;
+        Some(self.resume)
+    }
+}
+
+fn statement_token(statement: &dyn Statement) -> Option<&Token> {
+    match statement.kind() {
+        StatementKind::Let(let_statement) => Some(&let_statement.token),
+        StatementKind::Return(return_statement) => Some(&return_statement.token),
+        StatementKind::Expression(expression_statement) => Some(&expression_statement.token),
+        StatementKind::Block(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Evaluator;
+    use lexer::Lexer;
+    use parser::Parser;
+
+    // Answers each pause with the next resumption in order, and records
+    // where it paused as "line: statement (bindings in scope)".
+    struct Script {
+        resumes: Vec<Resume>,
+        pauses: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl DebugConsole for Script {
+        fn pause(&mut self, pause: &Pause, _output: &mut dyn Write) -> Resume {
+            let mut locals: Vec<String> = pause.environment.borrow().scope.iter()
+                .map(|(name, value)| match value {
+                    object::Object::Function(_) => format!("{}=fn", name),
+                    value => format!("{}={}", name, value.inspect()),
+                })
+                .collect();
+            locals.sort();
+            self.pauses.borrow_mut().push(format!("{}: {} ({})", pause.token.line, pause.statement.to_string(), locals.join(" ")));
+            if self.resumes.is_empty() { Resume::Continue } else { self.resumes.remove(0) }
+        }
+    }
+
+    fn debug(input: &str, breakpoints: Vec<Breakpoint>, resumes: Vec<Resume>) -> (String, Vec<String>) {
+        let pauses = Rc::new(RefCell::new(vec![]));
+        let mut debugger = Debugger::new(Box::new(Script { resumes, pauses: pauses.clone() }));
+        debugger.breakpoints = breakpoints;
+        let mut evaluator = Evaluator::new();
+        evaluator.debugger = Some(debugger);
+        let env = Rc::new(RefCell::new(object::Environment::new()));
+        let result = evaluator.evaluate_program(Parser::new(Lexer::new(input)).parse_program(), env).unwrap();
+        let pauses = pauses.borrow().clone();
+        (result.inspect(), pauses)
+    }
+
+    const PROGRAM: &str = "let add = fn(a, b) {\n  let sum = a + b;\n  sum\n};\nlet x = add(1, 2);\nlet y = x * 2; let z = y + 1;\nz";
+
+    #[test]
+    fn test_breakpoints() {
+        let (result, pauses) = debug(PROGRAM, vec![Breakpoint::Line { file: None, line: 6 }, Breakpoint::Function("add".to_string())], vec![]);
+        assert_eq!(result, "7");
+        assert_eq!(pauses, vec!["2: let sum = (a + b); (a=1 b=2)", "6: let y = (x * 2); (add=fn x=3)"]);
+
+        let (_, pauses) = debug("let f = fn(n) { if (n > 0) { f(n - 1) } else { n } };\nf(2)", vec![Breakpoint::Function("f".to_string())], vec![]);
+        assert_eq!(pauses.len(), 3);
+    }
+
+    #[test]
+    fn test_stepping() {
+        let line = |line| vec![Breakpoint::Line { file: None, line }];
+        let lines = |pauses: Vec<String>| pauses.iter().map(|p| p.split(':').next().unwrap().to_string()).collect::<Vec<_>>();
+
+        let (_, pauses) = debug(PROGRAM, line(5), vec![Resume::Step, Resume::Step, Resume::Step, Resume::Continue]);
+        assert_eq!(lines(pauses), vec!["5", "2", "3", "6"]);
+        let (_, pauses) = debug(PROGRAM, line(5), vec![Resume::Next, Resume::Next, Resume::Next]);
+        assert_eq!(lines(pauses), vec!["5", "6", "6", "7"]);
+        // Next from inside a call stops again once it has returned.
+        let (_, pauses) = debug(PROGRAM, line(3), vec![Resume::Next]);
+        assert_eq!(lines(pauses), vec!["3", "6"]);
+
+        let (result, pauses) = debug(PROGRAM, line(2), vec![Resume::Stop]);
+        assert_eq!(result, "evaluation interrupted");
+        assert_eq!(pauses.len(), 1);
+    }
+}
@@ -1,285 +1,2003 @@
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+#[cfg(feature = "clock")]
+use std::time::Instant;
 
-pub fn evaluate_program(program: ast::Program, env: Rc<RefCell<object::Environment>>) -> Option<Rc<dyn object::Object>> {
-    let mut result = None;
-    for statement in program.statements {
-        let evaluated = evaluate_statement(statement, env.clone());
-        match evaluated.object_type() {
-            object::ObjectType::RETURN_VALUE => {
-                result = Some(evaluated.as_ref().as_any().downcast_ref::<object::ReturnValue>().unwrap().value.clone());
-                break;
+use object::Object;
+
+pub mod builtins;
+pub mod call_stack;
+pub mod debugger;
+pub mod loader;
+mod macros;
+#[cfg(feature = "fs")]
+mod modules;
+
+pub use call_stack::{CallStack, Frame};
+pub use debugger::{Breakpoint, DebugConsole, Debugger, Pause, Resume};
+pub use loader::{FileSystemLoader, MemoryLoader, ModuleLoader};
+
+pub fn evaluate_program(program: ast::Program, env: Rc<RefCell<object::Environment>>) -> Option<Object> {
+    Evaluator::new().evaluate_program(program, env)
+}
+
+// Each nested call uses up to about 20 KiB of native stack in a debug build
+// and a few KiB in a release one, so deep recursion is cut off before it
+// overflows the thread. The default leaves room to spare on an 8 MiB main
+// thread; a host running evaluations on a thread of its own can allow more
+// with Limits::for_stack_size, as the REPL does.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 200;
+const STACK_PER_CALL: usize = 20 << 10;
+
+#[derive(Clone, Copy)]
+pub struct Limits {
+    pub max_string_length: Option<usize>,
+    // Elements one array may hold, checked wherever an array is built.
+    pub max_array_length: Option<usize>,
+    pub max_call_depth: Option<usize>,
+    pub allow_imports: bool,
+    // Whether the file builtins (read_file, write_file, ...) may touch the
+    // file system, and env may read environment variables. Off unless the
+    // embedder opts in.
+    pub allow_fs: bool,
+    // Expressions one run may evaluate, where a run is one call of
+    // evaluate_program or whatever a host does after start_run.
+    pub max_steps: Option<usize>,
+    // Bindings one scope may hold, checked whenever let adds one. The
+    // parameters a call binds are not checked, but they take up room.
+    pub max_env_entries: Option<usize>,
+    // How long one run may take. Checked where cancellation is, as for
+    // with_timeout. Ignored without the clock feature.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_string_length: None,
+            max_array_length: None,
+            max_call_depth: Some(DEFAULT_MAX_CALL_DEPTH),
+            allow_imports: true,
+            allow_fs: false,
+            max_steps: None,
+            max_env_entries: None,
+            timeout: None,
+        }
+    }
+}
+
+impl Limits {
+    // These limits with the call depth a thread of stack_size bytes has
+    // room for, twice over.
+    pub fn for_stack_size(self, stack_size: usize) -> Limits {
+        Limits { max_call_depth: Some(stack_size / STACK_PER_CALL / 2), ..self }
+    }
+
+    // How large a thread's stack should be to evaluate under these limits,
+    // the other way round from for_stack_size. Without a call depth limit
+    // no size is enough, and this is 64 MiB.
+    pub fn stack_size(&self) -> usize {
+        match self.max_call_depth {
+            Some(depth) => (depth * STACK_PER_CALL * 2).max(8 << 20),
+            None => 64 << 20,
+        }
+    }
+
+    pub fn sandbox() -> Limits {
+        Limits {
+            max_string_length: Some(1 << 20),
+            max_array_length: Some(1 << 20),
+            max_call_depth: Some(DEFAULT_MAX_CALL_DEPTH),
+            allow_imports: false,
+            allow_fs: false,
+            max_steps: None,
+            max_env_entries: None,
+            timeout: None,
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    // Set for a token made by linked: cancelling the token it came from
+    // cancels this one too, and resetting this one leaves that alone.
+    parent: Option<Arc<AtomicBool>>,
+}
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst) || self.parent.as_ref().is_some_and(|parent| parent.load(Ordering::SeqCst))
+    }
+
+    pub fn reset(&self) {
+        self.cancelled.store(false, Ordering::SeqCst);
+    }
+
+    // A token for an evaluator that resets its own, so that a cancel made
+    // through this one before the evaluation starts still stops it.
+    pub fn linked(&self) -> CancellationToken {
+        CancellationToken {
+            cancelled: Arc::default(),
+            parent: Some(self.cancelled.clone()),
+        }
+    }
+}
+
+// Set by with_timeout for the duration of a call. A deadline never lies past
+// the one enclosing it, so only the innermost needs checking.
+#[cfg(feature = "clock")]
+#[derive(Clone, Copy)]
+struct Deadline {
+    at: Instant,
+    ms: u64,
+}
+
+const ENVIRONMENT_POOL_CAPACITY: usize = 64;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Stats {
+    pub expressions_evaluated: usize,
+    // Strings, arrays, closures and string builders created by evaluation.
+    pub values_allocated: usize,
+    pub environments_allocated: usize,
+    pub environments_reused: usize,
+    pub environments_released: usize,
+    // Environments freed by the cycle collector.
+    pub environments_collected: usize,
+    pub max_call_depth: usize,
+}
+
+pub struct Evaluator {
+    pub limits: Limits,
+    pub output: Box<dyn Write>,
+    pub cancellation: CancellationToken,
+    pub source_path: Option<PathBuf>,
+    // What the args builtin returns: the arguments a script was run with.
+    pub script_args: Vec<String>,
+    // While set, assert and assert_eq record their failures here and let
+    // evaluation go on, as a test runner wants. Otherwise a failed
+    // assertion is a runtime error.
+    pub assertion_failures: Option<Vec<String>>,
+    // How results are shown to people, as by the REPL. inspect_pretty
+    // groups digits with ',' when this has no separator of its own.
+    pub pretty: object::PrettyConfig,
+    // Whether evaluate_program runs programs through the optimizer first.
+    pub optimize: bool,
+    // Where each statement and expression evaluated is logged, with what it
+    // came to, indented by nesting. Nothing is logged while this is None.
+    pub trace: Option<Box<dyn Write>>,
+    trace_depth: usize,
+    // Consulted before every statement while set, to pause at breakpoints
+    // and while stepping.
+    pub debugger: Option<Debugger>,
+    environment_pool: Vec<Rc<RefCell<object::Environment>>>,
+    stats: Stats,
+    call_stack: CallStack,
+    error_call_stack: Option<CallStack>,
+    error_operands: Vec<Rc<token::Token>>,
+    #[cfg(feature = "clock")]
+    deadlines: Vec<Deadline>,
+    steps: usize,
+    #[cfg(feature = "fs")]
+    modules: modules::Modules,
+}
+
+impl Default for Evaluator {
+    fn default() -> Self {
+        Evaluator {
+            limits: Limits::default(),
+            output: Box::new(io::stdout()),
+            cancellation: CancellationToken::default(),
+            source_path: None,
+            script_args: vec![],
+            assertion_failures: None,
+            pretty: object::PrettyConfig::default(),
+            optimize: false,
+            trace: None,
+            trace_depth: 0,
+            debugger: None,
+            environment_pool: vec![],
+            stats: Stats::default(),
+            call_stack: CallStack::default(),
+            error_call_stack: None,
+            error_operands: vec![],
+            #[cfg(feature = "clock")]
+            deadlines: vec![],
+            steps: 0,
+            #[cfg(feature = "fs")]
+            modules: modules::Modules::default(),
+        }
+    }
+}
+
+impl Evaluator {
+    pub fn new() -> Evaluator {
+        Evaluator::default()
+    }
+
+    pub fn with_limits(limits: Limits) -> Evaluator {
+        Evaluator {
+            limits,
+            ..Evaluator::default()
+        }
+    }
+
+    #[cfg(not(feature = "fs"))]
+    pub fn set_module_environment(&mut self, _env: Rc<RefCell<object::Environment>>) {}
+
+    #[cfg(not(feature = "fs"))]
+    pub fn set_module_loader(&mut self, _loader: Box<dyn ModuleLoader>) {}
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = Stats::default();
+    }
+
+    pub fn call_stack(&self) -> &CallStack {
+        &self.call_stack
+    }
+
+    // The call stack as it was when the last evaluation failed, before the
+    // error unwound it.
+    pub fn error_call_stack(&self) -> Option<&CallStack> {
+        self.error_call_stack.as_ref()
+    }
+
+    // Where the operands start when the last evaluation failed because an
+    // operator could not be applied to them, as in `1 + true`.
+    pub fn error_operands(&self) -> &[Rc<token::Token>] {
+        &self.error_operands
+    }
+
+    pub fn evaluate_program(&mut self, program: ast::Program, env: Rc<RefCell<object::Environment>>) -> Option<Object> {
+        self.begin_program();
+        let result = self.continue_program(program, env);
+        self.cancellation.reset();
+        result
+    }
+
+    // For a host that runs a program as it reads it: begin_program once,
+    // then continue_program with each part as it comes, which all make up
+    // one run, as a single evaluate_program would.
+    pub fn begin_program(&mut self) {
+        self.cancellation.reset();
+        self.call_stack.clear();
+        self.error_call_stack = None;
+        self.error_operands.clear();
+        self.trace_depth = 0;
+        if let Some(debugger) = self.debugger.as_mut() {
+            debugger.reset();
+        }
+        self.start_run();
+    }
+
+    pub fn continue_program(&mut self, program: ast::Program, env: Rc<RefCell<object::Environment>>) -> Option<Object> {
+        let program = if self.optimize { optimizer::optimize(program) } else { program };
+        let mut result = None;
+        for statement in program.statements {
+            let evaluated = self.evaluate_statement(statement, env.clone());
+            match evaluated {
+                Object::ReturnValue(value) => {
+                    result = Some(*value);
+                    break;
+                }
+                Object::Error(_) => {
+                    result = Some(evaluated);
+                    break;
+                }
+                _ => { result = Some(evaluated);}
             }
-            object::ObjectType::ERROR => {
-                result = Some(Rc::new(object::Error { message: evaluated.as_ref().as_any().downcast_ref::<object::Error>().unwrap().message.clone() }));
-                break;
+        }
+        result
+    }
+
+    // Starts the step count and the timeout of limits afresh.
+    // evaluate_program does this itself; hosts calling into the evaluator
+    // some other way, as with call_function, do it first.
+    pub fn start_run(&mut self) {
+        self.steps = 0;
+        #[cfg(feature = "clock")]
+        {
+            self.deadlines.clear();
+            if let Some(timeout) = self.limits.timeout {
+                self.deadlines.push(Deadline {
+                    at: Instant::now() + timeout,
+                    ms: timeout.as_millis() as u64,
+                });
             }
-            _ => { result = Some(evaluated);}
         }
     }
-    result
-}
 
-fn evaluate_statement(statement: Rc<dyn ast::Statement>, env: Rc<RefCell<object::Environment>>) -> Rc<dyn object::Object> {
-    match statement.node_type() {
-        ast::NodeType::EXPRESSION_STATEMENT => {
-            let expression = statement.as_ref().as_any().downcast_ref::<ast::ExpressionStatement>().unwrap().expression.as_ref().unwrap().clone();
-            evaluate_expression(expression, env)
-        },
-        ast::NodeType::LET_STATEMENT => {
-            let let_statement = statement.as_ref().as_any().downcast_ref::<ast::LetStatement>().unwrap();
-            let value = evaluate_expression(let_statement.value.as_ref().unwrap().clone(), env.clone());
-            if value.object_type() == object::ObjectType::ERROR {
-                return value;
+    pub fn evaluate_statement(&mut self, statement: Rc<dyn ast::Statement>, env: Rc<RefCell<object::Environment>>) -> Object {
+        if self.trace.is_none() {
+            return self.evaluate_statement_untraced(statement, env);
+        }
+        self.trace_enter(statement.as_ref());
+        let result = self.evaluate_statement_untraced(statement, env);
+        self.trace_exit(&result);
+        result
+    }
+
+    fn evaluate_statement_untraced(&mut self, statement: Rc<dyn ast::Statement>, env: Rc<RefCell<object::Environment>>) -> Object {
+        if let Some(debugger) = self.debugger.as_mut() {
+            if debugger.before(statement.as_ref(), &env, &self.call_stack, &mut *self.output) == Some(Resume::Stop) {
+                return interrupted();
             }
-            env.borrow_mut().set(let_statement.name.value.clone(), value);
-            Rc::new(object::Null {})
-        },
-        ast::NodeType::RETURN_STATEMENT => {
-            let return_statement = statement.as_ref().as_any().downcast_ref::<ast::ReturnStatement>().unwrap();
-            let value = evaluate_expression(return_statement.return_value.as_ref().unwrap().clone(), env);
-            if value.object_type() == object::ObjectType::ERROR {
-                return value;
+        }
+        match statement.kind() {
+            ast::StatementKind::Expression(expression_statement) => match &expression_statement.expression {
+                Some(expression) => self.evaluate_expression(expression.clone(), env),
+                None => Object::Null,
+            },
+            ast::StatementKind::Let(let_statement) => {
+                let value = match &let_statement.value {
+                    Some(value) => self.evaluate_expression(value.clone(), env.clone()),
+                    None => Object::Null,
+                };
+                if value.object_type() == object::ObjectType::ERROR {
+                    return value;
+                }
+                if let Some(error) = self.check_env_entries(&env.borrow(), &let_statement.name.value) {
+                    return error;
+                }
+                env.borrow_mut().set(let_statement.name.value.clone(), value);
+                Object::Null
+            },
+            ast::StatementKind::Return(return_statement) => {
+                let value = match &return_statement.return_value {
+                    Some(value) => self.evaluate_expression(value.clone(), env),
+                    None => Object::Null,
+                };
+                if value.object_type() == object::ObjectType::ERROR {
+                    return value;
+                }
+                Object::ReturnValue(Box::new(value))
+            },
+            ast::StatementKind::Block(block) => {
+                let block_env = object::Environment::new_enclosed(env);
+                self.evaluate_statements(&block.statements, block_env)
+            },
+        }
+    }
+
+    pub fn evaluate_expression(&mut self, exp: Rc<dyn ast::Expression>, env: Rc<RefCell<object::Environment>>) -> Object {
+        if self.trace.is_none() {
+            return self.evaluate_expression_untraced(exp, env);
+        }
+        self.trace_enter(exp.as_ref());
+        let result = self.evaluate_expression_untraced(exp, env);
+        self.trace_exit(&result);
+        result
+    }
+
+    fn evaluate_expression_untraced(&mut self, exp: Rc<dyn ast::Expression>, env: Rc<RefCell<object::Environment>>) -> Object {
+        self.stats.expressions_evaluated += 1;
+        self.steps += 1;
+        if let Some(max) = self.limits.max_steps {
+            if self.steps > max {
+                return Object::Error(format!("resource limit exceeded: more than {} evaluation steps", max));
             }
-            Rc::new(object::ReturnValue { value })
-        },  
-        ast::NodeType::BLOCK_STATEMENT => {
-            let block_env = object::Environment::new_enclosed(env);
-            let result = evaluate_block_statement(statement, block_env);
-            result
-        },
-        _ => Rc::new(object::Null {})
+        }
+        match exp.kind() {
+            ast::ExpressionKind::Identifier(identifier) => {
+                if let Some(obj) = env.borrow().get(identifier.value.as_str()) {
+                    return obj;
+                }
+                match builtins::lookup(&identifier.value) {
+                    Some(_) => Object::Builtin(identifier.value.as_str().into()),
+                    None => Object::Error(format!("identifier not found: {}", identifier.value))
+                }
+            },
+            ast::ExpressionKind::IntegerLiteral(integer) => Object::Integer(integer.value),
+            ast::ExpressionKind::FloatLiteral(float) => Object::Float(float.value),
+            ast::ExpressionKind::StringLiteral(string) => Object::String(string.value.clone()),
+            ast::ExpressionKind::Boolean(boolean) => Object::Boolean(boolean.value),
+            ast::ExpressionKind::NullLiteral(_) => Object::Null,
+            ast::ExpressionKind::InterpolatedString(interpolated) => {
+                let mut out = String::new();
+                for part in &interpolated.parts {
+                    let value = self.evaluate_expression(part.clone(), env.clone());
+                    if value.object_type() == object::ObjectType::ERROR {
+                        return value;
+                    }
+                    out.push_str(&value.inspect());
+                    if let Some(error) = self.check_string_length(out.len()) {
+                        return error;
+                    }
+                }
+                self.count_allocation(Object::String(out.into()))
+            },
+            ast::ExpressionKind::Prefix(prefix) => {
+                let right = self.evaluate_expression(prefix.right.clone(), env);
+                if right.object_type() == object::ObjectType::ERROR {
+                    return right;
+                }
+                let result = evaluate_prefix_expression(prefix.operator.as_str(), right);
+                self.record_error_operands(&result, &[&prefix.right]);
+                result
+            },
+            ast::ExpressionKind::Infix(infix) => {
+                let left = self.evaluate_expression(infix.left.clone(), env.clone());
+                if left.object_type() == object::ObjectType::ERROR {
+                    return left;
+                }
+                let right = self.evaluate_expression(infix.right.clone(), env.clone());
+                if right.object_type() == object::ObjectType::ERROR {
+                    return right;
+                }
+                let result = self.evaluate_infix_expression(infix.operator.as_str(), left, right);
+                self.record_error_operands(&result, &[&infix.left, &infix.right]);
+                result
+            },
+            ast::ExpressionKind::Assign(assign) => {
+                let mut value = self.evaluate_expression(assign.value.clone(), env.clone());
+                if value.object_type() == object::ObjectType::ERROR {
+                    return value;
+                }
+                if assign.operator != "=" {
+                    let current = match env.borrow().get(assign.name.value.as_str()) {
+                        Some(obj) => obj,
+                        None => return Object::Error(format!("identifier not found: {}", assign.name.value)),
+                    };
+                    value = self.evaluate_infix_expression(assign.operator.trim_end_matches('='), current, value);
+                    if value.object_type() == object::ObjectType::ERROR {
+                        if self.error_operands.is_empty() {
+                            self.error_operands = vec![assign.name.token.clone(), ast::first_token(assign.value.as_ref())];
+                        }
+                        return value;
+                    }
+                }
+                if let Err(message) = env.borrow_mut().assign(assign.name.value.as_str(), value.clone()) {
+                    return Object::Error(message);
+                }
+                value
+            },
+            ast::ExpressionKind::If(if_expression) => {
+                let condition = self.evaluate_expression(if_expression.condition.clone(), env.clone());
+                if condition.object_type() == object::ObjectType::ERROR {
+                    return condition;
+                }
+
+                if is_truthy(condition) {
+                    self.evaluate_block_statement(&if_expression.consequence, env)
+                } else if let Some(alternative) = &if_expression.alternative {
+                    self.evaluate_block_statement(alternative, env)
+                } else {
+                    Object::Null
+                }
+            },
+            ast::ExpressionKind::While(while_expression) => {
+                let mut result: Object = Object::Null;
+                loop {
+                    if let Some(error) = self.check_interrupted() {
+                        return error;
+                    }
+                    let condition = self.evaluate_expression(while_expression.condition.clone(), env.clone());
+                    if condition.object_type() == object::ObjectType::ERROR {
+                        return condition;
+                    }
+                    if !is_truthy(condition) {
+                        break;
+                    }
+
+                    let evaluated = self.evaluate_block_statement(&while_expression.body, env.clone());
+                    match evaluated.object_type() {
+                        object::ObjectType::RETURN_VALUE => return evaluated,
+                        object::ObjectType::ERROR => return evaluated,
+                        _ => { result = evaluated; }
+                    }
+                }
+                result
+            },
+            ast::ExpressionKind::FunctionLiteral(function_literal) => {
+                let env = self.capture(&env);
+                self.count_allocation(Object::Function(Rc::new(object::Function { parameters: function_literal.parameters.clone(), body: function_literal.body.clone(), 
+                    env })))
+            },
+            ast::ExpressionKind::MacroLiteral(macro_literal) => {
+                Object::Macro(Rc::new(object::Macro { parameters: macro_literal.parameters.clone(), body: macro_literal.body.clone(),
+                    env: self.capture(&env) }))
+            },
+            #[cfg(feature = "fs")]
+            ast::ExpressionKind::Import(import) => self.import(import, env),
+            #[cfg(not(feature = "fs"))]
+            ast::ExpressionKind::Import(import) => {
+                Object::Error(format!("cannot import {}: imports are not supported in this build", import.path.value))
+            },
+            ast::ExpressionKind::Call(call_expression) => {
+                if call_expression.function.token_literal() == "quote" {
+                    return match call_expression.arguments.as_slice() {
+                        [argument] => self.quote(argument.clone(), env),
+                        arguments => Object::Error(format!("wrong number of arguments to quote: got {}, want 1", arguments.len())),
+                    };
+                }
+                let function = self.evaluate_expression(call_expression.function.clone(), env.clone());
+                if function.object_type() == object::ObjectType::ERROR {
+                    return function;
+                }
+                let args = self.evaluate_expressions(call_expression.arguments.clone(), env.clone());
+                if args.len() == 1 && args[0].object_type() == object::ObjectType::ERROR {
+                    return args[0].clone();
+                }
+                let name = match call_expression.function.kind() {
+                    ast::ExpressionKind::Identifier(identifier) => identifier.value.clone(),
+                    ast::ExpressionKind::FunctionLiteral(function) => format!("<fn at {}:{}>", function.token.line, function.token.column),
+                    _ => "<anonymous>".to_string(),
+                };
+                self.apply_function(function, args, name, &call_expression.token)
+            },
+            ast::ExpressionKind::Array(array) => {
+                let elements = self.evaluate_expressions(array.elements.clone(), env);
+                if elements.len() == 1 && elements[0].object_type() == object::ObjectType::ERROR {
+                    return elements[0].clone();
+                }
+                if let Some(error) = self.check_array_length(elements.len()) {
+                    return error;
+                }
+                self.count_allocation(Object::Array(elements.into()))
+            },
+            ast::ExpressionKind::Index(index_expression) => {
+                let left = self.evaluate_expression(index_expression.left.clone(), env.clone());
+                if left.object_type() == object::ObjectType::ERROR {
+                    return left;
+                }
+                let index = self.evaluate_expression(index_expression.index.clone(), env);
+                if index.object_type() == object::ObjectType::ERROR {
+                    return index;
+                }
+                let result = evaluate_index_expression(left, index);
+                self.count_allocation(result)
+            },
+        }
     }
-}
 
-fn evaluate_expression(exp: Rc<dyn ast::Expression>, env: Rc<RefCell<object::Environment>>) -> Rc<dyn object::Object> {
-    match exp.node_type() {
-        ast::NodeType::IDENTIFIER => {
-            let identifier = exp.as_ref().as_any().downcast_ref::<ast::Identifier>().unwrap();
-            match env.borrow().get(identifier.value.as_str()) {
-                Some(obj) => obj,
-                None => Rc::new(object::Error { message: format!("identifier not found: {}", identifier.value) })
+    fn evaluate_infix_expression(&mut self, operator: &str, left: Object, right: Object) -> Object {
+        match (&left, &right) {
+            (Object::String(left_string), Object::String(right_string)) if operator == "+" => {
+                return self.evaluate_string_concatenation(left_string, right_string);
             }
-        },
-        ast::NodeType::INTEGER_LITERAL => {
-            let integer = exp.as_ref().as_any().downcast_ref::<ast::IntegerLiteral>().unwrap();
-            Rc::new(object::Integer { value: integer.value })
-        },
-        ast::NodeType::STRING_LITERAL => {
-            let string = exp.as_ref().as_any().downcast_ref::<ast::StringLiteral>().unwrap();
-            Rc::new(object::StringObj { value: string.value.clone() })
-        },
-        ast::NodeType::BOOLEAN => {
-            let boolean = exp.as_ref().as_any().downcast_ref::<ast::Boolean>().unwrap();
-            if boolean.value {
-                Rc::new(object::Boolean { value: true })
-            } else {
-                Rc::new(object::Boolean { value: false })
+            (Object::Integer(left_integer), Object::Integer(right_integer)) => {
+                return evaluate_integer_infix_expression(operator, *left_integer, *right_integer);
             }
-        },
-        ast::NodeType::PREFIX_EXPRESSION => {
-            let prefix = exp.as_ref().as_any().downcast_ref::<ast::PrefixExpression>().unwrap();
-            let right = evaluate_expression(prefix.right.clone(), env);
-            if right.object_type() == object::ObjectType::ERROR {
-                return right;
+            (Object::Boolean(left_boolean), Object::Boolean(right_boolean)) => {
+                return evaluate_boolean_infix_expression(operator, *left_boolean, *right_boolean);
             }
-            evaluate_prefix_expression(prefix.operator.as_str(), right)
-        },
-        ast::NodeType::INFIX_EXPRESSION => {
-            let infix = exp.as_ref().as_any().downcast_ref::<ast::InfixExpression>().unwrap();
-            let left = evaluate_expression(infix.left.clone(), env.clone());
-            if left.object_type() == object::ObjectType::ERROR {
-                return left;
+            // Any two values can be compared for equality.
+            _ if operator == "==" || operator == "!=" => {
+                return Object::Boolean(left.equals(&right) == (operator == "=="));
             }
-            let right = evaluate_expression(infix.right.clone(), env.clone());
-            if right.object_type() == object::ObjectType::ERROR {
-                return right;
+            _ => {}
+        }
+        if let (Some(left_float), Some(right_float)) = (to_float(&left), to_float(&right)) {
+            return evaluate_float_infix_expression(operator, left_float, right_float);
+        }
+        if left.object_type() != right.object_type() {
+            return Object::Error(format!("type mismatch: {:?} {} {:?}", left.object_type(), operator, right.object_type()));
+        }
+        Object::Error(format!("unknown operator: {:?} {} {:?}", left.object_type(), operator, right.object_type()))
+    }
+
+    // Only the innermost failure is kept; the error then propagates
+    // through operators that never see it as an operand.
+    fn record_error_operands(&mut self, result: &Object, operands: &[&Rc<dyn ast::Expression>]) {
+        if result.object_type() == object::ObjectType::ERROR && self.error_operands.is_empty() {
+            self.error_operands = operands.iter().map(|operand| ast::first_token(operand.as_ref())).collect();
+        }
+    }
+
+    fn evaluate_string_concatenation(&mut self, left: &str, right: &str) -> Object {
+        if let Some(error) = self.check_string_length(left.len() + right.len()) {
+            return error;
+        }
+        self.count_allocation(Object::String(format!("{}{}", left, right).into()))
+    }
+
+    // Counts obj in values_allocated if nothing else holds it yet, that is
+    // if it was just built rather than taken from a binding or an operand.
+    fn count_allocation(&mut self, obj: Object) -> Object {
+        let fresh = match &obj {
+            Object::String(value) => Rc::strong_count(value) == 1,
+            Object::Array(elements) => object::Array::strong_count(elements) == 1,
+            Object::Function(function) => Rc::strong_count(function) == 1,
+            Object::StringBuilder(builder) => Rc::strong_count(builder) == 1,
+            _ => false,
+        };
+        if fresh {
+            self.stats.values_allocated += 1;
+        }
+        obj
+    }
+
+    fn check_string_length(&self, length: usize) -> Option<Object> {
+        match self.limits.max_string_length {
+            Some(max) if length > max => Some(Object::Error(format!("resource limit exceeded: string length {} exceeds {}", length, max))),
+            _ => None,
+        }
+    }
+
+    fn check_array_length(&self, length: usize) -> Option<Object> {
+        match self.limits.max_array_length {
+            Some(max) if length > max => Some(Object::Error(format!("resource limit exceeded: array length {} exceeds {}", length, max))),
+            _ => None,
+        }
+    }
+
+    // Writes to the trace are best effort: a sink that fails does not stop
+    // the evaluation it is logging.
+    fn trace_enter<N: ast::Node + ?Sized>(&mut self, node: &N) {
+        let indent = "  ".repeat(self.trace_depth);
+        if let Some(trace) = self.trace.as_mut() {
+            let _ = writeln!(trace, "{}{:?} {}", indent, node.node_type(), trace_snippet(&node.to_string()));
+        }
+        self.trace_depth += 1;
+    }
+
+    fn trace_exit(&mut self, result: &Object) {
+        self.trace_depth = self.trace_depth.saturating_sub(1);
+        let indent = "  ".repeat(self.trace_depth);
+        if let Some(trace) = self.trace.as_mut() {
+            let _ = writeln!(trace, "{}=> {}", indent, trace_snippet(&result.inspect()));
+        }
+    }
+
+    fn check_env_entries(&self, env: &object::Environment, name: &str) -> Option<Object> {
+        match self.limits.max_env_entries {
+            Some(max) if env.scope.len() >= max && !env.scope.contains_key(name) => {
+                Some(Object::Error(format!("resource limit exceeded: more than {} bindings in one scope", max)))
             }
-            evaluate_infix_expression(infix.operator.as_str(), left, right)
-        },
-        ast::NodeType::EXPRESSION_STATEMENT => {
-            let expression = exp.as_ref().as_any().downcast_ref::<ast::ExpressionStatement>().unwrap().expression.as_ref().unwrap().clone();
-            evaluate_expression(expression, env)
-        },
-        ast::NodeType::IF_EXPRESSION => {
-            let if_expression = exp.as_ref().as_any().downcast_ref::<ast::IfExpression>().unwrap();
-            let condition = evaluate_expression(if_expression.condition.clone(), env.clone());
-            if condition.object_type() == object::ObjectType::ERROR {
-                return condition;
+            _ => None,
+        }
+    }
+
+    // Blocks of an if or while run in the enclosing environment.
+    fn evaluate_block_statement(&mut self, stmt: &Rc<dyn ast::Statement>, env: Rc<RefCell<object::Environment>>) -> Object {
+        match stmt.kind() {
+            ast::StatementKind::Block(block) => self.evaluate_statements(&block.statements, env),
+            _ => self.evaluate_statement(stmt.clone(), env),
+        }
+    }
+
+    fn evaluate_statements(&mut self, statements: &[Rc<dyn ast::Statement>], env: Rc<RefCell<object::Environment>>) -> Object {
+        let mut result: Object = Object::Null;
+        for statement in statements {
+            let evaluated = self.evaluate_statement(statement.clone(), env.clone());
+            match evaluated.object_type() {
+                object::ObjectType::RETURN_VALUE => return evaluated,
+                object::ObjectType::ERROR => return evaluated,
+                _ => { result = evaluated;}
             }
+        }
+        result
+    }
 
-            if is_truthy(condition) {
-                let result = evaluate_block_statement(if_expression.consequence.clone(), env);
-                result
-            } else if let Some(alternative) = if_expression.alternative.clone() {
-                evaluate_block_statement(alternative, env.clone())
-            } else {
-                Rc::new(object::Null {})
+    // Entry point for builtins and host code calling back into Monkey. No
+    // environment may stay borrowed across it, since the callee can read
+    // and assign through any scope it closes over.
+    pub fn call_function(&mut self, func: Object, args: Vec<Object>) -> Object {
+        let call_token = token::Token::new(token::TokenType::IDENT, "<callback>".to_string());
+        self.apply_function(func, args, "<callback>".to_string(), &call_token)
+    }
+
+    // Runs func with no arguments, failing with a timeout error once ms
+    // milliseconds have passed. Checked wherever cancellation is, so only
+    // loops and calls are cut short.
+    #[cfg(feature = "clock")]
+    pub fn call_with_timeout(&mut self, func: Object, ms: u64) -> Object {
+        let at = Instant::now() + Duration::from_millis(ms);
+        let deadline = match self.deadlines.last() {
+            Some(outer) if outer.at <= at => *outer,
+            _ => Deadline { at, ms },
+        };
+        self.deadlines.push(deadline);
+        let result = self.call_function(func, vec![]);
+        self.deadlines.pop();
+        result
+    }
+
+    fn check_interrupted(&self) -> Option<Object> {
+        if self.cancellation.is_cancelled() {
+            return Some(interrupted());
+        }
+        #[cfg(feature = "clock")]
+        if let Some(deadline) = self.deadlines.last() {
+            if Instant::now() >= deadline.at {
+                return Some(Object::Error(format!("timed out after {} ms", deadline.ms)));
             }
-        },
-        ast::NodeType::FUNCTION_LITERAL => {
-            let function_literal = exp.as_ref().as_any().downcast_ref::<ast::FunctionLiteral>().unwrap();
-            Rc::new(object::Function { parameters: function_literal.parameters.clone(), body: function_literal.body.clone(), 
-                env: env.clone() })
-        },
-        ast::NodeType::CALL_EXPRESSION => {
-            let call_expression = exp.as_ref().as_any().downcast_ref::<ast::CallExpression>().unwrap();
-            let function = evaluate_expression(call_expression.function.clone(), env.clone());
-            if function.object_type() == object::ObjectType::ERROR {
-                return function;
+        }
+        None
+    }
+
+    fn apply_function(&mut self, func: Object, args: Vec<Object>, name: String, call_token: &token::Token) -> Object {
+        if let Some(error) = self.check_interrupted() {
+            return error;
+        }
+        match func {
+            Object::Function(function) => {
+                if args.len() != function.parameters.len() {
+                    return Object::Error(format!("wrong number of arguments to {}: got {}, want {}", name, args.len(), function.parameters.len()));
+                }
+                if let Some(max) = self.limits.max_call_depth {
+                    if self.call_stack.depth() >= max {
+                        return Object::Error(format!("stack overflow: call depth exceeds {}", max));
+                    }
+                }
+                let extended_env = self.extend_function_env(&function, args);
+                self.call_stack.push(Frame {
+                    function: name,
+                    file: call_token.file,
+                    line: call_token.line,
+                    column: call_token.column,
+                    environment: extended_env.clone(),
+                });
+                self.stats.max_call_depth = self.stats.max_call_depth.max(self.call_stack.depth());
+                let evaluated = self.evaluate_block_statement(&function.body, extended_env.clone());
+                if evaluated.object_type() == object::ObjectType::ERROR && self.error_call_stack.is_none() {
+                    self.error_call_stack = Some(self.call_stack.clone());
+                }
+                self.call_stack.pop();
+                self.release_environment(extended_env);
+                unwrap_return_value(evaluated)
+            },
+            Object::NativeFunction(native) => (native.function)(args),
+            Object::Builtin(name) => {
+                match builtins::lookup(&name) {
+                    Some(function) => {
+                        let result = function(self, args);
+                        self.count_allocation(result)
+                    }
+                    None => Object::Error(format!("identifier not found: {}", name)),
+                }
+            },
+            _ => Object::Error(format!("not a function: {:?}", func.object_type()))
+        }
+    }
+
+    fn extend_function_env(&mut self, func: &object::Function, args: Vec<Object>) -> Rc<RefCell<object::Environment>> {
+        let env = match self.environment_pool.pop() {
+            Some(env) => {
+                self.stats.environments_reused += 1;
+                env.borrow_mut().outer = Some(func.env.clone());
+                env
+            }
+            None => {
+                self.stats.environments_allocated += 1;
+                object::Environment::new_enclosed(func.env.clone())
             }
-            let args = evaluate_expressions(call_expression.arguments.clone(), env.clone());
-            if args.len() == 1 && args[0].object_type() == object::ObjectType::ERROR {
-                return args[0].clone();
+        };
+        for (i, param) in func.parameters.iter().enumerate() {
+            env.borrow_mut().set(param.value.clone(), args[i].clone());
+        }
+        env
+    }
+
+    // A closure and the environment it closes over can hold each other, so
+    // the environment is handed to the cycle collector, which runs from here
+    // once enough of them have been captured.
+    fn capture(&mut self, env: &Rc<RefCell<object::Environment>>) -> Rc<RefCell<object::Environment>> {
+        object::gc::track(env);
+        self.stats.environments_collected += object::gc::maybe_collect();
+        env.clone()
+    }
+
+    // An environment captured by a closure is still referenced elsewhere
+    // and must not be handed out again.
+    fn release_environment(&mut self, env: Rc<RefCell<object::Environment>>) {
+        if Rc::strong_count(&env) != 1 || self.environment_pool.len() >= ENVIRONMENT_POOL_CAPACITY {
+            return;
+        }
+        env.borrow_mut().reset();
+        self.environment_pool.push(env);
+        self.stats.environments_released += 1;
+    }
+
+    fn evaluate_expressions(&mut self, exps: Vec<Rc<dyn ast::Expression>>, env: Rc<RefCell<object::Environment>>) -> Vec<Object> {
+        let mut result = Vec::new();
+        for exp in exps {
+            let evaluated = self.evaluate_expression(exp, env.clone());
+            if evaluated.object_type() == object::ObjectType::ERROR {
+                return vec![evaluated];
+            }
+            result.push(evaluated);
+        }
+        result
+    }
+}
+
+const TRACE_SNIPPET_LENGTH: usize = 40;
+
+// Keeps each trace entry on one line and of a readable length.
+fn trace_snippet(text: &str) -> String {
+    let line = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match line.char_indices().nth(TRACE_SNIPPET_LENGTH) {
+        Some((end, _)) => format!("{}...", &line[..end]),
+        None => line,
+    }
+}
+
+// Indexes past either end evaluate to null. Strings are indexed by
+// character, not byte.
+fn evaluate_index_expression(left: Object, index: Object) -> Object {
+    match (&left, &index) {
+        (Object::Array(elements), Object::Integer(i)) => {
+            usize::try_from(*i).ok().and_then(|i| elements.get(i)).unwrap_or(Object::Null)
+        },
+        (Object::String(value), Object::Integer(i)) => {
+            match usize::try_from(*i).ok().and_then(|i| value.chars().nth(i)) {
+                Some(ch) => Object::String(ch.to_string().into()),
+                None => Object::Null,
             }
-            apply_function(function, args)
         },
-        _ => Rc::new(object::Null {})
+        _ => Object::Error(format!("index operator not supported: {:?}[{:?}]", left.object_type(), index.object_type())),
     }
 }
 
-fn evaluate_prefix_expression(operator: &str, right: Rc<dyn object::Object>) -> Rc<dyn object::Object> {
+fn evaluate_prefix_expression(operator: &str, right: Object) -> Object {
     match operator {
         "!" => evaluate_bang_operator_expression(right),
         "-" => evaluate_minus_prefix_operator_expression(right),
-        _ => Rc::new(object::Null {})
+        _ => Object::Null
     }
 }
 
-fn evaluate_bang_operator_expression(right: Rc<dyn object::Object>) -> Rc<dyn object::Object> {
-    match right.object_type() {
-        object::ObjectType::BOOLEAN => {
-            let boolean = right.as_ref().as_any().downcast_ref::<object::Boolean>().unwrap();
-            if boolean.value {
-                Rc::new(object::Boolean { value: false })
-            } else {
-                Rc::new(object::Boolean { value: true })
-            }
-        },
-        object::ObjectType::NULL => Rc::new(object::Boolean { value: true }),
-        _ => Rc::new(object::Boolean { value: false })
+fn evaluate_bang_operator_expression(right: Object) -> Object {
+    match right {
+        Object::Boolean(value) => Object::Boolean(!value),
+        Object::Null => Object::Boolean(true),
+        _ => Object::Boolean(false)
     }
 }
 
-fn evaluate_minus_prefix_operator_expression(right: Rc<dyn object::Object>) -> Rc<dyn object::Object> {
-    match right.object_type() {
-        object::ObjectType::INTEGER => {
-            let integer = right.as_ref().as_any().downcast_ref::<object::Integer>().unwrap();
-            Rc::new(object::Integer { value: -integer.value })
+fn evaluate_minus_prefix_operator_expression(right: Object) -> Object {
+    match right {
+        Object::Integer(value) => match value.checked_neg() {
+            Some(negated) => Object::Integer(negated),
+            None => Object::Error("integer overflow in -".to_string()),
         },
-        _ => Rc::new(object::Error { message: format!("unknown operator: -{:?}", right.object_type()) })
+        Object::Float(value) => Object::Float(-value),
+        _ => Object::Error(format!("unknown operator: -{:?}", right.object_type()))
     }
 }
 
-fn evaluate_infix_expression(operator: &str, left: Rc<dyn object::Object>, right: Rc<dyn object::Object>) -> Rc<dyn object::Object> {
-    if left.object_type() == object::ObjectType::STRING && right.object_type() == object::ObjectType::STRING && operator == "+" {
-        return evaluate_string_concatenation(left, right);
-    }
-    if left.object_type() == object::ObjectType::INTEGER && right.object_type() == object::ObjectType::INTEGER {
-        return evaluate_integer_infix_expression(operator, left, right);
-    }
-    if left.object_type() == object::ObjectType::BOOLEAN && right.object_type() == object::ObjectType::BOOLEAN {
-        return evaluate_boolean_infix_expression(operator, left, right);
+fn evaluate_integer_infix_expression(operator: &str, left: i64, right: i64) -> Object {
+    let checked = |result: Option<i64>| match result {
+        Some(value) => Object::Integer(value),
+        None => Object::Error(format!("integer overflow in {}", operator)),
+    };
+    match operator {
+        "/" | "%" if right == 0 => Object::Error("division by zero".to_string()),
+        "+" => checked(left.checked_add(right)),
+        "-" => checked(left.checked_sub(right)),
+        "*" => checked(left.checked_mul(right)),
+        "/" => checked(left.checked_div(right)),
+        "%" => checked(left.checked_rem(right)),
+        "**" if right < 0 => Object::Error(format!("negative exponent in **: {}", right)),
+        "**" => checked(checked_pow(left, right)),
+        "<" => Object::Boolean(left < right),
+        ">" => Object::Boolean(left > right),
+        "<=" => Object::Boolean(left <= right),
+        ">=" => Object::Boolean(left >= right),
+        "==" => Object::Boolean(left == right),
+        "!=" => Object::Boolean(left != right),
+        _ => Object::Error(format!("unknown operator: {:?} {} {:?}", object::ObjectType::INTEGER, operator, object::ObjectType::INTEGER))
     }
-    if left.object_type() != right.object_type() {
-        return Rc::new(object::Error { message: format!("type mismatch: {:?} {} {:?}", left.object_type(), operator, right.object_type()) });
+}
+
+// Exponents too large for i64::checked_pow only fit for bases whose powers
+// never grow.
+fn checked_pow(base: i64, exponent: i64) -> Option<i64> {
+    match u32::try_from(exponent) {
+        Ok(exponent) => base.checked_pow(exponent),
+        Err(_) => match base {
+            0 | 1 => Some(base),
+            -1 => Some(if exponent % 2 == 0 { 1 } else { -1 }),
+            _ => None,
+        },
     }
-    Rc::new(object::Error { message: format!("unknown operator: {:?} {} {:?}", left.object_type(), operator, right.object_type()) })
 }
 
-fn evaluate_integer_infix_expression(operator: &str, left: Rc<dyn object::Object>, right: Rc<dyn object::Object>) -> Rc<dyn object::Object> {
-    let left_integer = left.as_ref().as_any().downcast_ref::<object::Integer>().unwrap();
-    let right_integer = right.as_ref().as_any().downcast_ref::<object::Integer>().unwrap();
+fn evaluate_float_infix_expression(operator: &str, left: f64, right: f64) -> Object {
     match operator {
-        "+" => Rc::new(object::Integer { value: left_integer.value + right_integer.value }),
-        "-" => Rc::new(object::Integer { value: left_integer.value - right_integer.value }),
-        "*" => Rc::new(object::Integer { value: left_integer.value * right_integer.value }),
-        "/" => Rc::new(object::Integer { value: left_integer.value / right_integer.value }),
-        "<" => Rc::new(object::Boolean { value: left_integer.value < right_integer.value }),
-        ">" => Rc::new(object::Boolean { value: left_integer.value > right_integer.value }),
-        "==" => Rc::new(object::Boolean { value: left_integer.value == right_integer.value }),
-        "!=" => Rc::new(object::Boolean { value: left_integer.value != right_integer.value }),
-        "%" => Rc::new(object::Integer {value: left_integer.value % right_integer.value }),
-        _ => Rc::new(object::Error { message: format!("unknown operator: {:?} {} {:?}", left.object_type(), operator, right.object_type()) })
+        "+" => Object::Float(left + right),
+        "-" => Object::Float(left - right),
+        "*" => Object::Float(left * right),
+        "/" => Object::Float(left / right),
+        "%" => Object::Float(left % right),
+        "**" => Object::Float(left.powf(right)),
+        "<" => Object::Boolean(left < right),
+        ">" => Object::Boolean(left > right),
+        "<=" => Object::Boolean(left <= right),
+        ">=" => Object::Boolean(left >= right),
+        "==" => Object::Boolean(left == right),
+        "!=" => Object::Boolean(left != right),
+        _ => Object::Error(format!("unknown operator: {:?} {} {:?}", object::ObjectType::FLOAT, operator, object::ObjectType::FLOAT))
     }
 }
 
-fn evaluate_string_concatenation(left: Rc<dyn object::Object>, right: Rc<dyn object::Object>) -> Rc<dyn object::Object> {
-    let left_string = left.as_ref().as_any().downcast_ref::<object::StringObj>().unwrap();
-    let right_string = right.as_ref().as_any().downcast_ref::<object::StringObj>().unwrap();
-    Rc::new(object::StringObj { value: format!("{}{}", left_string.value, right_string.value) })
+fn to_float(obj: &Object) -> Option<f64> {
+    match obj {
+        Object::Float(value) => Some(*value),
+        Object::Integer(value) => Some(*value as f64),
+        _ => None,
+    }
 }
 
-fn evaluate_boolean_infix_expression(operator: &str, left: Rc<dyn object::Object>, right: Rc<dyn object::Object>) -> Rc<dyn object::Object> {
-    let left_boolean = left.as_ref().as_any().downcast_ref::<object::Boolean>().unwrap();
-    let right_boolean = right.as_ref().as_any().downcast_ref::<object::Boolean>().unwrap();
+fn evaluate_boolean_infix_expression(operator: &str, left: bool, right: bool) -> Object {
     match operator {
-        "==" => Rc::new(object::Boolean { value: left_boolean.value == right_boolean.value }),
-        "!=" => Rc::new(object::Boolean { value: left_boolean.value != right_boolean.value }),
-        _ => Rc::new(object::Error { message: format!("unknown operator: {:?} {} {:?}", left.object_type(), operator, right.object_type()) })
+        "==" => Object::Boolean(left == right),
+        "!=" => Object::Boolean(left != right),
+        _ => Object::Error(format!("unknown operator: {:?} {} {:?}", object::ObjectType::BOOLEAN, operator, object::ObjectType::BOOLEAN))
     }
 }
 
-fn evaluate_block_statement(stmt: Rc<dyn ast::Statement>, env: Rc<RefCell<object::Environment>>) -> Rc<dyn object::Object> {
-    let block = stmt.as_ref().as_any().downcast_ref::<ast::BlockStatement>().unwrap();
-    let mut result = evaluate_statement(block.statements.first().unwrap().clone(), env.clone());
-    for statement in block.statements.iter() {
-        let evaluated = evaluate_statement(statement.clone(), env.clone());
-        match evaluated.object_type() {
-            object::ObjectType::RETURN_VALUE => return evaluated,
-            object::ObjectType::ERROR => return evaluated,
-            _ => { result = evaluated;}
-        }
+fn is_truthy(obj: Object) -> bool {
+    match obj {
+        Object::Null => false,
+        Object::Boolean(value) => value,
+        _ => true
     }
-    result
 }
 
-fn is_truthy(obj: Rc<dyn object::Object>) -> bool {
-    match obj.object_type() {
-        object::ObjectType::NULL => false,
-        object::ObjectType::BOOLEAN => {
-            let boolean = obj.as_ref().as_any().downcast_ref::<object::Boolean>().unwrap();
-            boolean.value
-        },
-        _ => true
-    }
+fn interrupted() -> Object {
+    Object::Error("evaluation interrupted".to_string())
 }
 
-fn apply_function(func: Rc<dyn object::Object>, args: Vec<Rc<dyn object::Object>>) -> Rc<dyn object::Object> {
-    match func.object_type() {
-        object::ObjectType::FUNCTION => {
-            let function = func.as_ref().as_any().downcast_ref::<object::Function>().unwrap();
-            let extended_env = extend_function_env(function, args);
-            let evaluated = evaluate_statement(function.body.clone(), extended_env);
-            unwrap_return_value(evaluated)
-        },
-        _ => Rc::new(object::Error { message: format!("not a function: {:?}", func.object_type()) })
+fn unwrap_return_value(obj: Object) -> Object {
+    match obj {
+        Object::ReturnValue(value) => *value,
+        _ => obj,
     }
 }
 
-fn extend_function_env(func: &object::Function, args: Vec<Rc<dyn object::Object>>) -> Rc<RefCell<object::Environment>> {
-    let env = object::Environment::new_enclosed(func.env.clone());
-    for (i, param) in func.parameters.iter().enumerate() {
-        env.borrow_mut().set(param.value.clone(), args[i].clone());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ast::Node;
+    use lexer::Lexer;
+    use parser::Parser;
+
+    fn test_eval(input: &str) -> Object {
+        let lexer = Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        assert!(parser.errors().is_empty(), "parser errors: {:?}", parser.errors());
+        let env = Rc::new(RefCell::new(object::Environment::new()));
+        evaluate_program(program, env).unwrap()
     }
-    env
-}
 
-fn unwrap_return_value(obj: Rc<dyn object::Object>) -> Rc<dyn object::Object> {
-    if obj.object_type() == object::ObjectType::RETURN_VALUE {
-        return obj.as_ref().as_any().downcast_ref::<object::ReturnValue>().unwrap().value.clone();
+    #[test]
+    fn test_null() {
+        let tests = vec![
+            ("let x; x", "null"),
+            ("let x; x == null", "true"),
+            ("null != null", "false"),
+            ("null == false", "false"),
+            ("[1] != null", "true"),
+            ("if (null) { 1 } else { 2 }", "2"),
+            ("!null", "true"),
+            ("let x; x = 5; x", "5"),
+            ("null + 1", "type mismatch: NULL + INTEGER"),
+            ("null < null", "unknown operator: NULL < NULL"),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(test_eval(input).inspect(), expected, "input: {}", input);
+        }
     }
-    obj
-}
 
-fn evaluate_expressions(exps: Vec<Rc<dyn ast::Expression>>, env: Rc<RefCell<object::Environment>>) -> Vec<Rc<dyn object::Object>> {
-    let mut result = Vec::new();
-    for exp in exps {
-        let evaluated = evaluate_expression(exp, env.clone());
-        if evaluated.object_type() == object::ObjectType::ERROR {
-            return vec![evaluated];
+    #[test]
+    fn test_equality() {
+        let equal = [
+            "1 == 1",
+            "1 == 1.0",
+            "0.5 == 0.5",
+            "true == true",
+            "null == null",
+            "\"monkey\" == \"monkey\"",
+            "\"\" == \"\"",
+            "[] == []",
+            "[1, \"a\", [true, null]] == [1, \"a\", [true, null]]",
+            "[1, 2] == [1.0, 2]",
+            "let xs = [1]; xs == xs",
+            "len == len",
+            "let f = fn(x) { x }; f == f",
+            "let f = fn(x) { x }; let g = f; f == g",
+            "let sb = string_builder(); sb == sb",
+            "quote(1 + 2) == quote(1 + 2)",
+        ];
+        let unequal = [
+            "1 == 2",
+            "1 == true",
+            "0 == false",
+            "0 == null",
+            "\"1\" == 1",
+            "\"a\" == \"A\"",
+            "[1, 2] == [2, 1]",
+            "[1] == [1, 1]",
+            "[[1]] == [[2]]",
+            "[] == null",
+            "len == puts",
+            "fn(x) { x } == fn(x) { x }",
+            "string_builder() == string_builder()",
+            "quote(1 + 2) == quote(2 + 1)",
+            "0.0 / 0 == 0.0 / 0",
+            "9007199254740993 == 9007199254740992",
+        ];
+        for input in equal {
+            assert_eq!(test_eval(input).inspect(), "true", "input: {}", input);
+            let negated = input.replacen("==", "!=", 1);
+            assert_eq!(test_eval(&negated).inspect(), "false", "input: {}", negated);
+        }
+        for input in unequal {
+            assert_eq!(test_eval(input).inspect(), "false", "input: {}", input);
+            let negated = input.replacen("==", "!=", 1);
+            assert_eq!(test_eval(&negated).inspect(), "true", "input: {}", negated);
+        }
+        assert_eq!(test_eval("[1] < [2]").inspect(), "unknown operator: ARRAY < ARRAY");
+        assert_eq!(test_eval("\"a\" < 1").inspect(), "type mismatch: STRING < INTEGER");
+    }
+
+    #[test]
+    fn test_optimized_programs() {
+        let tests = vec![
+            ("let f = fn() { if (1 < 2) { let x = 10; x } else { 20 } }; f() + 2 * 3", "16"),
+            ("if (true) { let y = 1; }; y + 1", "2"),
+            ("let x = 1; if (false) { x = 2 }; x", "1"),
+            ("(1 + 2) * true", "type mismatch: INTEGER * BOOLEAN"),
+        ];
+        for (input, expected) in tests {
+            let program = Parser::new(Lexer::new(input)).parse_program();
+            let mut evaluator = Evaluator::new();
+            evaluator.optimize = true;
+            let result = evaluator.evaluate_program(program, Rc::new(RefCell::new(object::Environment::new())));
+            assert_eq!(result.unwrap().inspect(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_closures() {
+        let input = "
+        let newAdder = fn(x) { fn(y) { x + y } };
+        let addTwo = newAdder(2);
+        addTwo(3);";
+        assert_eq!(test_eval(input).inspect(), "5");
+    }
+
+    #[test]
+    fn test_closure_sees_later_outer_bindings() {
+        let input = "
+        let f = fn() { let g = fn() { y }; let y = 7; g() };
+        f();";
+        assert_eq!(test_eval(input).inspect(), "7");
+    }
+
+    #[test]
+    fn test_recursive_function() {
+        let input = "
+        let fib = fn(n) { if (n < 2) { return n; } fib(n - 1) + fib(n - 2) };
+        fib(10);";
+        assert_eq!(test_eval(input).inspect(), "55");
+    }
+
+    #[test]
+    fn test_while_expression() {
+        assert_eq!(test_eval("while (false) { 5 }").inspect(), "null");
+        assert_eq!(test_eval("let f = fn() { while (true) { return 10; } }; f();").inspect(), "10");
+        assert_eq!(test_eval("while (1 + true) { 5 }").inspect(), "type mismatch: INTEGER + BOOLEAN");
+    }
+
+    #[test]
+    fn test_assign_expression() {
+        assert_eq!(test_eval("let x = 1; x = 2; x").inspect(), "2");
+        assert_eq!(test_eval("let x = 1; let y = x = 5; x + y").inspect(), "10");
+        assert_eq!(test_eval("let x = 10; x += 5; x -= 3; x *= 2; x /= 4; x").inspect(), "6");
+        assert_eq!(test_eval("let s = \"a\"; s += \"b\"; s").inspect(), "ab");
+        assert_eq!(test_eval("x = 2").inspect(), "identifier not found: x");
+        assert_eq!(test_eval("x += 2").inspect(), "identifier not found: x");
+        assert_eq!(test_eval("let x = 1; x += true").inspect(), "type mismatch: INTEGER + BOOLEAN");
+    }
+
+    #[test]
+    fn test_assign_updates_enclosing_scope() {
+        let input = "
+        let counter = 0;
+        let inc = fn() { counter += 1; };
+        inc(); inc(); inc();
+        counter";
+        assert_eq!(test_eval(input).inspect(), "3");
+        assert_eq!(test_eval("let i = 0; let sum = 0; while (i < 5) { i += 1; sum += i; } sum").inspect(), "15");
+        assert_eq!(test_eval("let x = 1; let f = fn(x) { x = 5; }; f(2); x").inspect(), "1");
+    }
+
+    #[test]
+    fn test_float_arithmetic() {
+        assert_eq!(test_eval("1.5 + 2.25").inspect(), "3.75");
+        assert_eq!(test_eval("-2.5").inspect(), "-2.5");
+        assert_eq!(test_eval("1 + 0.5").inspect(), "1.5");
+        assert_eq!(test_eval("3.0 * 2").inspect(), "6.0");
+        assert_eq!(test_eval("7 / 2.0").inspect(), "3.5");
+        assert_eq!(test_eval("7.5 % 2").inspect(), "1.5");
+        assert_eq!(test_eval("1 < 1.5").inspect(), "true");
+        assert_eq!(test_eval("2.0 > 3").inspect(), "false");
+        assert_eq!(test_eval("2.0 == 2").inspect(), "true");
+        assert_eq!(test_eval("2.5 != 2.5").inspect(), "false");
+        assert_eq!(test_eval("let x = 1; x += 0.5; x").inspect(), "1.5");
+        assert_eq!(test_eval("1.5 + true").inspect(), "type mismatch: FLOAT + BOOLEAN");
+    }
+
+    #[test]
+    fn test_integer_overflow_and_division_by_zero() {
+        let tests = vec![
+            ("9223372036854775807 + 1", "integer overflow in +"),
+            ("-9223372036854775807 - 2", "integer overflow in -"),
+            ("9223372036854775807 * 2", "integer overflow in *"),
+            ("(-9223372036854775807 - 1) / -1", "integer overflow in /"),
+            ("(-9223372036854775807 - 1) % -1", "integer overflow in %"),
+            ("-(-9223372036854775807 - 1)", "integer overflow in -"),
+            ("let x = 9223372036854775807; x += 1; x", "integer overflow in +"),
+            ("1 / 0", "division by zero"),
+            ("1 % 0", "division by zero"),
+            ("let x = 1; x /= 0;", "division by zero"),
+            ("9223372036854775806 + 1", "9223372036854775807"),
+            ("-7 / 2", "-3"),
+            ("-7 % 2", "-1"),
+            ("2 ** 62", "4611686018427387904"),
+            ("2 ** 63", "integer overflow in **"),
+            ("2 ** -1", "negative exponent in **: -1"),
+            ("(-1) ** 4294967297", "-1"),
+            ("1 ** 9223372036854775807", "1"),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(test_eval(input).inspect(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_less_and_greater_or_equal() {
+        assert_eq!(test_eval("1 <= 2").inspect(), "true");
+        assert_eq!(test_eval("2 <= 2").inspect(), "true");
+        assert_eq!(test_eval("3 <= 2").inspect(), "false");
+        assert_eq!(test_eval("1 >= 2").inspect(), "false");
+        assert_eq!(test_eval("2 >= 2").inspect(), "true");
+        assert_eq!(test_eval("2.5 >= 2").inspect(), "true");
+        assert_eq!(test_eval("2 <= 1.5").inspect(), "false");
+        assert_eq!(test_eval("true <= false").inspect(), "unknown operator: BOOLEAN <= BOOLEAN");
+    }
+
+    #[test]
+    fn test_stats() {
+        let input = "let down = fn(n) { if (n > 0) { down(n - 1) } else { n } };
+down(4);
+let s = \"a\" + \"b\";
+let xs = [s, \"c\"];
+xs[0];
+upper(s);
+s;";
+        let lexer = Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+        let env = Rc::new(RefCell::new(object::Environment::new()));
+        let mut evaluator = Evaluator::new();
+        evaluator.evaluate_program(parser.parse_program(), env);
+        let stats = evaluator.stats();
+        assert_eq!(stats.max_call_depth, 5);
+        // The closure, the concatenation, the array and upper's result.
+        assert_eq!(stats.values_allocated, 4);
+        assert_eq!(stats.expressions_evaluated, 58);
+
+        evaluator.reset_stats();
+        assert_eq!(evaluator.stats(), Stats::default());
+    }
+
+    #[test]
+    fn test_string_length_limit() {
+        let lexer = Lexer::new("let s = \"ab\"; while (true) { s += s; }");
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let env = Rc::new(RefCell::new(object::Environment::new()));
+        let mut evaluator = Evaluator::with_limits(Limits { max_string_length: Some(100), ..Limits::default() });
+        let result = evaluator.evaluate_program(program, env.clone()).unwrap();
+        assert_eq!(result.inspect(), "resource limit exceeded: string length 128 exceeds 100");
+        assert_eq!(env.borrow().get("s").unwrap().inspect().len(), 64);
+    }
+
+    #[test]
+    fn test_array_length_limit() {
+        let limits = Limits { max_array_length: Some(4), ..Limits::default() };
+        let tests = vec![
+            ("let xs = []; while (true) { xs = push(xs, 1) }", "resource limit exceeded: array length 5 exceeds 4"),
+            ("insert([1, 2, 3, 4], 0, 0)", "resource limit exceeded: array length 5 exceeds 4"),
+            ("[1, 2, 3, 4, 5]", "resource limit exceeded: array length 5 exceeds 4"),
+            ("split(\"a,b,c,d,e\", \",\")", "resource limit exceeded: array length 5 exceeds 4"),
+            ("map(args(), fn(x) { x })", "resource limit exceeded: array length 5 exceeds 4"),
+            ("[1, 2, 3, 4]", "[1, 2, 3, 4]"),
+            ("pop(push([1, 2, 3], 4))", "[1, 2, 3]"),
+        ];
+        for (input, expected) in tests {
+            let mut evaluator = Evaluator::with_limits(limits);
+            evaluator.script_args = vec!["a".to_string(); 5];
+            let env = Rc::new(RefCell::new(object::Environment::new()));
+            let result = evaluator.evaluate_program(Parser::new(Lexer::new(input)).parse_program(), env).unwrap();
+            assert_eq!(result.inspect(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_environment_pool() {
+        let input = "
+        let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } };
+        let makeAdder = fn(x) { fn(y) { x + y } };
+        let addOne = makeAdder(1);
+        let addTwo = makeAdder(2);
+        fib(15) + addOne(10) + addTwo(20);";
+        let lexer = Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let env = Rc::new(RefCell::new(object::Environment::new()));
+        let mut evaluator = Evaluator::new();
+        let result = evaluator.evaluate_program(program, env).unwrap();
+        assert_eq!(result.inspect(), "643");
+
+        let stats = evaluator.stats();
+        assert!(stats.environments_allocated <= 20, "{:?}", stats);
+        assert_eq!(stats.environments_allocated + stats.environments_reused, 1973 + 4);
+        assert_eq!(stats.environments_released, 1973 + 2);
+    }
+
+    #[test]
+    fn test_closure_cycles_are_collected() {
+        // Every call leaves a scope and a closure holding each other.
+        let input = "let make = fn(n) { let self = fn() { self }; n };
+        let i = 0;
+        while (i < 5000) { make(i); i = i + 1 };
+        i";
+        let program = Parser::new(Lexer::new(input)).parse_program();
+        let env = Rc::new(RefCell::new(object::Environment::new()));
+        let mut evaluator = Evaluator::new();
+        let result = evaluator.evaluate_program(program, env.clone()).unwrap();
+        assert_eq!(result.inspect(), "5000");
+        assert!(evaluator.stats().environments_collected >= 4000, "{:?}", evaluator.stats());
+
+        // The program's own scope holds make, which closes over it.
+        let weak = Rc::downgrade(&env);
+        drop(env);
+        assert!(weak.upgrade().is_some());
+        object::gc::collect();
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_puts_writes_to_output() {
+        let lexer = Lexer::new("let greet = fn(name) { puts(\"hello \" + name, 1 + 2) }; greet(\"monkey\"); puts(); puts");
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let env = Rc::new(RefCell::new(object::Environment::new()));
+        let output = SharedBuffer::default();
+        let mut evaluator = Evaluator::new();
+        evaluator.output = Box::new(output.clone());
+        let result = evaluator.evaluate_program(program, env).unwrap();
+        assert_eq!(result.inspect(), "builtin function puts");
+        assert_eq!(output.contents(), "hello monkey\n3\n");
+    }
+
+    #[test]
+    fn test_trace() {
+        let lexer = Lexer::new("let x = -2;\nif (x < 0) { \"negative\" }");
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let env = Rc::new(RefCell::new(object::Environment::new()));
+        let trace = SharedBuffer::default();
+        let mut evaluator = Evaluator::new();
+        evaluator.trace = Some(Box::new(trace.clone()));
+        evaluator.evaluate_program(program, env);
+        assert_eq!(trace.contents(), [
+            "LET_STATEMENT let x = (-2);",
+            "  PREFIX_EXPRESSION (-2)",
+            "    INTEGER_LITERAL 2",
+            "    => 2",
+            "  => -2",
+            "=> null",
+            "EXPRESSION_STATEMENT if(x < 0) {\"negative\"}",
+            "  IF_EXPRESSION if(x < 0) {\"negative\"}",
+            "    INFIX_EXPRESSION (x < 0)",
+            "      IDENTIFIER x",
+            "      => -2",
+            "      INTEGER_LITERAL 0",
+            "      => 0",
+            "    => true",
+            "    EXPRESSION_STATEMENT \"negative\"",
+            "      STRING_LITERAL \"negative\"",
+            "      => negative",
+            "    => negative",
+            "  => negative",
+            "=> negative",
+            "",
+        ].join("\n"));
+
+        assert_eq!(trace_snippet("fn(x) {\n    x * 2\n}"), "fn(x) { x * 2 }");
+        assert_eq!(trace_snippet(&"é".repeat(50)), format!("{}...", "é".repeat(40)));
+    }
+
+    #[test]
+    fn test_native_function() {
+        let lexer = Lexer::new("let twice = fn(f, x) { f(f(x)) }; twice(square, 3)");
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let env = Rc::new(RefCell::new(object::Environment::new()));
+        env.borrow_mut().register_native("square", |args| {
+            match args[0] {
+                Object::Integer(value) => Object::Integer(value * value),
+                _ => Object::Error("square expects an integer".to_string()),
+            }
+        });
+        assert_eq!(evaluate_program(program, env.clone()).unwrap().inspect(), "81");
+        assert_eq!(env.borrow().get("square").unwrap().inspect(), "native function square");
+    }
+
+    #[test]
+    fn test_builtins_can_be_shadowed() {
+        assert_eq!(test_eval("let puts = fn(x) { x * 2 }; puts(21)").inspect(), "42");
+    }
+
+    #[test]
+    fn test_cancellation() {
+        let lexer = Lexer::new("let i = 0; while (true) { i += 1 }");
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let env = Rc::new(RefCell::new(object::Environment::new()));
+        let mut evaluator = Evaluator::new();
+        let token = evaluator.cancellation.clone();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            token.cancel();
+        });
+        let result = evaluator.evaluate_program(program, env.clone()).unwrap();
+        canceller.join().unwrap();
+        assert_eq!(result.inspect(), "evaluation interrupted");
+        assert!(!evaluator.cancellation.is_cancelled());
+
+        evaluator.cancellation.cancel();
+        let lexer = Lexer::new("let f = fn(x) { x + 1 }; f(1)");
+        let mut parser = Parser::new(lexer);
+        assert_eq!(evaluator.evaluate_program(parser.parse_program(), env.clone()).unwrap().inspect(), "2");
+
+        // A cancel through the token a linked one came from outlasts the
+        // reset at the start of an evaluation.
+        let token = CancellationToken::default();
+        evaluator.cancellation = token.linked();
+        token.cancel();
+        let lexer = Lexer::new("while (true) {}");
+        let mut parser = Parser::new(lexer);
+        assert_eq!(evaluator.evaluate_program(parser.parse_program(), env).unwrap().inspect(), "evaluation interrupted");
+        assert!(token.is_cancelled() && evaluator.cancellation.is_cancelled());
+    }
+
+    #[test]
+    fn test_evaluate_built_ast() {
+        use ast::builder::*;
+        use ast::Node;
+
+        let built = program(vec![
+            let_stmt("add", function(&["a", "b"], vec![return_stmt(infix(ident("a"), "+", ident("b")))])),
+            let_stmt("i", int(0)),
+            expr_stmt(while_loop(infix(ident("i"), "<", int(3)), vec![
+                expr_stmt(assign("i", call(ident("add"), vec![ident("i"), int(1)]))),
+            ])),
+            expr_stmt(if_expr(prefix("!", boolean(false)), vec![
+                expr_stmt(infix(string("i="), "+", string("3"))),
+            ], None)),
+        ]);
+        let source = "let add = fn(a, b) { return (a + b); }; let i = 0; while (i < 3) { i = add(i, 1) } if (!false) { \"i=\" + \"3\" }";
+        let parsed = Parser::new(Lexer::new(source)).parse_program();
+        assert_eq!(built.to_string(), parsed.to_string());
+
+        let env = Rc::new(RefCell::new(object::Environment::new()));
+        assert_eq!(evaluate_program(built, env.clone()).unwrap().inspect(), "i=3");
+        assert_eq!(env.borrow().get("i").unwrap().inspect(), "3");
+
+        let floats = program(vec![expr_stmt(infix(float(1.0), "*", prefix("-", float(-2.5))))]);
+        let env = Rc::new(RefCell::new(object::Environment::new()));
+        assert_eq!(evaluate_program(floats, env).unwrap().inspect(), "2.5");
+    }
+
+    #[test]
+    fn test_error_call_stack() {
+        let input = "let check = fn(n) { if (n > 5) { n + true } else { n } };
+let outer = fn(x) { let y = x * 2; check(y) };
+outer(1);
+outer(3)";
+        let lexer = Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let env = Rc::new(RefCell::new(object::Environment::new()));
+        let mut evaluator = Evaluator::new();
+        let result = evaluator.evaluate_program(program, env).unwrap();
+        assert_eq!(result.inspect(), "type mismatch: INTEGER + BOOLEAN");
+        assert!(evaluator.call_stack().is_empty());
+
+        let stack = evaluator.error_call_stack().unwrap();
+        let frames: Vec<(&str, usize, usize)> = stack.frames().iter().map(|f| (f.function.as_str(), f.line, f.column)).collect();
+        assert_eq!(frames, vec![("outer", 4, 6), ("check", 2, 41)]);
+        assert_eq!(stack.get(0, "y").unwrap().inspect(), "6");
+        assert_eq!(stack.innermost().unwrap().get("n").unwrap().inspect(), "6");
+        let locals: Vec<String> = stack.frames()[0].locals().iter().map(|(name, value)| format!("{}={}", name, value.inspect())).collect();
+        assert_eq!(locals, vec!["x=3", "y=6"]);
+
+        let lexer = Lexer::new("fn(x) { x }(1)");
+        let mut parser = Parser::new(lexer);
+        let env = Rc::new(RefCell::new(object::Environment::new()));
+        evaluator.evaluate_program(parser.parse_program(), env);
+        assert!(evaluator.error_call_stack().is_none());
+
+        let lexer = Lexer::new("let f = fn(g) { g(true) };\nf(fn(x) { -x })");
+        let mut parser = Parser::new(lexer);
+        let env = Rc::new(RefCell::new(object::Environment::new()));
+        evaluator.evaluate_program(parser.parse_program(), env);
+        let functions: Vec<&str> = evaluator.error_call_stack().unwrap().frames().iter().map(|f| f.function.as_str()).collect();
+        assert_eq!(functions, vec!["f", "g"]);
+        let lexer = Lexer::new("fn(x) { -x }(true)");
+        let mut parser = Parser::new(lexer);
+        let env = Rc::new(RefCell::new(object::Environment::new()));
+        evaluator.evaluate_program(parser.parse_program(), env);
+        assert_eq!(evaluator.error_call_stack().unwrap().frames()[0].function, "<fn at 1:1>");
+    }
+
+    #[test]
+    fn test_string_builder() {
+        let input = "let sb = string_builder(); let i = 0; while (i < 3) { append(sb, \"n=\", i, \";\"); i += 1 } build(append(sb, true))";
+        assert_eq!(test_eval(input).inspect(), "n=0;n=1;n=2;true");
+        assert_eq!(test_eval("string_builder()").inspect(), "string builder (0 bytes)");
+        assert_eq!(test_eval("append(\"a\", \"b\")").inspect(), "first argument to append must be a STRING_BUILDER");
+        assert_eq!(test_eval("build(1)").inspect(), "argument to build must be a STRING_BUILDER, got INTEGER");
+
+        let lexer = Lexer::new("let sb = string_builder(); while (true) { append(sb, \"abcd\") }");
+        let mut parser = Parser::new(lexer);
+        let env = Rc::new(RefCell::new(object::Environment::new()));
+        let mut evaluator = Evaluator::with_limits(Limits { max_string_length: Some(10), ..Limits::default() });
+        let result = evaluator.evaluate_program(parser.parse_program(), env).unwrap();
+        assert_eq!(result.inspect(), "resource limit exceeded: string length 12 exceeds 10");
+    }
+
+    #[test]
+    fn test_nested_callbacks() {
+        let input = "
+        let n = 0;
+        let bump = fn() { n += 1; n };
+        let nest = fn(depth) { call(bump); if (depth > 0) { call(nest, depth - 1) }; n };
+        let i = 0;
+        while (i < 100) { call(nest, 20); i += 1 }
+        n";
+        assert_eq!(test_eval(input).inspect(), "2100");
+
+        let input = "let sb = string_builder(); let f = fn(i) { append(sb, i); if (i > 0) { call(f, i - 1) }; sb }; build(call(f, 3))";
+        assert_eq!(test_eval(input).inspect(), "3210");
+        assert_eq!(test_eval("call(fn(a, b) { a - b }, 5, 2)").inspect(), "3");
+        assert_eq!(test_eval("call(1)").inspect(), "not a function: INTEGER");
+        assert_eq!(test_eval("call()").inspect(), "wrong number of arguments to call: got 0, want at least 1");
+
+        let lexer = Lexer::new("let f = fn(x) { call(fn() { x + true }) }; f(1)");
+        let mut parser = Parser::new(lexer);
+        let env = Rc::new(RefCell::new(object::Environment::new()));
+        let mut evaluator = Evaluator::new();
+        let result = evaluator.evaluate_program(parser.parse_program(), env.clone()).unwrap();
+        assert_eq!(result.inspect(), "type mismatch: INTEGER + BOOLEAN");
+        let functions: Vec<&str> = evaluator.error_call_stack().unwrap().frames().iter().map(|f| f.function.as_str()).collect();
+        assert_eq!(functions, vec!["f", "<callback>"]);
+        assert!(env.try_borrow_mut().is_ok());
+    }
+
+    #[test]
+    fn test_with_timeout() {
+        assert_eq!(test_eval("with_timeout(1000, fn() { 1 + 2 })").inspect(), "3");
+        assert_eq!(test_eval("with_timeout(20, fn() { while (true) { 1 } })").inspect(), "timed out after 20 ms");
+        // An inner budget cannot outlast the one around it.
+        assert_eq!(test_eval("with_timeout(20, fn() { with_timeout(60000, fn() { while (true) { 1 } }) })").inspect(), "timed out after 20 ms");
+        assert_eq!(test_eval("with_timeout(-1, fn() { 1 })").inspect(), "timeout passed to with_timeout must not be negative, got -1");
+        assert_eq!(test_eval("with_timeout(\"1\", fn() { 1 })").inspect(), "first argument to with_timeout must be an INTEGER, got STRING");
+        assert_eq!(test_eval("with_timeout(1)").inspect(), "wrong number of arguments to with_timeout: got 1, want 2");
+        assert_eq!(test_eval("with_timeout(10, 1)").inspect(), "not a function: INTEGER");
+    }
+
+    #[test]
+    fn test_imports() {
+        let dir = std::env::temp_dir().join(format!("monkey_imports_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(dir.join("lib/math.monkey"), "import \"helpers.monkey\"; let square = fn(x) { times(x, x) }; 42").unwrap();
+        std::fs::write(dir.join("lib/helpers.monkey"), "puts(\"loading helpers\"); let times = fn(a, b) { a * b };").unwrap();
+        std::fs::write(dir.join("a.monkey"), "import \"b.monkey\"").unwrap();
+        std::fs::write(dir.join("b.monkey"), "import \"a.monkey\"").unwrap();
+        std::fs::write(dir.join("broken.monkey"), "let = 1;").unwrap();
+
+        let eval = |evaluator: &mut Evaluator, input: &str| {
+            let program = Parser::new(Lexer::new(input)).parse_program();
+            let env = Rc::new(RefCell::new(object::Environment::new()));
+            evaluator.evaluate_program(program, env).unwrap().inspect()
+        };
+        let output = SharedBuffer::default();
+        let mut evaluator = Evaluator::new();
+        evaluator.output = Box::new(output.clone());
+        evaluator.source_path = Some(dir.join("main.monkey"));
+
+        assert_eq!(eval(&mut evaluator, "let m = import \"lib/math.monkey\"; square(m) + times(1, 2)"), "1766");
+        assert_eq!(eval(&mut evaluator, "import \"lib/math.monkey\"; import \"lib/helpers.monkey\"; square(3)"), "9");
+        assert_eq!(output.contents(), "loading helpers\n");
+
+        let a = dir.join("a.monkey").canonicalize().unwrap();
+        let b = dir.join("b.monkey").canonicalize().unwrap();
+        assert_eq!(eval(&mut evaluator, "import \"a.monkey\""), format!("import cycle: {} -> {} -> {}", a.display(), b.display(), a.display()));
+        assert!(eval(&mut evaluator, "import \"missing.monkey\"").starts_with("cannot import missing.monkey: "));
+        assert!(eval(&mut evaluator, "import \"broken.monkey\"").starts_with("parser errors in "));
+
+        let mut sandboxed = Evaluator::with_limits(Limits::sandbox());
+        assert_eq!(eval(&mut sandboxed, "import \"lib/math.monkey\""), "cannot import lib/math.monkey: imports are disabled");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_imports_from_memory_loader() {
+        let eval = |evaluator: &mut Evaluator, input: &str| {
+            let program = Parser::new(Lexer::new(input)).parse_program();
+            let env = Rc::new(RefCell::new(object::Environment::new()));
+            evaluator.evaluate_program(program, env).unwrap().inspect()
+        };
+        let loader = MemoryLoader::new();
+        loader.insert("math", "import \"helpers\"; let square = fn(x) { times(x, x) }; 42");
+        loader.insert("helpers", "let times = fn(a, b) { a * b };");
+        loader.insert("a", "import \"b\"");
+        loader.insert("b", "import \"a\"");
+        let mut evaluator = Evaluator::new();
+        evaluator.set_module_loader(Box::new(loader.clone()));
+
+        assert_eq!(eval(&mut evaluator, "let m = import \"math\"; square(m) + times(1, 2)"), "1766");
+        assert_eq!(eval(&mut evaluator, "import \"a\""), "import cycle: a -> b -> a");
+        assert_eq!(eval(&mut evaluator, "import \"later\""), "cannot import later: no such module");
+        loader.insert("later", "let x = 7;");
+        assert_eq!(eval(&mut evaluator, "import \"later\"; x"), "7");
+        assert_eq!(eval(&mut evaluator, "import \"lib/math.monkey\""), "cannot import lib/math.monkey: no such module");
+    }
+
+    #[test]
+    fn test_string_interpolation() {
+        let tests = vec![
+            (r#"let name = "Ann"; let age = 41; "hello {name}, you are {age + 1}""#, "hello Ann, you are 42"),
+            (r#""{1.5 * 2} {true} {fn(x) { x }(2)} {"nested {1 + 1}"}""#, "3.0 true 2 nested 2"),
+            (r#""literal \{braces}""#, "literal {braces}"),
+            (r#""{missing}""#, "identifier not found: missing"),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(test_eval(input).inspect(), expected, "input: {}", input);
         }
-        result.push(evaluated);
+
+        let lexer = Lexer::new(r#"let s = "abcdef"; "{s}{s}""#);
+        let mut parser = Parser::new(lexer);
+        let env = Rc::new(RefCell::new(object::Environment::new()));
+        let mut evaluator = Evaluator::with_limits(Limits { max_string_length: Some(10), ..Limits::default() });
+        let result = evaluator.evaluate_program(parser.parse_program(), env).unwrap();
+        assert_eq!(result.inspect(), "resource limit exceeded: string length 12 exceeds 10");
+    }
+
+    #[test]
+    fn test_arrays_and_indexing() {
+        let tests = vec![
+            ("[1, 2 * 2, \"three\"]", "[1, 4, three]"),
+            ("let xs = [1, 2, 3]; xs[0] + xs[1 + 1]", "4"),
+            ("[1, 2, 3][3]", "null"),
+            ("[1, 2, 3][-1]", "null"),
+            ("[fn(x) { x * 2 }][0](21)", "42"),
+            ("\"héllo\"[1]", "é"),
+            ("\"abc\"[5]", "null"),
+            ("len(\"héllo\") + len([1, 2]) + len([])", "7"),
+            ("[1, missing]", "identifier not found: missing"),
+            ("1[0]", "index operator not supported: INTEGER[INTEGER]"),
+            ("[1][\"a\"]", "index operator not supported: ARRAY[STRING]"),
+            ("len(1)", "argument to len not supported, got INTEGER"),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(test_eval(input).inspect(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_iteration_builtins() {
+        let tests = vec![
+            ("map([1, 2, 3], fn(x) { x * x })", "[1, 4, 9]"),
+            ("map([], fn(x) { x })", "[]"),
+            ("filter([1, 2, 3, 4], fn(x) { x % 2 == 0 })", "[2, 4]"),
+            ("reduce([1, 2, 3, 4], 0, fn(acc, x) { acc + x })", "10"),
+            ("reduce([], \"empty\", fn(acc, x) { acc + x })", "empty"),
+            ("let total = 0; each([1, 2, 3], fn(x) { total += x }); total", "6"),
+            ("map(split(\"a b\", \" \"), upper)", "[A, B]"),
+            ("map([1, true], fn(x) { x + 1 })", "type mismatch: BOOLEAN + INTEGER"),
+            ("map(1, fn(x) { x })", "first argument to map must be an ARRAY, got INTEGER"),
+            ("filter([1], 2)", "not a function: INTEGER"),
+            ("reduce([1], fn(acc, x) { acc })", "wrong number of arguments to reduce: got 2, want 3"),
+            ("map([1], fn(a, b) { a })", "wrong number of arguments to <callback>: got 1, want 2"),
+            ("let f = fn(a, b) { a }; f(1)", "wrong number of arguments to f: got 1, want 2"),
+            ("let f = fn(a) { a }; f(1, 2)", "wrong number of arguments to f: got 2, want 1"),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(test_eval(input).inspect(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_array_builtins() {
+        let tests = vec![
+            ("push([1, 2], 3)", "[1, 2, 3]"),
+            ("pop([1, 2, 3])", "[1, 2]"),
+            ("insert([1, 3], 1, 2)", "[1, 2, 3]"),
+            ("insert([1, 2], 2, 3)", "[1, 2, 3]"),
+            ("remove([1, 2, 3], 0)", "[2, 3]"),
+            ("len(push([], [1]))", "1"),
+            // The array given is left as it was.
+            ("let xs = [1, 2]; let ys = push(xs, 3); [xs, ys]", "[[1, 2], [1, 2, 3]]"),
+            ("let xs = [1, 2, 3]; [pop(xs), remove(xs, 1), insert(xs, 0, 0), xs]", "[[1, 2], [1, 3], [0, 1, 2, 3], [1, 2, 3]]"),
+            // Pushing twice onto one array gives two arrays that do not see
+            // each other's element.
+            ("let xs = push([], 1); let a = push(xs, 2); let b = push(xs, 3); [a, b, xs]", "[[1, 2], [1, 3], [1]]"),
+            ("let xs = [1, 2]; let shorter = pop(xs); [push(shorter, 9), xs]", "[[1, 9], [1, 2]]"),
+            ("let xs = []; let i = 0; while (i < 10000) { xs = push(xs, i); i += 1 }; [len(xs), xs[9999]]", "[10000, 9999]"),
+            ("let xs = [1]; map(xs, fn(x) { xs = push(xs, x + 1) }); xs", "[1, 2]"),
+            ("push([1], 2) == [1, 2]", "true"),
+            ("pop([])", "cannot pop from an empty array"),
+            ("insert([1], 2, 0)", "index 2 out of range for insert on an array of length 1"),
+            ("remove([1], 1)", "index 1 out of range for remove on an array of length 1"),
+            ("remove([1], -1)", "index -1 out of range for remove on an array of length 1"),
+            ("remove([1], \"0\")", "second argument to remove must be an INTEGER, got STRING"),
+            ("push(\"a\", 1)", "first argument to push must be an ARRAY, got STRING"),
+            ("push([1])", "wrong number of arguments to push: got 1, want 2"),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(test_eval(input).inspect(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_string_builtins() {
+        let tests = vec![
+            ("split(\"a,b,,c\", \",\")", "[a, b, , c]"),
+            ("split(\"abc\", \"\")", "[a, b, c]"),
+            ("join(split(\"a b c\", \" \"), \"-\")", "a-b-c"),
+            ("join([1, true, \"x\"], \"\")", "1truex"),
+            ("join([], \",\")", ""),
+            ("upper(\"MonKey\") + lower(\"MonKey\")", "MONKEYmonkey"),
+            ("\"[\" + trim(\"  a b \\n\") + \"]\"", "[a b]"),
+            ("replace(\"a-b-c\", \"-\", \"+\")", "a+b+c"),
+            ("contains(\"monkey\", \"key\")", "true"),
+            ("contains(\"monkey\", \"donkey\")", "false"),
+            ("substr(\"héllo\", 1, 3)", "éll"),
+            ("substr(\"hello\", 3)", "lo"),
+            ("substr(\"hello\", 3, 10) + substr(\"hello\", 10)", "lo"),
+            ("split(\"a\")", "wrong number of arguments to split: got 1, want 2"),
+            ("upper(1)", "argument to upper must be a STRING, got INTEGER"),
+            ("contains(\"a\", [\"a\"])", "argument to contains must be a STRING, got ARRAY"),
+            ("replace(\"a\", \"\", \"b\")", "pattern passed to replace must not be empty"),
+            ("join(\"abc\", \",\")", "first argument to join must be an ARRAY, got STRING"),
+            ("join([1], 2)", "second argument to join must be a STRING, got INTEGER"),
+            ("substr(\"abc\", \"1\")", "arguments to substr must be (STRING, INTEGER[, INTEGER]), got (STRING, STRING)"),
+            ("substr(\"abc\", -1)", "start and length passed to substr must not be negative"),
+            ("substr(\"abc\")", "wrong number of arguments to substr: got 1, want 2 or 3"),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(test_eval(input).inspect(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_inspect_pretty() {
+        let tests = vec![
+            ("inspect_pretty(832040)", "832,040"),
+            ("inspect_pretty([1234, -5678.5, \"1234\"])", "[1,234, -5,678.5, \"1234\"]"),
+            ("inspect_pretty(1234567, \"_\")", "1_234_567"),
+            ("inspect_pretty(1, \"ab\")", "separator passed to inspect_pretty must be a single character, got \"ab\""),
+            ("inspect_pretty()", "wrong number of arguments to inspect_pretty: got 0, want 1 or 2"),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(test_eval(input).inspect(), expected, "input: {}", input);
+        }
+
+        let mut evaluator = Evaluator::new();
+        evaluator.pretty.digit_grouping = Some('.');
+        let result = builtins::lookup("inspect_pretty").unwrap()(&mut evaluator, vec![Object::Integer(1000)]);
+        assert_eq!(result.inspect(), "1.000");
+    }
+
+    #[test]
+    fn test_power() {
+        let tests = vec![
+            ("2 ** 10", "1024"),
+            ("2 ** 3 ** 2", "512"),
+            ("-2 ** 2", "4"),
+            ("0 ** 0", "1"),
+            ("2 * 3 ** 2", "18"),
+            ("2.0 ** 0.5 > 1.41", "true"),
+            ("4 ** 0.5", "2.0"),
+            ("2 ** -1.0", "0.5"),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(test_eval(input).inspect(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_else_if() {
+        let grade = "let grade = fn(score) { if (score >= 90) { \"A\" } else if (score >= 80) { \"B\" } else if (score >= 70) { \"C\" } else { \"F\" } };";
+        let tests = vec![
+            (format!("{} grade(95)", grade), "A"),
+            (format!("{} grade(85)", grade), "B"),
+            (format!("{} grade(70)", grade), "C"),
+            (format!("{} grade(12)", grade), "F"),
+            ("if (false) { 1 } else if (false) { 2 }".to_string(), "null"),
+            ("let f = fn(x) { if (x == 1) { return 10; } else if (x == 2) { return 20; } 30 }; [f(1), f(2), f(3)]".to_string(), "[10, 20, 30]"),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(test_eval(&input).inspect(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_introspection_builtins() {
+        let tests = vec![
+            ("type(1)", "INTEGER"),
+            ("type(1.5) + type(\"a\") + type(null)", "FLOATSTRINGNULL"),
+            ("type([]) + type(len) + type(fn() {})", "ARRAYBUILTINFUNCTION"),
+            ("type(string_builder())", "STRING_BUILDER"),
+            ("type()", "wrong number of arguments to type: got 0, want 1"),
+            ("arity(fn(a, b, c) { a })", "3"),
+            ("arity(fn() { 1 })", "0"),
+            ("params(fn(x, rest) { x })", "[x, rest]"),
+            ("params(fn() { 1 })", "[]"),
+            ("arity(len)", "argument to arity must be a FUNCTION, got BUILTIN"),
+            ("params(1, 2)", "wrong number of arguments to params: got 2, want 1"),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(test_eval(input).inspect(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_file_builtins() {
+        let dir = std::env::temp_dir().join(format!("monkey_file_builtins_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notes.txt");
+        let path = path.to_str().unwrap();
+
+        let mut evaluator = Evaluator::with_limits(Limits { allow_fs: true, ..Limits::default() });
+        let env = Rc::new(RefCell::new(object::Environment::new()));
+        let mut eval = |input: &str| {
+            let mut parser = Parser::new(Lexer::new(input));
+            evaluator.evaluate_program(parser.parse_program(), env.clone()).unwrap().inspect()
+        };
+        assert_eq!(eval(&format!("file_exists(\"{}\")", path)), "false");
+        assert_eq!(eval(&format!("write_file(\"{}\", \"a\"); append_file(\"{}\", \"b\")", path, path)), "null");
+        assert_eq!(eval(&format!("read_file(\"{}\")", path)), "ab");
+        assert_eq!(eval(&format!("file_exists(\"{}\")", path)), "true");
+        assert!(eval(&format!("read_file(\"{}/missing.txt\")", dir.display())).starts_with("cannot read "));
+        assert_eq!(eval("write_file(\"x.txt\", 1)"), "argument to write_file must be a STRING, got INTEGER");
+        assert_eq!(eval("read_file()"), "wrong number of arguments to read_file: got 0, want 1");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert_eq!(test_eval("read_file(\"notes.txt\")").inspect(), "cannot call read_file: file access is disabled");
+        assert_eq!(test_eval("file_exists(\"notes.txt\")").inspect(), "cannot call file_exists: file access is disabled");
+    }
+
+    #[test]
+    fn test_script_args_and_env() {
+        let mut evaluator = Evaluator::with_limits(Limits { allow_fs: true, ..Limits::default() });
+        evaluator.script_args = vec!["a".to_string(), "b c".to_string()];
+        let env = Rc::new(RefCell::new(object::Environment::new()));
+        let mut eval = |input: &str| {
+            let mut parser = Parser::new(Lexer::new(input));
+            evaluator.evaluate_program(parser.parse_program(), env.clone()).unwrap().inspect()
+        };
+        assert_eq!(eval("args()"), "[a, b c]");
+        assert_eq!(eval("len(args())"), "2");
+        assert_eq!(eval("args(1)"), "wrong number of arguments to args: got 1, want 0");
+        assert_eq!(eval("env(\"PATH\")"), std::env::var("PATH").unwrap());
+        assert_eq!(eval("env(\"MONKEY_SURELY_UNSET_VARIABLE\")"), "null");
+        assert_eq!(eval("env(1)"), "argument to env must be a STRING, got INTEGER");
+
+        assert_eq!(test_eval("args()").inspect(), "[]");
+        assert_eq!(test_eval("env(\"PATH\")").inspect(), "cannot call env: environment access is disabled");
+    }
+
+    #[test]
+    fn test_assertions() {
+        let tests = vec![
+            ("assert(1 < 2)", "null"),
+            ("assert(1 > 2)", "assertion failed"),
+            ("assert(null, \"nothing\")", "assertion failed: nothing"),
+            ("assert_eq([1, 2.0], [1, 2])", "null"),
+            ("assert_eq(\"1\", 1)", "assertion failed: \"1\" != 1"),
+            ("assert_eq(1)", "wrong number of arguments to assert_eq: got 1, want 2"),
+            ("assert()", "wrong number of arguments to assert: got 0, want 1 or 2"),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(test_eval(input).inspect(), expected, "input: {}", input);
+        }
+
+        let mut evaluator = Evaluator::new();
+        evaluator.assertion_failures = Some(vec![]);
+        let env = Rc::new(RefCell::new(object::Environment::new()));
+        let program = Parser::new(Lexer::new("assert(false, \"first\"); assert_eq(1 + 1, 3); \"went on\"")).parse_program();
+        assert_eq!(evaluator.evaluate_program(program, env).unwrap().inspect(), "went on");
+        assert_eq!(evaluator.assertion_failures, Some(vec!["assertion failed: first".to_string(), "assertion failed: 2 != 3".to_string()]));
+    }
+
+    #[test]
+    fn test_error_operands() {
+        let tests: Vec<(&str, Vec<&str>)> = vec![
+            ("let x = 1;\nlet y = x + true;", vec!["x at 2:9", "true at 2:13"]),
+            ("let f = fn() { \"a\" };\n1 * (f() - 2)", vec!["f at 2:6", "2 at 2:12"]),
+            ("[1, -true]", vec!["true at 1:6"]),
+            ("let s = \"a\";\ns -= [1, 2];", vec!["s at 2:1", "[ at 2:6"]),
+            ("1 + 2", vec![]),
+            ("missing + 1", vec![]),
+        ];
+        for (input, expected) in tests {
+            let mut parser = Parser::new(Lexer::new(input));
+            let mut evaluator = Evaluator::new();
+            let env = Rc::new(RefCell::new(object::Environment::new()));
+            evaluator.evaluate_program(parser.parse_program(), env);
+            let operands: Vec<String> = evaluator.error_operands().iter()
+                .map(|token| format!("{} at {}:{}", token.literal, token.line, token.column))
+                .collect();
+            assert_eq!(operands, expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_call_depth_limit() {
+        let lexer = Lexer::new("let countdown = fn(n) { if (n == 0) { 0 } else { countdown(n - 1) } }; countdown(100000)");
+        let mut parser = Parser::new(lexer);
+        let env = Rc::new(RefCell::new(object::Environment::new()));
+        let mut evaluator = Evaluator::with_limits(Limits { max_call_depth: Some(50), ..Limits::default() });
+        let result = evaluator.evaluate_program(parser.parse_program(), env.clone()).unwrap();
+        assert_eq!(result.inspect(), "stack overflow: call depth exceeds 50");
+        assert_eq!(evaluator.error_call_stack().unwrap().depth(), 50);
+
+        let lexer = Lexer::new("countdown(49)");
+        let mut parser = Parser::new(lexer);
+        assert_eq!(evaluator.evaluate_program(parser.parse_program(), env).unwrap().inspect(), "0");
+    }
+
+    #[test]
+    fn test_default_call_depth_fits_main_thread() {
+        // The size of a main thread's stack on most platforms.
+        let main_thread = std::thread::Builder::new().stack_size(8 << 20);
+        let results = main_thread.spawn(|| {
+            let env = Rc::new(RefCell::new(object::Environment::new()));
+            let mut evaluator = Evaluator::new();
+            ["let countdown = fn(n) { if (n == 0) { 0 } else { 1 + countdown(n - 1) } }; countdown(199)", "countdown(1000)"]
+                .map(|input| evaluator.evaluate_program(Parser::new(Lexer::new(input)).parse_program(), env.clone()).unwrap().inspect())
+        });
+        let overflow = format!("stack overflow: call depth exceeds {}", DEFAULT_MAX_CALL_DEPTH);
+        assert_eq!(results.unwrap().join().unwrap(), ["199".to_string(), overflow]);
+
+        let limits = Limits::default().for_stack_size(64 << 20);
+        assert_eq!(limits.max_call_depth, Some(1638));
+        assert!(limits.stack_size() <= 64 << 20);
+        assert_eq!(Limits::default().stack_size(), 8 << 20);
+    }
+
+    #[test]
+    fn test_continue_program() {
+        let mut evaluator = Evaluator::with_limits(Limits { max_steps: Some(10), ..Limits::default() });
+        let env = Rc::new(RefCell::new(object::Environment::new()));
+        evaluator.begin_program();
+        let mut results = vec![];
+        for input in ["let i = 0;", "i += 1; i", "i += 1; i", "i += 1; i", "i += 1; i", "i += 1; i"] {
+            let result = evaluator.continue_program(Parser::new(Lexer::new(input)).parse_program(), env.clone());
+            results.push(result.unwrap().inspect());
+        }
+        // The step limit is for all the parts together.
+        assert_eq!(results, ["null", "1", "2", "3", "resource limit exceeded: more than 10 evaluation steps", "resource limit exceeded: more than 10 evaluation steps"]);
+    }
+
+    #[test]
+    fn test_run_limits() {
+        let run = |limits: Limits, input: &str| {
+            let mut evaluator = Evaluator::with_limits(limits);
+            let env = Rc::new(RefCell::new(object::Environment::new()));
+            let result = evaluator.evaluate_program(Parser::new(Lexer::new(input)).parse_program(), env.clone()).unwrap();
+            // Each run starts with a fresh budget.
+            let again = evaluator.evaluate_program(Parser::new(Lexer::new("1 + 1")).parse_program(), env).unwrap();
+            (result.inspect(), again.inspect())
+        };
+
+        let steps = Limits { max_steps: Some(100), ..Limits::default() };
+        assert_eq!(run(steps, "let i = 0; while (true) { i += 1 }"), ("resource limit exceeded: more than 100 evaluation steps".to_string(), "2".to_string()));
+        assert_eq!(run(steps, "let i = 0; while (i < 10) { i += 1 }; i").0, "10");
+
+        let entries = Limits { max_env_entries: Some(2), ..Limits::default() };
+        assert_eq!(run(entries, "let a = 1; let b = 2; let a = 3; a + b").0, "5");
+        assert_eq!(run(entries, "let a = 1; let b = 2; let c = 3;").0, "resource limit exceeded: more than 2 bindings in one scope");
+        assert_eq!(run(entries, "let f = fn(x, y, z) { let w = 1; let v = 2; w }; f(1, 2, 3)").0, "resource limit exceeded: more than 2 bindings in one scope");
+
+        let timeout = Limits { timeout: Some(Duration::from_millis(20)), ..Limits::default() };
+        assert_eq!(run(timeout, "while (true) { 1 }").0, "timed out after 20 ms");
+        assert_eq!(run(timeout, "with_timeout(60000, fn() { while (true) { 1 } })").0, "timed out after 20 ms");
+    }
+
+    #[test]
+    fn test_object_values() {
+        assert!(matches!(test_eval("1 < 2"), Object::Boolean(true)));
+        assert!(matches!(test_eval("!true"), Object::Boolean(false)));
+        assert!(matches!(test_eval("if (false) { 1 }"), Object::Null));
+        assert!(matches!(test_eval("40 + 2"), Object::Integer(42)));
+        assert!(matches!(test_eval("-1.5"), Object::Float(value) if value == -1.5));
+    }
+
+    #[test]
+    fn test_quote_unquote() {
+        let tests = vec![
+            ("quote(5)", "QUOTE(5)"),
+            ("quote(foobar + barfoo)", "QUOTE((foobar + barfoo))"),
+            ("quote(unquote(4 + 4))", "QUOTE(8)"),
+            ("quote(8 + unquote(4 + 4))", "QUOTE((8 + 8))"),
+            ("let foobar = 8; quote(unquote(foobar))", "QUOTE(8)"),
+            ("quote(unquote(true == false))", "QUOTE(false)"),
+            ("quote(unquote(quote(4 + 4)))", "QUOTE((4 + 4))"),
+            ("let q = quote(4 + 4); quote(unquote(4 + 4) + unquote(q))", "QUOTE((8 + (4 + 4)))"),
+            ("quote(unquote(missing))", "identifier not found: missing"),
+            ("quote(1, 2)", "wrong number of arguments to quote: got 2, want 1"),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(test_eval(input).inspect(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_expand_macros() {
+        let expand = |input: &str| {
+            let mut program = Parser::new(Lexer::new(input)).parse_program();
+            let env = Rc::new(RefCell::new(object::Environment::new()));
+            let mut evaluator = Evaluator::new();
+            evaluator.define_macros(&mut program, env.clone());
+            evaluator.expand_macros(program, env).map(|program| program.to_string())
+        };
+
+        assert_eq!(expand("let number = 1; let m = macro(x, y) { x; }; number"), Ok("let number = 1;number".to_string()));
+        assert_eq!(expand("let reverse = macro(a, b) { quote(unquote(b) - unquote(a)); }; reverse(2 + 2, 10 - 5);"),
+            Ok("((10 - 5) - (2 + 2))".to_string()));
+        assert_eq!(expand("let unless = macro(c, x) { quote(if (!(unquote(c))) { unquote(x); }); }; unless(10 > 5, puts(1));"),
+            Ok("if(!(10 > 5)) {puts(1)}".to_string()));
+        assert_eq!(expand("let m = macro(x) { x; }; m(1, 2)"), Err("wrong number of arguments to macro m: got 2, want 1".to_string()));
+        assert_eq!(expand("let m = macro() { 1 }; m()"), Err("macro m must return a QUOTE, got INTEGER".to_string()));
+    }
+
+    #[test]
+    fn test_empty_block() {
+        assert_eq!(test_eval("if (true) {}").inspect(), "null");
     }
-    result
 }
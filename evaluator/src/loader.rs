@@ -0,0 +1,68 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+
+// Where import expressions get their modules from. A module is known by the
+// name resolve gives it: the evaluator caches modules and reports import
+// cycles under that name, and hands it to load for the source.
+pub trait ModuleLoader {
+    // importer is the name of the module containing the import, or the
+    // evaluator's source_path for imports in the main program.
+    fn resolve(&self, path: &str, importer: Option<&str>) -> Result<String, String>;
+
+    fn load(&self, name: &str) -> Result<String, String>;
+}
+
+// Reads modules from disk. Paths are relative to the importing file, and
+// modules are named by their canonical path.
+#[derive(Clone, Copy, Default)]
+pub struct FileSystemLoader;
+
+impl ModuleLoader for FileSystemLoader {
+    fn resolve(&self, path: &str, importer: Option<&str>) -> Result<String, String> {
+        let base = importer.and_then(|p| Path::new(p).parent()).unwrap_or(Path::new(""));
+        let resolved = base.join(path).canonicalize().map_err(|err| err.to_string())?;
+        Ok(resolved.display().to_string())
+    }
+
+    fn load(&self, name: &str) -> Result<String, String> {
+        std::fs::read_to_string(name).map_err(|err| err.to_string())
+    }
+}
+
+// Serves modules from sources held in memory, looked up by the exact path
+// the import names. Clones share their modules, so a host can keep one to
+// add modules after handing another to the evaluator.
+#[derive(Clone, Default)]
+pub struct MemoryLoader {
+    modules: Rc<RefCell<HashMap<String, String>>>,
+}
+
+impl MemoryLoader {
+    pub fn new() -> MemoryLoader {
+        MemoryLoader::default()
+    }
+
+    pub fn insert(&self, name: &str, source: &str) {
+        self.modules.borrow_mut().insert(name.to_string(), source.to_string());
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.modules.borrow().contains_key(name)
+    }
+}
+
+impl ModuleLoader for MemoryLoader {
+    fn resolve(&self, path: &str, _importer: Option<&str>) -> Result<String, String> {
+        if self.contains(path) {
+            Ok(path.to_string())
+        } else {
+            Err("no such module".to_string())
+        }
+    }
+
+    fn load(&self, name: &str) -> Result<String, String> {
+        self.modules.borrow().get(name).cloned().ok_or_else(|| "no such module".to_string())
+    }
+}
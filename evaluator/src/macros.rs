@@ -0,0 +1,121 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use ast::modify::{modify_expression, modify_program};
+use ast::{Expression, ExpressionKind, StatementKind};
+use object::Object;
+
+use crate::{unwrap_return_value, Evaluator};
+
+impl Evaluator {
+    // Returns the argument unevaluated, except for unquote(...) calls inside
+    // it, which are evaluated and spliced back into the tree.
+    pub(crate) fn quote(&mut self, node: Rc<dyn Expression>, env: Rc<RefCell<object::Environment>>) -> Object {
+        let mut error = None;
+        let node = modify_expression(node, &mut |exp| {
+            let argument = match unquote_argument(&exp) {
+                Some(argument) => argument,
+                None => return exp,
+            };
+            let evaluated = self.evaluate_expression(argument, env.clone());
+            if evaluated.object_type() == object::ObjectType::ERROR {
+                error.get_or_insert(evaluated);
+                return exp;
+            }
+            object_to_node(evaluated).unwrap_or(exp)
+        });
+        match error {
+            Some(error) => error,
+            None => Object::Quote(node),
+        }
+    }
+
+    // Moves every top-level `let name = macro(...) { ... };` out of the
+    // program and into env.
+    pub fn define_macros(&mut self, program: &mut ast::Program, env: Rc<RefCell<object::Environment>>) {
+        program.statements.retain(|statement| {
+            let let_statement = match statement.kind() {
+                StatementKind::Let(let_statement) => let_statement,
+                _ => return true,
+            };
+            let macro_literal = match let_statement.value.as_ref().map(|value| value.kind()) {
+                Some(ExpressionKind::MacroLiteral(macro_literal)) => macro_literal,
+                _ => return true,
+            };
+            object::gc::track(&env);
+            let macro_object = object::Macro {
+                parameters: macro_literal.parameters.clone(),
+                body: macro_literal.body.clone(),
+                env: env.clone(),
+            };
+            env.borrow_mut().set(let_statement.name.value.clone(), Object::Macro(Rc::new(macro_object)));
+            false
+        });
+    }
+
+    pub fn expand_macros(&mut self, program: ast::Program, env: Rc<RefCell<object::Environment>>) -> Result<ast::Program, String> {
+        let mut error = None;
+        let program = modify_program(program, &mut |exp| {
+            let call = match exp.kind() {
+                ExpressionKind::Call(call) => call,
+                _ => return exp,
+            };
+            let name = match call.function.kind() {
+                ExpressionKind::Identifier(identifier) => identifier.value.clone(),
+                _ => return exp,
+            };
+            let macro_object = match env.borrow().get(&name) {
+                Some(Object::Macro(macro_object)) => macro_object,
+                _ => return exp,
+            };
+            match self.apply_macro(&name, &macro_object, &call.arguments) {
+                Ok(expanded) => expanded,
+                Err(message) => {
+                    error.get_or_insert(message);
+                    exp
+                }
+            }
+        });
+        match error {
+            Some(message) => Err(message),
+            None => Ok(program),
+        }
+    }
+
+    fn apply_macro(&mut self, name: &str, macro_object: &object::Macro, arguments: &[Rc<dyn Expression>]) -> Result<Rc<dyn Expression>, String> {
+        if arguments.len() != macro_object.parameters.len() {
+            return Err(format!("wrong number of arguments to macro {}: got {}, want {}", name, arguments.len(), macro_object.parameters.len()));
+        }
+        let env = object::Environment::new_enclosed(macro_object.env.clone());
+        for (param, argument) in macro_object.parameters.iter().zip(arguments) {
+            env.borrow_mut().set(param.value.clone(), Object::Quote(argument.clone()));
+        }
+        match unwrap_return_value(self.evaluate_block_statement(&macro_object.body, env)) {
+            Object::Quote(node) => Ok(node),
+            Object::Error(message) => Err(message),
+            other => Err(format!("macro {} must return a QUOTE, got {:?}", name, other.object_type())),
+        }
+    }
+}
+
+fn unquote_argument(exp: &Rc<dyn Expression>) -> Option<Rc<dyn Expression>> {
+    let call = match exp.kind() {
+        ExpressionKind::Call(call) => call,
+        _ => return None,
+    };
+    match (call.function.kind(), call.arguments.as_slice()) {
+        (ExpressionKind::Identifier(identifier), [argument]) if identifier.value == "unquote" => Some(argument.clone()),
+        _ => None,
+    }
+}
+
+fn object_to_node(obj: Object) -> Option<Rc<dyn Expression>> {
+    match obj {
+        Object::Integer(value) => Some(ast::builder::int(value)),
+        Object::Float(value) => Some(ast::builder::float(value)),
+        Object::Boolean(value) => Some(ast::builder::boolean(value)),
+        Object::String(value) => Some(ast::builder::string(&value)),
+        Object::Quote(node) => Some(node),
+        _ => None,
+    }
+}
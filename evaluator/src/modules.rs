@@ -0,0 +1,110 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use lexer::Lexer;
+use object::Object;
+use parser::Parser;
+
+use crate::loader::{FileSystemLoader, ModuleLoader};
+use crate::{unwrap_return_value, Evaluator};
+
+struct Module {
+    value: Object,
+    env: Rc<RefCell<object::Environment>>,
+}
+
+// Modules are cached by the name the loader resolves them to, so a module
+// imported from several places is evaluated once. `loading` holds the
+// modules currently being evaluated, innermost last.
+pub(crate) struct Modules {
+    loader: Box<dyn ModuleLoader>,
+    outer: Rc<RefCell<object::Environment>>,
+    loading: Vec<String>,
+    cache: HashMap<String, Module>,
+}
+
+impl Default for Modules {
+    fn default() -> Self {
+        Modules {
+            loader: Box::new(FileSystemLoader),
+            outer: Rc::default(),
+            loading: vec![],
+            cache: HashMap::new(),
+        }
+    }
+}
+
+impl Evaluator {
+    // Modules are evaluated in a scope enclosed by env, typically the
+    // prelude, rather than in the scope of the file importing them. Modules
+    // cached against a previous environment are dropped.
+    pub fn set_module_environment(&mut self, env: Rc<RefCell<object::Environment>>) {
+        self.modules.outer = env;
+        self.modules.cache.clear();
+    }
+
+    // Modules cached from the previous loader are dropped.
+    pub fn set_module_loader(&mut self, loader: Box<dyn ModuleLoader>) {
+        self.modules.loader = loader;
+        self.modules.cache.clear();
+    }
+
+    // Binds the module's top-level names in env and evaluates to the value
+    // of its last statement.
+    pub(crate) fn import(&mut self, import: &ast::ImportExpression, env: Rc<RefCell<object::Environment>>) -> Object {
+        if !self.limits.allow_imports {
+            return Object::Error(format!("cannot import {}: imports are disabled", import.path.value));
+        }
+        let importer = self.modules.loading.last().cloned()
+            .or_else(|| self.source_path.as_ref().map(|path| path.display().to_string()));
+        let name = match self.modules.loader.resolve(&import.path.value, importer.as_deref()) {
+            Ok(name) => name,
+            Err(message) => return Object::Error(format!("cannot import {}: {}", import.path.value, message)),
+        };
+        if let Some(start) = self.modules.loading.iter().position(|loading| *loading == name) {
+            let cycle: Vec<&str> = self.modules.loading[start..].iter()
+                .chain([&name])
+                .map(String::as_str)
+                .collect();
+            return Object::Error(format!("import cycle: {}", cycle.join(" -> ")));
+        }
+        if !self.modules.cache.contains_key(&name) {
+            match self.load_module(&name) {
+                Ok(module) => {
+                    self.modules.cache.insert(name.clone(), module);
+                }
+                Err(error) => return error,
+            }
+        }
+
+        let module = &self.modules.cache[&name];
+        for (name, value) in &module.env.borrow().scope {
+            env.borrow_mut().set(name.clone(), value.clone());
+        }
+        module.value.clone()
+    }
+
+    fn load_module(&mut self, name: &str) -> Result<Module, Object> {
+        let source = self.modules.loader.load(name)
+            .map_err(|err| Object::Error(format!("cannot import {}: {}", name, err)))?;
+        let mut parser = Parser::new(Lexer::new(&source));
+        let mut program = parser.parse_program();
+        if !parser.errors().is_empty() {
+            return Err(Object::Error(format!("parser errors in {}: {}", name, parser.errors().join("; "))));
+        }
+        let macros = Rc::new(RefCell::new(object::Environment::new()));
+        self.define_macros(&mut program, macros.clone());
+        let program = self.expand_macros(program, macros)
+            .map_err(|message| Object::Error(format!("macro expansion error in {}: {}", name, message)))?;
+
+        let env = object::Environment::new_enclosed(self.modules.outer.clone());
+        self.modules.loading.push(name.to_string());
+        let value = unwrap_return_value(self.evaluate_statements(&program.statements, env.clone()));
+        self.modules.loading.pop();
+        if value.object_type() == object::ObjectType::ERROR {
+            return Err(value);
+        }
+        Ok(Module { value, env })
+    }
+}
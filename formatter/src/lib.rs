@@ -0,0 +1,717 @@
+use std::path::Path;
+use std::rc::Rc;
+
+use ast::{Expression, Statement};
+use token::{Token, TokenType};
+
+// Looked up in the formatted file's directory and each directory above it.
+pub const CONFIG_FILE: &str = "monkeyfmt.toml";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BraceStyle {
+    // `if (x) {`
+    SameLine,
+    // `if (x)` with the `{` on a line of its own, as is `else`.
+    NextLine,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FormatConfig {
+    pub indent_width: usize,
+    pub use_tabs: bool,
+    pub crlf: bool,
+    // Calls and arrays that would run past this column are split with one
+    // argument or element per line.
+    pub max_line_length: usize,
+    pub brace_style: BraceStyle,
+}
+
+impl Default for FormatConfig {
+    fn default() -> Self {
+        FormatConfig {
+            indent_width: 4,
+            use_tabs: false,
+            crlf: false,
+            max_line_length: 100,
+            brace_style: BraceStyle::SameLine,
+        }
+    }
+}
+
+impl FormatConfig {
+    // Keys left out of the file keep their default.
+    pub fn from_toml(source: &str) -> Result<FormatConfig, String> {
+        let table: toml::Table = source.parse().map_err(|err: toml::de::Error| err.message().to_string())?;
+        let mut config = FormatConfig::default();
+        for (key, value) in &table {
+            match key.as_str() {
+                "indent_width" => config.indent_width = positive_integer(key, value)?,
+                "max_line_length" => config.max_line_length = positive_integer(key, value)?,
+                "use_tabs" => config.use_tabs = boolean(key, value)?,
+                "crlf" => config.crlf = boolean(key, value)?,
+                "brace_style" => config.brace_style = match value.as_str() {
+                    Some("same_line") => BraceStyle::SameLine,
+                    Some("next_line") => BraceStyle::NextLine,
+                    _ => return Err(format!("brace_style must be \"same_line\" or \"next_line\", got {}", value)),
+                },
+                _ => return Err(format!("unknown key {}", key)),
+            }
+        }
+        Ok(config)
+    }
+
+    // The configuration for formatting the file at path: the nearest
+    // monkeyfmt.toml above it, or the default when there is none.
+    pub fn discover(path: &Path) -> Result<FormatConfig, String> {
+        let directory = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => std::env::current_dir().map_err(|err| err.to_string())?,
+        };
+        for dir in directory.ancestors() {
+            let candidate = dir.join(CONFIG_FILE);
+            if candidate.is_file() {
+                let source = std::fs::read_to_string(&candidate).map_err(|err| format!("{}: {}", candidate.display(), err))?;
+                return FormatConfig::from_toml(&source).map_err(|err| format!("{}: {}", candidate.display(), err));
+            }
+        }
+        Ok(FormatConfig::default())
+    }
+}
+
+fn positive_integer(key: &str, value: &toml::Value) -> Result<usize, String> {
+    match value.as_integer() {
+        Some(n) if n > 0 => Ok(n as usize),
+        _ => Err(format!("{} must be a positive integer, got {}", key, value)),
+    }
+}
+
+fn boolean(key: &str, value: &toml::Value) -> Result<bool, String> {
+    value.as_bool().ok_or_else(|| format!("{} must be true or false, got {}", key, value))
+}
+
+pub fn format(program: &ast::Program, config: &FormatConfig) -> String {
+    format_with_comments(program, &[], config)
+}
+
+// Like format, but keeps the comments among tokens, which are the program's
+// tokens with trivia, as Lexer::tokenize(source, true) gives them. A comment
+// that follows code on its line stays at the end of the statement it
+// follows; any other comment goes on its own line before the statement after
+// it, or before the closing brace of its block. Comments inside a statement
+// end up after it.
+pub fn format_with_comments(program: &ast::Program, tokens: &[Token], config: &FormatConfig) -> String {
+    let mut formatter = Formatter {
+        config,
+        depth: 0,
+        out: String::new(),
+        unterminated: None,
+        flat: false,
+        comments: comments(tokens),
+        next_comment: 0,
+    };
+    let statements = &program.statements;
+    for (i, statement) in statements.iter().enumerate() {
+        formatter.comments_on_own_lines(statement.span().start);
+        formatter.format_statement(statement);
+        let next = statements.get(i + 1).map_or(usize::MAX, |next| next.span().start);
+        formatter.trailing_comments(next);
+        formatter.newline();
+    }
+    formatter.comments_on_own_lines(usize::MAX);
+    formatter.out
+}
+
+struct Comment {
+    text: String,
+    start: usize,
+    // Whether only whitespace comes before it on its line.
+    own_line: bool,
+}
+
+fn comments(tokens: &[Token]) -> Vec<Comment> {
+    let mut comments = vec![];
+    let mut own_line = true;
+    for token in tokens {
+        match token.token_type {
+            TokenType::COMMENT => {
+                let text = match token.literal.starts_with("//") {
+                    true => token.literal.trim_end().to_string(),
+                    false => token.literal.clone(),
+                };
+                comments.push(Comment { text, start: token.span.start, own_line });
+                own_line = false;
+            }
+            TokenType::WHITESPACE => own_line |= token.literal.contains('\n'),
+            _ => own_line = false,
+        }
+    }
+    comments
+}
+
+const ASSIGN: u8 = 1;
+const EQUALS: u8 = 2;
+const LESSGREATER: u8 = 3;
+const SUM: u8 = 4;
+const PRODUCT: u8 = 5;
+const POWER: u8 = 6;
+const PREFIX: u8 = 7;
+const CALL: u8 = 8;
+const ATOM: u8 = 9;
+
+struct Formatter<'a> {
+    config: &'a FormatConfig,
+    depth: usize,
+    out: String,
+    unterminated: Option<usize>,
+    // Set while measuring a list on one line, so lists inside it stay on
+    // one line too.
+    flat: bool,
+    comments: Vec<Comment>,
+    // The first comment not yet written.
+    next_comment: usize,
+}
+
+impl Formatter<'_> {
+    // `if` and `while` statements are written without a trailing `;`, but the
+    // parser would read a following `-x`, `(x)` or `"s"` as continuing them.
+    fn format_statement(&mut self, statement: &Rc<dyn Statement>) {
+        let unterminated = self.unterminated.take();
+        let start = self.out.len();
+        self.format_statement_body(statement);
+        if let Some(position) = unterminated {
+            if self.out[start..].starts_with(['-', '(', '"']) {
+                self.out.insert(position, ';');
+            }
+        }
+    }
+
+    fn format_statement_body(&mut self, statement: &Rc<dyn Statement>) {
+        match statement.node_type() {
+            ast::NodeType::LET_STATEMENT => {
+                let stmt = statement.as_any().downcast_ref::<ast::LetStatement>().unwrap();
+                self.out.push_str("let ");
+                self.out.push_str(&stmt.name.value);
+                if let Some(annotation) = &stmt.type_annotation {
+                    self.out.push_str(": ");
+                    self.out.push_str(&annotation.name);
+                }
+                if let Some(value) = &stmt.value {
+                    self.out.push_str(" = ");
+                    self.format_expression(value);
+                }
+                self.out.push(';');
+            },
+            ast::NodeType::RETURN_STATEMENT => {
+                let stmt = statement.as_any().downcast_ref::<ast::ReturnStatement>().unwrap();
+                self.out.push_str("return");
+                if let Some(value) = &stmt.return_value {
+                    self.out.push(' ');
+                    self.format_expression(value);
+                }
+                self.out.push(';');
+            },
+            ast::NodeType::EXPRESSION_STATEMENT => {
+                let stmt = statement.as_any().downcast_ref::<ast::ExpressionStatement>().unwrap();
+                if let Some(expression) = &stmt.expression {
+                    self.format_expression(expression);
+                    if matches!(expression.node_type(), ast::NodeType::IF_EXPRESSION | ast::NodeType::WHILE_EXPRESSION) {
+                        self.unterminated = Some(self.out.len());
+                    } else {
+                        self.out.push(';');
+                    }
+                }
+            },
+            ast::NodeType::BLOCK_STATEMENT => {
+                let block = statement.as_any().downcast_ref::<ast::BlockStatement>().unwrap();
+                if block.statements.is_empty() && !self.has_comment_before(block.end.start) {
+                    self.out.push_str("{}");
+                    return;
+                }
+                if self.config.brace_style == BraceStyle::NextLine {
+                    if self.out.ends_with(' ') {
+                        self.out.pop();
+                    }
+                    self.newline();
+                }
+                self.out.push('{');
+                self.depth += 1;
+                for (i, statement) in block.statements.iter().enumerate() {
+                    self.newline();
+                    self.comments_on_own_lines(statement.span().start);
+                    self.format_statement(statement);
+                    let next = block.statements.get(i + 1).map_or(block.end.start, |next| next.span().start);
+                    self.trailing_comments(next);
+                }
+                while let Some(comment) = self.take_comment_before(block.end.start) {
+                    self.newline();
+                    self.out.push_str(&comment);
+                }
+                self.unterminated = None;
+                self.depth -= 1;
+                self.newline();
+                self.out.push('}');
+            },
+            _ => self.out.push_str(&statement.to_string()),
+        }
+    }
+
+    fn format_expression(&mut self, exp: &Rc<dyn Expression>) {
+        match exp.node_type() {
+            ast::NodeType::PREFIX_EXPRESSION => {
+                let prefix = exp.as_any().downcast_ref::<ast::PrefixExpression>().unwrap();
+                self.out.push_str(&prefix.operator);
+                self.format_operand(&prefix.right, PREFIX);
+            },
+            ast::NodeType::INFIX_EXPRESSION => {
+                let infix = exp.as_any().downcast_ref::<ast::InfixExpression>().unwrap();
+                let precedence = precedence(exp);
+                // ** groups to the right, the other operators to the left.
+                let (left, right) = match precedence {
+                    POWER => (precedence + 1, precedence),
+                    _ => (precedence, precedence + 1),
+                };
+                self.format_operand(&infix.left, left);
+                self.out.push(' ');
+                self.out.push_str(&infix.operator);
+                self.out.push(' ');
+                self.format_operand(&infix.right, right);
+            },
+            ast::NodeType::ASSIGN_EXPRESSION => {
+                let assign = exp.as_any().downcast_ref::<ast::AssignExpression>().unwrap();
+                self.out.push_str(&assign.name.value);
+                self.out.push(' ');
+                self.out.push_str(&assign.operator);
+                self.out.push(' ');
+                self.format_expression(&assign.value);
+            },
+            ast::NodeType::IF_EXPRESSION => {
+                let if_expression = exp.as_any().downcast_ref::<ast::IfExpression>().unwrap();
+                self.out.push_str("if (");
+                self.format_expression(&if_expression.condition);
+                self.out.push_str(") ");
+                self.format_statement(&if_expression.consequence);
+                if let Some(alternative) = &if_expression.alternative {
+                    match self.config.brace_style {
+                        BraceStyle::SameLine => self.out.push(' '),
+                        BraceStyle::NextLine => self.newline(),
+                    }
+                    self.out.push_str("else ");
+                    match else_if(alternative) {
+                        Some(expression) => self.format_expression(expression),
+                        None => self.format_statement(alternative),
+                    }
+                }
+            },
+            ast::NodeType::WHILE_EXPRESSION => {
+                let while_expression = exp.as_any().downcast_ref::<ast::WhileExpression>().unwrap();
+                self.out.push_str("while (");
+                self.format_expression(&while_expression.condition);
+                self.out.push_str(") ");
+                self.format_statement(&while_expression.body);
+            },
+            ast::NodeType::FUNCTION_LITERAL => {
+                let function = exp.as_any().downcast_ref::<ast::FunctionLiteral>().unwrap();
+                let parameters: Vec<String> = function.parameters.iter().zip(&function.parameter_types)
+                    .map(|(p, t)| match t {
+                        Some(annotation) => format!("{}: {}", p.value, annotation.name),
+                        None => p.value.clone(),
+                    })
+                    .collect();
+                self.out.push_str("fn(");
+                self.out.push_str(&parameters.join(", "));
+                self.out.push_str(") ");
+                if let Some(annotation) = &function.return_type {
+                    self.out.push_str("-> ");
+                    self.out.push_str(&annotation.name);
+                    self.out.push(' ');
+                }
+                self.format_statement(&function.body);
+            },
+            ast::NodeType::INTERPOLATED_STRING => {
+                let interpolated = exp.as_any().downcast_ref::<ast::InterpolatedString>().unwrap();
+                self.out.push('"');
+                for part in &interpolated.parts {
+                    match part.as_any().downcast_ref::<ast::StringLiteral>() {
+                        Some(text) => self.out.push_str(&ast::escape_string(&text.value)),
+                        None => {
+                            self.out.push('{');
+                            self.format_expression(part);
+                            self.out.push('}');
+                        }
+                    }
+                }
+                self.out.push('"');
+            },
+            ast::NodeType::MACRO_LITERAL => {
+                let macro_literal = exp.as_any().downcast_ref::<ast::MacroLiteral>().unwrap();
+                let parameters: Vec<&str> = macro_literal.parameters.iter().map(|p| p.value.as_str()).collect();
+                self.out.push_str("macro(");
+                self.out.push_str(&parameters.join(", "));
+                self.out.push_str(") ");
+                self.format_statement(&macro_literal.body);
+            },
+            ast::NodeType::CALL_EXPRESSION => {
+                let call = exp.as_any().downcast_ref::<ast::CallExpression>().unwrap();
+                self.format_operand(&call.function, CALL);
+                self.format_list('(', &call.arguments, ')');
+            },
+            ast::NodeType::ARRAY_LITERAL => {
+                let array = exp.as_any().downcast_ref::<ast::ArrayLiteral>().unwrap();
+                self.format_list('[', &array.elements, ']');
+            },
+            ast::NodeType::INDEX_EXPRESSION => {
+                let index = exp.as_any().downcast_ref::<ast::IndexExpression>().unwrap();
+                self.format_operand(&index.left, CALL);
+                self.out.push('[');
+                self.format_expression(&index.index);
+                self.out.push(']');
+            },
+            ast::NodeType::INTEGER_LITERAL | ast::NodeType::FLOAT_LITERAL => self.out.push_str(&exp.token_literal()),
+            _ => self.out.push_str(&exp.to_string()),
+        }
+    }
+
+    fn format_operand(&mut self, exp: &Rc<dyn Expression>, min_precedence: u8) {
+        if precedence(exp) < min_precedence {
+            self.out.push('(');
+            self.format_expression(exp);
+            self.out.push(')');
+        } else {
+            self.format_expression(exp);
+        }
+    }
+
+    // Writes the list on one line when it fits, and otherwise one item per
+    // line, indented a level further than the brackets. Whether it fits is
+    // judged with every list inside it kept on one line too.
+    fn format_list(&mut self, open: char, items: &[Rc<dyn Expression>], close: char) {
+        if self.flat || items.is_empty() {
+            self.write_list(open, items, close);
+            return;
+        }
+        let start = self.out.len();
+        let next_comment = self.next_comment;
+        self.flat = true;
+        self.write_list(open, items, close);
+        self.flat = false;
+        let spans_lines = self.out[start..].contains('\n');
+        if !spans_lines && self.line_width() <= self.config.max_line_length {
+            return;
+        }
+
+        self.out.truncate(start);
+        self.next_comment = next_comment;
+        if spans_lines {
+            self.write_list(open, items, close);
+            return;
+        }
+        self.out.push(open);
+        self.depth += 1;
+        for (i, item) in items.iter().enumerate() {
+            self.newline();
+            self.format_expression(item);
+            if i + 1 < items.len() {
+                self.out.push(',');
+            }
+        }
+        self.depth -= 1;
+        self.newline();
+        self.out.push(close);
+    }
+
+    fn write_list(&mut self, open: char, items: &[Rc<dyn Expression>], close: char) {
+        self.out.push(open);
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            self.format_expression(item);
+        }
+        self.out.push(close);
+    }
+
+    fn has_comment_before(&self, limit: usize) -> bool {
+        self.comments.get(self.next_comment).is_some_and(|comment| comment.start < limit)
+    }
+
+    fn take_comment_before(&mut self, limit: usize) -> Option<String> {
+        if !self.has_comment_before(limit) {
+            return None;
+        }
+        self.next_comment += 1;
+        Some(self.comments[self.next_comment - 1].text.clone())
+    }
+
+    // Writes each comment before limit on a line of its own, at the start of
+    // a line and leaving the next one started.
+    fn comments_on_own_lines(&mut self, limit: usize) {
+        while let Some(comment) = self.take_comment_before(limit) {
+            self.out.push_str(&comment);
+            self.newline();
+        }
+    }
+
+    // Writes the comments before limit that follow code on their line after
+    // the statement just written.
+    fn trailing_comments(&mut self, limit: usize) {
+        while self.comments.get(self.next_comment).is_some_and(|comment| !comment.own_line) {
+            match self.take_comment_before(limit) {
+                Some(comment) => {
+                    self.out.push(' ');
+                    self.out.push_str(&comment);
+                }
+                None => break,
+            }
+        }
+    }
+
+    // The width of the line being written, with tabs as wide as an indent.
+    fn line_width(&self) -> usize {
+        let line = self.out.rsplit('\n').next().unwrap_or("");
+        line.chars().map(|c| if c == '\t' { self.config.indent_width } else { 1 }).sum()
+    }
+
+    fn newline(&mut self) {
+        self.out.push_str(if self.config.crlf { "\r\n" } else { "\n" });
+        if self.config.use_tabs {
+            self.out.push_str(&"\t".repeat(self.depth));
+        } else {
+            self.out.push_str(&" ".repeat(self.depth * self.config.indent_width));
+        }
+    }
+}
+
+fn precedence(exp: &Rc<dyn Expression>) -> u8 {
+    match exp.node_type() {
+        ast::NodeType::ASSIGN_EXPRESSION => ASSIGN,
+        ast::NodeType::PREFIX_EXPRESSION => PREFIX,
+        ast::NodeType::CALL_EXPRESSION | ast::NodeType::INDEX_EXPRESSION => CALL,
+        ast::NodeType::INFIX_EXPRESSION => {
+            let infix = exp.as_any().downcast_ref::<ast::InfixExpression>().unwrap();
+            match infix.operator.as_str() {
+                "==" | "!=" => EQUALS,
+                "<" | ">" | "<=" | ">=" => LESSGREATER,
+                "+" | "-" => SUM,
+                "**" => POWER,
+                _ => PRODUCT,
+            }
+        },
+        _ => ATOM,
+    }
+}
+
+// The inner if of an else if, which is laid out on the else's line rather
+// than as a statement of its own.
+fn else_if(alternative: &Rc<dyn Statement>) -> Option<&Rc<dyn Expression>> {
+    let statement = alternative.as_any().downcast_ref::<ast::ExpressionStatement>()?;
+    statement.expression.as_ref().filter(|expression| matches!(expression.node_type(), ast::NodeType::IF_EXPRESSION))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ast::Node;
+    use lexer::Lexer;
+    use parser::Parser;
+
+    fn parse(input: &str) -> ast::Program {
+        let lexer = Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        assert!(parser.errors().is_empty(), "parser errors: {:?}", parser.errors());
+        program
+    }
+
+    #[test]
+    fn test_format() {
+        let input = "let add=fn(a,b){let x=5;let y=10;return a+b*x-(y-1);};if(add(1,2)>=3){puts(\"big\\n\")}else{}
+let i=0;while(i<3){i+=1};-(a+b)*!c; (a==b)==c; a==(b==c); x=y=1.50; fn(x){x}(2); [1,xs[i+1]][0]; (-a)[0]; let n;n!=null";
+        let expected = "let add = fn(a, b) {
+    let x = 5;
+    let y = 10;
+    return a + b * x - (y - 1);
+};
+if (add(1, 2) >= 3) {
+    puts(\"big\\n\");
+} else {}
+let i = 0;
+while (i < 3) {
+    i += 1;
+};
+-(a + b) * !c;
+a == b == c;
+a == (b == c);
+x = y = 1.50;
+fn(x) {
+    x;
+}(2);
+[1, xs[i + 1]][0];
+(-a)[0];
+let n;
+n != null;
+";
+        let formatted = format(&parse(input), &FormatConfig::default());
+        assert_eq!(formatted, expected);
+        assert_eq!(parse(&formatted).to_string(), parse(input).to_string());
+        assert_eq!(format(&parse(&formatted), &FormatConfig::default()), formatted);
+    }
+
+    #[test]
+    fn test_format_power() {
+        let input = "(2**3)**2; 2**(3**2); -(a**b); (-a)**b; a*b**c; (a*b)**c;";
+        let formatted = format(&parse(input), &FormatConfig::default());
+        assert_eq!(formatted, "(2 ** 3) ** 2;\n2 ** 3 ** 2;\n-(a ** b);\n-a ** b;\na * b ** c;\n(a * b) ** c;\n");
+        assert_eq!(parse(&formatted).to_string(), parse(input).to_string());
+    }
+
+    #[test]
+    fn test_format_else_if() {
+        let input = "let sign=if(x<0){-1}else if(x==0){0}else if(x<10){1}else{2};-a";
+        let expected = "let sign = if (x < 0) {
+    -1;
+} else if (x == 0) {
+    0;
+} else if (x < 10) {
+    1;
+} else {
+    2;
+};
+-a;
+";
+        let formatted = format(&parse(input), &FormatConfig::default());
+        assert_eq!(formatted, expected);
+        assert_eq!(parse(&formatted).to_string(), parse(input).to_string());
+    }
+
+    #[test]
+    fn test_format_type_annotations() {
+        let input = "let n:int=5;let greet=fn(name:string,times)->string{name};let f:fn;";
+        let expected = "let n: int = 5;
+let greet = fn(name: string, times) -> string {
+    name;
+};
+let f: fn;
+";
+        let formatted = format(&parse(input), &FormatConfig::default());
+        assert_eq!(formatted, expected);
+        assert_eq!(parse(&formatted).to_string(), parse(input).to_string());
+    }
+
+    #[test]
+    fn test_format_interpolated_string() {
+        let input = r#"puts("{a+1} of {f( "x{y}" )}\{\n")"#;
+        let formatted = format(&parse(input), &FormatConfig::default());
+        assert_eq!(formatted, "puts(\"{a + 1} of {f(\"x{y}\")}\\{\\n\");\n");
+        assert_eq!(parse(&formatted).to_string(), parse(input).to_string());
+    }
+
+    #[test]
+    fn test_format_config() {
+        let program = parse("if (x) { while (y) { z } }");
+        let tabs = FormatConfig { use_tabs: true, crlf: true, ..FormatConfig::default() };
+        assert_eq!(format(&program, &tabs), "if (x) {\r\n\twhile (y) {\r\n\t\tz;\r\n\t}\r\n}\r\n");
+        let narrow = FormatConfig { indent_width: 2, ..FormatConfig::default() };
+        assert_eq!(format(&program, &narrow), "if (x) {\n  while (y) {\n    z;\n  }\n}\n");
+
+        let next_line = FormatConfig { brace_style: BraceStyle::NextLine, ..FormatConfig::default() };
+        let program = parse("let f = fn(x) { if (x) { 1 } else { 2 } }; if (y) {} else { 3 }");
+        let formatted = format(&program, &next_line);
+        assert_eq!(formatted, "let f = fn(x)\n{\n    if (x)\n    {\n        1;\n    }\n    else\n    {\n        2;\n    }\n};\nif (y) {}\nelse\n{\n    3;\n}\n");
+        assert_eq!(parse(&formatted).to_string(), program.to_string());
+    }
+
+    #[test]
+    fn test_format_max_line_length() {
+        let config = FormatConfig { max_line_length: 30, ..FormatConfig::default() };
+        let program = parse("f(1, 2); let xs = [alpha, beta, gamma, delta]; outer(inner(first, second), [third, fourth])");
+        let formatted = format(&program, &config);
+        let expected = "f(1, 2);
+let xs = [
+    alpha,
+    beta,
+    gamma,
+    delta
+];
+outer(
+    inner(first, second),
+    [third, fourth]
+);
+";
+        assert_eq!(formatted, expected);
+        assert_eq!(parse(&formatted).to_string(), program.to_string());
+        assert_eq!(format(&parse(&formatted), &config), formatted);
+    }
+
+    #[test]
+    fn test_format_config_from_toml() {
+        let config = FormatConfig::from_toml("indent_width = 2\nuse_tabs = true\nmax_line_length = 80\nbrace_style = \"next_line\"\n").unwrap();
+        assert_eq!(config, FormatConfig { indent_width: 2, use_tabs: true, max_line_length: 80, brace_style: BraceStyle::NextLine, ..FormatConfig::default() });
+        assert_eq!(FormatConfig::from_toml("").unwrap(), FormatConfig::default());
+
+        assert_eq!(FormatConfig::from_toml("indent = 2").unwrap_err(), "unknown key indent");
+        assert_eq!(FormatConfig::from_toml("indent_width = 0").unwrap_err(), "indent_width must be a positive integer, got 0");
+        assert_eq!(FormatConfig::from_toml("crlf = \"yes\"").unwrap_err(), "crlf must be true or false, got \"yes\"");
+        assert_eq!(FormatConfig::from_toml("brace_style = \"k&r\"").unwrap_err(), "brace_style must be \"same_line\" or \"next_line\", got \"k&r\"");
+        assert!(FormatConfig::from_toml("indent_width = ").is_err());
+    }
+
+    #[test]
+    fn test_format_config_discover() {
+        let dir = std::env::temp_dir().join(format!("monkey_fmt_config_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("src/nested")).unwrap();
+        std::fs::write(dir.join(CONFIG_FILE), "indent_width = 2").unwrap();
+        std::fs::write(dir.join("src/nested").join(CONFIG_FILE), "use_tabs = true").unwrap();
+
+        let found = FormatConfig::discover(&dir.join("src/main.monkey"));
+        let nearest = FormatConfig::discover(&dir.join("src/nested/lib.monkey"));
+        std::fs::write(dir.join(CONFIG_FILE), "indent_width = -1").unwrap();
+        let invalid = FormatConfig::discover(&dir.join("main.monkey"));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert_eq!(found.unwrap().indent_width, 2);
+        assert_eq!(nearest.unwrap(), FormatConfig { use_tabs: true, ..FormatConfig::default() });
+        assert!(invalid.unwrap_err().ends_with("monkeyfmt.toml: indent_width must be a positive integer, got -1"));
+    }
+
+    #[test]
+    fn test_format_comments() {
+        let input = "// header
+/* block */ let x=1; // one
+let f=fn(a){
+  // inside
+  a*2 // double
+  /* last */
+};
+if(x){ // open
+}
+f(x,
+  // between
+  2) // after
+// footer
+";
+        let expected = "// header
+/* block */
+let x = 1; // one
+let f = fn(a) {
+    // inside
+    a * 2; // double
+    /* last */
+};
+if (x) {
+    // open
+}
+f(x, 2);
+// between
+// after
+// footer
+";
+        let format = |input: &str| format_with_comments(&parse(input), &Lexer::tokenize(input, true), &FormatConfig::default());
+        let formatted = format(input);
+        assert_eq!(formatted, expected);
+        assert_eq!(format(&formatted), formatted);
+        assert_eq!(parse(&formatted).to_string(), parse(input).to_string());
+
+        let wide = "g(fn(a) {\n    // kept when the call is split\n    a;\n}, [alpha, beta, gamma, delta, epsilon, zeta, eta, theta, iota, kappa, lambda, mu]);\n";
+        assert!(format(wide).contains("// kept when the call is split"));
+    }
+}
@@ -0,0 +1,126 @@
+use token::{Span, TokenType};
+
+use crate::Lexer;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenClass {
+    Keyword,
+    Identifier,
+    // Numbers, strings, booleans and null.
+    Literal,
+    Operator,
+    // Brackets, commas and semicolons.
+    Punctuation,
+    Comment,
+    // Characters the lexer does not accept, and unterminated strings and
+    // comments.
+    Error,
+}
+
+impl TokenClass {
+    // None for whitespace and the end of input, which are not highlighted.
+    pub fn of(token_type: TokenType) -> Option<TokenClass> {
+        let class = match token_type {
+            TokenType::FUNCTION
+            | TokenType::LET
+            | TokenType::RETURN
+            | TokenType::IF
+            | TokenType::ELSE
+            | TokenType::WHILE
+            | TokenType::MACRO
+            | TokenType::IMPORT => TokenClass::Keyword,
+            TokenType::IDENT => TokenClass::Identifier,
+            TokenType::INT
+            | TokenType::FLOAT
+            | TokenType::STRING
+            | TokenType::TEMPLATE
+            | TokenType::TRUE
+            | TokenType::FALSE
+            | TokenType::NULL => TokenClass::Literal,
+            TokenType::ASSIGN
+            | TokenType::PLUS_ASSIGN
+            | TokenType::MINUS_ASSIGN
+            | TokenType::ASTERISK_ASSIGN
+            | TokenType::SLASH_ASSIGN
+            | TokenType::PLUS
+            | TokenType::MINUS
+            | TokenType::ASTERISK
+            | TokenType::POWER
+            | TokenType::SLASH
+            | TokenType::BANG
+            | TokenType::MODULO
+            | TokenType::LT
+            | TokenType::RT
+            | TokenType::LT_EQ
+            | TokenType::GT_EQ
+            | TokenType::EQ
+            | TokenType::NOT_EQ => TokenClass::Operator,
+            TokenType::COMMA
+            | TokenType::SEMICOLON
+            | TokenType::COLON
+            | TokenType::ARROW
+            | TokenType::LPAREN
+            | TokenType::RPAREN
+            | TokenType::LBRACE
+            | TokenType::RBRACE
+            | TokenType::LBRACKET
+            | TokenType::RBRACKET
+            | TokenType::DOUBLE_QUOTE => TokenClass::Punctuation,
+            TokenType::COMMENT => TokenClass::Comment,
+            TokenType::ILLEGAL => TokenClass::Error,
+            TokenType::WHITESPACE | TokenType::EOF => return None,
+        };
+        Some(class)
+    }
+}
+
+// Classifies every token of source, comments included, in order. Spans are
+// byte offsets into source. Strings with interpolations are one literal;
+// the expressions inside them are not classified.
+pub fn highlight(source: &str) -> Vec<(Span, TokenClass)> {
+    Lexer::tokenize(source, true)
+        .into_iter()
+        .filter_map(|tok| TokenClass::of(tok.token_type).map(|class| (tok.span, class)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_highlight() {
+        let source = "let é = fn(x) { x >= 1.5 }; // done\n\"a{é}\" @ true";
+        let classes: Vec<(&str, TokenClass)> = highlight(source)
+            .into_iter()
+            .map(|(span, class)| (&source[span.start..span.end], class))
+            .collect();
+        assert_eq!(classes, vec![
+            ("let", TokenClass::Keyword),
+            ("é", TokenClass::Identifier),
+            ("=", TokenClass::Operator),
+            ("fn", TokenClass::Keyword),
+            ("(", TokenClass::Punctuation),
+            ("x", TokenClass::Identifier),
+            (")", TokenClass::Punctuation),
+            ("{", TokenClass::Punctuation),
+            ("x", TokenClass::Identifier),
+            (">=", TokenClass::Operator),
+            ("1.5", TokenClass::Literal),
+            ("}", TokenClass::Punctuation),
+            (";", TokenClass::Punctuation),
+            ("// done", TokenClass::Comment),
+            ("\"a{é}\"", TokenClass::Literal),
+            ("@", TokenClass::Error),
+            ("true", TokenClass::Literal),
+        ]);
+    }
+
+    #[test]
+    fn test_highlight_unterminated() {
+        let source = "x /* open";
+        let spans = highlight(source);
+        assert_eq!(spans[1], (Span { start: 2, end: 9 }, TokenClass::Error));
+        assert_eq!(highlight("\"abc"), vec![(Span { start: 0, end: 4 }, TokenClass::Error)]);
+    }
+}
@@ -0,0 +1,132 @@
+use std::io::{self, Read};
+
+const CHUNK: usize = 8 * 1024;
+
+// The characters a lexer reads, indexed from the start of the whole input.
+// Input from a reader is read and decoded only as the lexer reaches it, and
+// the characters before the token being read are dropped once enough of
+// them pile up, so a source need not fit in memory.
+pub(crate) struct Input {
+    chars: Vec<char>,
+    // The index of chars[0] in the whole input.
+    start: usize,
+    reader: Option<Box<dyn Read>>,
+    // The start of a character split between two reads.
+    undecoded: Vec<u8>,
+    // Reading stops at the first error, which the lexer reports in place
+    // of the end of input.
+    error: Option<io::Error>,
+}
+
+impl Input {
+    pub(crate) fn from_str(input: &str) -> Input {
+        Input {
+            chars: input.chars().collect(),
+            start: 0,
+            reader: None,
+            undecoded: vec![],
+            error: None,
+        }
+    }
+
+    pub(crate) fn from_reader(reader: Box<dyn Read>) -> Input {
+        Input {
+            chars: vec![],
+            start: 0,
+            reader: Some(reader),
+            undecoded: vec![],
+            error: None,
+        }
+    }
+
+    // None past the end of input. Characters already dropped are gone too.
+    pub(crate) fn get(&mut self, index: usize) -> Option<char> {
+        while index >= self.start + self.chars.len() {
+            if !self.fill() {
+                return None;
+            }
+        }
+        self.chars.get(index.checked_sub(self.start)?).copied()
+    }
+
+    // The characters from `from` up to `to`, as far as they have been read.
+    pub(crate) fn slice(&self, from: usize, to: usize) -> String {
+        let end = to.saturating_sub(self.start).min(self.chars.len());
+        let start = from.saturating_sub(self.start).min(end);
+        self.chars[start..end].iter().collect()
+    }
+
+    // Lets go of the characters before index. They are dropped in bulk, and
+    // only once they make up half the buffer, so the ones kept are not moved
+    // again and again.
+    pub(crate) fn discard_before(&mut self, index: usize) {
+        let done = index.saturating_sub(self.start).min(self.chars.len());
+        if done >= CHUNK && done * 2 >= self.chars.len() {
+            self.chars.drain(..done);
+            self.start += done;
+        }
+    }
+
+    pub(crate) fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    // Reads and decodes the next chunk. Returns false at the end of input.
+    fn fill(&mut self) -> bool {
+        let Some(reader) = self.reader.as_mut() else {
+            return false;
+        };
+        let mut buffer = [0; CHUNK];
+        let read = loop {
+            match reader.read(&mut buffer) {
+                Ok(read) => break read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    self.error = Some(err);
+                    break 0;
+                }
+            }
+        };
+        if read == 0 {
+            self.reader = None;
+            if self.undecoded.is_empty() {
+                return false;
+            }
+            self.undecoded.clear();
+            self.chars.push(char::REPLACEMENT_CHARACTER);
+            return true;
+        }
+        self.undecoded.extend_from_slice(&buffer[..read]);
+        self.decode();
+        true
+    }
+
+    // Invalid UTF-8 is read as U+FFFD, as String::from_utf8_lossy reads it.
+    fn decode(&mut self) {
+        let mut rest = &self.undecoded[..];
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(text) => {
+                    self.chars.extend(text.chars());
+                    rest = &[];
+                    break;
+                }
+                Err(err) => {
+                    let (valid, after) = rest.split_at(err.valid_up_to());
+                    self.chars.extend(std::str::from_utf8(valid).unwrap().chars());
+                    match err.error_len() {
+                        Some(len) => {
+                            self.chars.push(char::REPLACEMENT_CHARACTER);
+                            rest = &after[len..];
+                        }
+                        None => {
+                            rest = after;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        self.undecoded = rest.to_vec();
+    }
+}
@@ -1,40 +1,200 @@
-use token::{Token, TokenType};
+use std::collections::VecDeque;
+use std::io::Read;
+
+use token::{FileId, SourceMap, Span, Token, TokenType};
+
+mod highlight;
+mod input;
+
+pub use highlight::{highlight, TokenClass};
+
+use input::Input;
+
+#[derive(Debug, PartialEq)]
+pub enum Segment {
+    Text(String),
+    Expression(String),
+}
+
+// Splits the body of a TEMPLATE token into its text and `{...}` parts.
+pub fn split_template(raw: &str) -> Vec<Segment> {
+    Lexer::new(raw).read_template()
+}
 
 
 pub struct Lexer {
-    input: String,
+    // Indexed by character, so positions and columns count characters.
+    input: Input,
     position: usize,
     read_position: usize,
-    ch: char, 
+    // The byte offset of position in the source, for token spans.
+    offset: usize,
+    ch: char,
+    line: usize,
+    column: usize,
+    file: Option<FileId>,
+    // Whether whitespace and comments come out as WHITESPACE and COMMENT
+    // tokens instead of being skipped.
+    trivia: bool,
+    // Tokens already read by peek_nth, next one first.
+    lookahead: VecDeque<Token>,
 }
 
 impl Lexer {
     pub fn new(input: &str) -> Lexer {
+        Lexer::with_input(Input::from_str(input))
+    }
+
+    // Reads the source from reader as tokens are asked for, so it can be a
+    // pipe, or a file too large to load. An error reading it is reported as
+    // an ILLEGAL token where the input ends.
+    pub fn from_reader<R: Read + 'static>(reader: R) -> Lexer {
+        Lexer::with_input(Input::from_reader(Box::new(reader)))
+    }
+
+    fn with_input(input: Input) -> Lexer {
         let mut l = Lexer {
-            input: input.to_string(),
+            input,
             position: 0,
             read_position: 0,
+            offset: 0,
             ch: '\0',
+            line: 1,
+            column: 0,
+            file: None,
+            trivia: false,
+            lookahead: VecDeque::new(),
         };
         l.read_char();
         l
     }
 
+    // Tokens are tagged with file, so diagnostics about them can be
+    // rendered against its source.
+    pub fn with_file(input: &str, file: FileId) -> Lexer {
+        let mut l = Lexer::new(input);
+        l.file = Some(file);
+        l
+    }
+
+    // Replays the token stream of a file registered in sources.
+    pub fn for_file(sources: &SourceMap, file: FileId) -> Option<Lexer> {
+        sources.get(file).map(|source| Lexer::with_file(&source.contents, file))
+    }
+
+    // All tokens of input up to, but not including, EOF.
+    pub fn tokenize(input: &str, trivia: bool) -> Vec<Token> {
+        let mut l = Lexer::new(input);
+        l.trivia = trivia;
+        l.collect()
+    }
+
+    pub fn set_trivia(&mut self, trivia: bool) {
+        self.trivia = trivia;
+    }
+
+    // Tags the tokens read from here on with file, as with_file does.
+    pub fn set_file(&mut self, file: FileId) {
+        self.file = Some(file);
+    }
+
+    // Looks n tokens past the next one without consuming anything;
+    // peek_nth(0) is the token next_token returns next.
+    pub fn peek_nth(&mut self, n: usize) -> &Token {
+        while self.lookahead.len() <= n {
+            let tok = self.lex_token();
+            self.lookahead.push_back(tok);
+        }
+        &self.lookahead[n]
+    }
+
     fn read_char(&mut self) {
-        if self.read_position >= self.input.len() {
-            self.ch = '\0';
+        if self.ch == '\n' {
+            self.line += 1;
+            self.column = 1;
         } else {
-            self.ch = self.input.chars().nth(self.read_position).unwrap();
+            self.column += 1;
         }
+        if self.read_position > self.position {
+            self.offset += self.input.get(self.position).map_or(0, |c| c.len_utf8());
+        }
+        self.ch = self.input.get(self.read_position).unwrap_or('\0');
         self.position = self.read_position;
         self.read_position += 1;
     }
 
     pub fn next_token(&mut self) -> Token {
-        self.skip_whitespace();
+        match self.lookahead.pop_front() {
+            Some(tok) => tok,
+            None => self.lex_token(),
+        }
+    }
 
+    fn lex_token(&mut self) -> Token {
+        self.input.discard_before(self.position);
+        let (line, column, start) = (self.line, self.column, self.offset);
+        if self.trivia {
+            if let Some(tok) = self.read_trivia() {
+                return self.locate(tok, line, column, start);
+            }
+        } else {
+            self.skip_whitespace();
+            while self.ch == '/' && (self.peek_char() == '/' || self.peek_char() == '*') {
+                let (line, column, start) = (self.line, self.column, self.offset);
+                if self.peek_char() == '/' {
+                    self.skip_line_comment();
+                } else if !self.skip_block_comment() {
+                    let tok = Token::new(TokenType::ILLEGAL, "unterminated comment".to_string());
+                    return self.locate(tok, line, column, start);
+                }
+                self.skip_whitespace();
+            }
+        }
+
+        let (line, column, start) = (self.line, self.column, self.offset);
+        if self.ch == '\0' {
+            if let Some(err) = self.input.take_error() {
+                let tok = Token::new(TokenType::ILLEGAL, format!("could not read input: {}", err));
+                return self.locate(tok, line, column, start);
+            }
+        }
+        let tok = self.read_token();
+        self.locate(tok, line, column, start)
+    }
+
+    // The token ends where the lexer now stands.
+    fn locate(&self, mut tok: Token, line: usize, column: usize, start: usize) -> Token {
+        tok.line = line;
+        tok.column = column;
+        tok.span = Span { start, end: self.offset };
+        tok.file = self.file;
+        tok
+    }
+
+    // A run of whitespace or one comment, holding the text as written.
+    fn read_trivia(&mut self) -> Option<Token> {
+        let start = self.position;
+        let token_type = if self.ch.is_whitespace() {
+            self.skip_whitespace();
+            TokenType::WHITESPACE
+        } else if self.ch == '/' && self.peek_char() == '/' {
+            self.skip_line_comment();
+            TokenType::COMMENT
+        } else if self.ch == '/' && self.peek_char() == '*' {
+            if !self.skip_block_comment() {
+                return Some(Token::new(TokenType::ILLEGAL, "unterminated comment".to_string()));
+            }
+            TokenType::COMMENT
+        } else {
+            return None;
+        };
+        Some(Token::new(token_type, self.slice(start)))
+    }
+
+    fn read_token(&mut self) -> Token {
         let tok = match self.ch {
             ';' => Token::new(TokenType::SEMICOLON, self.ch.to_string()),
+            ':' => Token::new(TokenType::COLON, self.ch.to_string()),
             '=' => {
                 if self.peek_char() == '=' {
                     self.read_char();
@@ -43,12 +203,64 @@ impl Lexer {
                 }
                 Token::new(TokenType::ASSIGN, self.ch.to_string())
             },
-            '+' => Token::new(TokenType::PLUS, self.ch.to_string()),   
-            '-' => Token::new(TokenType::MINUS, self.ch.to_string()),   
-            '*' => Token::new(TokenType::ASTERISK, self.ch.to_string()),   
-            '/' => Token::new(TokenType::SLASH, self.ch.to_string()),   
-            '<' => Token::new(TokenType::LT, self.ch.to_string()),   
-            '>' => Token::new(TokenType::RT, self.ch.to_string()),   
+            '+' => {
+                if self.peek_char() == '=' {
+                    self.read_char();
+                    self.read_char();
+                    return Token::new(TokenType::PLUS_ASSIGN, "+=".to_string());
+                }
+                Token::new(TokenType::PLUS, self.ch.to_string())
+            },
+            '-' => {
+                if self.peek_char() == '=' {
+                    self.read_char();
+                    self.read_char();
+                    return Token::new(TokenType::MINUS_ASSIGN, "-=".to_string());
+                }
+                if self.peek_char() == '>' {
+                    self.read_char();
+                    self.read_char();
+                    return Token::new(TokenType::ARROW, "->".to_string());
+                }
+                Token::new(TokenType::MINUS, self.ch.to_string())
+            },
+            '*' => {
+                if self.peek_char() == '=' {
+                    self.read_char();
+                    self.read_char();
+                    return Token::new(TokenType::ASTERISK_ASSIGN, "*=".to_string());
+                }
+                if self.peek_char() == '*' {
+                    self.read_char();
+                    self.read_char();
+                    return Token::new(TokenType::POWER, "**".to_string());
+                }
+                Token::new(TokenType::ASTERISK, self.ch.to_string())
+            },
+            '/' => {
+                if self.peek_char() == '=' {
+                    self.read_char();
+                    self.read_char();
+                    return Token::new(TokenType::SLASH_ASSIGN, "/=".to_string());
+                }
+                Token::new(TokenType::SLASH, self.ch.to_string())
+            },
+            '<' => {
+                if self.peek_char() == '=' {
+                    self.read_char();
+                    self.read_char();
+                    return Token::new(TokenType::LT_EQ, "<=".to_string());
+                }
+                Token::new(TokenType::LT, self.ch.to_string())
+            },
+            '>' => {
+                if self.peek_char() == '=' {
+                    self.read_char();
+                    self.read_char();
+                    return Token::new(TokenType::GT_EQ, ">=".to_string());
+                }
+                Token::new(TokenType::RT, self.ch.to_string())
+            },
             '!' => {
                 if self.peek_char() == '=' {
                     self.read_char();
@@ -61,17 +273,19 @@ impl Lexer {
             ')' => Token::new(TokenType::RPAREN, self.ch.to_string()),   
             '{' => Token::new(TokenType::LBRACE, self.ch.to_string()),   
             '}' => Token::new(TokenType::RBRACE, self.ch.to_string()),   
+            '[' => Token::new(TokenType::LBRACKET, self.ch.to_string()),
+            ']' => Token::new(TokenType::RBRACKET, self.ch.to_string()),
             ',' => Token::new(TokenType::COMMA, self.ch.to_string()),   
             '%' => Token::new(TokenType::MODULO, self.ch.to_string()),
             '\0' => Token::new(TokenType::EOF, self.ch.to_string()),
             _ => {
-                if self.ch.is_alphabetic() {
+                if is_letter(self.ch) {
                     let mut tok = self.read_identifier();
                     if token::lookup_ident(&tok.literal).to_string() != TokenType::IDENT.to_string() {
                         tok.token_type = token::lookup_ident(&tok.literal);
                     }
                     tok
-                } else if self.ch.is_digit(10) {
+                } else if self.ch.is_ascii_digit() {
                     self.read_number()
                 } else if self.ch == '"' {
                     self.read_string()  
@@ -87,7 +301,7 @@ impl Lexer {
 
     fn read_identifier(&mut self) -> Token {
         let mut ident = String::new();
-        while self.ch.is_alphabetic() {
+        while is_letter(self.ch) || self.ch.is_numeric() {
             ident.push(self.ch);
             self.read_char();
         }
@@ -98,32 +312,119 @@ impl Lexer {
 
     fn read_number(&mut self) -> Token {
         let mut number = String::new();
-        while self.ch.is_digit(10) {
+        let mut token_type = TokenType::INT;
+        while self.ch.is_ascii_digit() || (self.ch == '.' && token_type == TokenType::INT && self.peek_char().is_ascii_digit()) {
+            if self.ch == '.' {
+                token_type = TokenType::FLOAT;
+            }
             number.push(self.ch);
             self.read_char();
         }
         self.revert_char();
 
-        Token::new(TokenType::INT, number)
+        Token::new(token_type, number)
     }
 
+    // A string without interpolations becomes a STRING token holding its
+    // unescaped value; otherwise a TEMPLATE token holds the body as written,
+    // for the parser to split with split_template.
     fn read_string(&mut self) -> Token {
         self.read_char();
-        let mut str = String::new();
-        while self.ch != '"' {
-            str.push(self.ch);
-            self.read_char();
+        let start = self.position;
+        let (mut segments, open_brace) = self.read_segments();
+        if open_brace {
+            let message = "unterminated interpolation in string: expected }, or \\{ for a literal brace";
+            return Token::new(TokenType::ILLEGAL, message.to_string());
+        }
+        if self.ch != '"' {
+            return Token::new(TokenType::ILLEGAL, "unterminated string".to_string());
+        }
+
+        match segments.as_mut_slice() {
+            [Segment::Text(text)] => Token::new(TokenType::STRING, std::mem::take(text)),
+            _ => Token::new(TokenType::TEMPLATE, self.slice(start)),
         }
+    }
 
-        Token::new(TokenType::STRING, str)
+    fn read_template(&mut self) -> Vec<Segment> {
+        self.read_segments().0
     }
 
-    fn peek_char(&self) -> char {
-        if self.read_position >= self.input.len() {
-            '\0'
-        } else {
-            self.input.chars().nth(self.read_position).unwrap()
+    // Reads up to the closing quote, or the end of input, which the caller
+    // reports as an unterminated string. The flag is set when the input ends
+    // inside a `{...}` interpolation.
+    fn read_segments(&mut self) -> (Vec<Segment>, bool) {
+        let mut segments = vec![];
+        let mut text = String::new();
+        while self.ch != '"' && self.ch != '\0' {
+            match self.ch {
+                '\\' => {
+                    self.read_char();
+                    match self.ch {
+                        'n' => text.push('\n'),
+                        't' => text.push('\t'),
+                        'r' => text.push('\r'),
+                        '"' => text.push('"'),
+                        '\\' => text.push('\\'),
+                        '{' => text.push('{'),
+                        '\0' => break,
+                        _ => {
+                            text.push('\\');
+                            text.push(self.ch);
+                        }
+                    }
+                }
+                '{' => {
+                    if !text.is_empty() {
+                        segments.push(Segment::Text(std::mem::take(&mut text)));
+                    }
+                    self.read_char();
+                    let start = self.position;
+                    self.skip_interpolation();
+                    segments.push(Segment::Expression(self.slice(start)));
+                    if self.ch == '\0' {
+                        return (segments, true);
+                    }
+                }
+                _ => text.push(self.ch),
+            }
+            self.read_char();
         }
+        if !text.is_empty() || segments.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+        (segments, false)
+    }
+
+    // Stops on the `}` closing the interpolation, skipping over nested
+    // braces and string literals inside it.
+    fn skip_interpolation(&mut self) {
+        let mut depth = 0;
+        while self.ch != '\0' {
+            match self.ch {
+                '{' => depth += 1,
+                '}' if depth == 0 => return,
+                '}' => depth -= 1,
+                '"' => {
+                    self.read_char();
+                    self.read_template();
+                    if self.ch == '\0' {
+                        return;
+                    }
+                }
+                _ => {}
+            }
+            self.read_char();
+        }
+    }
+
+    // The input from start up to the current character.
+    fn slice(&self, start: usize) -> String {
+        self.input.slice(start, self.position)
+    }
+
+    fn peek_char(&mut self) -> char {
+        self.input.get(self.read_position).unwrap_or('\0')
     }
 
     fn skip_whitespace(&mut self) {
@@ -132,12 +433,59 @@ impl Lexer {
         }
     }
 
+    fn skip_line_comment(&mut self) {
+        while self.ch != '\n' && self.ch != '\0' {
+            self.read_char();
+        }
+    }
+
+    fn skip_block_comment(&mut self) -> bool {
+        self.read_char();
+        self.read_char();
+        let mut depth = 1;
+        loop {
+            if self.ch == '\0' {
+                return false;
+            }
+            if self.ch == '/' && self.peek_char() == '*' {
+                self.read_char();
+                depth += 1;
+            } else if self.ch == '*' && self.peek_char() == '/' {
+                self.read_char();
+                depth -= 1;
+                if depth == 0 {
+                    self.read_char();
+                    return true;
+                }
+            }
+            self.read_char();
+        }
+    }
+
     fn revert_char(&mut self) {
         self.read_position = self.position;
         self.position -= 1;
+        self.column -= 1;
+        self.ch = self.input.get(self.position).unwrap_or('\0');
+        self.offset -= self.ch.len_utf8();
     }
 }
 
+// Yields tokens up to, but not including, EOF.
+impl Iterator for Lexer {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        let tok = self.next_token();
+        (tok.token_type != TokenType::EOF).then_some(tok)
+    }
+}
+
+// Identifiers start with a letter or underscore and may continue with
+// digits too, in any script.
+fn is_letter(ch: char) -> bool {
+    ch.is_alphabetic() || ch == '_'
+}
 
 #[cfg(test)]
 mod tests {
@@ -152,8 +500,8 @@ mod tests {
         let ten = 5 + 5;
         let zero = 5 - 5;
         let ten = 5 * 2;
-        */=!;
-        == != < > ;
+        */ =!;
+        == != < > <= >= ;
 
         fn(x, y) {
             x + y;
@@ -166,6 +514,9 @@ mod tests {
         }
 
         let s = \"hello world\";
+        x = 1; x += 2; x -= 3; x *= 4; x /= 5;
+        [1, 2];
+        3.14 10.5.
                         ";
         let mut lexer = Lexer::new(input);
 
@@ -215,6 +566,8 @@ mod tests {
             Token::new(TokenType::NOT_EQ, "!=".to_string()),
             Token::new(TokenType::LT, "<".to_string()),
             Token::new(TokenType::RT, ">".to_string()),
+            Token::new(TokenType::LT_EQ, "<=".to_string()),
+            Token::new(TokenType::GT_EQ, ">=".to_string()),
             Token::new(TokenType::SEMICOLON, ";".to_string()),
             Token::new(TokenType::FUNCTION, "fn".to_string()),
             Token::new(TokenType::LPAREN, "(".to_string()),
@@ -251,6 +604,35 @@ mod tests {
             Token::new(TokenType::ASSIGN, "=".to_string()),
             Token::new(TokenType::STRING, "hello world".to_string()),
             Token::new(TokenType::SEMICOLON, ";".to_string()),
+            Token::new(TokenType::IDENT, "x".to_string()),
+            Token::new(TokenType::ASSIGN, "=".to_string()),
+            Token::new(TokenType::INT, "1".to_string()),
+            Token::new(TokenType::SEMICOLON, ";".to_string()),
+            Token::new(TokenType::IDENT, "x".to_string()),
+            Token::new(TokenType::PLUS_ASSIGN, "+=".to_string()),
+            Token::new(TokenType::INT, "2".to_string()),
+            Token::new(TokenType::SEMICOLON, ";".to_string()),
+            Token::new(TokenType::IDENT, "x".to_string()),
+            Token::new(TokenType::MINUS_ASSIGN, "-=".to_string()),
+            Token::new(TokenType::INT, "3".to_string()),
+            Token::new(TokenType::SEMICOLON, ";".to_string()),
+            Token::new(TokenType::IDENT, "x".to_string()),
+            Token::new(TokenType::ASTERISK_ASSIGN, "*=".to_string()),
+            Token::new(TokenType::INT, "4".to_string()),
+            Token::new(TokenType::SEMICOLON, ";".to_string()),
+            Token::new(TokenType::IDENT, "x".to_string()),
+            Token::new(TokenType::SLASH_ASSIGN, "/=".to_string()),
+            Token::new(TokenType::INT, "5".to_string()),
+            Token::new(TokenType::SEMICOLON, ";".to_string()),
+            Token::new(TokenType::LBRACKET, "[".to_string()),
+            Token::new(TokenType::INT, "1".to_string()),
+            Token::new(TokenType::COMMA, ",".to_string()),
+            Token::new(TokenType::INT, "2".to_string()),
+            Token::new(TokenType::RBRACKET, "]".to_string()),
+            Token::new(TokenType::SEMICOLON, ";".to_string()),
+            Token::new(TokenType::FLOAT, "3.14".to_string()),
+            Token::new(TokenType::FLOAT, "10.5".to_string()),
+            Token::new(TokenType::ILLEGAL, ".".to_string()),
             Token::new(TokenType::EOF, '\0'.to_string()),
         ];
 
@@ -261,4 +643,326 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_comments() {
+        let input = "
+        // a line comment
+        let x = 5; // trailing
+        /* a block
+           /* nested */ still a comment */
+        x / 2 /**/ * 3
+        // last line without newline";
+        let mut lexer = Lexer::new(input);
+        let expected = vec![
+            (TokenType::LET, "let"),
+            (TokenType::IDENT, "x"),
+            (TokenType::ASSIGN, "="),
+            (TokenType::INT, "5"),
+            (TokenType::SEMICOLON, ";"),
+            (TokenType::IDENT, "x"),
+            (TokenType::SLASH, "/"),
+            (TokenType::INT, "2"),
+            (TokenType::ASTERISK, "*"),
+            (TokenType::INT, "3"),
+            (TokenType::EOF, "\0"),
+        ];
+        for (token_type, literal) in expected {
+            let tok = lexer.next_token();
+            assert_eq!(tok.token_type, token_type);
+            assert_eq!(tok.literal, literal);
+        }
+    }
+
+    #[test]
+    fn test_power_operator() {
+        let mut lexer = Lexer::new("2 ** 3 * 4 *= 5 **");
+        let expected = vec![
+            (TokenType::INT, "2"),
+            (TokenType::POWER, "**"),
+            (TokenType::INT, "3"),
+            (TokenType::ASTERISK, "*"),
+            (TokenType::INT, "4"),
+            (TokenType::ASTERISK_ASSIGN, "*="),
+            (TokenType::INT, "5"),
+            (TokenType::POWER, "**"),
+            (TokenType::EOF, "\0"),
+        ];
+        for (token_type, literal) in expected {
+            let tok = lexer.next_token();
+            assert_eq!(tok.token_type, token_type);
+            assert_eq!(tok.literal, literal);
+        }
+    }
+
+    #[test]
+    fn test_type_annotation_tokens() {
+        let mut lexer = Lexer::new("x: int) -> - >");
+        let expected = vec![
+            (TokenType::IDENT, "x"),
+            (TokenType::COLON, ":"),
+            (TokenType::IDENT, "int"),
+            (TokenType::RPAREN, ")"),
+            (TokenType::ARROW, "->"),
+            (TokenType::MINUS, "-"),
+            (TokenType::RT, ">"),
+            (TokenType::EOF, "\0"),
+        ];
+        for (token_type, literal) in expected {
+            let tok = lexer.next_token();
+            assert_eq!(tok.token_type, token_type);
+            assert_eq!(tok.literal, literal);
+        }
+    }
+
+    #[test]
+    fn test_unterminated_block_comment() {
+        let mut lexer = Lexer::new("1 /* open /* nested */");
+        assert_eq!(lexer.next_token().token_type, TokenType::INT);
+        let tok = lexer.next_token();
+        assert_eq!(tok.token_type, TokenType::ILLEGAL);
+        assert_eq!(tok.literal, "unterminated comment");
+        assert_eq!(lexer.next_token().token_type, TokenType::EOF);
+    }
+
+    #[test]
+    fn test_token_positions() {
+        let input = "let x = 10;\n  x == \"a\"\n/* c */ y";
+        let mut lexer = Lexer::new(input);
+        let expected = vec![
+            ("let", 1, 1),
+            ("x", 1, 5),
+            ("=", 1, 7),
+            ("10", 1, 9),
+            (";", 1, 11),
+            ("x", 2, 3),
+            ("==", 2, 5),
+            ("a", 2, 8),
+            ("y", 3, 9),
+        ];
+        for (literal, line, column) in expected {
+            let tok = lexer.next_token();
+            assert_eq!(tok.literal, literal);
+            assert_eq!((tok.line, tok.column), (line, column), "position of {}", literal);
+        }
+    }
+
+    #[test]
+    fn test_token_spans() {
+        let input = "let s = \"é\\n\";\n  s2 /* c */ >= 10";
+        let texts: Vec<&str> = Lexer::new(input).map(|tok| &input[tok.span.start..tok.span.end]).collect();
+        assert_eq!(texts, vec!["let", "s", "=", "\"é\\n\"", ";", "s2", ">=", "10"]);
+        let mut lexer = Lexer::new(input);
+        while lexer.next_token().token_type != TokenType::EOF {}
+        assert_eq!(lexer.next_token().span, Span { start: input.len(), end: input.len() });
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        let mut lexer = Lexer::new(r#""a\nb\tc\"d\\e\q""#);
+        let tok = lexer.next_token();
+        assert_eq!(tok.token_type, TokenType::STRING);
+        assert_eq!(tok.literal, "a\nb\tc\"d\\e\\q");
+        assert_eq!(lexer.next_token().token_type, TokenType::EOF);
+    }
+
+    #[test]
+    fn test_multibyte_characters() {
+        let mut lexer = Lexer::new("\"héllo wörld\" ü");
+        assert_eq!(lexer.next_token().literal, "héllo wörld");
+        let tok = lexer.next_token();
+        assert_eq!((tok.token_type, tok.literal.as_str()), (TokenType::IDENT, "ü"));
+        assert_eq!(lexer.next_token().token_type, TokenType::EOF);
+
+        let tokens: Vec<(TokenType, String, usize)> = Lexer::new("let größe2 = \"日本\" + 名前_1;").map(|tok| (tok.token_type, tok.literal, tok.column)).collect();
+        assert_eq!(tokens, vec![
+            (TokenType::LET, "let".to_string(), 1),
+            (TokenType::IDENT, "größe2".to_string(), 5),
+            (TokenType::ASSIGN, "=".to_string(), 12),
+            (TokenType::STRING, "日本".to_string(), 14),
+            (TokenType::PLUS, "+".to_string(), 19),
+            (TokenType::IDENT, "名前_1".to_string(), 21),
+            (TokenType::SEMICOLON, ";".to_string(), 25),
+        ]);
+        let tokens: Vec<String> = Lexer::new("1x x1").map(|tok| tok.literal).collect();
+        assert_eq!(tokens, vec!["1", "x", "x1"]);
+    }
+
+    #[test]
+    fn test_long_input() {
+        let input = "let émoji_ß = \"ü\";\n".repeat(20_000);
+        assert_eq!(Lexer::new(&input).count(), 100_000);
+    }
+
+    // Gives out its bytes a few at a time, splitting characters between
+    // reads, then fails if asked to.
+    struct Trickle {
+        bytes: Vec<u8>,
+        read: usize,
+        fail: bool,
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.read == self.bytes.len() && self.fail {
+                return Err(std::io::Error::other("disk on fire"));
+            }
+            let n = buf.len().min(3).min(self.bytes.len() - self.read);
+            buf[..n].copy_from_slice(&self.bytes[self.read..self.read + n]);
+            self.read += n;
+            Ok(n)
+        }
+    }
+
+    fn trickle(bytes: &[u8], fail: bool) -> Lexer {
+        Lexer::from_reader(Trickle { bytes: bytes.to_vec(), read: 0, fail })
+    }
+
+    #[test]
+    fn test_lexing_from_reader() {
+        let tokens = |lexer: Lexer| lexer.map(|tok| (tok.token_type, tok.literal, tok.line, tok.column, tok.span)).collect::<Vec<_>>();
+        let input = "let größe = \"日本 {x}\" + 名前; /* ü */ x -> 1.5\n// é";
+        assert_eq!(tokens(trickle(input.as_bytes(), false)), tokens(Lexer::new(input)));
+
+        // Long enough that characters are dropped as the lexer moves on.
+        let input = "let émoji_ß = \"ü\"; // ü\n".repeat(5_000);
+        let (mut streamed, mut loaded) = (trickle(input.as_bytes(), false), Lexer::new(&input));
+        streamed.set_trivia(true);
+        loaded.set_trivia(true);
+        assert!(tokens(streamed) == tokens(loaded));
+
+        let tokens: Vec<(TokenType, String)> = trickle(b"x \xff\xfe y \xc3", false).map(|tok| (tok.token_type, tok.literal)).collect();
+        assert_eq!(tokens, vec![
+            (TokenType::IDENT, "x".to_string()),
+            (TokenType::ILLEGAL, "\u{fffd}".to_string()),
+            (TokenType::ILLEGAL, "\u{fffd}".to_string()),
+            (TokenType::IDENT, "y".to_string()),
+            (TokenType::ILLEGAL, "\u{fffd}".to_string()),
+        ]);
+
+        let mut lexer = trickle(b"let x", true);
+        lexer.next_token();
+        lexer.next_token();
+        let tok = lexer.next_token();
+        assert_eq!((tok.token_type, tok.literal.as_str(), tok.column), (TokenType::ILLEGAL, "could not read input: disk on fire", 6));
+        assert_eq!(lexer.next_token().token_type, TokenType::EOF);
+    }
+
+    #[test]
+    fn test_tokens_carry_file() {
+        let mut sources = SourceMap::new();
+        let file = sources.add("<repl:1>", "let x = /* */");
+        let mut lexer = Lexer::for_file(&sources, file).unwrap();
+        assert_eq!(lexer.next_token().file, Some(file));
+        lexer.next_token();
+        lexer.next_token();
+        assert_eq!(lexer.next_token().file, Some(file));
+        assert_eq!(Lexer::new("x").next_token().file, None);
+    }
+
+    #[test]
+    fn test_string_templates() {
+        let mut lexer = Lexer::new(r#""hi {name}, {f("}", "{x}")}\{!" "\{}" "{"#);
+        let tok = lexer.next_token();
+        assert_eq!(tok.token_type, TokenType::TEMPLATE);
+        assert_eq!(tok.literal, r#"hi {name}, {f("}", "{x}")}\{!"#);
+        assert_eq!(split_template(&tok.literal), vec![
+            Segment::Text("hi ".to_string()),
+            Segment::Expression("name".to_string()),
+            Segment::Text(", ".to_string()),
+            Segment::Expression(r#"f("}", "{x}")"#.to_string()),
+            Segment::Text("{!".to_string()),
+        ]);
+
+        let tok = lexer.next_token();
+        assert_eq!(tok.token_type, TokenType::STRING);
+        assert_eq!(tok.literal, "{}");
+
+        let tok = lexer.next_token();
+        assert_eq!(tok.token_type, TokenType::ILLEGAL);
+        assert!(tok.literal.starts_with("unterminated interpolation"));
+    }
+
+    #[test]
+    fn test_unterminated_interpolation() {
+        let expected = r"unterminated interpolation in string: expected }, or \{ for a literal brace";
+        for input in [r#""{""#, r#""{x"#, r#""a {1 + 2""#, r#""{"}""#] {
+            let tok = Lexer::new(input).next_token();
+            assert_eq!(tok.token_type, TokenType::ILLEGAL, "{}", input);
+            assert_eq!(tok.literal, expected, "{}", input);
+        }
+
+        let tok = Lexer::new(r#""{x}"#).next_token();
+        assert_eq!(tok.literal, "unterminated string");
+    }
+
+    #[test]
+    fn test_unterminated_string() {
+        let mut lexer = Lexer::new("let s = \"abc");
+        lexer.next_token();
+        lexer.next_token();
+        lexer.next_token();
+        let tok = lexer.next_token();
+        assert_eq!(tok.token_type, TokenType::ILLEGAL);
+        assert_eq!(tok.literal, "unterminated string");
+        assert_eq!(lexer.next_token().token_type, TokenType::EOF);
+
+        let mut lexer = Lexer::new("\"abc\\");
+        assert_eq!(lexer.next_token().token_type, TokenType::ILLEGAL);
+        assert_eq!(lexer.next_token().token_type, TokenType::EOF);
+    }
+
+    #[test]
+    fn test_identifiers_with_underscores() {
+        let mut lexer = Lexer::new("string_builder _x");
+        assert_eq!(lexer.next_token().literal, "string_builder");
+        let tok = lexer.next_token();
+        assert_eq!(tok.token_type, TokenType::IDENT);
+        assert_eq!(tok.literal, "_x");
+    }
+
+    #[test]
+    fn test_tokenize() {
+        let tokens: Vec<(TokenType, String)> = Lexer::tokenize("let x = 1; // one", false).into_iter()
+            .map(|tok| (tok.token_type, tok.literal))
+            .collect();
+        assert_eq!(tokens, vec![
+            (TokenType::LET, "let".to_string()),
+            (TokenType::IDENT, "x".to_string()),
+            (TokenType::ASSIGN, "=".to_string()),
+            (TokenType::INT, "1".to_string()),
+            (TokenType::SEMICOLON, ";".to_string()),
+        ]);
+
+        let input = "x /* a\nb */+\t1 // end\n";
+        let tokens = Lexer::tokenize(input, true);
+        let literals: Vec<&str> = tokens.iter().map(|tok| tok.literal.as_str()).collect();
+        assert_eq!(literals.concat(), input);
+        let summary: Vec<(TokenType, usize, usize)> = tokens.iter().map(|tok| (tok.token_type, tok.line, tok.column)).collect();
+        assert_eq!(summary, vec![
+            (TokenType::IDENT, 1, 1),
+            (TokenType::WHITESPACE, 1, 2),
+            (TokenType::COMMENT, 1, 3),
+            (TokenType::PLUS, 2, 5),
+            (TokenType::WHITESPACE, 2, 6),
+            (TokenType::INT, 2, 7),
+            (TokenType::WHITESPACE, 2, 8),
+            (TokenType::COMMENT, 2, 9),
+            (TokenType::WHITESPACE, 2, 15),
+        ]);
+
+        let tokens = Lexer::tokenize("1 /* open", true);
+        assert_eq!(tokens.last().unwrap().literal, "unterminated comment");
+    }
+
+    #[test]
+    fn test_peek_nth() {
+        let mut lexer = Lexer::new("a + b;");
+        assert_eq!(lexer.peek_nth(2).literal, "b");
+        assert_eq!(lexer.peek_nth(0).literal, "a");
+        assert_eq!(lexer.peek_nth(10).token_type, TokenType::EOF);
+        assert_eq!(lexer.next_token().literal, "a");
+        assert_eq!(lexer.peek_nth(0).literal, "+");
+        let rest: Vec<String> = lexer.map(|tok| tok.literal).collect();
+        assert_eq!(rest, vec!["+", "b", ";"]);
+    }
 }
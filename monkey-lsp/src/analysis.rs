@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use ast::{Expression, ExpressionKind, Identifier, Statement, StatementKind, Visitor};
+use lexer::Lexer;
+use parser::{ParseError, Parser};
+
+// Part of a single line. Lines and columns count from 1, like token
+// positions.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub length: usize,
+}
+
+impl Span {
+    fn of(identifier: &Identifier) -> Span {
+        Span {
+            line: identifier.token.line,
+            column: identifier.token.column,
+            length: identifier.value.chars().count(),
+        }
+    }
+
+    fn contains(&self, line: usize, column: usize) -> bool {
+        line == self.line && column >= self.column && column <= self.column + self.length
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SymbolKind {
+    Function,
+    Variable,
+}
+
+// A let statement. Functions list the lets in their body as children.
+#[derive(Debug, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub span: Span,
+    pub children: Vec<Symbol>,
+}
+
+pub struct Analysis {
+    pub errors: Vec<ParseError>,
+    program: ast::Program,
+}
+
+// The parser recovers from errors, so symbols and definitions are still
+// found in the statements around them.
+pub fn analyze(source: &str) -> Analysis {
+    let mut parser = Parser::new(Lexer::new(source));
+    let program = parser.parse_program();
+    Analysis {
+        errors: parser.parse_errors().to_vec(),
+        program,
+    }
+}
+
+impl Analysis {
+    pub fn symbols(&self) -> Vec<Symbol> {
+        let mut symbols = vec![];
+        collect_symbols(&self.program.statements, &mut symbols);
+        symbols
+    }
+
+    // Where the name at line and column is bound: a let or a parameter in
+    // scope there. None for builtins, unbound names and positions that are
+    // not on a name.
+    pub fn definition(&self, line: usize, column: usize) -> Option<Span> {
+        let mut resolver = Resolver {
+            scopes: vec![HashMap::new()],
+            line,
+            column,
+            found: None,
+        };
+        ast::walk_program(&mut resolver, &self.program);
+        resolver.found.flatten()
+    }
+}
+
+fn collect_symbols(statements: &[Rc<dyn Statement>], symbols: &mut Vec<Symbol>) {
+    for statement in statements {
+        match statement.kind() {
+            StatementKind::Let(let_statement) => {
+                let mut symbol = Symbol {
+                    name: let_statement.name.value.clone(),
+                    kind: SymbolKind::Variable,
+                    span: Span::of(&let_statement.name),
+                    children: vec![],
+                };
+                if let Some(ExpressionKind::FunctionLiteral(function)) = let_statement.value.as_ref().map(|v| v.kind()) {
+                    symbol.kind = SymbolKind::Function;
+                    collect_symbols(std::slice::from_ref(&function.body), &mut symbol.children);
+                }
+                symbols.push(symbol);
+            },
+            StatementKind::Block(block) => collect_symbols(&block.statements, symbols),
+            // Blocks share the scope they are in, so lets in them are listed
+            // alongside the statement.
+            StatementKind::Expression(expression_statement) => match expression_statement.expression.as_ref().map(|e| e.kind()) {
+                Some(ExpressionKind::If(if_expression)) => {
+                    collect_symbols(std::slice::from_ref(&if_expression.consequence), symbols);
+                    if let Some(alternative) = &if_expression.alternative {
+                        collect_symbols(std::slice::from_ref(alternative), symbols);
+                    }
+                },
+                Some(ExpressionKind::While(while_expression)) => collect_symbols(std::slice::from_ref(&while_expression.body), symbols),
+                _ => {},
+            },
+            StatementKind::Return(_) => {},
+        }
+    }
+}
+
+// Walks the program in order, binding names as the evaluator would, until
+// it reaches the name at the target position.
+struct Resolver {
+    scopes: Vec<HashMap<String, Span>>,
+    line: usize,
+    column: usize,
+    // Some once the target is reached, holding where its name is bound.
+    found: Option<Option<Span>>,
+}
+
+impl Visitor for Resolver {
+    fn visit_statement(&mut self, statement: &Rc<dyn Statement>) {
+        if self.found.is_some() {
+            return;
+        }
+        match statement.kind() {
+            StatementKind::Let(let_statement) => {
+                // A function can call itself by the name it is bound to.
+                let recursive = matches!(let_statement.value.as_ref().map(|v| v.kind()), Some(ExpressionKind::FunctionLiteral(_)));
+                if recursive {
+                    self.bind(&let_statement.name);
+                }
+                if let Some(value) = &let_statement.value {
+                    self.visit_expression(value);
+                }
+                if !recursive {
+                    self.bind(&let_statement.name);
+                }
+            },
+            _ => ast::walk_statement(self, statement),
+        }
+    }
+
+    fn visit_expression(&mut self, exp: &Rc<dyn Expression>) {
+        if self.found.is_some() {
+            return;
+        }
+        match exp.kind() {
+            ExpressionKind::Identifier(identifier) => self.reference(identifier),
+            ExpressionKind::Assign(assign) => {
+                self.reference(&assign.name);
+                ast::walk_expression(self, exp);
+            },
+            // Parameters are bound in a scope of the function's own.
+            ExpressionKind::FunctionLiteral(_) | ExpressionKind::MacroLiteral(_) => {
+                self.scopes.push(HashMap::new());
+                ast::walk_expression(self, exp);
+                self.scopes.pop();
+            },
+            _ => ast::walk_expression(self, exp),
+        }
+    }
+
+    fn visit_binding(&mut self, name: &Rc<Identifier>) {
+        self.bind(name);
+    }
+}
+
+impl Resolver {
+    fn bind(&mut self, name: &Identifier) {
+        let span = Span::of(name);
+        if self.found.is_none() && span.contains(self.line, self.column) {
+            self.found = Some(Some(span));
+        }
+        self.scopes.last_mut().unwrap().insert(name.value.clone(), span);
+    }
+
+    fn reference(&mut self, name: &Identifier) {
+        if self.found.is_none() && Span::of(name).contains(self.line, self.column) {
+            self.found = Some(self.scopes.iter().rev().find_map(|scope| scope.get(&name.value)).copied());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(line: usize, column: usize, length: usize) -> Span {
+        Span { line, column, length }
+    }
+
+    #[test]
+    fn test_symbols() {
+        let analysis = analyze("let x = 1;\nlet add = fn(a, b) {\n  let sum = a + b;\n  sum\n};\nif (x) { let y = 2; }");
+        assert!(analysis.errors.is_empty());
+        let symbols = analysis.symbols();
+        let names: Vec<(&str, SymbolKind)> = symbols.iter().map(|s| (s.name.as_str(), s.kind)).collect();
+        assert_eq!(names, vec![("x", SymbolKind::Variable), ("add", SymbolKind::Function), ("y", SymbolKind::Variable)]);
+        assert_eq!(symbols[1].span, span(2, 5, 3));
+        assert_eq!(symbols[1].children, vec![Symbol { name: "sum".to_string(), kind: SymbolKind::Variable, span: span(3, 7, 3), children: vec![] }]);
+    }
+
+    #[test]
+    fn test_definition() {
+        let source = "let x = 1;\nlet f = fn(x, n) {\n  if (n > 0) { f(x, n - 1) } else { x }\n};\nf(x, 2); puts(x); missing";
+        let analysis = analyze(source);
+        // The parameter shadows the outer x inside f.
+        assert_eq!(analysis.definition(3, 37), Some(span(2, 12, 1)));
+        assert_eq!(analysis.definition(3, 18), Some(span(2, 12, 1)));
+        assert_eq!(analysis.definition(3, 16), Some(span(2, 5, 1)));
+        assert_eq!(analysis.definition(5, 3), Some(span(1, 5, 1)));
+        assert_eq!(analysis.definition(5, 15), Some(span(1, 5, 1)));
+        assert_eq!(analysis.definition(1, 5), Some(span(1, 5, 1)));
+        assert_eq!(analysis.definition(5, 10), None);
+        assert_eq!(analysis.definition(5, 20), None);
+        assert_eq!(analysis.definition(1, 9), None);
+    }
+
+    #[test]
+    fn test_analysis_survives_errors() {
+        let analysis = analyze("let x 5;\nlet y = 2;\ny");
+        assert_eq!(analysis.errors.len(), 1);
+        assert_eq!(analysis.errors[0].line, 1);
+        assert_eq!(analysis.symbols().len(), 1);
+        assert_eq!(analysis.definition(3, 1), Some(span(2, 5, 1)));
+    }
+}
@@ -0,0 +1,33 @@
+use std::io::{self, BufReader};
+use std::process;
+
+mod analysis;
+mod server;
+
+use server::Server;
+
+// Speaks the Language Server Protocol over stdin and stdout.
+fn main() {
+    let mut reader = BufReader::new(io::stdin().lock());
+    let mut writer = io::stdout().lock();
+    let mut server = Server::default();
+    loop {
+        let message = match server::read_message(&mut reader) {
+            Ok(Some(message)) => message,
+            Ok(None) => process::exit(1),
+            Err(err) => {
+                eprintln!("monkey-lsp: {}", err);
+                process::exit(1);
+            }
+        };
+        for reply in server.handle(&message) {
+            if let Err(err) = server::write_message(&mut writer, &reply) {
+                eprintln!("monkey-lsp: {}", err);
+                process::exit(1);
+            }
+        }
+        if let Some(code) = server.exit_code {
+            process::exit(code);
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use serde_json::{json, Value};
+
+use crate::analysis::{self, Span, Symbol, SymbolKind};
+
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INVALID_REQUEST: i64 = -32600;
+
+// LSP numbers its symbol kinds; these are Function and Variable.
+const SYMBOL_FUNCTION: i64 = 12;
+const SYMBOL_VARIABLE: i64 = 13;
+
+// Open documents are kept whole: the client is asked to send the full text
+// on every change.
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<String, String>,
+    shutdown: bool,
+    // Set by the exit notification: 0 if shutdown was requested first.
+    pub exit_code: Option<i32>,
+}
+
+impl Server {
+    // Returns the messages to send back: the response to a request, and
+    // any diagnostics the message caused to be published.
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message.get("method").and_then(Value::as_str).unwrap_or("");
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            None => return self.notification(method, &params),
+        };
+        let result = match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "documentSymbolProvider": true,
+                    "definitionProvider": true,
+                },
+                "serverInfo": { "name": "monkey-lsp", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            "textDocument/documentSymbol" => self.document(&params).map(|(_, source)| {
+                analysis::analyze(source).symbols().iter().map(symbol_json).collect()
+            }),
+            "textDocument/definition" => self.definition(&params),
+            "" => Err((INVALID_REQUEST, "missing method".to_string())),
+            _ => Err((METHOD_NOT_FOUND, format!("method not found: {}", method))),
+        };
+        vec![match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } }),
+        }]
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("").to_string();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or("").to_string();
+                let diagnostics = publish_diagnostics(&uri, &text);
+                self.documents.insert(uri, text);
+                vec![diagnostics]
+            }
+            "textDocument/didChange" => {
+                let text = match params["contentChanges"].as_array().and_then(|changes| changes.last()) {
+                    Some(change) => change["text"].as_str().unwrap_or("").to_string(),
+                    None => return vec![],
+                };
+                let diagnostics = publish_diagnostics(&uri, &text);
+                self.documents.insert(uri, text);
+                vec![diagnostics]
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                vec![json!({
+                    "jsonrpc": "2.0",
+                    "method": "textDocument/publishDiagnostics",
+                    "params": { "uri": uri, "diagnostics": [] },
+                })]
+            }
+            "exit" => {
+                self.exit_code = Some(if self.shutdown { 0 } else { 1 });
+                vec![]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn document<'a>(&'a self, params: &Value) -> Result<(&'a str, &'a str), (i64, String)> {
+        let uri = params["textDocument"]["uri"].as_str().ok_or((INVALID_PARAMS, "missing textDocument.uri".to_string()))?;
+        match self.documents.get_key_value(uri) {
+            Some((uri, source)) => Ok((uri, source)),
+            None => Err((INVALID_PARAMS, format!("document not open: {}", uri))),
+        }
+    }
+
+    fn definition(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let (uri, source) = self.document(params)?;
+        let (line, character) = match (params["position"]["line"].as_u64(), params["position"]["character"].as_u64()) {
+            (Some(line), Some(character)) => (line as usize, character as usize),
+            _ => return Err((INVALID_PARAMS, "missing position".to_string())),
+        };
+        Ok(match analysis::analyze(source).definition(line + 1, character + 1) {
+            Some(span) => json!({ "uri": uri, "range": range(span) }),
+            None => Value::Null,
+        })
+    }
+}
+
+fn publish_diagnostics(uri: &str, source: &str) -> Value {
+    let diagnostics: Vec<Value> = analysis::analyze(source).errors.iter()
+        .map(|error| json!({
+            "range": range(Span { line: error.line, column: error.column, length: error.length }),
+            "severity": 1,
+            "source": "monkey",
+            "message": error.message,
+        }))
+        .collect();
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+// LSP positions count from 0. Columns are counted in characters, which
+// matches the UTF-16 units LSP expects outside the astral planes.
+fn range(span: Span) -> Value {
+    let line = span.line.saturating_sub(1);
+    let start = span.column.saturating_sub(1);
+    json!({
+        "start": { "line": line, "character": start },
+        "end": { "line": line, "character": start + span.length },
+    })
+}
+
+fn symbol_json(symbol: &Symbol) -> Value {
+    let kind = match symbol.kind {
+        SymbolKind::Function => SYMBOL_FUNCTION,
+        SymbolKind::Variable => SYMBOL_VARIABLE,
+    };
+    json!({
+        "name": symbol.name,
+        "kind": kind,
+        "range": range(symbol.span),
+        "selectionRange": range(symbol.span),
+        "children": symbol.children.iter().map(symbol_json).collect::<Vec<_>>(),
+    })
+}
+
+// Reads one message framed by a Content-Length header. Returns None at the
+// end of the input.
+pub fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<Value>> {
+    let mut content_length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().ok();
+            }
+        }
+    }
+    let content_length = content_length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length header"))?;
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    serde_json::from_slice(&body).map(Some).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+pub fn write_message<W: Write>(writer: &mut W, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open(server: &mut Server, text: &str) -> Vec<Value> {
+        server.handle(&json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": "file:///a.monkey", "languageId": "monkey", "version": 1, "text": text } },
+        }))
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let mut server = Server::default();
+        let messages = open(&mut server, "let x = 1;\nlet y 2;");
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0]["method"], "textDocument/publishDiagnostics");
+        let diagnostics = &messages[0]["params"]["diagnostics"];
+        assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+        assert_eq!(diagnostics[0]["message"], "expected next token to be ASSIGN, got INT instead");
+        assert_eq!(diagnostics[0]["range"], json!({ "start": { "line": 1, "character": 6 }, "end": { "line": 1, "character": 7 } }));
+
+        let messages = server.handle(&json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didChange",
+            "params": { "textDocument": { "uri": "file:///a.monkey", "version": 2 }, "contentChanges": [{ "text": "let y = 2;" }] },
+        }));
+        assert_eq!(messages[0]["params"]["diagnostics"], json!([]));
+    }
+
+    #[test]
+    fn test_requests() {
+        let mut server = Server::default();
+        let response = server.handle(&json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }));
+        assert_eq!(response[0]["result"]["capabilities"]["definitionProvider"], true);
+        open(&mut server, "let double = fn(x) { x * 2 };\ndouble(3)");
+
+        let response = server.handle(&json!({
+            "jsonrpc": "2.0", "id": 2, "method": "textDocument/documentSymbol",
+            "params": { "textDocument": { "uri": "file:///a.monkey" } },
+        }));
+        assert_eq!(response[0]["result"][0]["name"], "double");
+        assert_eq!(response[0]["result"][0]["kind"], SYMBOL_FUNCTION);
+
+        let response = server.handle(&json!({
+            "jsonrpc": "2.0", "id": 3, "method": "textDocument/definition",
+            "params": { "textDocument": { "uri": "file:///a.monkey" }, "position": { "line": 1, "character": 2 } },
+        }));
+        assert_eq!(response[0]["result"], json!({
+            "uri": "file:///a.monkey",
+            "range": { "start": { "line": 0, "character": 4 }, "end": { "line": 0, "character": 10 } },
+        }));
+
+        let response = server.handle(&json!({
+            "jsonrpc": "2.0", "id": 4, "method": "textDocument/definition",
+            "params": { "textDocument": { "uri": "file:///b.monkey" }, "position": { "line": 0, "character": 0 } },
+        }));
+        assert_eq!(response[0]["error"]["code"], INVALID_PARAMS);
+        let response = server.handle(&json!({ "jsonrpc": "2.0", "id": 5, "method": "textDocument/hover", "params": {} }));
+        assert_eq!(response[0]["error"]["code"], METHOD_NOT_FOUND);
+
+        server.handle(&json!({ "jsonrpc": "2.0", "id": 6, "method": "shutdown" }));
+        server.handle(&json!({ "jsonrpc": "2.0", "method": "exit" }));
+        assert_eq!(server.exit_code, Some(0));
+    }
+
+    #[test]
+    fn test_framing() {
+        let mut output = Vec::new();
+        write_message(&mut output, &json!({ "id": 1 })).unwrap();
+        assert_eq!(String::from_utf8(output.clone()).unwrap(), "Content-Length: 8\r\n\r\n{\"id\":1}");
+
+        let mut input = io::Cursor::new([output.clone(), output].concat());
+        assert_eq!(read_message(&mut input).unwrap(), Some(json!({ "id": 1 })));
+        assert_eq!(read_message(&mut input).unwrap(), Some(json!({ "id": 1 })));
+        assert_eq!(read_message(&mut input).unwrap(), None);
+        assert!(read_message(&mut io::Cursor::new("Content-Type: x\r\n\r\n{}")).is_err());
+    }
+}
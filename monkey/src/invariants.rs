@@ -0,0 +1,225 @@
+// Programs that must come back from every stage of the pipeline as errors
+// or values, never as panics. Each case is lexed, parsed and then run by a
+// sandboxed interpreter, which also expands macros and validates.
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
+
+use ast::Node;
+use lexer::Lexer;
+use parser::Parser;
+
+use crate::{Interpreter, Limits};
+
+const CASES: &[&str] = &[
+    // Lexer
+    "",
+    " \n\t ",
+    "\"unterminated",
+    "\"unterminated {interpolation",
+    "\"{\"",
+    "\"{}\"",
+    "\"{{{\"",
+    "\"\\",
+    "/* unterminated comment",
+    "/* nested /* comment */",
+    "@#$~`",
+    "1.",
+    "1..2",
+    ".5",
+    "99999999999999999999999999",
+    "1e10",
+    "é = 1",
+    "\0",
+    // Stray operators and delimiters
+    "+",
+    "-",
+    "!",
+    "*",
+    "=",
+    "==",
+    "+=",
+    "1 +",
+    "1 + * 2",
+    "* 2",
+    "!!!!",
+    "- - -",
+    ")",
+    "(",
+    "]",
+    "[",
+    "}",
+    "{",
+    ",",
+    ";;;;",
+    "(1, 2)",
+    "[1, , 2]",
+    "[,]",
+    "1 2 3",
+    // Statements
+    "let",
+    "let x",
+    "let x =",
+    "let = 1;",
+    "let 1 = 2;",
+    "let let = 1;",
+    "return",
+    "return;",
+    "x = ",
+    "1 = 2",
+    "x += 1",
+    "import",
+    "import \"\"",
+    "import 1",
+    // Empty and broken blocks
+    "fn() {}",
+    "fn() {}()",
+    "fn(",
+    "fn(x",
+    "fn(x,) { x }",
+    "fn(1) { 1 }",
+    "fn(x, x) { x }",
+    "fn { }",
+    "if",
+    "if (",
+    "if () {}",
+    "if (true) {}",
+    "if (true) {} else",
+    "if (true) {} else {}",
+    "else {}",
+    "while",
+    "while () {}",
+    "while (false) {}",
+    "{}",
+    "{ { { } } }",
+    "macro",
+    "macro(x) {}",
+    "macro(x) { x }(1)",
+    "quote",
+    "quote()",
+    "unquote(1)",
+    "quote(unquote(unquote(1)))",
+    // Bad calls
+    "1()",
+    "\"a\"()",
+    "true(1)",
+    "[](0)",
+    "null()",
+    "x()",
+    "len()",
+    "len(1, 2)",
+    "len(len)",
+    "puts(fn() {})",
+    "fn(x) { x }()",
+    "fn() { 1 }(1, 2, 3)",
+    "let f = fn(f) { f(f) }; f(1)",
+    "call()",
+    "call(1)",
+    "map()",
+    "map([1], 1)",
+    "reduce([], fn(a, b) { a })",
+    "split(\"\", \"\")",
+    "substr(\"a\", 99999999999999)",
+    "substr(\"a\", -9223372036854775807)",
+    "with_timeout(-1, fn() { 1 })",
+    "with_timeout(0, 1)",
+    "append(1, 2)",
+    "build(1)",
+    "read_file(\"/\")",
+    // Operators on the wrong types
+    "1 + true",
+    "\"a\" - \"b\"",
+    "-\"a\"",
+    "!fn() {}",
+    "[1] + [2]",
+    "1 / 0",
+    "1 % 0",
+    "1.5 / 0",
+    "1.5 % 0",
+    "-9223372036854775807 - 2",
+    "9223372036854775807 + 1",
+    "9223372036854775807 * 2",
+    "-9223372036854775807 - 1 / -1",
+    "(-9223372036854775807 - 1) / -1",
+    "(-9223372036854775807 - 1) % -1",
+    "-(-9223372036854775807 - 1)",
+    // Indexing
+    "[1][5]",
+    "[1][-1]",
+    "[1][-9223372036854775807 - 1]",
+    "[1][\"a\"]",
+    "1[0]",
+    "\"abc\"[1]",
+    "[][0]",
+    "[1][",
+    "[1][]",
+    // Recursion
+    "let f = fn() { f() }; f()",
+    "let f = fn(n) { if (n == 0) { 0 } else { 1 + f(n - 1) } }; f(100000)",
+];
+
+// Inputs that still panic, each to be taken off this list when the code
+// path it reaches is fixed. test_no_panics fails both when a new input
+// panics and when one listed here no longer does.
+const KNOWN_PANICS: &[&str] = &[];
+
+fn nested(open: &str, middle: &str, close: &str, depth: usize) -> String {
+    format!("{}{}{}", open.repeat(depth), middle, close.repeat(depth))
+}
+
+// Nesting deep enough to reach every recursive path, but well within the
+// stack the cases run on.
+fn generated_cases() -> Vec<String> {
+    let depth = 64;
+    vec![
+        nested("(", "1", ")", depth),
+        nested("[", "1", "]", depth),
+        nested("{", "1", "}", depth),
+        nested("fn() { ", "1", " }()", depth),
+        nested("if (true) { ", "1", " }", depth),
+        nested("-", "1", "", depth),
+        nested("!", "true", "", depth),
+        nested("(", "", "", depth),
+        nested("", "1", ")", depth),
+        nested("[", "", "", depth),
+        nested("{", "", "", depth),
+        nested("\"{", "", "", depth),
+        vec!["1"; 1000].join(" + "),
+        vec!["1"; 1000].join(" +"),
+        format!("[{}]", vec!["1"; 1000].join(", ")),
+    ]
+}
+
+fn run(source: &str) {
+    let tokens = Lexer::tokenize(source, true);
+    let _ = tokens.last().map(|tok| tok.span);
+    let mut parser = Parser::new(Lexer::new(source));
+    let program = parser.parse_program();
+    let _ = program.to_string();
+    let mut interpreter = Interpreter::with_limits(Limits::sandbox());
+    interpreter.set_output(Box::new(std::io::sink()));
+    let _ = interpreter.eval(source);
+}
+
+// Hosts run the interpreter with a stack this large, as the REPL does, so
+// the call depth limit is reached before the native stack runs out.
+const STACK_SIZE: usize = 64 << 20;
+
+#[test]
+fn test_no_panics() {
+    let cases: Vec<String> = CASES.iter().map(|case| case.to_string()).chain(generated_cases()).collect();
+    let panicked = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || {
+            let mut panicked = vec![];
+            for case in cases {
+                if panic::catch_unwind(AssertUnwindSafe(|| run(&case))).is_err() {
+                    panicked.push(case);
+                }
+            }
+            panicked
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+    assert_eq!(panicked, KNOWN_PANICS, "inputs panicking");
+}
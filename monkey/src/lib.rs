@@ -0,0 +1,608 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use evaluator::Evaluator;
+use lexer::Lexer;
+use object::Object;
+use parser::Parser;
+
+#[cfg(feature = "prelude")]
+pub mod prelude;
+mod value;
+mod worker;
+#[cfg(test)]
+mod invariants;
+
+pub use ast;
+pub use evaluator::{CancellationToken, Limits};
+pub use validator::types::Type;
+pub use value::Value;
+pub use worker::EvalHandle;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MonkeyError {
+    Parse(Vec<String>),
+    Validation(Vec<String>),
+    Macro(String),
+    Runtime(String),
+}
+
+impl fmt::Display for MonkeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MonkeyError::Parse(errors) => write!(f, "parser errors: {}", errors.join("; ")),
+            MonkeyError::Validation(errors) => write!(f, "validation errors: {}", errors.join("; ")),
+            MonkeyError::Macro(message) => write!(f, "macro expansion error: {}", message),
+            MonkeyError::Runtime(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for MonkeyError {}
+
+// A boolean expression compiled once by Interpreter::compile_rule, to be
+// checked against many sets of bindings with Interpreter::eval_rule.
+pub struct Rule {
+    expression: Rc<dyn ast::Expression>,
+}
+
+pub struct Interpreter {
+    environment: Rc<RefCell<object::Environment>>,
+    macros: Rc<RefCell<object::Environment>>,
+    evaluator: Evaluator,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        Interpreter::with_limits(Limits::default())
+    }
+
+    #[cfg(feature = "prelude")]
+    pub fn with_limits(limits: Limits) -> Interpreter {
+        let mut evaluator = Evaluator::with_limits(limits);
+        Interpreter {
+            environment: prelude::environment(&mut evaluator),
+            macros: Rc::new(RefCell::new(object::Environment::new())),
+            evaluator,
+        }
+    }
+
+    #[cfg(not(feature = "prelude"))]
+    pub fn with_limits(limits: Limits) -> Interpreter {
+        Interpreter::without_prelude(limits)
+    }
+
+    pub fn without_prelude(limits: Limits) -> Interpreter {
+        Interpreter {
+            environment: Rc::new(RefCell::new(object::Environment::new())),
+            macros: Rc::new(RefCell::new(object::Environment::new())),
+            evaluator: Evaluator::with_limits(limits),
+        }
+    }
+
+    // An interpreter for running less trusted code, such as a script a
+    // plugin was handed. It starts with a copy of this interpreter's
+    // globals and macros: it can read them, but what it binds or assigns,
+    // even through a closure it inherited, stays its own. Its limits are narrowed to this interpreter's, so a
+    // child never gets a capability its parent lacks. What it prints is
+    // discarded until set_output is called.
+    pub fn spawn_child(&self, limits: Limits) -> Interpreter {
+        let parent = &self.evaluator.limits;
+        let limits = Limits {
+            max_string_length: narrower(parent.max_string_length, limits.max_string_length),
+            max_array_length: narrower(parent.max_array_length, limits.max_array_length),
+            max_call_depth: narrower(parent.max_call_depth, limits.max_call_depth),
+            allow_imports: parent.allow_imports && limits.allow_imports,
+            allow_fs: parent.allow_fs && limits.allow_fs,
+            max_steps: narrower(parent.max_steps, limits.max_steps),
+            max_env_entries: narrower(parent.max_env_entries, limits.max_env_entries),
+            timeout: narrower(parent.timeout, limits.timeout),
+        };
+        let mut evaluator = Evaluator::with_limits(limits);
+        evaluator.output = Box::new(io::sink());
+        let mut copier = ScopeCopier::default();
+        let environment = copier.scope(&self.environment);
+        if let Some(outer) = environment.borrow().outer.clone() {
+            evaluator.set_module_environment(outer);
+        }
+        Interpreter {
+            environment,
+            macros: copier.scope(&self.macros),
+            evaluator,
+        }
+    }
+
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.evaluator.output = output;
+    }
+
+    // What the args builtin gives scripts.
+    pub fn set_script_args(&mut self, args: Vec<String>) {
+        self.evaluator.script_args = args;
+    }
+
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.evaluator.cancellation.clone()
+    }
+
+    pub fn register_native<F>(&mut self, name: &str, function: F)
+    where
+        F: Fn(Vec<Value>) -> Result<Value, String> + 'static,
+    {
+        self.environment.borrow_mut().register_native(name, move |args| {
+            match function(args.into_iter().map(Value::from).collect()) {
+                Ok(value) => value.into(),
+                Err(message) => Object::Error(message),
+            }
+        });
+    }
+
+    pub fn eval(&mut self, source: &str) -> Result<Value, MonkeyError> {
+        let program = self.prepare(source)?;
+        match self.evaluator.evaluate_program(program, self.environment.clone()) {
+            Some(result) => into_result(result),
+            None => Ok(Value::Null),
+        }
+    }
+
+    // Calls the function bound to name, or the builtin of that name, with
+    // args, as a script calling name(args...) would.
+    pub fn call(&mut self, name: &str, args: &[Value]) -> Result<Value, MonkeyError> {
+        let function = self.environment.borrow().get(name)
+            .or_else(|| evaluator::builtins::lookup(name).map(|_| Object::Builtin(name.into())))
+            .ok_or_else(|| MonkeyError::Runtime(format!("identifier not found: {}", name)))?;
+        let args: Vec<Object> = args.iter().cloned().map(Object::from).collect();
+        if let Some(Object::Error(message)) = args.iter().find(|arg| arg.object_type() == object::ObjectType::ERROR) {
+            return Err(MonkeyError::Runtime(message.clone()));
+        }
+        self.evaluator.cancellation.reset();
+        self.evaluator.start_run();
+        let result = self.evaluator.call_function(function, args);
+        self.evaluator.cancellation.reset();
+        into_result(result)
+    }
+
+    // Parses, expands and validates a rule such as `age >= 18`, and folds
+    // its constants, so eval_rule only has to evaluate it.
+    pub fn compile_rule(&mut self, source: &str) -> Result<Rule, MonkeyError> {
+        let program = optimizer::optimize(self.prepare(source)?);
+        let expression = match program.statements.as_slice() {
+            [statement] => match statement.kind() {
+                ast::StatementKind::Expression(statement) => statement.expression.clone(),
+                _ => None,
+            },
+            _ => None,
+        };
+        let expression = expression.ok_or_else(|| MonkeyError::Parse(vec!["a rule must be a single expression".to_string()]))?;
+        match infer_type(&program, &self.environment) {
+            Type::BOOLEAN | Type::UNKNOWN => Ok(Rule { expression }),
+            ty => Err(MonkeyError::Validation(vec![format!("a rule must be a BOOLEAN expression, got {}", ty)])),
+        }
+    }
+
+    // Evaluates rule with each binding visible as a variable, in a scope of
+    // its own so bindings do not leak into later evaluations.
+    pub fn eval_rule(&mut self, rule: &Rule, bindings: &HashMap<String, Value>) -> Result<bool, MonkeyError> {
+        let env = object::Environment::new_enclosed(self.environment.clone());
+        for (name, value) in bindings {
+            env.borrow_mut().set(name.clone(), value.clone().into());
+        }
+        match self.eval_expression_in(rule.expression.clone(), env)? {
+            Value::Boolean(value) => Ok(value),
+            value => Err(MonkeyError::Runtime(format!("a rule must evaluate to a BOOLEAN, got {}", value))),
+        }
+    }
+
+    // Runs source through every stage before evaluation.
+    fn prepare(&mut self, source: &str) -> Result<ast::Program, MonkeyError> {
+        let l = Lexer::new(source);
+        let mut p = Parser::new(l);
+        let mut program = p.parse_program();
+        if !p.errors().is_empty() {
+            return Err(MonkeyError::Parse(p.errors()));
+        }
+
+        self.evaluator.define_macros(&mut program, self.macros.clone());
+        let program = self.evaluator.expand_macros(program, self.macros.clone()).map_err(MonkeyError::Macro)?;
+
+        let errors: Vec<String> = validator::validate(&program).iter()
+            .filter(|d| d.is_error())
+            .map(|d| d.to_string())
+            .collect();
+        if !errors.is_empty() {
+            return Err(MonkeyError::Validation(errors));
+        }
+        Ok(program)
+    }
+
+    // Infers the type of source without expanding macros or evaluating it;
+    // names bound by earlier calls to eval have the type of their value.
+    pub fn type_of(&self, source: &str) -> Result<Type, MonkeyError> {
+        let mut p = Parser::new(Lexer::new(source));
+        let program = p.parse_program();
+        if !p.errors().is_empty() {
+            return Err(MonkeyError::Parse(p.errors()));
+        }
+        Ok(infer_type(&program, &self.environment))
+    }
+
+    // Binds every name in globals, replacing earlier bindings, as if the
+    // script had assigned them itself.
+    pub fn define_globals(&mut self, globals: HashMap<String, Value>) {
+        let mut env = self.environment.borrow_mut();
+        for (name, value) in globals {
+            env.set(name, value.into());
+        }
+    }
+
+    // Everything bound at the top level, by define_globals or by the
+    // scripts evaluated so far. Prelude helpers are not included.
+    pub fn extract_globals(&self) -> HashMap<String, Value> {
+        self.environment.borrow().scope.iter()
+            .map(|(name, value)| (name.clone(), Value::from(value.clone())))
+            .collect()
+    }
+
+    pub fn environment(&self) -> Rc<RefCell<object::Environment>> {
+        self.environment.clone()
+    }
+
+    pub fn eval_expression(&mut self, exp: Rc<dyn ast::Expression>) -> Result<Value, MonkeyError> {
+        self.eval_expression_in(exp, self.environment.clone())
+    }
+
+    pub fn eval_expression_in(&mut self, exp: Rc<dyn ast::Expression>, env: Rc<RefCell<object::Environment>>) -> Result<Value, MonkeyError> {
+        self.evaluator.start_run();
+        into_result(self.evaluator.evaluate_expression(exp, env))
+    }
+
+    pub fn eval_statement(&mut self, statement: Rc<dyn ast::Statement>) -> Result<Value, MonkeyError> {
+        self.evaluator.start_run();
+        into_result(self.evaluator.evaluate_statement(statement, self.environment.clone()))
+    }
+}
+
+pub fn infer_type(program: &ast::Program, env: &Rc<RefCell<object::Environment>>) -> Type {
+    validator::types::infer(program, &|name| env.borrow().get(name).map(|obj| object_type(&obj)))
+}
+
+fn object_type(obj: &Object) -> Type {
+    match obj {
+        Object::Integer(_) => Type::INTEGER,
+        Object::Float(_) => Type::FLOAT,
+        Object::Boolean(_) => Type::BOOLEAN,
+        Object::String(_) => Type::STRING,
+        Object::Null => Type::NULL,
+        Object::Array(_) => Type::ARRAY,
+        Object::Function(_) | Object::Builtin(_) | Object::NativeFunction(_) => Type::FUNCTION,
+        Object::Macro(_) => Type::MACRO,
+        _ => Type::UNKNOWN,
+    }
+}
+
+fn narrower<T: Ord>(a: Option<T>, b: Option<T>) -> Option<T> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+// Copies scopes for spawn_child. Functions and macros in them are rebound
+// to the copies of the scopes they closed over, and string builders are
+// copied too, so nothing a child runs can change its parent's state. Each
+// scope and function is copied once, however many values share it.
+#[derive(Default)]
+struct ScopeCopier {
+    scopes: HashMap<*const RefCell<object::Environment>, Rc<RefCell<object::Environment>>>,
+    functions: HashMap<*const object::Function, Rc<object::Function>>,
+}
+
+impl ScopeCopier {
+    fn scope(&mut self, env: &Rc<RefCell<object::Environment>>) -> Rc<RefCell<object::Environment>> {
+        if let Some(copy) = self.scopes.get(&Rc::as_ptr(env)) {
+            return copy.clone();
+        }
+        // Registered before its values are copied, since a closure in the
+        // scope may have closed over the scope itself.
+        let copy = Rc::new(RefCell::new(object::Environment::new()));
+        self.scopes.insert(Rc::as_ptr(env), copy.clone());
+        object::gc::track(&copy);
+        let (outer, scope, sealed) = {
+            let env = env.borrow();
+            (env.outer.clone(), env.scope.clone(), env.sealed)
+        };
+        let outer = outer.map(|outer| self.scope(&outer));
+        let scope = scope.into_iter().map(|(name, value)| (name, self.value(value))).collect();
+        *copy.borrow_mut() = object::Environment { outer, scope, sealed };
+        copy
+    }
+
+    fn value(&mut self, value: Object) -> Object {
+        match value {
+            Object::Function(function) => {
+                if let Some(copy) = self.functions.get(&Rc::as_ptr(&function)) {
+                    return Object::Function(copy.clone());
+                }
+                let copy = Rc::new(object::Function {
+                    parameters: function.parameters.clone(),
+                    body: function.body.clone(),
+                    env: self.scope(&function.env),
+                });
+                self.functions.insert(Rc::as_ptr(&function), copy.clone());
+                Object::Function(copy)
+            }
+            Object::Macro(macro_object) => Object::Macro(Rc::new(object::Macro {
+                parameters: macro_object.parameters.clone(),
+                body: macro_object.body.clone(),
+                env: self.scope(&macro_object.env),
+            })),
+            Object::Array(elements) => Object::Array(elements.iter().map(|element| self.value(element)).collect()),
+            Object::StringBuilder(buffer) => Object::StringBuilder(Rc::new(RefCell::new(buffer.borrow().clone()))),
+            Object::ReturnValue(value) => Object::ReturnValue(Box::new(self.value(*value))),
+            value => value,
+        }
+    }
+}
+
+fn into_result(obj: Object) -> Result<Value, MonkeyError> {
+    match obj {
+        Object::Error(message) => Err(MonkeyError::Runtime(message)),
+        Object::ReturnValue(value) => Ok(Value::from(*value)),
+        _ => Ok(Value::from(obj)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use evaluator::SharedBuffer;
+
+    #[test]
+    fn test_eval_values() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.eval("1 + 2"), Ok(Value::Integer(3)));
+        assert_eq!(interp.eval("1.5 * 2"), Ok(Value::Float(3.0)));
+        assert_eq!(interp.eval("1 < 2"), Ok(Value::Boolean(true)));
+        assert_eq!(interp.eval("\"mon\" + \"key\""), Ok(Value::String("monkey".to_string())));
+        assert_eq!(interp.eval(""), Ok(Value::Null));
+        assert!(matches!(interp.eval("fn(x) { x }"), Ok(Value::Function(_))));
+        assert!(matches!(interp.eval("puts"), Ok(Value::Function(_))));
+    }
+
+    #[test]
+    fn test_state_persists_between_calls() {
+        let mut interp = Interpreter::new();
+        interp.eval("let counter = 0; let bump = fn() { counter += 1 };").unwrap();
+        interp.eval("bump(); bump();").unwrap();
+        assert_eq!(interp.eval("counter"), Ok(Value::Integer(2)));
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.eval("let = 1;"), Err(MonkeyError::Parse(vec![
+            "expected next token to be IDENT, got ASSIGN instead".to_string(),
+        ])));
+        assert!(matches!(interp.eval("fn(a, a) { a }"), Err(MonkeyError::Validation(_))));
+        assert_eq!(interp.eval("1 + true"), Err(MonkeyError::Runtime("type mismatch: INTEGER + BOOLEAN".to_string())));
+        assert_eq!(interp.eval("1 + true").unwrap_err().to_string(), "type mismatch: INTEGER + BOOLEAN");
+    }
+
+    #[test]
+    fn test_register_native() {
+        let mut interp = Interpreter::new();
+        interp.register_native("shout", |args| match args.as_slice() {
+            [Value::String(s)] => Ok(Value::String(s.to_uppercase())),
+            _ => Err("shout expects one string".to_string()),
+        });
+        assert_eq!(interp.eval("shout(\"hi\" + \"!\")"), Ok(Value::String("HI!".to_string())));
+        assert_eq!(interp.eval("shout(1)"), Err(MonkeyError::Runtime("shout expects one string".to_string())));
+        assert_eq!(interp.eval("shout"), Ok(Value::Function("native function shout".to_string())));
+        interp.register_native("total", |args| {
+            let numbers = Vec::<i64>::try_from(args.into_iter().next().unwrap_or(Value::Null))?;
+            Ok(numbers.iter().sum::<i64>().into())
+        });
+        assert_eq!(interp.eval("total([1, 2, 3])"), Ok(Value::Integer(6)));
+        assert_eq!(interp.eval("total([1, \"2\"])"), Err(MonkeyError::Runtime("element 1: expected INTEGER, got STRING".to_string())));
+        assert_eq!(interp.eval("[1, [\"a\"]]"), Ok(Value::from(vec![Value::from(1), Value::from(vec!["a"])])));
+    }
+
+    #[test]
+    fn test_call() {
+        let mut interp = Interpreter::new();
+        interp.eval("let add = fn(a, b) { a + b }; let count = 0; let bump = fn() { count += 1; count };").unwrap();
+        assert_eq!(interp.call("add", &[Value::Integer(1), Value::Integer(2)]), Ok(Value::Integer(3)));
+        assert_eq!(interp.call("add", &["a".into(), "b".into()]), Ok(Value::from("ab")));
+        assert_eq!(interp.call("bump", &[]), Ok(Value::Integer(1)));
+        assert_eq!(interp.call("bump", &[]), Ok(Value::Integer(2)));
+        assert_eq!(interp.eval("count"), Ok(Value::Integer(2)));
+        assert_eq!(interp.call("len", &["four".into()]), Ok(Value::Integer(4)));
+
+        assert_eq!(interp.call("missing", &[]), Err(MonkeyError::Runtime("identifier not found: missing".to_string())));
+        assert_eq!(interp.call("count", &[]), Err(MonkeyError::Runtime("not a function: INTEGER".to_string())));
+        assert_eq!(interp.call("add", &[Value::Integer(1), Value::Boolean(true)]), Err(MonkeyError::Runtime("type mismatch: INTEGER + BOOLEAN".to_string())));
+        assert!(interp.call("add", &[Value::Integer(1)]).is_err());
+        assert_eq!(
+            interp.call("add", &[Value::Function("f".to_string()), Value::Integer(1)]),
+            Err(MonkeyError::Runtime("cannot pass function value f from the host".to_string())),
+        );
+    }
+
+    #[test]
+    fn test_spawn_child() {
+        let mut parent = Interpreter::with_limits(Limits { max_call_depth: Some(50), ..Limits::default() });
+        parent.register_native("double", |args| Ok(Value::Integer(i64::try_from(args[0].clone())? * 2)));
+        parent.eval("let secret = 21; let count = 0; let swap = macro(a, b) { quote(unquote(b) - unquote(a)) };").unwrap();
+
+        let mut child = parent.spawn_child(Limits::sandbox());
+        assert_eq!(child.eval("double(secret)"), Ok(Value::Integer(42)));
+        assert_eq!(child.eval("swap(1, 3)"), Ok(Value::Integer(2)));
+        assert_eq!(child.eval("count = 5; let mine = 1; count"), Ok(Value::Integer(5)));
+        assert!(child.eval("abs = 0").is_err());
+        assert_eq!(parent.eval("count"), Ok(Value::Integer(0)));
+        assert_eq!(parent.eval("abs(-1)"), Ok(Value::Integer(1)));
+        assert!(parent.eval("mine").is_err());
+
+        // Closures the child inherits work on the child's copies of the
+        // scopes they closed over.
+        parent.eval("let bump = fn() { count += 1 }; let counter = fn() { let n = 0; fn() { n += 1 } }(); counter(); let sb = string_builder();").unwrap();
+        let mut child = parent.spawn_child(Limits::sandbox());
+        assert_eq!(child.eval("bump(); bump(); count"), Ok(Value::Integer(2)));
+        assert_eq!(child.eval("counter()"), Ok(Value::Integer(2)));
+        assert_eq!(child.eval("append(sb, \"x\"); build(sb)"), Ok(Value::String("x".to_string())));
+        assert_eq!(child.eval("let f = bump; f == bump"), Ok(Value::Boolean(true)));
+        assert_eq!(parent.eval("[count, counter(), len(build(sb))]").unwrap().to_string(), "[0, 2, 0]");
+
+        assert!(child.eval("let f = fn(n) { if (n > 0) { f(n - 1) } else { 0 } }; f(100)").unwrap_err().to_string().contains("depth"));
+        assert_eq!(child.eval("import \"lib.monkey\""), Err(MonkeyError::Runtime("cannot import lib.monkey: imports are disabled".to_string())));
+        let grandchild = child.spawn_child(Limits { max_steps: Some(1000), ..Limits::default() });
+        assert_eq!(grandchild.evaluator.limits.max_call_depth, Some(50));
+        assert_eq!(grandchild.evaluator.limits.max_steps, Some(1000));
+        assert!(!grandchild.evaluator.limits.allow_imports);
+
+        assert_eq!(child.eval("puts(\"quiet\")"), Ok(Value::Null));
+        let output = SharedBuffer::default();
+        child.set_output(Box::new(output.clone()));
+        child.eval("puts(secret)").unwrap();
+        assert_eq!(output.contents(), "21\n");
+    }
+
+    #[test]
+    fn test_step_limit_applies_per_call() {
+        let mut interp = Interpreter::with_limits(Limits { max_steps: Some(200), ..Limits::default() });
+        interp.eval("let spin = fn(n) { let i = 0; while (i < n) { i += 1 }; i };").unwrap();
+        assert_eq!(interp.call("spin", &[Value::Integer(10)]), Ok(Value::Integer(10)));
+        assert_eq!(interp.call("spin", &[Value::Integer(10)]), Ok(Value::Integer(10)));
+        assert_eq!(
+            interp.call("spin", &[Value::Integer(1000)]),
+            Err(MonkeyError::Runtime("resource limit exceeded: more than 200 evaluation steps".to_string())),
+        );
+        assert_eq!(interp.eval("spin(5)"), Ok(Value::Integer(5)));
+    }
+
+    #[test]
+    fn test_eval_fragments() {
+        use ast::builder::*;
+
+        let mut interp = Interpreter::new();
+        interp.eval("let x = 40; let inc = fn(n) { n + 1 };").unwrap();
+        assert_eq!(interp.eval_expression(call(ident("inc"), vec![infix(ident("x"), "+", int(1))])), Ok(Value::Integer(42)));
+        assert_eq!(interp.eval_statement(let_stmt("y", int(2))), Ok(Value::Null));
+        assert_eq!(interp.eval_statement(return_stmt(ident("y"))), Ok(Value::Integer(2)));
+        assert_eq!(interp.eval("x + y"), Ok(Value::Integer(42)));
+        assert_eq!(interp.eval_expression(ident("missing")), Err(MonkeyError::Runtime("identifier not found: missing".to_string())));
+
+        let frame = object::Environment::new_enclosed(interp.environment());
+        frame.borrow_mut().set("x".to_string(), Object::Integer(1));
+        assert_eq!(interp.eval_expression_in(infix(ident("x"), "+", ident("y")), frame), Ok(Value::Integer(3)));
+    }
+
+    #[test]
+    fn test_macros() {
+        let mut interp = Interpreter::new();
+        interp.eval("let unless = macro(condition, consequence, alternative) {
+            quote(if (!(unquote(condition))) { unquote(consequence); } else { unquote(alternative); });
+        };").unwrap();
+        assert_eq!(interp.eval("unless(10 > 5, 1, 2)"), Ok(Value::Integer(2)));
+        assert_eq!(interp.eval("unless"), Err(MonkeyError::Runtime("identifier not found: unless".to_string())));
+        assert_eq!(interp.eval("let m = macro() { 1 }; m()"), Err(MonkeyError::Macro("macro m must return a QUOTE, got INTEGER".to_string())));
+    }
+
+    #[test]
+    fn test_type_of() {
+        let mut interp = Interpreter::new();
+        interp.eval("let n = 2; let name = \"monkey\"; let inc = fn(x) { x + 1 };").unwrap();
+        assert_eq!(interp.type_of("n * 1.5"), Ok(Type::FLOAT));
+        assert_eq!(interp.type_of("upper(name)"), Ok(Type::STRING));
+        assert_eq!(interp.type_of("inc"), Ok(Type::FUNCTION));
+        assert_eq!(interp.type_of("inc(n)"), Ok(Type::UNKNOWN));
+        assert_eq!(interp.type_of("let sq = fn(x) { x * x }; sq(n)"), Ok(Type::INTEGER));
+        assert_eq!(interp.type_of("puts(\"not evaluated\"); n = true"), Ok(Type::BOOLEAN));
+        assert_eq!(interp.eval("n"), Ok(Value::Integer(2)));
+        assert!(matches!(interp.type_of("let = 1;"), Err(MonkeyError::Parse(_))));
+    }
+
+    #[test]
+    fn test_globals() {
+        let mut interp = Interpreter::new();
+        interp.define_globals(HashMap::from([
+            ("price".to_string(), Value::Float(9.5)),
+            ("quantity".to_string(), Value::Integer(3)),
+            ("customer".to_string(), Value::String("ada".to_string())),
+        ]));
+        interp.eval("let total = price * quantity; let label = upper(customer); quantity = 4;").unwrap();
+
+        let globals = interp.extract_globals();
+        assert_eq!(globals.len(), 5);
+        assert_eq!(globals["total"], Value::Float(28.5));
+        assert_eq!(globals["label"], Value::String("ADA".to_string()));
+        assert_eq!(globals["quantity"], Value::Integer(4));
+        assert!(!globals.contains_key("abs"));
+
+        interp.define_globals(HashMap::from([("price".to_string(), Value::Null)]));
+        assert_eq!(interp.eval("price"), Ok(Value::Null));
+    }
+
+    #[test]
+    fn test_rules() {
+        let mut interp = Interpreter::new();
+        interp.eval("let adult = fn(age) { age >= 18 };").unwrap();
+        let rule = interp.compile_rule("if (adult(age)) { contains(country, \"NL\") } else { score > 10 * 5 }").unwrap();
+
+        let bindings = |age, country: &str, score| HashMap::from([
+            ("age".to_string(), Value::Integer(age)),
+            ("country".to_string(), Value::String(country.to_string())),
+            ("score".to_string(), Value::Float(score)),
+        ]);
+        assert_eq!(interp.eval_rule(&rule, &bindings(30, "NL", 75.5)), Ok(true));
+        assert_eq!(interp.eval_rule(&rule, &bindings(30, "BE", 75.5)), Ok(false));
+        assert_eq!(interp.eval_rule(&rule, &bindings(16, "BE", 75.5)), Ok(true));
+        assert_eq!(interp.eval_rule(&rule, &bindings(16, "NL", 12.0)), Ok(false));
+        assert_eq!(interp.eval_rule(&rule, &HashMap::new()), Err(MonkeyError::Runtime("identifier not found: age".to_string())));
+        assert_eq!(interp.eval("age"), Err(MonkeyError::Runtime("identifier not found: age".to_string())));
+
+        let rule = interp.compile_rule("limit").unwrap();
+        let bindings = HashMap::from([("limit".to_string(), Value::Integer(3))]);
+        assert_eq!(interp.eval_rule(&rule, &bindings), Err(MonkeyError::Runtime("a rule must evaluate to a BOOLEAN, got 3".to_string())));
+
+        let mut error = |source| interp.compile_rule(source).err();
+        assert_eq!(error("let x = 1; x > 0"), Some(MonkeyError::Parse(vec!["a rule must be a single expression".to_string()])));
+        assert_eq!(error("1 + 2"), Some(MonkeyError::Validation(vec!["a rule must be a BOOLEAN expression, got INTEGER".to_string()])));
+        assert!(matches!(error("x >"), Some(MonkeyError::Parse(_))));
+    }
+
+    #[test]
+    fn test_without_prelude() {
+        assert_eq!(Interpreter::new().eval("abs(-1)"), Ok(Value::Integer(1)));
+        let mut interp = Interpreter::without_prelude(Limits::default());
+        assert_eq!(interp.eval("abs(-1)"), Err(MonkeyError::Runtime("identifier not found: abs".to_string())));
+    }
+
+    #[test]
+    fn test_output_and_limits() {
+        let output = SharedBuffer::default();
+        let mut interp = Interpreter::with_limits(Limits { max_string_length: Some(4), ..Limits::default() });
+        interp.set_output(Box::new(output.clone()));
+        assert_eq!(interp.eval("puts(\"hi\")"), Ok(Value::Null));
+        assert_eq!(output.contents(), "hi\n");
+        assert!(matches!(interp.eval("\"abc\" + \"de\""), Err(MonkeyError::Runtime(_))));
+    }
+
+    #[test]
+    fn test_script_args() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.eval("args()"), Ok(Value::Array(vec![])));
+        interp.set_script_args(vec!["input.txt".to_string()]);
+        assert_eq!(interp.eval("first(args())"), Ok(Value::String("input.txt".to_string())));
+    }
+}
@@ -0,0 +1,105 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use evaluator::Evaluator;
+use lexer::Lexer;
+use parser::Parser;
+
+// The standard library, in the order it is loaded.
+pub const FILES: &[(&str, &str)] = &[
+    ("core.monkey", include_str!("stdlib/core.monkey")),
+    ("math.monkey", include_str!("stdlib/math.monkey")),
+    ("array.monkey", include_str!("stdlib/array.monkey")),
+    ("string.monkey", include_str!("stdlib/string.monkey")),
+];
+
+// The prelude lives in its own environment, and the returned scope encloses
+// it, so user bindings shadow the helpers and listing the scope shows only
+// what the user defined. Imported modules see the prelude too. It is sealed
+// once loaded: assigning to a helper from user code is an error rather than
+// a change to what the other helpers call.
+pub fn environment(evaluator: &mut Evaluator) -> Rc<RefCell<object::Environment>> {
+    let prelude = Rc::new(RefCell::new(object::Environment::new()));
+    // The prelude has to load whole, however tightly the code run after it
+    // is limited.
+    let limits = std::mem::take(&mut evaluator.limits);
+    for (_, source) in FILES {
+        let program = Parser::new(Lexer::new(source)).parse_program();
+        evaluator.evaluate_program(program, prelude.clone());
+    }
+    evaluator.limits = limits;
+    prelude.borrow_mut().sealed = true;
+    evaluator.set_module_environment(prelude.clone());
+    object::Environment::new_enclosed(prelude)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Interpreter, Value};
+
+    #[test]
+    fn test_prelude_is_clean() {
+        for (name, source) in FILES {
+            let mut parser = Parser::new(Lexer::new(source));
+            let program = parser.parse_program();
+            assert!(parser.errors().is_empty(), "{}: parser errors: {:?}", name, parser.errors());
+            let diagnostics: Vec<String> = validator::validate(&program).iter().map(|d| d.to_string()).collect();
+            assert!(diagnostics.is_empty(), "{}: diagnostics: {:?}", name, diagnostics);
+        }
+
+        let mut evaluator = Evaluator::new();
+        let env = environment(&mut evaluator);
+        assert!(env.borrow().scope.is_empty());
+        assert!(env.borrow().get("compose").is_some());
+    }
+
+    #[test]
+    fn test_prelude_helpers() {
+        let cases = [
+            ("identity(5)", Value::Integer(5)),
+            ("compose(fn(x) { x * 2 }, fn(x) { x + 1 })(4)", Value::Integer(10)),
+            ("let total = 0; times(4, fn(i) { total += i })", Value::Integer(4)),
+            ("let total = 0; times(4, fn(i) { total += i }); total", Value::Integer(6)),
+            ("min(3, -2)", Value::Integer(-2)),
+            ("max(1.5, 1)", Value::Float(1.5)),
+            ("min_by(-5, 3, abs)", Value::Integer(3)),
+            ("max_by(-5, 3, abs)", Value::Integer(-5)),
+            ("abs(-7)", Value::Integer(7)),
+            ("clamp(12, 0, 10)", Value::Integer(10)),
+            ("pow(2, 10)", Value::Integer(1024)),
+            ("gcd(-12, 18)", Value::Integer(6)),
+            ("sum([1, 2, 3]) + product([2, 3, 4])", Value::Integer(30)),
+            ("first([]) == null", Value::Boolean(true)),
+            ("last([1, 2, 3])", Value::Integer(3)),
+            ("count([1, 2, 3, 4], fn(x) { x % 2 == 0 })", Value::Integer(2)),
+            ("any([1, 3], fn(x) { x > 2 })", Value::Boolean(true)),
+            ("all([1, 3], fn(x) { x > 2 })", Value::Boolean(false)),
+            ("find([1, 5, 7], fn(x) { x > 2 })", Value::Integer(5)),
+            ("reverse([1, 2, 3])", Value::Array(vec![Value::Integer(3), Value::Integer(2), Value::Integer(1)])),
+            ("repeat(\"ab\", 3)", Value::String("ababab".to_string())),
+            ("starts_with(\"monkey\", \"mon\")", Value::Boolean(true)),
+            ("starts_with(\"mo\", \"mon\")", Value::Boolean(false)),
+            ("ends_with(\"monkey\", \"key\")", Value::Boolean(true)),
+            ("ends_with(\"ey\", \"key\")", Value::Boolean(false)),
+            ("pad_left(\"7\", 3, \"0\") + pad_right(\"ab\", 1, \".\")", Value::String("007ab".to_string())),
+            ("len(words(\"  a  b c \"))", Value::Integer(3)),
+            ("len(lines(\"a\\nb\"))", Value::Integer(2)),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(Interpreter::new().eval(source), Ok(expected), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_prelude_is_sealed() {
+        let mut interp = Interpreter::new();
+        assert_eq!(
+            interp.eval("min = fn(a, b) { a }"),
+            Err(crate::MonkeyError::Runtime("cannot assign to min: it is read-only here, bind it with let to shadow it".to_string()))
+        );
+        // A let in user code shadows the helper without changing what the
+        // other helpers call.
+        assert_eq!(interp.eval("let min = fn(a, b) { 0 }; [min(3, 4), clamp(12, 0, 10)]"), Ok(Value::Array(vec![Value::Integer(0), Value::Integer(10)])));
+    }
+}
@@ -0,0 +1,222 @@
+use std::fmt;
+
+use object::Object;
+
+// Monkey values as seen by host code. Functions can be passed back out but
+// not in, so they are only described.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    String(String),
+    Array(Vec<Value>),
+    Function(String),
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Null => "NULL",
+            Value::Integer(_) => "INTEGER",
+            Value::Float(_) => "FLOAT",
+            Value::Boolean(_) => "BOOLEAN",
+            Value::String(_) => "STRING",
+            Value::Array(_) => "ARRAY",
+            Value::Function(_) => "FUNCTION",
+        }
+    }
+}
+
+impl From<Object> for Value {
+    fn from(obj: Object) -> Self {
+        match obj {
+            Object::Integer(value) => Value::Integer(value),
+            Object::Float(value) => Value::Float(value),
+            Object::Boolean(value) => Value::Boolean(value),
+            Object::String(value) => Value::String(value.to_string()),
+            Object::Array(elements) => Value::Array(elements.iter().map(Value::from).collect()),
+            Object::Function(_) | Object::Builtin(_) | Object::NativeFunction(_) => Value::Function(obj.inspect()),
+            _ => Value::Null,
+        }
+    }
+}
+
+impl From<Value> for Object {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Null => Object::Null,
+            Value::Integer(value) => Object::Integer(value),
+            Value::Float(value) => Object::Float(value),
+            Value::Boolean(value) => Object::Boolean(value),
+            Value::String(value) => Object::String(value.into()),
+            Value::Array(elements) => {
+                let elements: Vec<Object> = elements.into_iter().map(Object::from).collect();
+                match elements.iter().find(|e| e.object_type() == object::ObjectType::ERROR) {
+                    Some(error) => error.clone(),
+                    None => Object::Array(elements.into()),
+                }
+            },
+            Value::Function(name) => Object::Error(format!("cannot pass function value {} from the host", name)),
+        }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Integer(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Float(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Boolean(value)
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.to_string())
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(elements: Vec<T>) -> Self {
+        Value::Array(elements.into_iter().map(Into::into).collect())
+    }
+}
+
+// None becomes null, as a missing value does in Monkey.
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+// The errors read like Monkey's own, so a native function can pass them on
+// with `?`.
+fn mismatch(expected: &str, found: &Value) -> String {
+    format!("expected {}, got {}", expected, found.type_name())
+}
+
+impl TryFrom<Value> for i64 {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Integer(value) => Ok(value),
+            other => Err(mismatch("INTEGER", &other)),
+        }
+    }
+}
+
+// Integers are accepted too, as arithmetic in Monkey promotes them.
+impl TryFrom<Value> for f64 {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Float(value) => Ok(value),
+            Value::Integer(value) => Ok(value as f64),
+            other => Err(mismatch("FLOAT", &other)),
+        }
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Boolean(value) => Ok(value),
+            other => Err(mismatch("BOOLEAN", &other)),
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::String(value) => Ok(value),
+            other => Err(mismatch("STRING", &other)),
+        }
+    }
+}
+
+impl<T: TryFrom<Value, Error = String>> TryFrom<Value> for Vec<T> {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Array(elements) => elements.into_iter()
+                .enumerate()
+                .map(|(i, element)| T::try_from(element).map_err(|err| format!("element {}: {}", i, err)))
+                .collect(),
+            other => Err(mismatch("ARRAY", &other)),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Integer(value) => write!(f, "{}", value),
+            Value::Float(value) => write!(f, "{:?}", value),
+            Value::Boolean(value) => write!(f, "{}", value),
+            Value::String(value) => write!(f, "{}", value),
+            Value::Array(elements) => {
+                let elements: Vec<String> = elements.iter().map(|e| e.to_string()).collect();
+                write!(f, "[{}]", elements.join(", "))
+            },
+            Value::Function(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(Value::from(3), Value::Integer(3));
+        assert_eq!(Value::from("a"), Value::String("a".to_string()));
+        assert_eq!(Value::from(vec![1, 2]), Value::Array(vec![Value::Integer(1), Value::Integer(2)]));
+        assert_eq!(Value::from(None::<bool>), Value::Null);
+
+        assert_eq!(i64::try_from(Value::Integer(3)), Ok(3));
+        assert_eq!(f64::try_from(Value::Integer(3)), Ok(3.0));
+        assert_eq!(bool::try_from(Value::Boolean(true)), Ok(true));
+        assert_eq!(String::try_from(Value::from("a")), Ok("a".to_string()));
+        assert_eq!(Vec::<Vec<i64>>::try_from(Value::from(vec![vec![1], vec![2, 3]])), Ok(vec![vec![1], vec![2, 3]]));
+        assert_eq!(i64::try_from(Value::from("a")), Err("expected INTEGER, got STRING".to_string()));
+        assert_eq!(Vec::<i64>::try_from(Value::from(vec![Value::Integer(1), Value::Null])), Err("element 1: expected INTEGER, got NULL".to_string()));
+        assert_eq!(String::try_from(Value::Integer(1)), Err("expected STRING, got INTEGER".to_string()));
+    }
+
+    #[test]
+    fn test_object_round_trip() {
+        let value = Value::from(vec![Value::from(1), Value::from("a"), Value::from(vec![true])]);
+        let obj = Object::from(value.clone());
+        assert_eq!(obj.inspect(), "[1, a, [true]]");
+        assert_eq!(value.to_string(), "[1, a, [true]]");
+        assert_eq!(Value::from(obj), value);
+        assert_eq!(Object::from(Value::from(vec![Value::Function("f".to_string())])).inspect(), "cannot pass function value f from the host");
+    }
+}
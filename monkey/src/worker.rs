@@ -0,0 +1,189 @@
+use std::future::Future;
+use std::io::Write;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+use crate::{CancellationToken, Interpreter, Limits, MonkeyError, Value};
+
+// Where the worker leaves its result, and who to wake when it does.
+#[derive(Default)]
+struct Slot {
+    result: Option<Result<Value, MonkeyError>>,
+    waker: Option<Waker>,
+}
+
+#[derive(Default)]
+struct Shared {
+    slot: Mutex<Slot>,
+    done: Condvar,
+}
+
+// An evaluation running on its own thread. Awaiting the handle, or calling
+// wait, gives its result.
+pub struct EvalHandle {
+    shared: Arc<Shared>,
+    cancellation: CancellationToken,
+}
+
+impl Interpreter {
+    // Evaluates source on a new thread, in an interpreter of its own made
+    // there with limits, as Interpreter::with_limits would make it. What
+    // the program prints is written to output as it goes, so a host can
+    // show it before the evaluation finishes. The thread's stack is sized
+    // for the call depth limits allow, since a stack overflow cannot be
+    // caught and would abort the host.
+    pub fn spawn_eval(limits: Limits, source: &str, output: Box<dyn Write + Send>) -> EvalHandle {
+        let shared = Arc::new(Shared::default());
+        let cancellation = CancellationToken::default();
+        let (worker_shared, token, source) = (shared.clone(), cancellation.clone(), source.to_string());
+        let worker = thread::Builder::new().stack_size(limits.stack_size()).spawn(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                let mut interpreter = Interpreter::with_limits(limits);
+                interpreter.evaluator.output = output;
+                interpreter.evaluator.cancellation = token.linked();
+                interpreter.eval(&source)
+            }));
+            let result = result.unwrap_or_else(|_| Err(MonkeyError::Runtime("evaluation panicked".to_string())));
+            let mut slot = worker_shared.slot.lock().unwrap();
+            slot.result = Some(result);
+            if let Some(waker) = slot.waker.take() {
+                waker.wake();
+            }
+            worker_shared.done.notify_all();
+        });
+        if let Err(err) = worker {
+            shared.slot.lock().unwrap().result = Some(Err(MonkeyError::Runtime(format!("could not start evaluation thread: {}", err))));
+        }
+        EvalHandle { shared, cancellation }
+    }
+}
+
+impl EvalHandle {
+    // Stops the evaluation at its next step, which then fails as
+    // interrupted. Cancelling one that has finished does nothing.
+    pub fn cancel(&self) {
+        self.cancellation.cancel();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.shared.slot.lock().unwrap().result.is_some()
+    }
+
+    // Blocks until the evaluation finishes.
+    pub fn wait(self) -> Result<Value, MonkeyError> {
+        let mut slot = self.shared.slot.lock().unwrap();
+        loop {
+            if let Some(result) = slot.result.take() {
+                return result;
+            }
+            slot = self.shared.done.wait(slot).unwrap();
+        }
+    }
+}
+
+impl Future for EvalHandle {
+    type Output = Result<Value, MonkeyError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut slot = self.shared.slot.lock().unwrap();
+        match slot.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::task::Wake;
+    use std::time::Duration;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Buffer {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    struct Unpark(thread::Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    // Enough of an executor to drive one future on this thread.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    #[test]
+    fn test_spawn_eval() {
+        let output = Buffer::default();
+        let handle = Interpreter::spawn_eval(Limits::default(), "puts(\"summing\"); 1 + 2 + 3", Box::new(output.clone()));
+        assert_eq!(block_on(handle), Ok(Value::Integer(6)));
+        assert_eq!(output.text(), "summing\n");
+
+        let handle = Interpreter::spawn_eval(Limits::default(), "let x = ", Box::new(io::sink()));
+        assert!(matches!(handle.wait(), Err(MonkeyError::Parse(_))));
+    }
+
+    #[test]
+    fn test_spawn_eval_deep_recursion() {
+        let countdown = "let f = fn(n) { if (n == 0) { 0 } else { 1 + f(n - 1) } }; f(990)";
+        let limits = Limits { max_call_depth: Some(1000), ..Limits::default() };
+        let handle = Interpreter::spawn_eval(limits, countdown, Box::new(io::sink()));
+        assert_eq!(handle.wait(), Ok(Value::Integer(990)));
+
+        let handle = Interpreter::spawn_eval(Limits::default(), countdown, Box::new(io::sink()));
+        assert_eq!(handle.wait(), Err(MonkeyError::Runtime("stack overflow: call depth exceeds 200".to_string())));
+    }
+
+    #[test]
+    fn test_cancel_spawned_eval() {
+        let output = Buffer::default();
+        let handle = Interpreter::spawn_eval(Limits::default(), "puts(\"started\"); while (true) {}", Box::new(output.clone()));
+        while output.text().is_empty() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(!handle.is_finished());
+        handle.cancel();
+        assert_eq!(handle.wait(), Err(MonkeyError::Runtime("evaluation interrupted".to_string())));
+        assert_eq!(output.text(), "started\n");
+
+        // Cancelled before the worker has started evaluating.
+        let handle = Interpreter::spawn_eval(Limits::default(), "while (true) {}", Box::new(io::sink()));
+        handle.cancel();
+        assert_eq!(block_on(handle), Err(MonkeyError::Runtime("evaluation interrupted".to_string())));
+    }
+}
@@ -0,0 +1,169 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::Object;
+
+// An array value. Arrays never change; push and the others return a new
+// array and leave the one they were given alone. Arrays share their
+// elements where they can: an array is the first len elements of a buffer,
+// and pushing onto the array that reaches the end of its buffer extends the
+// buffer in place, so building an array one push at a time copies nothing.
+// Pushing onto any other array, as a second push onto the same array does,
+// copies its elements into a buffer of its own first.
+#[derive(Clone)]
+pub struct Array {
+    // Only ever appended to, so the elements of every array sharing it
+    // stay as they were.
+    buffer: Rc<RefCell<Vec<Object>>>,
+    len: usize,
+}
+
+impl Array {
+    pub fn new(elements: Vec<Object>) -> Array {
+        Array { len: elements.len(), buffer: Rc::new(RefCell::new(elements)) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<Object> {
+        if index >= self.len {
+            return None;
+        }
+        Some(self.buffer.borrow()[index].clone())
+    }
+
+    // The buffer is not kept borrowed between elements, so the caller may
+    // run code that pushes onto this array while iterating.
+    pub fn iter(&self) -> impl Iterator<Item = Object> + '_ {
+        (0..self.len).map(|i| self.buffer.borrow()[i].clone())
+    }
+
+    pub fn to_vec(&self) -> Vec<Object> {
+        self.buffer.borrow()[..self.len].to_vec()
+    }
+
+    pub fn push(&self, value: Object) -> Array {
+        let mut buffer = self.buffer.borrow_mut();
+        if buffer.len() == self.len {
+            buffer.push(value);
+            return Array { buffer: self.buffer.clone(), len: self.len + 1 };
+        }
+        let mut elements = Vec::with_capacity(self.len + 1);
+        elements.extend_from_slice(&buffer[..self.len]);
+        elements.push(value);
+        Array::new(elements)
+    }
+
+    // All but the last element, sharing them with this array. None for an
+    // empty array.
+    pub fn pop(&self) -> Option<Array> {
+        let len = self.len.checked_sub(1)?;
+        Some(Array { buffer: self.buffer.clone(), len })
+    }
+
+    // None unless index is at most len.
+    pub fn insert(&self, index: usize, value: Object) -> Option<Array> {
+        if index > self.len {
+            return None;
+        }
+        if index == self.len {
+            return Some(self.push(value));
+        }
+        let mut elements = self.to_vec();
+        elements.insert(index, value);
+        Some(Array::new(elements))
+    }
+
+    // None unless index is below len.
+    pub fn remove(&self, index: usize) -> Option<Array> {
+        if index >= self.len {
+            return None;
+        }
+        if index + 1 == self.len {
+            return self.pop();
+        }
+        let mut elements = self.to_vec();
+        elements.remove(index);
+        Some(Array::new(elements))
+    }
+
+    // Whether both are the same elements of the same buffer.
+    pub fn ptr_eq(this: &Array, other: &Array) -> bool {
+        Rc::ptr_eq(&this.buffer, &other.buffer) && this.len == other.len
+    }
+
+    // The number of arrays sharing this one's buffer, itself included.
+    pub fn strong_count(this: &Array) -> usize {
+        Rc::strong_count(&this.buffer)
+    }
+
+    // Every array sharing the buffer reaches all of it, and the cycle
+    // collector counts it that way.
+    pub(crate) fn buffer(&self) -> &Rc<RefCell<Vec<Object>>> {
+        &self.buffer
+    }
+}
+
+impl From<Vec<Object>> for Array {
+    fn from(elements: Vec<Object>) -> Array {
+        Array::new(elements)
+    }
+}
+
+impl FromIterator<Object> for Array {
+    fn from_iter<I: IntoIterator<Item = Object>>(iter: I) -> Array {
+        Array::new(iter.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn integers(array: &Array) -> Vec<i64> {
+        array.iter().map(|e| match e {
+            Object::Integer(value) => value,
+            other => panic!("not an integer: {:?}", other),
+        }).collect()
+    }
+
+    #[test]
+    fn test_push_shares_elements() {
+        let empty = Array::new(vec![]);
+        let one = empty.push(Object::Integer(1));
+        let two = one.push(Object::Integer(2));
+        assert!(Rc::ptr_eq(&one.buffer, &two.buffer));
+        assert_eq!((integers(&empty), integers(&one), integers(&two)), (vec![], vec![1], vec![1, 2]));
+
+        // one no longer reaches the end of the buffer, so pushing onto it
+        // again copies instead of overwriting two's second element.
+        let other = one.push(Object::Integer(3));
+        assert!(!Rc::ptr_eq(&one.buffer, &other.buffer));
+        assert_eq!((integers(&two), integers(&other)), (vec![1, 2], vec![1, 3]));
+
+        let popped = two.pop().unwrap();
+        assert_eq!(integers(&popped), vec![1]);
+        assert!(Array::ptr_eq(&popped, &one));
+        assert_eq!(integers(&popped.push(Object::Integer(4))), vec![1, 4]);
+        assert_eq!(integers(&two), vec![1, 2]);
+        assert!(empty.pop().is_none());
+    }
+
+    #[test]
+    fn test_insert_and_remove() {
+        let array: Array = (1..=3).map(Object::Integer).collect();
+        assert_eq!(integers(&array.insert(0, Object::Integer(0)).unwrap()), vec![0, 1, 2, 3]);
+        assert_eq!(integers(&array.insert(3, Object::Integer(4)).unwrap()), vec![1, 2, 3, 4]);
+        assert!(array.insert(4, Object::Integer(5)).is_none());
+        assert_eq!(integers(&array.remove(1).unwrap()), vec![1, 3]);
+        assert_eq!(integers(&array.remove(2).unwrap()), vec![1, 2]);
+        assert!(array.remove(3).is_none());
+        assert_eq!(integers(&array), vec![1, 2, 3]);
+    }
+}
@@ -0,0 +1,247 @@
+use std::cell::RefCell;
+use std::collections::{hash_map, HashMap, HashSet};
+use std::rc::{Rc, Weak};
+
+use crate::{Environment, Function, Macro, Object};
+
+// Closures hold the environment they were created in, and that environment
+// often holds the closure, so reference counting alone never frees either.
+// Environments captured by a closure are tracked here, and collect finds
+// the ones nothing outside the tracked graph can reach and empties them,
+// which breaks their cycles.
+
+// Collections start once this many environments are tracked, and after
+// that once the count has doubled since the last collection.
+const MIN_THRESHOLD: usize = 1000;
+
+struct Tracked {
+    environments: HashMap<usize, Weak<RefCell<Environment>>>,
+    threshold: usize,
+}
+
+thread_local! {
+    static TRACKED: RefCell<Tracked> = RefCell::new(Tracked {
+        environments: HashMap::new(),
+        threshold: MIN_THRESHOLD,
+    });
+}
+
+pub fn track(env: &Rc<RefCell<Environment>>) {
+    TRACKED.with(|tracked| {
+        tracked.borrow_mut().environments.insert(Rc::as_ptr(env) as usize, Rc::downgrade(env));
+    });
+}
+
+// The number of tracked environments still alive.
+pub fn tracked() -> usize {
+    TRACKED.with(|tracked| tracked.borrow().environments.values().filter(|env| env.strong_count() > 0).count())
+}
+
+// Collects once enough environments have been tracked since the last time.
+// Returns the number of environments freed.
+pub fn maybe_collect() -> usize {
+    let due = TRACKED.with(|tracked| {
+        let tracked = tracked.borrow();
+        tracked.environments.len() >= tracked.threshold
+    });
+    if !due {
+        return 0;
+    }
+    let freed = collect();
+    TRACKED.with(|tracked| {
+        let mut tracked = tracked.borrow_mut();
+        tracked.threshold = MIN_THRESHOLD.max(tracked.environments.len() * 2);
+    });
+    freed
+}
+
+// A value that can hold an environment, directly or through the values it
+// holds.
+enum Node {
+    Environment(Rc<RefCell<Environment>>),
+    Function(Rc<Function>),
+    Macro(Rc<Macro>),
+    Array(Rc<RefCell<Vec<Object>>>),
+}
+
+impl Node {
+    fn address(&self) -> usize {
+        match self {
+            Node::Environment(env) => Rc::as_ptr(env) as usize,
+            Node::Function(function) => Rc::as_ptr(function) as usize,
+            Node::Macro(macro_object) => Rc::as_ptr(macro_object) as usize,
+            Node::Array(elements) => Rc::as_ptr(elements) as usize,
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Environment(env) => Rc::strong_count(env),
+            Node::Function(function) => Rc::strong_count(function),
+            Node::Macro(macro_object) => Rc::strong_count(macro_object),
+            Node::Array(elements) => Rc::strong_count(elements),
+        }
+    }
+
+    // None for an environment that is borrowed for writing, whose contents
+    // cannot be read. Such an environment is in use, so it is kept.
+    fn children(&self) -> Option<Vec<Node>> {
+        let mut children = vec![];
+        match self {
+            Node::Environment(env) => {
+                let env = env.try_borrow().ok()?;
+                children.extend(env.outer.clone().map(Node::Environment));
+                env.scope.values().for_each(|value| value_nodes(value, &mut children));
+            },
+            Node::Function(function) => children.push(Node::Environment(function.env.clone())),
+            Node::Macro(macro_object) => children.push(Node::Environment(macro_object.env.clone())),
+            Node::Array(elements) => elements.try_borrow().ok()?.iter().for_each(|value| value_nodes(value, &mut children)),
+        }
+        Some(children)
+    }
+}
+
+fn value_nodes(value: &Object, nodes: &mut Vec<Node>) {
+    match value {
+        Object::Function(function) => nodes.push(Node::Function(function.clone())),
+        Object::Macro(macro_object) => nodes.push(Node::Macro(macro_object.clone())),
+        Object::Array(elements) => nodes.push(Node::Array(elements.buffer().clone())),
+        Object::ReturnValue(value) => value_nodes(value, nodes),
+        _ => {},
+    }
+}
+
+struct Entry {
+    node: Node,
+    children: Vec<usize>,
+    // References to the node from the nodes found, or None when it is in
+    // use and must be kept whatever refers to it.
+    internal: Option<usize>,
+}
+
+// Frees every tracked environment that is only reachable from other
+// tracked garbage, by emptying it. Anything the graph does not account for,
+// such as an evaluator holding an environment or a host holding a value,
+// counts as a reference from outside and keeps what it reaches alive.
+// Returns the number of environments freed.
+pub fn collect() -> usize {
+    let starts: Vec<Rc<RefCell<Environment>>> = TRACKED.with(|tracked| {
+        let mut tracked = tracked.borrow_mut();
+        tracked.environments.retain(|_, env| env.strong_count() > 0);
+        tracked.environments.values().filter_map(Weak::upgrade).collect()
+    });
+
+    let mut entries: HashMap<usize, Entry> = HashMap::new();
+    let mut pending = vec![];
+    for env in starts {
+        let node = Node::Environment(env);
+        let address = node.address();
+        if let hash_map::Entry::Vacant(vacant) = entries.entry(address) {
+            vacant.insert(Entry { node, children: vec![], internal: Some(0) });
+            pending.push(address);
+        }
+    }
+    while let Some(address) = pending.pop() {
+        let children = match entries[&address].node.children() {
+            Some(children) => children,
+            None => {
+                entries.get_mut(&address).unwrap().internal = None;
+                continue;
+            },
+        };
+        let mut addresses = vec![];
+        for child in children {
+            let child_address = child.address();
+            addresses.push(child_address);
+            match entries.get_mut(&child_address) {
+                Some(entry) => entry.internal = entry.internal.map(|internal| internal + 1),
+                None => {
+                    entries.insert(child_address, Entry { node: child, children: vec![], internal: Some(1) });
+                    pending.push(child_address);
+                },
+            }
+        }
+        entries.get_mut(&address).unwrap().children = addresses;
+    }
+
+    // Every entry holds one reference of its own. Anything referred to
+    // more often than the graph explains is reachable from outside it.
+    let mut reachable: Vec<usize> = entries
+        .iter()
+        .filter(|(_, entry)| entry.internal.is_none_or(|internal| entry.node.strong_count() - 1 > internal))
+        .map(|(address, _)| *address)
+        .collect();
+    let mut marked: HashSet<usize> = reachable.iter().copied().collect();
+    while let Some(address) = reachable.pop() {
+        for child in &entries[&address].children {
+            if marked.insert(*child) {
+                reachable.push(*child);
+            }
+        }
+    }
+
+    // The contents are dropped only once every cycle has been broken, so
+    // nothing is freed while an environment is borrowed here.
+    let mut garbage = vec![];
+    for (address, entry) in &entries {
+        if let (false, Node::Environment(env)) = (marked.contains(address), &entry.node) {
+            let mut env = env.borrow_mut();
+            garbage.push((env.outer.take(), std::mem::take(&mut env.scope)));
+        }
+    }
+    let freed = garbage.len();
+    drop(entries);
+    drop(garbage);
+    freed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn function(env: &Rc<RefCell<Environment>>) -> Object {
+        track(env);
+        let body = ast::builder::block(vec![]);
+        Object::Function(Rc::new(Function { parameters: vec![], body, env: env.clone() }))
+    }
+
+    #[test]
+    fn test_collect_cycles() {
+        // A scope holding a closure over itself, and a scope reaching it
+        // through an array.
+        let outer = Rc::new(RefCell::new(Environment::new()));
+        let inner = Environment::new_enclosed(outer.clone());
+        let closure = function(&inner);
+        inner.borrow_mut().set("self".to_string(), closure.clone());
+        outer.borrow_mut().set("list".to_string(), Object::Array(vec![function(&outer)].into()));
+        let (weak_outer, weak_inner) = (Rc::downgrade(&outer), Rc::downgrade(&inner));
+
+        // The closure is still held, and keeps both scopes alive.
+        drop((outer, inner));
+        assert_eq!(collect(), 0);
+        assert_eq!(tracked(), 2);
+        assert_eq!(weak_inner.upgrade().unwrap().borrow().get("list").map(|list| list.object_type()), Some(crate::ObjectType::ARRAY));
+
+        drop(closure);
+        assert_eq!(collect(), 2);
+        assert!(weak_outer.upgrade().is_none() && weak_inner.upgrade().is_none());
+        assert_eq!(tracked(), 0);
+    }
+
+    #[test]
+    fn test_borrowed_environment_is_kept() {
+        let env = Rc::new(RefCell::new(Environment::new()));
+        let closure = function(&env);
+        env.borrow_mut().set("f".to_string(), closure);
+        let weak = Rc::downgrade(&env);
+        let held = env.clone();
+        drop(env);
+        {
+            let _borrowed = held.borrow_mut();
+            assert_eq!(collect(), 0);
+        }
+        drop(held);
+        assert_eq!(collect(), 1);
+        assert!(weak.upgrade().is_none());
+    }
+}
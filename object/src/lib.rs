@@ -1,193 +1,357 @@
+#![allow(non_camel_case_types)]
+
 use std::{cell::RefCell, fmt::{Debug, Formatter}, rc::Rc};
 
 use ast::Node;
 
+mod array;
+pub mod gc;
+mod pretty;
+mod snapshot;
+
+pub use array::Array;
+pub use pretty::PrettyConfig;
+pub use snapshot::Snapshot;
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum ObjectType {
     INTEGER,
+    FLOAT,
     BOOLEAN,
     NULL,
     ERROR,
     RETURN_VALUE,
     FUNCTION,
+    BUILTIN,
+    NATIVE_FUNCTION,
+    STRING_BUILDER,
+    ARRAY,
+    QUOTE,
+    MACRO,
     IDENTIFIER,
     STRING,
 }
 
-impl Debug for dyn Object {
+#[derive(Clone)]
+pub enum Object {
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Null,
+    Error(String),
+    ReturnValue(Box<Object>),
+    Function(Rc<Function>),
+    Builtin(Rc<str>),
+    NativeFunction(Rc<NativeFunction>),
+    String(Rc<str>),
+    StringBuilder(Rc<RefCell<String>>),
+    Array(Array),
+    Quote(Rc<dyn ast::Expression>),
+    Macro(Rc<Macro>),
+}
+
+impl Debug for Object {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.inspect())
     }
 }
 
-pub trait Object {
-    fn object_type(&self) -> ObjectType;
-    fn inspect(&self) -> String;
-    fn as_any(&self) -> &dyn std::any::Any;
-}
-
-pub struct StringObj {
-    pub value: String,
-}
+impl Object {
+    pub fn object_type(&self) -> ObjectType {
+        match self {
+            Object::Integer(_) => ObjectType::INTEGER,
+            Object::Float(_) => ObjectType::FLOAT,
+            Object::Boolean(_) => ObjectType::BOOLEAN,
+            Object::Null => ObjectType::NULL,
+            Object::Error(_) => ObjectType::ERROR,
+            Object::ReturnValue(_) => ObjectType::RETURN_VALUE,
+            Object::Function(_) => ObjectType::FUNCTION,
+            Object::Builtin(_) => ObjectType::BUILTIN,
+            Object::NativeFunction(_) => ObjectType::NATIVE_FUNCTION,
+            Object::String(_) => ObjectType::STRING,
+            Object::StringBuilder(_) => ObjectType::STRING_BUILDER,
+            Object::Array(_) => ObjectType::ARRAY,
+            Object::Quote(_) => ObjectType::QUOTE,
+            Object::Macro(_) => ObjectType::MACRO,
+        }
+    }
 
-impl Object for StringObj {
-    fn object_type(&self) -> ObjectType {
-        ObjectType::STRING
+    pub fn inspect(&self) -> String {
+        match self {
+            Object::Integer(value) => value.to_string(),
+            Object::Float(value) => format!("{:?}", value),
+            Object::Boolean(value) => value.to_string(),
+            Object::Null => "null".to_string(),
+            Object::Error(message) => message.clone(),
+            Object::ReturnValue(value) => value.inspect(),
+            Object::Function(function) => function.inspect(),
+            Object::Builtin(name) => format!("builtin function {}", name),
+            Object::NativeFunction(native) => format!("native function {}", native.name),
+            Object::String(value) => value.to_string(),
+            Object::StringBuilder(buffer) => format!("string builder ({} bytes)", buffer.borrow().len()),
+            Object::Array(elements) => {
+                let elements: Vec<String> = elements.iter().map(|e| e.inspect()).collect();
+                format!("[{}]", elements.join(", "))
+            },
+            Object::Quote(node) => format!("QUOTE({})", node.to_string()),
+            Object::Macro(macro_object) => macro_object.inspect(),
+        }
     }
 
-    fn inspect(&self) -> String {
-        self.value.clone()
+    // What == means in Monkey. Values of different types are unequal,
+    // except integers and floats, which compare as numbers. Arrays and
+    // quotes compare by content; functions, macros, native functions and
+    // string builders, which can change under a name, by identity.
+    pub fn equals(&self, other: &Object) -> bool {
+        match (self, other) {
+            (Object::Integer(left), Object::Integer(right)) => left == right,
+            (Object::Integer(_) | Object::Float(_), Object::Integer(_) | Object::Float(_)) => as_float(self) == as_float(other),
+            (Object::Boolean(left), Object::Boolean(right)) => left == right,
+            (Object::Null, Object::Null) => true,
+            (Object::String(left), Object::String(right)) => left == right,
+            (Object::Array(left), Object::Array(right)) => {
+                Array::ptr_eq(left, right) || (left.len() == right.len() && left.iter().zip(right.iter()).all(|(l, r)| l.equals(&r)))
+            },
+            (Object::Builtin(left), Object::Builtin(right)) => left == right,
+            (Object::Function(left), Object::Function(right)) => Rc::ptr_eq(left, right),
+            (Object::Macro(left), Object::Macro(right)) => Rc::ptr_eq(left, right),
+            (Object::NativeFunction(left), Object::NativeFunction(right)) => Rc::ptr_eq(left, right),
+            (Object::StringBuilder(left), Object::StringBuilder(right)) => Rc::ptr_eq(left, right),
+            (Object::Quote(left), Object::Quote(right)) => left.to_string() == right.to_string(),
+            (Object::ReturnValue(left), Object::ReturnValue(right)) => left.equals(right),
+            (Object::Error(left), Object::Error(right)) => left == right,
+            _ => false,
+        }
     }
+}
 
-    fn as_any(&self) -> &dyn std::any::Any {
-        self
+fn as_float(object: &Object) -> f64 {
+    match object {
+        Object::Integer(value) => *value as f64,
+        Object::Float(value) => *value,
+        _ => f64::NAN,
     }
 }
 
-pub struct Integer {
-    pub value: i64,
+pub struct Function {
+    pub parameters: Vec<Rc<ast::Identifier>>,
+    pub body: Rc<dyn ast::Statement>,
+    pub env: Rc<RefCell<Environment>>,
 }
 
-impl Object for Integer {
-    fn object_type(&self) -> ObjectType {
-        ObjectType::INTEGER
+// Functions and macros show as their source, as the AST prints it. Tools
+// that lay it out as monkey fmt would do so with the formatter themselves.
+impl Function {
+    pub fn literal(&self) -> ast::FunctionLiteral {
+        ast::FunctionLiteral {
+            token: Rc::new(token::Token::new(token::TokenType::FUNCTION, "fn".to_string())),
+            parameters: self.parameters.clone(),
+            parameter_types: vec![None; self.parameters.len()],
+            return_type: None,
+            body: self.body.clone(),
+        }
     }
 
     fn inspect(&self) -> String {
-        self.value.to_string()
-    }
-
-    fn as_any(&self) -> &dyn std::any::Any {
-        self
+        self.literal().to_string()
     }
 }
 
-pub struct Boolean {
-    pub value: bool,
+pub struct Macro {
+    pub parameters: Vec<Rc<ast::Identifier>>,
+    pub body: Rc<dyn ast::Statement>,
+    pub env: Rc<RefCell<Environment>>,
 }
 
-impl Object for Boolean {
-    fn object_type(&self) -> ObjectType {
-        ObjectType::BOOLEAN
+impl Macro {
+    pub fn literal(&self) -> ast::MacroLiteral {
+        ast::MacroLiteral {
+            token: Rc::new(token::Token::new(token::TokenType::MACRO, "macro".to_string())),
+            parameters: self.parameters.clone(),
+            body: self.body.clone(),
+        }
     }
 
     fn inspect(&self) -> String {
-        self.value.to_string()
-    }
-
-    fn as_any(&self) -> &dyn std::any::Any {
-        self
+        self.literal().to_string()
     }
 }
 
-pub struct Null {}
+pub type NativeFn = dyn Fn(Vec<Object>) -> Object;
 
-impl Object for Null {
-    fn object_type(&self) -> ObjectType {
-        ObjectType::NULL
-    }
-
-    fn inspect(&self) -> String {
-        "null".to_string()
-    }
-
-    fn as_any(&self) -> &dyn std::any::Any {
-        self
-    }
+pub struct NativeFunction {
+    pub name: String,
+    pub function: Box<NativeFn>,
 }
 
-pub struct Error {
-    pub message: String,
+pub struct Environment {
+    pub outer: Option<Rc<RefCell<Environment>>>,
+    pub scope: std::collections::HashMap<String, Object>,
+    // The bindings of a sealed scope can be shadowed with let from the
+    // scopes it encloses, but not assigned to from them.
+    pub sealed: bool,
 }
 
-impl Object for Error {
-    fn object_type(&self) -> ObjectType {
-        ObjectType::ERROR
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
-    fn inspect(&self) -> String {
-        self.message.clone()
+impl Environment {
+    pub fn new() -> Environment {
+        Environment {
+            outer: None,
+            scope: std::collections::HashMap::new(),
+            sealed: false,
+        }
     }
 
-    fn as_any(&self) -> &dyn std::any::Any {
-        self
+    pub fn new_enclosed(outer: Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
+        let mut env = Environment::new();
+        env.outer = Some(outer);
+        Rc::new(RefCell::new(env))
     }
-}
 
-pub struct ReturnValue {
-    pub value: Rc<dyn Object>,
-}
-
-impl Object for ReturnValue {
-    fn object_type(&self) -> ObjectType {
-        ObjectType::RETURN_VALUE
+    // The chain is walked one scope at a time, so at most one outer scope is
+    // borrowed at any point and none stay borrowed once the lookup returns.
+    pub fn get(&self, name: &str) -> Option<Object> {
+        if let Some(obj) = self.scope.get(name) {
+            return Some(obj.clone());
+        }
+        let mut next = self.outer.clone();
+        while let Some(env) = next {
+            let env = env.borrow();
+            if let Some(obj) = env.scope.get(name) {
+                return Some(obj.clone());
+            }
+            next = env.outer.clone();
+        }
+        None
     }
 
-    fn inspect(&self) -> String {
-        self.value.inspect()
+    pub fn set(&mut self, name: String, value: Object) -> Option<Object> {
+        self.scope.insert(name, value)
     }
 
-    fn as_any(&self) -> &dyn std::any::Any {
-        self
+    pub fn register_native<F>(&mut self, name: &str, function: F)
+    where
+        F: Fn(Vec<Object>) -> Object + 'static,
+    {
+        let native = NativeFunction {
+            name: name.to_string(),
+            function: Box::new(function),
+        };
+        self.set(name.to_string(), Object::NativeFunction(Rc::new(native)));
     }
-}
-
-pub struct Function {
-    pub parameters: Vec<Rc<ast::Identifier>>,
-    pub body: Rc<dyn ast::Statement>,
-    pub env: Rc<RefCell<Environment>>,
-}
 
-impl Object for Function {
-    fn object_type(&self) -> ObjectType {
-        ObjectType::FUNCTION
+    pub fn reset(&mut self) {
+        self.outer = None;
+        self.scope.clear();
+        self.sealed = false;
     }
 
-    fn inspect(&self) -> String {
-        let mut out = String::new();
-        out.push_str("fn(");
-        for p in &self.parameters {
-            out.push_str(&p.value);
-            out.push_str(", ");
+    pub fn assign(&mut self, name: &str, value: Object) -> Result<(), String> {
+        if let Some(slot) = self.scope.get_mut(name) {
+            *slot = value;
+            return Ok(());
+        }
+        let mut next = self.outer.clone();
+        while let Some(env) = next {
+            let mut env = env.borrow_mut();
+            if env.sealed && env.scope.contains_key(name) {
+                return Err(format!("cannot assign to {}: it is read-only here, bind it with let to shadow it", name));
+            }
+            if let Some(slot) = env.scope.get_mut(name) {
+                *slot = value;
+                return Ok(());
+            }
+            next = env.outer.clone();
         }
-        out.push_str(") {\n");
-        out.push_str(&self.body.to_string());
-        out.push_str("\n}");
-        out
+        Err(format!("identifier not found: {}", name))
     }
+}
 
-    fn as_any(&self) -> &dyn std::any::Any {
-        self
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inspect() {
+        assert_eq!(Object::Integer(-129).inspect(), "-129");
+        assert_eq!(Object::Float(2.0).inspect(), "2.0");
+        assert_eq!(Object::String("monkey".into()).inspect(), "monkey");
+        assert_eq!(Object::ReturnValue(Box::new(Object::Boolean(true))).inspect(), "true");
+        assert_eq!(Object::Builtin("puts".into()).inspect(), "builtin function puts");
+        assert_eq!(Object::StringBuilder(Rc::new(RefCell::new("abc".to_string()))).object_type(), ObjectType::STRING_BUILDER);
+        assert_eq!(Object::Array(Array::new(vec![Object::Integer(1), Object::String("a".into())])).inspect(), "[1, a]");
     }
-}
 
-pub struct Environment {
-    pub outer : Option<Rc<Environment>>,
-    pub scope: std::collections::HashMap<String, Rc<dyn Object>>,
-}
+    #[test]
+    fn test_inspect_function() {
+        let parse = |source: &str| {
+            let program = parser::Parser::new(lexer::Lexer::new(source)).parse_program();
+            match program.statements[0].kind() {
+                ast::StatementKind::Expression(ast::ExpressionStatement { expression: Some(expression), .. }) => expression.clone(),
+                _ => unreachable!(),
+            }
+        };
+        let env = Rc::new(RefCell::new(Environment::new()));
+        let function = |source| match parse(source).kind() {
+            ast::ExpressionKind::FunctionLiteral(literal) => Object::Function(Rc::new(Function {
+                parameters: literal.parameters.clone(),
+                body: literal.body.clone(),
+                env: env.clone(),
+            })),
+            _ => unreachable!(),
+        };
+        assert_eq!(function("fn(x){x}").inspect(), "fn(x) {x}");
+        assert_eq!(function("fn(a, b) { let c = a + b; c * 2 }").inspect(), "fn(a, b) {let c = (a + b);(c * 2)}");
+        assert_eq!(function("fn() {}").inspect(), "fn() {}");
+    }
 
-impl Environment {
-    pub fn new() -> Environment {
-        Environment {
-            outer: None,
-            scope: std::collections::HashMap::new(),
+    #[test]
+    fn test_lookup_leaves_no_borrows() {
+        let outer = Rc::new(RefCell::new(Environment::new()));
+        outer.borrow_mut().set("a".to_string(), Object::Integer(1));
+        let mut inner = outer.clone();
+        for _ in 0..1000 {
+            inner = Environment::new_enclosed(inner);
         }
+        assert_eq!(inner.borrow().get("a").unwrap().inspect(), "1");
+        assert_eq!(inner.borrow_mut().assign("a", Object::Integer(2)), Ok(()));
+        assert!(outer.try_borrow_mut().is_ok());
+        assert_eq!(outer.borrow().get("a").unwrap().inspect(), "2");
     }
 
-    pub fn new_enclosed(outer: Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
-        let mut env = Environment::new();
-        env.scope = outer.borrow().scope.clone();
-        Rc::new(RefCell::new(env))
+    #[test]
+    fn test_sealed_scope() {
+        let sealed = Rc::new(RefCell::new(Environment::new()));
+        sealed.borrow_mut().set("a".to_string(), Object::Integer(1));
+        sealed.borrow_mut().sealed = true;
+        let inner = Environment::new_enclosed(sealed.clone());
+        assert_eq!(
+            inner.borrow_mut().assign("a", Object::Integer(2)),
+            Err("cannot assign to a: it is read-only here, bind it with let to shadow it".to_string())
+        );
+        assert_eq!(inner.borrow_mut().assign("b", Object::Integer(2)), Err("identifier not found: b".to_string()));
+        inner.borrow_mut().set("a".to_string(), Object::Integer(3));
+        assert_eq!(inner.borrow_mut().assign("a", Object::Integer(4)), Ok(()));
+        assert_eq!(sealed.borrow().get("a").unwrap().inspect(), "1");
+        assert_eq!(sealed.borrow_mut().assign("a", Object::Integer(5)), Ok(()));
     }
 
-    pub fn get(&self, name: &str) -> Option<Rc<dyn Object>> {
-        match self.scope.get(name) {
-            Some(obj) => Some(obj.clone()),
-            None => None,
-        }
-    }
+    #[test]
+    fn test_enclosed_environment_sees_outer_updates() {
+        let outer = Rc::new(RefCell::new(Environment::new()));
+        let inner = Environment::new_enclosed(outer.clone());
+        assert!(inner.borrow().get("a").is_none());
 
-    pub fn set(&mut self, name: String, value: Rc<dyn Object>) -> Option<Rc<dyn Object>> {
-        self.scope.insert(name, value)
+        outer.borrow_mut().set("a".to_string(), Object::Integer(1));
+        assert_eq!(inner.borrow().get("a").unwrap().inspect(), "1");
+
+        inner.borrow_mut().set("a".to_string(), Object::Integer(2));
+        assert_eq!(inner.borrow().get("a").unwrap().inspect(), "2");
+        assert_eq!(outer.borrow().get("a").unwrap().inspect(), "1");
     }
 }
@@ -0,0 +1,137 @@
+use crate::Object;
+
+// How inspect_pretty lays values out for people to read. Arrays nested more
+// than max_depth deep show as [...]. Arrays show their first max_length
+// elements, and strings their first max_length characters. An array that
+// does not fit in width columns is broken over several lines.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PrettyConfig {
+    pub max_depth: usize,
+    pub max_length: usize,
+    pub width: usize,
+    // Separator for the digits of numbers, grouped in threes as in 832,040.
+    pub digit_grouping: Option<char>,
+}
+
+impl Default for PrettyConfig {
+    fn default() -> Self {
+        PrettyConfig {
+            max_depth: 6,
+            max_length: 100,
+            width: 80,
+            digit_grouping: None,
+        }
+    }
+}
+
+const INDENT: &str = "    ";
+
+impl Object {
+    // Like inspect, but meant for display only: strings are quoted and
+    // escaped as they would be written, and long or deeply nested values
+    // are cut short. inspect stays canonical.
+    pub fn inspect_pretty(&self, config: &PrettyConfig) -> String {
+        pretty(self, config, 0)
+    }
+}
+
+fn pretty(value: &Object, config: &PrettyConfig, depth: usize) -> String {
+    match value {
+        Object::Integer(_) | Object::Float(_) => match config.digit_grouping {
+            Some(separator) => group_digits(&value.inspect(), separator),
+            None => value.inspect(),
+        },
+        Object::String(value) => {
+            let mut chars = value.char_indices();
+            match chars.nth(config.max_length) {
+                Some((end, _)) => format!("\"{}...\"", ast::escape_string(&value[..end])),
+                None => format!("\"{}\"", ast::escape_string(value)),
+            }
+        },
+        Object::ReturnValue(value) => pretty(value, config, depth),
+        Object::Array(elements) if elements.is_empty() => "[]".to_string(),
+        Object::Array(_) if depth >= config.max_depth => "[...]".to_string(),
+        Object::Array(elements) => {
+            let mut shown: Vec<String> = elements.iter().take(config.max_length).map(|e| pretty(&e, config, depth + 1)).collect();
+            if elements.len() > config.max_length {
+                shown.push(format!("... {} more", elements.len() - config.max_length));
+            }
+            let flat = format!("[{}]", shown.join(", "));
+            if !flat.contains('\n') && depth * INDENT.len() + flat.chars().count() <= config.width {
+                return flat;
+            }
+            let lines: Vec<String> = shown.iter().map(|element| indent(element)).collect();
+            format!("[\n{}\n]", lines.join(",\n"))
+        },
+        _ => value.inspect(),
+    }
+}
+
+fn indent(text: &str) -> String {
+    let lines: Vec<String> = text.lines().map(|line| format!("{}{}", INDENT, line)).collect();
+    lines.join("\n")
+}
+
+// Groups the leading run of digits, leaving a sign before it and any
+// fraction or exponent after it alone.
+fn group_digits(number: &str, separator: char) -> String {
+    let (sign, rest) = match number.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", number),
+    };
+    let end = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+    let (digits, tail) = rest.split_at(end);
+    let mut grouped = String::from(sign);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            grouped.push(separator);
+        }
+        grouped.push(digit);
+    }
+    grouped.push_str(tail);
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn array(elements: Vec<Object>) -> Object {
+        Object::Array(elements.into())
+    }
+
+    #[test]
+    fn test_inspect_pretty() {
+        let config = PrettyConfig::default();
+        assert_eq!(Object::String("say \"hi\"\n{x}".into()).inspect_pretty(&config), "\"say \\\"hi\\\"\\n\\{x}\"");
+        assert_eq!(array(vec![Object::Integer(1), Object::String("a".into()), array(vec![])]).inspect_pretty(&config), "[1, \"a\", []]");
+        assert_eq!(Object::ReturnValue(Box::new(Object::Null)).inspect_pretty(&config), "null");
+
+        let config = PrettyConfig { max_depth: 2, max_length: 3, width: 40, digit_grouping: None };
+        assert_eq!(Object::String("monkey".into()).inspect_pretty(&config), "\"mon...\"");
+        assert_eq!(Object::String("ape".into()).inspect_pretty(&config), "\"ape\"");
+        let numbers = array((1..=5).map(Object::Integer).collect());
+        assert_eq!(numbers.inspect_pretty(&config), "[1, 2, 3, ... 2 more]");
+        let nested = array(vec![array(vec![array(vec![Object::Integer(1)])]), Object::Integer(2)]);
+        assert_eq!(nested.inspect_pretty(&config), "[[[...]], 2]");
+
+        let rows = array(vec![
+            array(vec![Object::String("first row!".into()), Object::Integer(1)]),
+            array(vec![Object::String("second".into()), Object::Integer(2)]),
+        ]);
+        let config = PrettyConfig { width: 20, ..PrettyConfig::default() };
+        assert_eq!(rows.inspect_pretty(&config), "[\n    [\n        \"first row!\",\n        1\n    ],\n    [\"second\", 2]\n]");
+    }
+
+    #[test]
+    fn test_digit_grouping() {
+        let grouped = |value: Object, separator| value.inspect_pretty(&PrettyConfig { digit_grouping: Some(separator), ..PrettyConfig::default() });
+        assert_eq!(grouped(Object::Integer(832040), ','), "832,040");
+        assert_eq!(grouped(Object::Integer(-1234567), '_'), "-1_234_567");
+        assert_eq!(grouped(Object::Integer(999), ','), "999");
+        assert_eq!(grouped(Object::Float(12345.6789), ','), "12,345.6789");
+        assert_eq!(grouped(Object::Float(1e21), ','), "1e21");
+        assert_eq!(grouped(Object::String("1234".into()), ','), "\"1234\"");
+        assert_eq!(grouped(array(vec![Object::Integer(1000), Object::Boolean(true)]), ' '), "[1 000, true]");
+    }
+}
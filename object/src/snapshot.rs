@@ -0,0 +1,198 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use ast::{ExpressionKind, StatementKind};
+use lexer::Lexer;
+use parser::Parser;
+use serde_json::{json, Map, Value};
+use crate::{Environment, Function, Macro, Object};
+
+// The bindings of one scope as JSON, and the names left out of it because
+// their values cannot be written down: errors, native functions, and
+// arrays holding either.
+pub struct Snapshot {
+    pub value: Value,
+    pub skipped: Vec<String>,
+}
+
+impl Environment {
+    // Outer scopes are not included. Functions and macros are kept as their
+    // source text, so the variables a closure captured are lost with it.
+    pub fn snapshot(&self) -> Snapshot {
+        let mut bindings = Map::new();
+        let mut skipped = vec![];
+        for (name, value) in &self.scope {
+            match value.to_json() {
+                Some(value) => {
+                    bindings.insert(name.clone(), value);
+                },
+                None => skipped.push(name.clone()),
+            }
+        }
+        skipped.sort();
+        Snapshot {
+            value: json!({ "bindings": bindings }),
+            skipped,
+        }
+    }
+
+    // Binds everything in a snapshot into env, replacing bindings of the
+    // same name. Functions and macros are restored closing over env. Nothing
+    // is bound unless the whole snapshot reads back. Returns the number of
+    // bindings restored.
+    pub fn restore(env: &Rc<RefCell<Environment>>, snapshot: &Value) -> Result<usize, String> {
+        let bindings = snapshot.get("bindings").and_then(Value::as_object).ok_or("missing field: bindings")?;
+        let restored = bindings
+            .iter()
+            .map(|(name, value)| Object::from_json(value, env).map(|value| (name.clone(), value)).map_err(|err| format!("{}: {}", name, err)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let count = restored.len();
+        let mut env = env.borrow_mut();
+        for (name, value) in restored {
+            env.set(name, value);
+        }
+        Ok(count)
+    }
+}
+
+impl Object {
+    // None for values that have no JSON form.
+    pub fn to_json(&self) -> Option<Value> {
+        let object_type = format!("{:?}", self.object_type());
+        let value = match self {
+            Object::Integer(value) => json!({ "type": object_type, "value": value }),
+            // Kept as text so NaN and the infinities survive.
+            Object::Float(value) => json!({ "type": object_type, "value": format!("{:?}", value) }),
+            Object::Boolean(value) => json!({ "type": object_type, "value": value }),
+            Object::Null => json!({ "type": object_type }),
+            Object::String(value) => json!({ "type": object_type, "value": value.as_ref() }),
+            Object::StringBuilder(buffer) => json!({ "type": object_type, "value": buffer.borrow().as_str() }),
+            Object::Array(elements) => {
+                let elements = elements.iter().map(|e| e.to_json()).collect::<Option<Vec<_>>>()?;
+                json!({ "type": object_type, "elements": elements })
+            },
+            Object::Builtin(name) => json!({ "type": object_type, "name": name.as_ref() }),
+            Object::Function(_) | Object::Macro(_) => json!({ "type": object_type, "source": self.inspect() }),
+            Object::Quote(node) => json!({ "type": object_type, "source": node.to_string() }),
+            Object::Error(_) | Object::ReturnValue(_) | Object::NativeFunction(_) => return None,
+        };
+        Some(value)
+    }
+
+    // Functions and macros read back close over env.
+    pub fn from_json(value: &Value, env: &Rc<RefCell<Environment>>) -> Result<Object, String> {
+        let object_type = value.get("type").and_then(Value::as_str).ok_or("missing field: type")?;
+        let field = |name: &str| value.get(name).ok_or_else(|| format!("missing field: {}", name));
+        let string = |name: &str| field(name)?.as_str().ok_or_else(|| format!("{} must be a string", name));
+        let object = match object_type {
+            "INTEGER" => Object::Integer(field("value")?.as_i64().ok_or("value must be an integer")?),
+            "FLOAT" => Object::Float(string("value")?.parse().map_err(|_| "value must be a number")?),
+            "BOOLEAN" => Object::Boolean(field("value")?.as_bool().ok_or("value must be a boolean")?),
+            "NULL" => Object::Null,
+            "STRING" => Object::String(string("value")?.into()),
+            "STRING_BUILDER" => Object::StringBuilder(Rc::new(RefCell::new(string("value")?.to_string()))),
+            "ARRAY" => {
+                let elements = field("elements")?.as_array().ok_or("elements must be an array")?;
+                Object::Array(elements.iter().map(|e| Object::from_json(e, env)).collect::<Result<_, _>>()?)
+            },
+            "BUILTIN" => Object::Builtin(string("name")?.into()),
+            "FUNCTION" | "MACRO" | "QUOTE" => {
+                crate::gc::track(env);
+                let expression = parse(string("source")?)?;
+                match (object_type, expression.kind()) {
+                    ("FUNCTION", ExpressionKind::FunctionLiteral(function)) => Object::Function(Rc::new(Function {
+                        parameters: function.parameters.clone(),
+                        body: function.body.clone(),
+                        env: env.clone(),
+                    })),
+                    ("MACRO", ExpressionKind::MacroLiteral(macro_literal)) => Object::Macro(Rc::new(Macro {
+                        parameters: macro_literal.parameters.clone(),
+                        body: macro_literal.body.clone(),
+                        env: env.clone(),
+                    })),
+                    ("QUOTE", _) => Object::Quote(expression),
+                    _ => return Err(format!("source is not a {} literal", object_type.to_lowercase())),
+                }
+            },
+            other => return Err(format!("cannot restore a value of type {}", other)),
+        };
+        Ok(object)
+    }
+}
+
+fn parse(source: &str) -> Result<Rc<dyn ast::Expression>, String> {
+    let mut parser = Parser::new(Lexer::new(source));
+    let program = parser.parse_program();
+    if let Some(error) = parser.errors().first() {
+        return Err(format!("could not parse source: {}", error));
+    }
+    match program.statements.as_slice() {
+        [statement] => match statement.kind() {
+            StatementKind::Expression(ast::ExpressionStatement { expression: Some(expression), .. }) => Ok(expression.clone()),
+            _ => Err("source is not an expression".to_string()),
+        },
+        _ => Err("source must hold exactly one expression".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut env = Environment::new();
+        env.set("n".to_string(), Object::Integer(-42));
+        env.set("nan".to_string(), Object::Float(f64::NAN));
+        env.set("half".to_string(), Object::Float(0.5));
+        env.set("s".to_string(), Object::String("a \"quoted\"\nline".into()));
+        env.set("nothing".to_string(), Object::Null);
+        env.set("list".to_string(), Object::Array(vec![Object::Boolean(true), Object::Array(vec![].into())].into()));
+        env.set("say".to_string(), Object::Builtin("puts".into()));
+        env.set("failed".to_string(), Object::Error("boom".to_string()));
+        env.register_native("host", |_| Object::Null);
+
+        let parse_function = |source: &str| match parse(source).unwrap().kind() {
+            ExpressionKind::FunctionLiteral(function) => (function.parameters.clone(), function.body.clone()),
+            _ => unreachable!(),
+        };
+        let (parameters, body) = parse_function("fn(a, b) { let c = a + b; if (c > 1) { \"{c}!\" } else { c * 2 } }");
+        let scratch = Rc::new(RefCell::new(Environment::new()));
+        env.set("f".to_string(), Object::Function(Rc::new(Function { parameters, body, env: scratch })));
+
+        let snapshot = env.snapshot();
+        assert_eq!(snapshot.skipped, vec!["failed", "host"]);
+        // Through text, as a saved session would be.
+        let saved: Value = serde_json::from_str(&snapshot.value.to_string()).unwrap();
+
+        let restored = Rc::new(RefCell::new(Environment::new()));
+        assert_eq!(Environment::restore(&restored, &saved), Ok(8));
+        let restored_ref = restored.borrow();
+        for name in ["n", "half", "s", "nothing", "list", "say", "f"] {
+            assert_eq!(restored_ref.get(name).unwrap().inspect(), env.get(name).unwrap().inspect(), "binding: {}", name);
+        }
+        assert!(matches!(restored_ref.get("nan"), Some(Object::Float(value)) if value.is_nan()));
+        match restored_ref.get("f") {
+            Some(Object::Function(function)) => assert!(Rc::ptr_eq(&function.env, &restored)),
+            other => panic!("expected a function, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_restore_errors() {
+        let env = Rc::new(RefCell::new(Environment::new()));
+        env.borrow_mut().set("kept".to_string(), Object::Integer(1));
+        let tests = vec![
+            (json!({}), "missing field: bindings"),
+            (json!({ "bindings": { "a": { "type": "INTEGER", "value": "1" } } }), "a: value must be an integer"),
+            (json!({ "bindings": { "a": { "type": "ERROR" } } }), "a: cannot restore a value of type ERROR"),
+            (json!({ "bindings": { "a": { "type": "FUNCTION", "source": "1 + 1" } } }), "a: source is not a function literal"),
+            (json!({ "bindings": { "a": { "type": "NULL" }, "b": { "type": "MACRO", "source": "macro(" } } }),
+                "b: could not parse source: expected next token to be RPAREN, got EOF instead"),
+        ];
+        for (snapshot, expected) in tests {
+            assert_eq!(Environment::restore(&env, &snapshot), Err(expected.to_string()));
+        }
+        assert_eq!(env.borrow().scope.len(), 1);
+    }
+}
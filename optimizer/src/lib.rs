@@ -0,0 +1,489 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use ast::{Expression, Statement};
+use token::{Token, TokenType};
+
+pub fn optimize(program: ast::Program) -> ast::Program {
+    let mut optimizer = Optimizer::new();
+    optimizer.optimize_program(program)
+}
+
+pub struct Optimizer {
+    strings: HashMap<Rc<str>, Rc<ast::StringLiteral>>,
+}
+
+impl Default for Optimizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Optimizer {
+    pub fn new() -> Optimizer {
+        Optimizer {
+            strings: HashMap::new(),
+        }
+    }
+
+    pub fn optimize_program(&mut self, program: ast::Program) -> ast::Program {
+        ast::Program {
+            statements: self.optimize_statements(&program.statements),
+        }
+    }
+
+    // An if whose condition is a constant is replaced by the statements of
+    // the branch it takes. Blocks of an if run in the enclosing environment,
+    // so this keeps their lets where they were. An if taking no branch is
+    // dropped, unless it is last and so gives the list its value.
+    fn optimize_statements(&mut self, statements: &[Rc<dyn Statement>]) -> Vec<Rc<dyn Statement>> {
+        let mut optimized = vec![];
+        for (i, statement) in statements.iter().enumerate() {
+            let statement = self.optimize_statement(statement.clone());
+            let last = i == statements.len() - 1;
+            match decided_branch(&statement) {
+                Some(Some(branch)) if !(last && branch.is_empty()) => optimized.extend(branch),
+                Some(None) if !last => {},
+                _ => optimized.push(statement),
+            }
+        }
+        optimized
+    }
+
+    fn optimize_statement(&mut self, statement: Rc<dyn Statement>) -> Rc<dyn Statement> {
+        match statement.node_type() {
+            ast::NodeType::EXPRESSION_STATEMENT => {
+                let stmt = statement.as_any().downcast_ref::<ast::ExpressionStatement>().unwrap();
+                Rc::new(ast::ExpressionStatement {
+                    token: stmt.token.clone(),
+                    expression: stmt.expression.clone().map(|e| self.optimize_expression(e)),
+                })
+            },
+            ast::NodeType::LET_STATEMENT => {
+                let stmt = statement.as_any().downcast_ref::<ast::LetStatement>().unwrap();
+                Rc::new(ast::LetStatement {
+                    token: stmt.token.clone(),
+                    name: stmt.name.clone(),
+                    type_annotation: stmt.type_annotation.clone(),
+                    value: stmt.value.clone().map(|e| self.optimize_expression(e)),
+                })
+            },
+            ast::NodeType::RETURN_STATEMENT => {
+                let stmt = statement.as_any().downcast_ref::<ast::ReturnStatement>().unwrap();
+                Rc::new(ast::ReturnStatement {
+                    token: stmt.token.clone(),
+                    return_value: stmt.return_value.clone().map(|e| self.optimize_expression(e)),
+                })
+            },
+            ast::NodeType::BLOCK_STATEMENT => {
+                let block = statement.as_any().downcast_ref::<ast::BlockStatement>().unwrap();
+                Rc::new(ast::BlockStatement {
+                    token: block.token.clone(),
+                    statements: self.optimize_statements(&block.statements),
+                    end: block.end,
+                })
+            },
+            _ => statement,
+        }
+    }
+
+    fn optimize_expression(&mut self, exp: Rc<dyn Expression>) -> Rc<dyn Expression> {
+        match exp.node_type() {
+            ast::NodeType::STRING_LITERAL => {
+                let string = exp.as_any().downcast_ref::<ast::StringLiteral>().unwrap();
+                self.pool_string(string.value.clone())
+            },
+            ast::NodeType::INTERPOLATED_STRING => {
+                let interpolated = exp.as_any().downcast_ref::<ast::InterpolatedString>().unwrap();
+                Rc::new(ast::InterpolatedString {
+                    token: interpolated.token.clone(),
+                    parts: interpolated.parts.iter().map(|p| self.optimize_expression(p.clone())).collect(),
+                })
+            },
+            ast::NodeType::PREFIX_EXPRESSION => {
+                let prefix = exp.as_any().downcast_ref::<ast::PrefixExpression>().unwrap();
+                let right = self.optimize_expression(prefix.right.clone());
+                if let Some(folded) = fold_prefix(&prefix.operator, &right) {
+                    return literal(folded, &prefix.token);
+                }
+                Rc::new(ast::PrefixExpression {
+                    token: prefix.token.clone(),
+                    operator: prefix.operator.clone(),
+                    right,
+                })
+            },
+            ast::NodeType::INFIX_EXPRESSION => {
+                let infix = exp.as_any().downcast_ref::<ast::InfixExpression>().unwrap();
+                let left = self.optimize_expression(infix.left.clone());
+                let right = self.optimize_expression(infix.right.clone());
+                if infix.operator == "+" {
+                    if let Some(folded) = self.fold_string_concatenation(&left, &right) {
+                        return folded;
+                    }
+                }
+                if let Some(folded) = fold_infix(&infix.operator, &left, &right) {
+                    return literal(folded, &ast::first_token(left.as_ref()));
+                }
+                Rc::new(ast::InfixExpression {
+                    token: infix.token.clone(),
+                    left,
+                    operator: infix.operator.clone(),
+                    right,
+                })
+            },
+            ast::NodeType::ASSIGN_EXPRESSION => {
+                let assign = exp.as_any().downcast_ref::<ast::AssignExpression>().unwrap();
+                Rc::new(ast::AssignExpression {
+                    token: assign.token.clone(),
+                    name: assign.name.clone(),
+                    operator: assign.operator.clone(),
+                    value: self.optimize_expression(assign.value.clone()),
+                })
+            },
+            ast::NodeType::IF_EXPRESSION => {
+                let if_expression = exp.as_any().downcast_ref::<ast::IfExpression>().unwrap();
+                let condition = self.optimize_expression(if_expression.condition.clone());
+                let consequence = self.optimize_statement(if_expression.consequence.clone());
+                let alternative = if_expression.alternative.clone().map(|a| self.optimize_statement(a));
+                // A branch that is a single expression can stand in for the
+                // whole if; other branches are kept for the statement lists
+                // around the if to take in.
+                let (condition, consequence, alternative) = match truthiness(&condition) {
+                    Some(taken) => {
+                        let branch = if taken { Some(consequence.clone()) } else { alternative };
+                        match branch {
+                            Some(branch) => match single_expression(&branch) {
+                                Some(expression) => return expression,
+                                None => (literal(Constant::Boolean(true), &if_expression.token), branch, None),
+                            },
+                            None => (condition, consequence, None),
+                        }
+                    },
+                    None => (condition, consequence, alternative),
+                };
+                Rc::new(ast::IfExpression {
+                    token: if_expression.token.clone(),
+                    condition,
+                    consequence,
+                    alternative,
+                })
+            },
+            ast::NodeType::WHILE_EXPRESSION => {
+                let while_expression = exp.as_any().downcast_ref::<ast::WhileExpression>().unwrap();
+                Rc::new(ast::WhileExpression {
+                    token: while_expression.token.clone(),
+                    condition: self.optimize_expression(while_expression.condition.clone()),
+                    body: self.optimize_statement(while_expression.body.clone()),
+                })
+            },
+            ast::NodeType::FUNCTION_LITERAL => {
+                let function = exp.as_any().downcast_ref::<ast::FunctionLiteral>().unwrap();
+                Rc::new(ast::FunctionLiteral {
+                    token: function.token.clone(),
+                    parameters: function.parameters.clone(),
+                    parameter_types: function.parameter_types.clone(),
+                    return_type: function.return_type.clone(),
+                    body: self.optimize_statement(function.body.clone()),
+                })
+            },
+            ast::NodeType::CALL_EXPRESSION => {
+                let call = exp.as_any().downcast_ref::<ast::CallExpression>().unwrap();
+                Rc::new(ast::CallExpression {
+                    token: call.token.clone(),
+                    function: self.optimize_expression(call.function.clone()),
+                    arguments: call.arguments.iter().map(|a| self.optimize_expression(a.clone())).collect(),
+                    end: call.end,
+                })
+            },
+            ast::NodeType::ARRAY_LITERAL => {
+                let array = exp.as_any().downcast_ref::<ast::ArrayLiteral>().unwrap();
+                Rc::new(ast::ArrayLiteral {
+                    token: array.token.clone(),
+                    elements: array.elements.iter().map(|e| self.optimize_expression(e.clone())).collect(),
+                    end: array.end,
+                })
+            },
+            ast::NodeType::INDEX_EXPRESSION => {
+                let index = exp.as_any().downcast_ref::<ast::IndexExpression>().unwrap();
+                Rc::new(ast::IndexExpression {
+                    token: index.token.clone(),
+                    left: self.optimize_expression(index.left.clone()),
+                    index: self.optimize_expression(index.index.clone()),
+                    end: index.end,
+                })
+            },
+            _ => exp,
+        }
+    }
+
+    // `x + "a" + "b"` parses as `(x + "a") + "b"`, so the trailing literal
+    // is folded into the inner right operand as well.
+    fn fold_string_concatenation(&mut self, left: &Rc<dyn Expression>, right: &Rc<dyn Expression>) -> Option<Rc<dyn Expression>> {
+        let right_string = right.as_any().downcast_ref::<ast::StringLiteral>()?;
+        if let Some(left_string) = left.as_any().downcast_ref::<ast::StringLiteral>() {
+            return Some(self.pool_string(format!("{}{}", left_string.value, right_string.value).into()));
+        }
+
+        let inner = left.as_any().downcast_ref::<ast::InfixExpression>()?;
+        let inner_right = inner.right.as_any().downcast_ref::<ast::StringLiteral>()?;
+        if inner.operator != "+" {
+            return None;
+        }
+        Some(Rc::new(ast::InfixExpression {
+            token: inner.token.clone(),
+            left: inner.left.clone(),
+            operator: inner.operator.clone(),
+            right: self.pool_string(format!("{}{}", inner_right.value, right_string.value).into()),
+        }))
+    }
+
+    fn pool_string(&mut self, value: Rc<str>) -> Rc<dyn Expression> {
+        if let Some(literal) = self.strings.get(&value) {
+            return literal.clone();
+        }
+        let literal = Rc::new(ast::StringLiteral {
+            token: Rc::new(Token::new(TokenType::STRING, value.to_string())),
+            value: value.clone(),
+        });
+        self.strings.insert(value, literal.clone());
+        literal
+    }
+}
+
+// The value of a literal, as the evaluator would produce it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Constant {
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    String,
+}
+
+fn constant(exp: &Rc<dyn Expression>) -> Option<Constant> {
+    let any = exp.as_any();
+    if let Some(integer) = any.downcast_ref::<ast::IntegerLiteral>() {
+        Some(Constant::Integer(integer.value))
+    } else if let Some(float) = any.downcast_ref::<ast::FloatLiteral>() {
+        Some(Constant::Float(float.value))
+    } else if let Some(boolean) = any.downcast_ref::<ast::Boolean>() {
+        Some(Constant::Boolean(boolean.value))
+    } else if any.is::<ast::StringLiteral>() {
+        Some(Constant::String)
+    } else {
+        None
+    }
+}
+
+fn truthiness(exp: &Rc<dyn Expression>) -> Option<bool> {
+    match constant(exp)? {
+        Constant::Boolean(value) => Some(value),
+        _ => Some(true),
+    }
+}
+
+// Operations that would fail at run time, such as an overflow or a
+// division by zero, are left for the evaluator to report.
+fn fold_prefix(operator: &str, right: &Rc<dyn Expression>) -> Option<Constant> {
+    match (operator, constant(right)?) {
+        ("!", Constant::Boolean(value)) => Some(Constant::Boolean(!value)),
+        ("!", _) => Some(Constant::Boolean(false)),
+        ("-", Constant::Integer(value)) => value.checked_neg().map(Constant::Integer),
+        ("-", Constant::Float(value)) => Some(Constant::Float(-value)),
+        _ => None,
+    }
+}
+
+fn fold_infix(operator: &str, left: &Rc<dyn Expression>, right: &Rc<dyn Expression>) -> Option<Constant> {
+    let folded = match (constant(left)?, constant(right)?) {
+        (Constant::Integer(left), Constant::Integer(right)) => match operator {
+            "+" => Constant::Integer(left.checked_add(right)?),
+            "-" => Constant::Integer(left.checked_sub(right)?),
+            "*" => Constant::Integer(left.checked_mul(right)?),
+            "/" => Constant::Integer(left.checked_div(right)?),
+            "%" => Constant::Integer(left.checked_rem(right)?),
+            "**" => Constant::Integer(left.checked_pow(u32::try_from(right).ok()?)?),
+            _ => Constant::Boolean(compare(operator, left.cmp(&right))?),
+        },
+        (Constant::Boolean(left), Constant::Boolean(right)) => match operator {
+            "==" => Constant::Boolean(left == right),
+            "!=" => Constant::Boolean(left != right),
+            _ => return None,
+        },
+        (Constant::Integer(_) | Constant::Float(_), Constant::Integer(_) | Constant::Float(_)) => {
+            let (left, right) = (as_float(constant(left)?), as_float(constant(right)?));
+            match operator {
+                "+" => Constant::Float(left + right),
+                "-" => Constant::Float(left - right),
+                "*" => Constant::Float(left * right),
+                "/" => Constant::Float(left / right),
+                "%" => Constant::Float(left % right),
+                "**" => Constant::Float(left.powf(right)),
+                _ => Constant::Boolean(compare(operator, left.partial_cmp(&right)?)?),
+            }
+        },
+        _ => return None,
+    };
+    match folded {
+        // Infinities and NaN have no literal to stand for them.
+        Constant::Float(value) if !value.is_finite() => None,
+        _ => Some(folded),
+    }
+}
+
+fn compare(operator: &str, ordering: Ordering) -> Option<bool> {
+    match operator {
+        "<" => Some(ordering == Ordering::Less),
+        ">" => Some(ordering == Ordering::Greater),
+        "<=" => Some(ordering != Ordering::Greater),
+        ">=" => Some(ordering != Ordering::Less),
+        "==" => Some(ordering == Ordering::Equal),
+        "!=" => Some(ordering != Ordering::Equal),
+        _ => None,
+    }
+}
+
+fn as_float(value: Constant) -> f64 {
+    match value {
+        Constant::Integer(value) => value as f64,
+        Constant::Float(value) => value,
+        _ => f64::NAN,
+    }
+}
+
+// A literal for a folded value, placed where the expression it replaces
+// began so errors about it still point there.
+fn literal(value: Constant, at: &Token) -> Rc<dyn Expression> {
+    let token = |token_type, literal: String| Rc::new(Token {
+        token_type,
+        literal,
+        line: at.line,
+        column: at.column,
+        span: at.span,
+        file: at.file,
+    });
+    match value {
+        Constant::Integer(value) => Rc::new(ast::IntegerLiteral { token: token(TokenType::INT, value.to_string()), value }),
+        Constant::Float(value) => Rc::new(ast::FloatLiteral { token: token(TokenType::FLOAT, format!("{:?}", value)), value }),
+        Constant::Boolean(true) => Rc::new(ast::Boolean { token: token(TokenType::TRUE, "true".to_string()), value: true }),
+        Constant::Boolean(false) => Rc::new(ast::Boolean { token: token(TokenType::FALSE, "false".to_string()), value: false }),
+        Constant::String => unreachable!("strings are folded by fold_string_concatenation"),
+    }
+}
+
+// The statements of the branch a statement-level if with a constant
+// condition takes: Some(None) when it takes none.
+fn decided_branch(statement: &Rc<dyn Statement>) -> Option<Option<Vec<Rc<dyn Statement>>>> {
+    let expression = statement.as_any().downcast_ref::<ast::ExpressionStatement>()?.expression.as_ref()?;
+    let if_expression = expression.as_any().downcast_ref::<ast::IfExpression>()?;
+    let branch = match truthiness(&if_expression.condition)? {
+        true => Some(&if_expression.consequence),
+        false => if_expression.alternative.as_ref(),
+    };
+    Some(branch.map(|branch| match branch.as_any().downcast_ref::<ast::BlockStatement>() {
+        Some(block) => block.statements.clone(),
+        None => vec![branch.clone()],
+    }))
+}
+
+// The expression a branch consists of, if that is all it holds.
+fn single_expression(branch: &Rc<dyn Statement>) -> Option<Rc<dyn Expression>> {
+    let block = branch.as_any().downcast_ref::<ast::BlockStatement>()?;
+    match block.statements.as_slice() {
+        [statement] => statement.as_any().downcast_ref::<ast::ExpressionStatement>()?.expression.clone(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ast::Node;
+    use lexer::Lexer;
+    use parser::Parser;
+
+    fn parse(input: &str) -> ast::Program {
+        let lexer = Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        assert!(parser.errors().is_empty(), "parser errors: {:?}", parser.errors());
+        program
+    }
+
+    fn expression(program: &ast::Program, i: usize) -> Rc<dyn Expression> {
+        let stmt = program.statements[i].as_any().downcast_ref::<ast::ExpressionStatement>().unwrap();
+        stmt.expression.clone().unwrap()
+    }
+
+    #[test]
+    fn test_folds_adjacent_string_literals() {
+        let program = optimize(parse("\"a\" + \"b\" + \"c\"; x + \"d\" + \"e\"; \"f\" + x + \"g\";"));
+        assert_eq!(program.statements[0].to_string(), "\"abc\"");
+        assert_eq!(program.statements[1].to_string(), "(x + \"de\")");
+        assert_eq!(program.statements[2].to_string(), "((\"f\" + x) + \"g\")");
+    }
+
+    #[test]
+    fn test_folds_inside_nested_nodes() {
+        let program = optimize(parse("let f = fn() { if (a < b) { \"a\" + \"b\" } else { g(\"c\" + \"d\") } };"));
+        assert_eq!(program.to_string(), "let f = fn() {if(a < b) {\"ab\"} else {g(\"cd\")}};");
+        let program = optimize(parse("[\"a\" + \"b\", xs[\"c\" + \"d\"]]"));
+        assert_eq!(program.to_string(), "[\"ab\", (xs[\"cd\"])]");
+    }
+
+    #[test]
+    fn test_does_not_fold_other_operators() {
+        let program = optimize(parse("\"a\" == \"a\"; x - \"a\" + \"b\";"));
+        assert_eq!(program.statements[0].to_string(), "(\"a\" == \"a\")");
+        assert_eq!(program.statements[1].to_string(), "((x - \"a\") + \"b\")");
+    }
+
+    #[test]
+    fn test_folds_constant_expressions() {
+        let program = optimize(parse("1 + 2 * 3; 7 / 2 - 7 % 3; -5 + 2; 1.5 * 2; 1 + 0.5; 2 <= 3; !(1 == 2); true != false; -(-3); !5; x * (2 + 3); 2 ** 3 ** 2; 4 ** 0.5;"));
+        let folded: Vec<String> = program.statements.iter().map(|s| s.to_string()).collect();
+        assert_eq!(folded, vec!["7", "2", "-3", "3.0", "1.5", "true", "true", "true", "3", "false", "(x * 5)", "512", "2.0"]);
+    }
+
+    #[test]
+    fn test_leaves_failing_operations_to_the_evaluator() {
+        let program = optimize(parse("1 / 0; 9223372036854775807 + 1; 1 + true; \"a\" == \"a\"; x + 1 + 2; 1.0 / 0; 2 ** 64; 2 ** -1;"));
+        let kept: Vec<String> = program.statements.iter().map(|s| s.to_string()).collect();
+        assert_eq!(kept, vec!["(1 / 0)", "(9223372036854775807 + 1)", "(1 + true)", "(\"a\" == \"a\")", "((x + 1) + 2)", "(1.0 / 0)", "(2 ** 64)", "(2 ** -1)"]);
+    }
+
+    #[test]
+    fn test_folded_literals_keep_positions() {
+        let program = optimize(parse("x;\n  (1 + 2) * 3"));
+        let folded = expression(&program, 1);
+        let token = ast::first_token(folded.as_ref());
+        assert_eq!((token.literal.as_str(), token.line, token.column), ("9", 2, 4));
+    }
+
+    #[test]
+    fn test_eliminates_constant_branches() {
+        let program = optimize(parse("let a = if (1 < 2) { x } else { y }; let b = if (false) { x } else { y }; let c = if (false) { x };"));
+        assert_eq!(program.to_string(), "let a = x;let b = y;let c = iffalse {x};");
+
+        let program = optimize(parse("if (true) { let x = 1; puts(x); } else { y }; if (!true) { puts(2) }; if (x) { 1 }; x"));
+        assert_eq!(program.to_string(), "let x = 1;puts(x)ifx {1}x");
+
+        // The last statement gives a block its value, so an if taking no
+        // branch stays there.
+        let program = optimize(parse("fn() { 1; if (false) { 2 } }"));
+        assert_eq!(program.to_string(), "fn() {1iffalse {2}}");
+    }
+
+    #[test]
+    fn test_pools_duplicate_literals() {
+        let program = optimize(parse("\"hello\"; \"hel\" + \"lo\"; \"world\";"));
+        let first = expression(&program, 0);
+        let second = expression(&program, 1);
+        let third = expression(&program, 2);
+        let first = first.as_any().downcast_ref::<ast::StringLiteral>().unwrap();
+        let second = second.as_any().downcast_ref::<ast::StringLiteral>().unwrap();
+        let third = third.as_any().downcast_ref::<ast::StringLiteral>().unwrap();
+        assert!(Rc::ptr_eq(&first.value, &second.value));
+        assert!(!Rc::ptr_eq(&first.value, &third.value));
+    }
+}
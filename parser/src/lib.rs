@@ -1,18 +1,63 @@
 use std::rc::Rc;
 use ast::InfixExpression;
-use lexer::Lexer;
-use token::{Token, TokenType};
+use lexer::{Lexer, Segment};
+use token::{FileId, Token, TokenType};
 use std::collections::HashMap;
+use std::num::IntErrorKind;
 
-#[derive(PartialEq, PartialOrd)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
 enum Precedence {
     LOWEST = 1,
+    ASSIGN,
     EQUALS,
     LESSGREATER,
     SUM,
     PRODUCT,
+    POWER,
     PREFIX,
     CALL,
+    INDEX,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Associativity {
+    Left,
+    Right,
+}
+
+// Every infix operator: how tightly it binds, which way a chain of it
+// groups, and what parses it. Adding an operator takes a lexer token and
+// one entry here.
+const INFIX_OPERATORS: &[(TokenType, Precedence, Associativity, InfixParseFn)] = &[
+    (TokenType::ASSIGN, Precedence::ASSIGN, Associativity::Right, Parser::parse_assign_expression),
+    (TokenType::PLUS_ASSIGN, Precedence::ASSIGN, Associativity::Right, Parser::parse_assign_expression),
+    (TokenType::MINUS_ASSIGN, Precedence::ASSIGN, Associativity::Right, Parser::parse_assign_expression),
+    (TokenType::ASTERISK_ASSIGN, Precedence::ASSIGN, Associativity::Right, Parser::parse_assign_expression),
+    (TokenType::SLASH_ASSIGN, Precedence::ASSIGN, Associativity::Right, Parser::parse_assign_expression),
+    (TokenType::EQ, Precedence::EQUALS, Associativity::Left, Parser::parse_infix_expression),
+    (TokenType::NOT_EQ, Precedence::EQUALS, Associativity::Left, Parser::parse_infix_expression),
+    (TokenType::LT, Precedence::LESSGREATER, Associativity::Left, Parser::parse_infix_expression),
+    (TokenType::RT, Precedence::LESSGREATER, Associativity::Left, Parser::parse_infix_expression),
+    (TokenType::LT_EQ, Precedence::LESSGREATER, Associativity::Left, Parser::parse_infix_expression),
+    (TokenType::GT_EQ, Precedence::LESSGREATER, Associativity::Left, Parser::parse_infix_expression),
+    (TokenType::PLUS, Precedence::SUM, Associativity::Left, Parser::parse_infix_expression),
+    (TokenType::MINUS, Precedence::SUM, Associativity::Left, Parser::parse_infix_expression),
+    (TokenType::ASTERISK, Precedence::PRODUCT, Associativity::Left, Parser::parse_infix_expression),
+    (TokenType::SLASH, Precedence::PRODUCT, Associativity::Left, Parser::parse_infix_expression),
+    (TokenType::MODULO, Precedence::PRODUCT, Associativity::Left, Parser::parse_infix_expression),
+    (TokenType::POWER, Precedence::POWER, Associativity::Right, Parser::parse_infix_expression),
+    (TokenType::LPAREN, Precedence::CALL, Associativity::Left, Parser::parse_call_expression),
+    (TokenType::LBRACKET, Precedence::INDEX, Associativity::Left, Parser::parse_index_expression),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+    pub length: usize,
+    pub file: Option<FileId>,
 }
 
 type PrefixParseFn = fn(&mut Parser) -> Option<Rc<dyn ast::Expression>>;
@@ -24,7 +69,11 @@ pub struct Parser {
     current_token: Rc<Token>,
     peek_token: Rc<Token>,
 
-    errors: Vec<String>,
+    errors: Vec<ParseError>,
+    // Set by the first error in a statement and cleared once the parser has
+    // skipped past it; errors reported in between are consequences of the
+    // first one and are dropped.
+    panicking: bool,
 
     prefix_parse_fns: HashMap<token::TokenType, PrefixParseFn>,
     infix_parse_fns: HashMap<token::TokenType, InfixParseFn>
@@ -43,36 +92,48 @@ impl Parser {
             prefix_parse_fns,
             infix_parse_fns,
             errors: vec![],
+            panicking: false,
         };
 
+        p.register_prefix(TokenType::ILLEGAL, Parser::parse_illegal);
         p.register_prefix(TokenType::IDENT, Parser::parse_identifier);
         p.register_prefix(TokenType::INT, Parser::parse_integer_literal);
+        p.register_prefix(TokenType::FLOAT, Parser::parse_float_literal);
         p.register_prefix(TokenType::STRING, Parser::parse_string_literal);
+        p.register_prefix(TokenType::TEMPLATE, Parser::parse_interpolated_string);
         p.register_prefix(TokenType::TRUE, Parser::parse_boolean);
         p.register_prefix(TokenType::FALSE, Parser::parse_boolean);
+        p.register_prefix(TokenType::NULL, Parser::parse_null);
         p.register_prefix(TokenType::BANG, Parser::parse_prefix_expression);
         p.register_prefix(TokenType::MINUS, Parser::parse_prefix_expression);
         p.register_prefix(TokenType::LPAREN, Parser::parse_grouped_expression);
         p.register_prefix(TokenType::IF, Parser::parse_if_expression);
+        p.register_prefix(TokenType::WHILE, Parser::parse_while_expression);
         p.register_prefix(TokenType::FUNCTION, Parser::parse_function_literal);
+        p.register_prefix(TokenType::MACRO, Parser::parse_macro_literal);
+        p.register_prefix(TokenType::IMPORT, Parser::parse_import_expression);
+        p.register_prefix(TokenType::LBRACKET, Parser::parse_array_literal);
 
-        p.register_infix(TokenType::PLUS, Parser::parse_infix_expression);
-        p.register_infix(TokenType::MINUS, Parser::parse_infix_expression);
-        p.register_infix(TokenType::SLASH, Parser::parse_infix_expression);
-        p.register_infix(TokenType::ASTERISK, Parser::parse_infix_expression);
-        p.register_infix(TokenType::LT, Parser::parse_infix_expression);
-        p.register_infix(TokenType::RT, Parser::parse_infix_expression);
-        p.register_infix(TokenType::EQ, Parser::parse_infix_expression);
-        p.register_infix(TokenType::NOT_EQ, Parser::parse_infix_expression);
-        p.register_infix(TokenType::LPAREN, Parser::parse_call_expression);
-        p.register_infix(TokenType::MODULO, Parser::parse_infix_expression);
+        for (token_type, _, _, parse) in INFIX_OPERATORS {
+            p.register_infix(*token_type, *parse);
+        }
         p.register_infix(TokenType::STRING, Parser::parse_infix_expression);
-        
+
         p
     }
 
     pub fn errors(&self) -> Vec<String> {
-        self.errors.clone()
+        self.errors.iter().map(|e| e.message.clone()).collect()
+    }
+
+    pub fn parse_errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+
+    // Looks past peek_token without consuming anything; peek_nth(0) is the
+    // token that follows it.
+    pub fn peek_nth(&mut self, n: usize) -> &Token {
+        self.lexer.peek_nth(n)
     }
 
     pub fn next_token(&mut self) {
@@ -85,16 +146,48 @@ impl Parser {
             statements: vec![]
         };
     
-        while self.current_token.token_type.to_string() != "EOF" {
-            let stmt = self.parse_statement();
-            if stmt.is_some() {
-                program.statements.push(stmt.unwrap());
-            }
-            self.next_token();
+        // Statements that failed to parse are left out.
+        while let Some(statement) = self.next_statement() {
+            program.statements.extend(statement);
         }
 
         program
     }
+
+    // Parses one top-level statement at a time, for hosts that handle a
+    // program as it is read rather than once all of it is. Returns None at
+    // the end of input, and the errors found instead of the statement when
+    // it has any; they are kept in parse_errors too. A statement is returned
+    // once the parser has read the first tokens of the one after it.
+    pub fn parse_next_statement(&mut self) -> Option<Result<Rc<dyn ast::Statement>, Vec<ParseError>>> {
+        let seen = self.errors.len();
+        loop {
+            let statement = self.next_statement()?;
+            match statement {
+                Some(statement) if self.errors.len() == seen => return Some(Ok(statement)),
+                _ if self.errors.len() > seen => return Some(Err(self.errors[seen..].to_vec())),
+                _ => {},
+            }
+        }
+    }
+
+    // The next top-level statement, or None in its place if it failed to
+    // parse. A statement may parse with errors inside it, in blocks that
+    // recovered from them.
+    fn next_statement(&mut self) -> Option<Option<Rc<dyn ast::Statement>>> {
+        if self.current_token_is(TokenType::EOF) {
+            return None;
+        }
+        let statement = match self.parse_statement() {
+            Some(stmt) if !self.panicking => Some(stmt),
+            _ => {
+                self.synchronize();
+                None
+            },
+        };
+        self.next_token();
+        Some(statement)
+    }
     
     fn parse_statement(&mut self) -> Option<Rc<dyn ast::Statement>> {
         match self.current_token.clone().token_type {
@@ -129,6 +222,23 @@ impl Parser {
             value: self.current_token.clone().literal.clone(),
         });
 
+        let mut type_annotation = None;
+        if self.peek_token_is(TokenType::COLON) {
+            self.next_token();
+            type_annotation = Some(self.parse_type_annotation()?);
+        }
+
+        // `let x;` binds x to null.
+        if self.peek_token_is(TokenType::SEMICOLON) {
+            self.next_token();
+            return Some(Rc::new(ast::LetStatement {
+                token,
+                name,
+                type_annotation,
+                value: None,
+            }));
+        }
+
         if !self.expect_peek(TokenType::ASSIGN) {
             return None;
         }
@@ -144,17 +254,95 @@ impl Parser {
         Some(Rc::new(ast::LetStatement {
             token,
             name,
+            type_annotation,
             value,
         }))
     }
 
+    // Called on the COLON or ARROW that introduces the type. Types are
+    // names; fn and null are keywords but name types too.
+    fn parse_type_annotation(&mut self) -> Option<ast::TypeAnnotation> {
+        match self.peek_token.token_type {
+            TokenType::IDENT | TokenType::FUNCTION | TokenType::NULL => {
+                self.next_token();
+                Some(ast::TypeAnnotation {
+                    token: self.current_token.clone(),
+                    name: self.current_token.literal.clone(),
+                })
+            },
+            _ => {
+                let msg = format!("expected a type, got {} instead", self.peek_token.token_type);
+                self.add_error(msg, &self.peek_token.clone());
+                None
+            },
+        }
+    }
+
     fn parse_string_literal(&mut self) -> Option<Rc<dyn ast::Expression>> {
         Some(Rc::new(ast::StringLiteral {
-            token: self.current_token.clone(), 
-            value: self.current_token.literal.clone(),
+            token: self.current_token.clone(),
+            value: self.current_token.literal.as_str().into(),
         }))
     }
 
+    // Each interpolation is parsed on its own, and its errors are reported
+    // at the position of the string.
+    fn parse_interpolated_string(&mut self) -> Option<Rc<dyn ast::Expression>> {
+        let token = self.current_token.clone();
+        let mut parts: Vec<Rc<dyn ast::Expression>> = vec![];
+        let mut valid = true;
+        for segment in lexer::split_template(&token.literal) {
+            match segment {
+                Segment::Text(text) => parts.push(Rc::new(ast::StringLiteral {
+                    token: token.clone(),
+                    value: text.into(),
+                })),
+                Segment::Expression(source) => {
+                    let mut parser = Parser::new(Lexer::new(&source));
+                    match parser.parse_interpolation() {
+                        Some(expression) => parts.push(expression),
+                        None => valid = false,
+                    }
+                    for error in parser.errors {
+                        self.add_error(format!("in string interpolation: {}", error.message), &token);
+                    }
+                }
+            }
+        }
+        if !valid {
+            return None;
+        }
+        Some(Rc::new(ast::InterpolatedString { token, parts }))
+    }
+
+    fn parse_interpolation(&mut self) -> Option<Rc<dyn ast::Expression>> {
+        if self.current_token_is(TokenType::EOF) {
+            self.add_error("empty interpolation".to_string(), &self.current_token.clone());
+            return None;
+        }
+        let expression = self.parse_expression(Precedence::LOWEST)?;
+        if !self.peek_token_is(TokenType::EOF) {
+            let msg = format!("expected end of interpolation, got {} instead", self.peek_token.token_type);
+            self.add_error(msg, &self.peek_token.clone());
+            return None;
+        }
+        Some(expression)
+    }
+
+    fn parse_import_expression(&mut self) -> Option<Rc<dyn ast::Expression>> {
+        let token = self.current_token.clone();
+
+        if !self.expect_peek(TokenType::STRING) {
+            return None;
+        }
+
+        let path = Rc::new(ast::StringLiteral {
+            token: self.current_token.clone(),
+            value: self.current_token.literal.as_str().into(),
+        });
+        Some(Rc::new(ast::ImportExpression { token, path }))
+    }
+
     fn parse_return_statement(&mut self) -> Option<Rc<dyn ast::Statement>> {
         let token = self.current_token.clone();
         self.next_token();
@@ -177,9 +365,9 @@ impl Parser {
         self.next_token();
 
         while !self.current_token_is(TokenType::RBRACE) && !self.current_token_is(TokenType::EOF) {
-            let stmt = self.parse_statement();
-            if stmt.is_some() {
-                statements.push(stmt.unwrap());
+            match self.parse_statement() {
+                Some(stmt) if !self.panicking => statements.push(stmt),
+                _ => self.synchronize(),
             }
             self.next_token();
         }
@@ -187,46 +375,100 @@ impl Parser {
         Some(Rc::new(ast::BlockStatement {
             token,
             statements,
+            end: self.current_token.span,
         }))
     }
 
+    // After an error, skips to the last token of the broken statement: its
+    // semicolon, or the token before the closing brace of the enclosing
+    // block, the next let or return, or the end of input. Blocks opened in
+    // between are skipped whole.
+    fn synchronize(&mut self) {
+        if !self.panicking {
+            return;
+        }
+        self.panicking = false;
+        let mut depth = 0;
+        loop {
+            match self.current_token.token_type {
+                TokenType::SEMICOLON if depth == 0 => return,
+                TokenType::LBRACE => depth += 1,
+                TokenType::RBRACE if depth > 0 => depth -= 1,
+                _ => {}
+            }
+            match self.peek_token.token_type {
+                TokenType::EOF => return,
+                TokenType::RBRACE | TokenType::LET | TokenType::RETURN if depth == 0 => return,
+                _ => self.next_token(),
+            }
+        }
+    }
+
     fn parse_expression(&mut self, precedence: Precedence) -> Option<Rc<dyn ast::Expression>> {
-        let curr_token_type = self.current_token.token_type.clone();
+        let curr_token_type = self.current_token.token_type;
         let prefix = self.prefix_parse_fns.get(&curr_token_type);
         if prefix.is_none() {
             self.no_prefix_parse_fn_error(curr_token_type);
             return None;
         }
 
-        let mut left_exp = prefix.unwrap()(self);
+        let mut left_exp = prefix.unwrap()(self)?;
 
-        while !self.peek_token_is(TokenType::SEMICOLON) && precedence < Parser::get_precedence(self.peek_token.clone().token_type) {
-            let peek_token_type = self.peek_token.token_type.clone();
+        while !self.peek_token_is(TokenType::SEMICOLON) && Parser::binds_tighter(self.peek_token.token_type, precedence) {
+            let peek_token_type = self.peek_token.token_type;
             let infix = self.infix_parse_fns.get(&peek_token_type);
             if infix.is_none() {
-                return left_exp;
+                return Some(left_exp);
             }
 
             self.current_token = self.peek_token.clone();
             self.peek_token = Rc::new(self.lexer.next_token());
 
-            left_exp = infix.unwrap()(self, left_exp.unwrap());
+            left_exp = infix.unwrap()(self, left_exp)?;
         }
 
-        left_exp
+        Some(left_exp)
 
     }
 
     fn parse_integer_literal(&mut self) -> Option<Rc<dyn ast::Expression>> {
-        let value = self.current_token.literal.parse::<i64>();
+        let token = self.current_token.clone();
+        let value = self.parse_integer(token.clone())?;
+        Some(Rc::new(ast::IntegerLiteral { token, value }))
+    }
+
+    // Literals come without a sign, so one past i64::MAX only fits when the
+    // minus prefix is folded into it; parse_prefix_expression does that.
+    fn parse_integer(&mut self, token: Rc<Token>) -> Option<i64> {
+        let err = match token.literal.parse::<i64>() {
+            Ok(value) => return Some(value),
+            Err(err) => err,
+        };
+        let msg = match err.kind() {
+            IntErrorKind::PosOverflow => format!(
+                "integer literal {} is out of range (maximum is {}); use a float literal such as {}.0 instead",
+                token.literal, i64::MAX, token.literal,
+            ),
+            IntErrorKind::NegOverflow => format!(
+                "integer literal {} is out of range (minimum is {}); use a float literal such as {}.0 instead",
+                token.literal, i64::MIN, token.literal,
+            ),
+            _ => format!("could not parse {} as integer", token.literal),
+        };
+        self.add_error(msg, &token);
+        None
+    }
+
+    fn parse_float_literal(&mut self) -> Option<Rc<dyn ast::Expression>> {
+        let value = self.current_token.literal.parse::<f64>();
 
         if value.is_err() {
-            let msg = format!("could not parse {} as integer", self.current_token.literal);
-            self.errors.push(msg);
+            let msg = format!("could not parse {} as float", self.current_token.literal);
+            self.add_error(msg, &self.current_token.clone());
             return None;
         }
 
-        Some(Rc::new(ast::IntegerLiteral {
+        Some(Rc::new(ast::FloatLiteral {
             token: self.current_token.clone(),
             value: value.unwrap(),
         }))
@@ -246,26 +488,88 @@ impl Parser {
         }))
     }
 
+    fn parse_null(&mut self) -> Option<Rc<dyn ast::Expression>> {
+        Some(Rc::new(ast::NullLiteral {
+            token: self.current_token.clone(),
+        }))
+    }
+
+    fn parse_illegal(&mut self) -> Option<Rc<dyn ast::Expression>> {
+        let token = self.current_token.clone();
+        if token.literal.chars().count() != 1 {
+            let msg = format!("{} at line {}, column {}", token.literal, token.line, token.column);
+            self.push_error(ParseError {
+                message: msg,
+                line: token.line,
+                column: token.column,
+                length: 1,
+                file: token.file,
+            });
+            return None;
+        }
+
+        let mut characters = token.literal.clone();
+        while self.peek_token_is(TokenType::ILLEGAL)
+            && self.peek_token.literal.chars().count() == 1
+            && self.peek_token.line == token.line
+            && self.peek_token.column == token.column + characters.chars().count() {
+            self.next_token();
+            characters.push_str(&self.current_token.literal);
+        }
+
+        let msg = if characters.chars().count() == 1 {
+            format!("illegal character '{}' at line {}, column {}", characters, token.line, token.column)
+        } else {
+            format!("illegal characters '{}' at line {}, column {}", characters, token.line, token.column)
+        };
+        self.push_error(ParseError {
+            message: msg,
+            line: token.line,
+            column: token.column,
+            length: characters.chars().count(),
+            file: token.file,
+        });
+        None
+    }
+
     fn parse_prefix_expression(&mut self) -> Option<Rc<dyn ast::Expression>> {
-        let operator = &self.current_token.clone().literal;
+        if self.current_token_is(TokenType::MINUS) && self.peek_token_is(TokenType::INT)
+            && self.peek_token.literal.parse::<i64>().is_err() {
+            return self.parse_negative_integer_literal();
+        }
+        let token = self.current_token.clone();
         self.next_token();
-        let right = self.parse_expression(Precedence::PREFIX).unwrap();
+        let right = self.parse_expression(Precedence::PREFIX)?;
         Some(Rc::new(
             ast::PrefixExpression {
-                token: self.current_token.clone(),
-                operator: operator.to_string(),
+                operator: token.literal.clone(),
+                token,
                 right,
             }
         ))
     }
 
+    // A literal too large for i64 on its own may still fit once negated, as
+    // i64::MIN does, so the sign is read as part of the literal.
+    fn parse_negative_integer_literal(&mut self) -> Option<Rc<dyn ast::Expression>> {
+        let minus = self.current_token.clone();
+        self.next_token();
+        let mut token = Token::new(TokenType::INT, format!("-{}", self.current_token.literal));
+        token.line = minus.line;
+        token.column = minus.column;
+        token.file = minus.file;
+        let token = Rc::new(token);
+        let value = self.parse_integer(token.clone())?;
+        Some(Rc::new(ast::IntegerLiteral { token, value }))
+    }
+
     fn parse_infix_expression(&mut self, left: Rc<dyn ast::Expression>) -> Option<Rc<dyn ast::Expression>> {
         let operator = &self.current_token.clone().literal;
         let token = self.current_token.clone();
         
         let precedence = Parser::get_precedence(self.current_token.clone().token_type);
         self.next_token();
-        let right = self.parse_expression(precedence).unwrap();
+        let right = self.parse_expression(precedence)?;
 
         Some(Rc::new(
             InfixExpression {
@@ -277,6 +581,31 @@ impl Parser {
         ))
     }
 
+    fn parse_assign_expression(&mut self, left: Rc<dyn ast::Expression>) -> Option<Rc<dyn ast::Expression>> {
+        let token = self.current_token.clone();
+        let name = match left.kind() {
+            ast::ExpressionKind::Identifier(ident) => Rc::new(ast::Identifier {
+                token: ident.token.clone(),
+                value: ident.value.clone(),
+            }),
+            _ => {
+                let msg = format!("cannot assign to {}", left.to_string());
+                self.add_error(msg, &token);
+                return None;
+            }
+        };
+
+        self.next_token();
+        let value = self.parse_expression(Precedence::LOWEST)?;
+
+        Some(Rc::new(ast::AssignExpression {
+            token: token.clone(),
+            name,
+            operator: token.literal.clone(),
+            value,
+        }))
+    }
+
     fn parse_grouped_expression(&mut self) -> Option<Rc<dyn ast::Expression>> {
         self.next_token();
         let exp = self.parse_expression(Precedence::LOWEST);
@@ -287,21 +616,24 @@ impl Parser {
     }
 
     fn get_precedence(token_type: TokenType) -> Precedence {
-        match token_type {
-            TokenType::EQ => Precedence::EQUALS,
-            TokenType::NOT_EQ => Precedence::EQUALS,
-            TokenType::LT => Precedence::LESSGREATER,
-            TokenType::RT => Precedence::LESSGREATER,
-            TokenType::PLUS => Precedence::SUM,
-            TokenType::MINUS => Precedence::SUM,
-            TokenType::SLASH => Precedence::PRODUCT,
-            TokenType::ASTERISK => Precedence::PRODUCT,
-            TokenType::LPAREN => Precedence::CALL,
-            TokenType::MODULO => Precedence::PRODUCT,
-            _ => Precedence::LOWEST,
+        Parser::operator(token_type).map_or(Precedence::LOWEST, |(_, precedence, _, _)| *precedence)
+    }
+
+    // Whether an operator following an operand parsed at precedence takes
+    // that operand as its left side. A right-associative operator does so
+    // at its own precedence too, so a ** b ** c groups as a ** (b ** c).
+    fn binds_tighter(token_type: TokenType, precedence: Precedence) -> bool {
+        match Parser::operator(token_type) {
+            Some((_, operator, Associativity::Right, _)) if *operator == precedence => true,
+            Some((_, operator, _, _)) => precedence < *operator,
+            None => false,
         }
     }
 
+    fn operator(token_type: TokenType) -> Option<&'static (TokenType, Precedence, Associativity, InfixParseFn)> {
+        INFIX_OPERATORS.iter().find(|(operator, _, _, _)| *operator == token_type)
+    }
+
     fn parse_if_expression(&mut self) -> Option<Rc<dyn ast::Expression>> {
         let token = self.current_token.clone();
         if !self.expect_peek(TokenType::LPAREN) {
@@ -309,7 +641,7 @@ impl Parser {
         }
 
         self.next_token();
-        let condition = self.parse_expression(Precedence::LOWEST).unwrap();
+        let condition = self.parse_expression(Precedence::LOWEST)?;
 
         if !self.expect_peek(TokenType::RPAREN) {
             return None;
@@ -321,9 +653,7 @@ impl Parser {
 
         let if_body = self.parse_block_statement();
 
-        if if_body.as_ref().is_none() {
-            return None;
-        }
+        if_body.as_ref()?;
 
         let mut if_exp = ast::IfExpression {
             token,
@@ -334,19 +664,52 @@ impl Parser {
 
         if self.peek_token_is(TokenType::ELSE) {
             self.next_token();
+            // An else if is kept as an alternative holding just the inner
+            // if, so each link of the chain is an ordinary IfExpression.
+            if self.peek_token_is(TokenType::IF) {
+                self.next_token();
+                let token = self.current_token.clone();
+                let expression = self.parse_if_expression()?;
+                if_exp.alternative = Some(Rc::new(ast::ExpressionStatement { token, expression: Some(expression) }));
+                return Some(Rc::new(if_exp));
+            }
             if !self.expect_peek(TokenType::LBRACE) {
                 return None;
             }
             let alternative = self.parse_block_statement();
-            if alternative.is_none() {
-                return None;
-            }
+            alternative.as_ref()?;
             if_exp.alternative = alternative;
         }
 
         Some(Rc::new(if_exp))
     }
 
+    fn parse_while_expression(&mut self) -> Option<Rc<dyn ast::Expression>> {
+        let token = self.current_token.clone();
+        if !self.expect_peek(TokenType::LPAREN) {
+            return None;
+        }
+
+        self.next_token();
+        let condition = self.parse_expression(Precedence::LOWEST)?;
+
+        if !self.expect_peek(TokenType::RPAREN) {
+            return None;
+        }
+
+        if !self.expect_peek(TokenType::LBRACE) {
+            return None;
+        }
+
+        let body = self.parse_block_statement()?;
+
+        Some(Rc::new(ast::WhileExpression {
+            token,
+            condition,
+            body,
+        }))
+    }
+
     fn parse_function_literal(&mut self) -> Option<Rc<dyn ast::Expression>> {
         let token = self.current_token.clone();
 
@@ -354,7 +717,13 @@ impl Parser {
             return None;
         }
 
-        let parameters = self.parse_function_parameters();
+        let (parameters, parameter_types) = self.parse_function_parameters();
+
+        let mut return_type = None;
+        if self.peek_token_is(TokenType::ARROW) {
+            self.next_token();
+            return_type = Some(self.parse_type_annotation()?);
+        }
 
         if !self.expect_peek(TokenType::LBRACE) {
             return None;
@@ -362,85 +731,143 @@ impl Parser {
 
         let body = self.parse_block_statement();
 
-        if body.as_ref().is_none() {
-            return None;
-        }
+        body.as_ref()?;
 
         Some(Rc::new(ast::FunctionLiteral {
             token,
             parameters,
+            parameter_types,
+            return_type,
             body: body.unwrap(),
         }))
     }
 
-    fn parse_function_parameters(&mut self) -> Vec<Rc<ast::Identifier>> {
-        let mut identifiers = vec![];
+    fn parse_macro_literal(&mut self) -> Option<Rc<dyn ast::Expression>> {
+        let token = self.current_token.clone();
 
-        if self.peek_token_is(TokenType::RPAREN) {
-            self.next_token();
-            return identifiers;
+        if !self.expect_peek(TokenType::LPAREN) {
+            return None;
         }
 
-        self.next_token();
+        let (parameters, parameter_types) = self.parse_function_parameters();
 
-        let ident = Rc::new(ast::Identifier {
-            token: self.current_token.clone(),
-            value: self.current_token.literal.clone(),
-        });
+        // Macros work on code, not values, so there is nothing to annotate.
+        if let Some(annotation) = parameter_types.into_iter().flatten().next() {
+            self.add_error("macro parameters cannot have type annotations".to_string(), &annotation.token);
+            return None;
+        }
 
-        identifiers.push(ident);
+        if !self.expect_peek(TokenType::LBRACE) {
+            return None;
+        }
 
-        while self.peek_token_is(TokenType::COMMA) {
+        let body = self.parse_block_statement()?;
+
+        Some(Rc::new(ast::MacroLiteral {
+            token,
+            parameters,
+            body,
+        }))
+    }
+
+    // The parameters, and the type each is annotated with, if any.
+    fn parse_function_parameters(&mut self) -> (Vec<Rc<ast::Identifier>>, Vec<Option<ast::TypeAnnotation>>) {
+        let mut identifiers = vec![];
+        let mut types = vec![];
+
+        if self.peek_token_is(TokenType::RPAREN) {
             self.next_token();
+            return (identifiers, types);
+        }
+
+        loop {
             self.next_token();
             let ident = Rc::new(ast::Identifier {
                 token: self.current_token.clone(),
                 value: self.current_token.literal.clone(),
             });
             identifiers.push(ident);
+
+            let mut annotation = None;
+            if self.peek_token_is(TokenType::COLON) {
+                self.next_token();
+                annotation = self.parse_type_annotation();
+                if annotation.is_none() {
+                    return (vec![], vec![]);
+                }
+            }
+            types.push(annotation);
+
+            if !self.peek_token_is(TokenType::COMMA) {
+                break;
+            }
+            self.next_token();
         }
 
         if !self.expect_peek(TokenType::RPAREN) {
-            return vec![];
+            return (vec![], vec![]);
         }
 
-        identifiers
+        (identifiers, types)
     }
 
     fn parse_call_expression(&mut self, function: Rc<dyn ast::Expression>) -> Option<Rc<dyn ast::Expression>> {
         let token = self.current_token.clone();
-        let arguments = self.parse_call_arguments();
+        let arguments = self.parse_expression_list(TokenType::RPAREN);
         Some(Rc::new(ast::CallExpression {
             token,
             function,
             arguments,
+            end: self.current_token.span,
         }))
     }
 
-    fn parse_call_arguments(&mut self) -> Vec<Rc<dyn ast::Expression>> {
-        let mut args = vec![];
+    fn parse_array_literal(&mut self) -> Option<Rc<dyn ast::Expression>> {
+        let token = self.current_token.clone();
+        let elements = self.parse_expression_list(TokenType::RBRACKET);
+        Some(Rc::new(ast::ArrayLiteral { token, elements, end: self.current_token.span }))
+    }
 
-        if self.peek_token_is(TokenType::RPAREN) {
+    fn parse_index_expression(&mut self, left: Rc<dyn ast::Expression>) -> Option<Rc<dyn ast::Expression>> {
+        let token = self.current_token.clone();
+        self.next_token();
+        let index = self.parse_expression(Precedence::LOWEST)?;
+        if !self.expect_peek(TokenType::RBRACKET) {
+            return None;
+        }
+        Some(Rc::new(ast::IndexExpression { token, left, index, end: self.current_token.span }))
+    }
+
+    // Comma-separated expressions up to `end`, used for call arguments and
+    // array elements.
+    fn parse_expression_list(&mut self, end: TokenType) -> Vec<Rc<dyn ast::Expression>> {
+        let mut list = vec![];
+
+        if self.peek_token_is(end) {
             self.next_token();
-            return args;
+            return list;
         }
 
         self.next_token();
-        let arg = self.parse_expression(Precedence::LOWEST).unwrap();
-        args.push(arg);
+        match self.parse_expression(Precedence::LOWEST) {
+            Some(exp) => list.push(exp),
+            None => return vec![],
+        }
 
         while self.peek_token_is(TokenType::COMMA) {
             self.next_token();
             self.next_token();
-            let arg = self.parse_expression(Precedence::LOWEST).unwrap();
-            args.push(arg);
+            match self.parse_expression(Precedence::LOWEST) {
+                Some(exp) => list.push(exp),
+                None => return vec![],
+            }
         }
 
-        if !self.expect_peek(TokenType::RPAREN) {
+        if !self.expect_peek(end) {
             return vec![];
         }
 
-        args
+        list
     }
 
     fn current_token_is(&self, token_type: TokenType) -> bool {
@@ -463,12 +890,29 @@ impl Parser {
 
     fn add_peak_error(&mut self, token_type: TokenType) {
         let msg = format!("expected next token to be {}, got {} instead", token_type, self.peek_token.token_type);
-        self.errors.push(msg);
+        self.add_error(msg, &self.peek_token.clone());
     }
 
     fn no_prefix_parse_fn_error(&mut self, token_type: TokenType) {
         let msg = format!("no prefix parse function for {} found", token_type);
-        self.errors.push(msg);
+        self.add_error(msg, &self.current_token.clone());
+    }
+
+    fn add_error(&mut self, message: String, token: &Token) {
+        self.push_error(ParseError {
+            message,
+            line: token.line,
+            column: token.column,
+            length: token.literal.chars().count().max(1),
+            file: token.file,
+        });
+    }
+
+    fn push_error(&mut self, error: ParseError) {
+        if !self.panicking {
+            self.errors.push(error);
+            self.panicking = true;
+        }
     }
 
     fn register_prefix(&mut self, token_type: TokenType, func: PrefixParseFn) {
@@ -507,6 +951,54 @@ mod tests {
         assert_eq!(value.value, 5);
     }
 
+    #[test]
+    fn test_integer_literal_bounds() {
+        let tests = vec![
+            ("9223372036854775807", "9223372036854775807"),
+            ("-9223372036854775808", "-9223372036854775808"),
+            ("-9223372036854775807", "(-9223372036854775807)"),
+            ("f(-9223372036854775808 * 1)", "f((-9223372036854775808 * 1))"),
+        ];
+        for (input, expected) in tests {
+            let mut parser = Parser::new(Lexer::new(input));
+            let program = parser.parse_program();
+            assert!(parser.errors().is_empty(), "parser errors: {:?}", parser.errors());
+            assert_eq!(program.to_string(), expected);
+        }
+
+        let mut parser = Parser::new(Lexer::new("let x = 9223372036854775808;"));
+        parser.parse_program();
+        assert_eq!(parser.parse_errors()[0], ParseError {
+            message: "integer literal 9223372036854775808 is out of range (maximum is 9223372036854775807); use a float literal such as 9223372036854775808.0 instead".to_string(),
+            line: 1,
+            column: 9,
+            length: 19,
+            file: None,
+        });
+
+        let mut parser = Parser::new(Lexer::new("x = -9223372036854775809"));
+        parser.parse_program();
+        assert_eq!(parser.parse_errors()[0], ParseError {
+            message: "integer literal -9223372036854775809 is out of range (minimum is -9223372036854775808); use a float literal such as -9223372036854775809.0 instead".to_string(),
+            line: 1,
+            column: 5,
+            length: 20,
+            file: None,
+        });
+    }
+
+    #[test]
+    fn test_parsing_float_literal() {
+        let lexer = Lexer::new("3.25;");
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        assert_eq!(program.statements.len(), 1);
+        let stmt: &ast::ExpressionStatement = program.statements[0].as_any().downcast_ref::<ast::ExpressionStatement>().unwrap();
+        let value: &ast::FloatLiteral = stmt.expression.as_ref().unwrap().as_any().downcast_ref::<ast::FloatLiteral>().unwrap();
+        assert_eq!(value.value, 3.25);
+        assert_eq!(value.to_string(), "3.25");
+    }
+
     #[test]
     fn test_parsing_string_literal() {
         let lexer = Lexer::new("\"hello\";");
@@ -515,7 +1007,42 @@ mod tests {
         assert_eq!(program.statements.len(), 1);
         let stmt: &ast::ExpressionStatement = program.statements[0].as_any().downcast_ref::<ast::ExpressionStatement>().unwrap();
         let value: &ast::StringLiteral = stmt.expression.as_ref().unwrap().as_any().downcast_ref::<ast::StringLiteral>().unwrap();
-        assert_eq!(value.value, "hello");
+        assert_eq!(&*value.value, "hello");
+    }
+
+    #[test]
+    fn test_string_literal_to_string_escapes() {
+        let lexer = Lexer::new(r#""a\n\"b\"";"#);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let stmt: &ast::ExpressionStatement = program.statements[0].as_any().downcast_ref::<ast::ExpressionStatement>().unwrap();
+        let value: &ast::StringLiteral = stmt.expression.as_ref().unwrap().as_any().downcast_ref::<ast::StringLiteral>().unwrap();
+        assert_eq!(&*value.value, "a\n\"b\"");
+        assert_eq!(value.to_string(), r#""a\n\"b\"""#);
+    }
+
+    #[test]
+    fn test_parsing_interpolated_string() {
+        let lexer = Lexer::new(r#""hello {name}, you are {age + 1}\{}""#);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        assert!(parser.errors().is_empty(), "parser errors: {:?}", parser.errors());
+        let stmt = program.statements[0].as_any().downcast_ref::<ast::ExpressionStatement>().unwrap();
+        let interpolated = stmt.expression.as_ref().unwrap().as_any().downcast_ref::<ast::InterpolatedString>().unwrap();
+        assert_eq!(interpolated.parts.len(), 5);
+        assert_eq!(program.to_string(), r#""hello {name}, you are {(age + 1)}\{}""#);
+
+        let tests = vec![
+            (r#""{}""#, "in string interpolation: empty interpolation"),
+            (r#""{a b}""#, "in string interpolation: expected end of interpolation, got IDENT instead"),
+            (r#""{let}""#, "in string interpolation: no prefix parse function for LET found"),
+        ];
+        for (input, expected) in tests {
+            let mut parser = Parser::new(Lexer::new(input));
+            parser.parse_program();
+            assert_eq!(parser.errors()[0], expected, "input: {}", input);
+            assert_eq!((parser.parse_errors()[0].line, parser.parse_errors()[0].column), (1, 1));
+        }
     }
 
     #[test]
@@ -528,9 +1055,9 @@ mod tests {
         let infix: &ast::InfixExpression = stmt.expression.as_ref().unwrap().as_any().downcast_ref::<ast::InfixExpression>().unwrap();
         let left: &ast::StringLiteral = infix.left.as_ref().as_any().downcast_ref::<ast::StringLiteral>().unwrap();
         let right: &ast::StringLiteral = infix.right.as_ref().as_any().downcast_ref::<ast::StringLiteral>().unwrap();
-        assert_eq!(left.value, "hello");
+        assert_eq!(&*left.value, "hello");
         assert_eq!(infix.operator, "+");
-        assert_eq!(right.value, "world");
+        assert_eq!(&*right.value, "world");
     }
 
     #[test]
@@ -553,11 +1080,11 @@ mod tests {
 
         let true_exp_stmt: &ast::ExpressionStatement = program.statements[0].as_any().downcast_ref::<ast::ExpressionStatement>().unwrap();
         let tru: &ast::Boolean = true_exp_stmt.expression.as_ref().unwrap().as_any().downcast_ref::<ast::Boolean>().unwrap();
-        assert_eq!(tru.value, true);
+        assert!(tru.value);
 
         let false_exp_stmt: &ast::ExpressionStatement = program.statements[1].as_any().downcast_ref::<ast::ExpressionStatement>().unwrap();
-        let falsE: &ast::Boolean = true_exp_stmt.expression.as_ref().unwrap().as_any().downcast_ref::<ast::Boolean>().unwrap();
-        assert_eq!(falsE.value, true);
+        let fals: &ast::Boolean = false_exp_stmt.expression.as_ref().unwrap().as_any().downcast_ref::<ast::Boolean>().unwrap();
+        assert!(!fals.value);
     }
 
     #[test]
@@ -605,6 +1132,66 @@ mod tests {
         assert_eq!(infix.to_string(), "((5 * 2) - (3 / 3))");
     }
 
+    #[test]
+    fn test_node_spans() {
+        let input = "let add = fn(a, b) { a + b };\nadd(1, [2, 3][0]) * -x;\nif (x) { 1 } else if (y) { 2 }\nreturn \"{x}!\";";
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program();
+        assert!(parser.errors().is_empty(), "parser errors: {:?}", parser.errors());
+        let text = |node: &dyn ast::Node| &input[node.span().start..node.span().end];
+
+        let statements: Vec<&str> = program.statements.iter().map(|s| text(s.as_ref())).collect();
+        assert_eq!(statements, vec![
+            "let add = fn(a, b) { a + b }",
+            "add(1, [2, 3][0]) * -x",
+            "if (x) { 1 } else if (y) { 2 }",
+            "return \"{x}!\"",
+        ]);
+        assert_eq!(text(&program), &input[..input.len() - 1]);
+
+        let expression = |i: usize| match program.statements[i].kind() {
+            ast::StatementKind::Expression(statement) => statement.expression.clone().unwrap(),
+            _ => unreachable!(),
+        };
+        let (product, if_expression) = (expression(1), expression(2));
+        let ast::ExpressionKind::Infix(product) = product.kind() else { unreachable!() };
+        assert_eq!(text(product.right.as_ref()), "-x");
+        let ast::ExpressionKind::Call(call) = product.left.kind() else { unreachable!() };
+        assert_eq!(text(call), "add(1, [2, 3][0])");
+        assert_eq!(text(call.arguments[1].as_ref()), "[2, 3][0]");
+        let ast::ExpressionKind::If(if_expression) = if_expression.kind() else { unreachable!() };
+        assert_eq!(text(if_expression.consequence.as_ref()), "{ 1 }");
+        assert_eq!(text(if_expression.alternative.as_ref().unwrap().as_ref()), "if (y) { 2 }");
+    }
+
+    #[test]
+    fn test_power_precedence() {
+        let tests = vec![
+            ("2 ** 3 ** 2", "(2 ** (3 ** 2))"),
+            ("a * b ** c", "(a * (b ** c))"),
+            ("a ** b * c", "((a ** b) * c)"),
+            ("-a ** b", "((-a) ** b)"),
+            ("a ** -b", "(a ** (-b))"),
+            ("a ** f(b)[0]", "(a ** (f(b)[0]))"),
+            ("x = a ** b", "x = (a ** b)"),
+        ];
+        for (input, expected) in tests {
+            let mut parser = Parser::new(Lexer::new(input));
+            let program = parser.parse_program();
+            assert!(parser.errors().is_empty(), "parser errors: {:?}", parser.errors());
+            assert_eq!(program.to_string(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_comparison_precedence() {
+        let lexer = Lexer::new("a + 1 <= b * 2 == c >= d;");
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        assert_eq!(program.statements.len(), 1);
+        assert_eq!(program.statements[0].to_string(), "(((a + 1) <= (b * 2)) == (c >= d))");
+    }
+
     #[test]
     fn test_parsing_block_statement() {
        let lexer = Lexer::new("{
@@ -635,10 +1222,35 @@ mod tests {
        let exp = exp_stmt.expression.as_ref().unwrap().as_any().downcast_ref::<ast::IfExpression>().unwrap();
        assert_eq!(exp.token_literal().to_string(), "if");
        assert_eq!(exp.condition.to_string(), "(x < y)");
-       assert_eq!(exp.alternative.is_some(), true);
+       assert!(exp.alternative.is_some());
        assert_eq!(exp.to_string(), "if(x < y) {let x = 5;let y = 10;let foobar = 838383;} else {x}");
     }
 
+    #[test]
+    fn test_parsing_else_if_chain() {
+        let mut parser = Parser::new(Lexer::new("if (x < 1) { 1 } else if (x < 2) { 2 } else if (x < 3) { 3 } else { 4 }"));
+        let program = parser.parse_program();
+        assert!(parser.errors().is_empty(), "parser errors: {:?}", parser.errors());
+        assert_eq!(program.to_string(), "if(x < 1) {1} else if(x < 2) {2} else if(x < 3) {3} else {4}");
+
+        let mut parser = Parser::new(Lexer::new("if (a) { 1 } else if { 2 }"));
+        parser.parse_program();
+        assert_eq!(parser.errors()[0].to_string(), "expected next token to be LPAREN, got LBRACE instead");
+    }
+
+    #[test]
+    fn test_parsing_while_expression() {
+       let lexer = Lexer::new("while (x < 10) { let y = x; y }");
+       let mut parser = Parser::new(lexer);
+       let program = parser.parse_program();
+       assert_eq!(program.statements.len(), 1);
+       let exp_stmt = program.statements[0].as_any().downcast_ref::<ast::ExpressionStatement>().unwrap();
+       let exp = exp_stmt.expression.as_ref().unwrap().as_any().downcast_ref::<ast::WhileExpression>().unwrap();
+       assert_eq!(exp.token_literal(), "while");
+       assert_eq!(exp.condition.to_string(), "(x < 10)");
+       assert_eq!(exp.to_string(), "while(x < 10) {let y = x;y}");
+    }
+
     #[test]
     fn test_parsing_functions() {
        let lexer = Lexer::new("fn (x, y) {if(x < y) {
@@ -654,6 +1266,32 @@ mod tests {
        assert_eq!(exp.to_string(), "fn(x, y) {if(x < y) {let x = 5;let y = 10;let foobar = 838383;} else {x}}");
     }
 
+    #[test]
+    fn test_parsing_macro_literal() {
+       let lexer = Lexer::new("macro(x, y) { x + y; }");
+       let mut parser = Parser::new(lexer);
+       let program = parser.parse_program();
+       assert_eq!(program.statements.len(), 1);
+       let exp_stmt = program.statements[0].as_any().downcast_ref::<ast::ExpressionStatement>().unwrap();
+       let exp = exp_stmt.expression.as_ref().unwrap().as_any().downcast_ref::<ast::MacroLiteral>().unwrap();
+       assert_eq!(exp.parameters.len(), 2);
+       assert_eq!(exp.to_string(), "macro(x, y) {(x + y)}");
+    }
+
+    #[test]
+    fn test_parsing_import_expression() {
+       let lexer = Lexer::new("let utils = import \"lib/utils.monkey\";");
+       let mut parser = Parser::new(lexer);
+       let program = parser.parse_program();
+       assert!(parser.errors().is_empty(), "parser errors: {:?}", parser.errors());
+       assert_eq!(program.to_string(), "let utils = import \"lib/utils.monkey\";");
+
+       let lexer = Lexer::new("import utils");
+       let mut parser = Parser::new(lexer);
+       parser.parse_program();
+       assert_eq!(parser.errors()[0], "expected next token to be STRING, got IDENT instead");
+    }
+
     #[test]
     fn test_parsing_call_expresssions_0_args() {
        let lexer = Lexer::new("add();"); 
@@ -691,11 +1329,231 @@ mod tests {
         assert_eq!(infix.to_string(), "((-3) + ((!add(x, y)) * 2))");
     }
 
+    #[test]
+    fn test_parsing_array_literal() {
+        let lexer = Lexer::new("[1, 2 * 2, \"three\"]; []");
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        assert!(parser.errors().is_empty(), "parser errors: {:?}", parser.errors());
+        assert_eq!(program.statements.len(), 2);
+        let stmt = program.statements[0].as_any().downcast_ref::<ast::ExpressionStatement>().unwrap();
+        let array = stmt.expression.as_ref().unwrap().as_any().downcast_ref::<ast::ArrayLiteral>().unwrap();
+        assert_eq!(array.elements.len(), 3);
+        assert_eq!(array.to_string(), "[1, (2 * 2), \"three\"]");
+        assert_eq!(program.statements[1].to_string(), "[]");
+    }
+
+    #[test]
+    fn test_parsing_index_expression() {
+        let tests = vec![
+            ("xs[1 + 1]", "(xs[(1 + 1)])"),
+            ("a * [1, 2][b * c] * d", "((a * ([1, 2][(b * c)])) * d)"),
+            ("f(xs[0], ys[1])", "f((xs[0]), (ys[1]))"),
+            ("-xs[0]", "(-(xs[0]))"),
+        ];
+        for (input, expected) in tests {
+            let mut parser = Parser::new(Lexer::new(input));
+            let program = parser.parse_program();
+            assert!(parser.errors().is_empty(), "parser errors: {:?}", parser.errors());
+            assert_eq!(program.to_string(), expected);
+        }
+
+        let mut parser = Parser::new(Lexer::new("xs[0"));
+        parser.parse_program();
+        assert_eq!(parser.errors()[0], "expected next token to be RBRACKET, got EOF instead");
+    }
+
+    #[test]
+    fn test_parsing_assign_expression() {
+        let lexer = Lexer::new("x = y += 2 * 3;");
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        assert_eq!(program.statements.len(), 1);
+        let stmt: &ast::ExpressionStatement = program.statements[0].as_any().downcast_ref::<ast::ExpressionStatement>().unwrap();
+        let assign: &ast::AssignExpression = stmt.expression.as_ref().unwrap().as_any().downcast_ref::<ast::AssignExpression>().unwrap();
+        assert_eq!(assign.name.value, "x");
+        assert_eq!(assign.operator, "=");
+        assert_eq!(assign.to_string(), "x = y += (2 * 3)");
+    }
+
+    #[test]
+    fn test_parsing_invalid_assign_target() {
+        let lexer = Lexer::new("5 = 3;");
+        let mut parser = Parser::new(lexer);
+        let _program = parser.parse_program();
+        assert_eq!(parser.errors(), vec!["cannot assign to 5".to_string()]);
+        assert_eq!(parser.parse_errors()[0], ParseError {
+            message: "cannot assign to 5".to_string(),
+            line: 1,
+            column: 3,
+            length: 1,
+            file: None,
+        });
+    }
+
+    #[test]
+    fn test_reporting_illegal_characters() {
+        let lexer = Lexer::new("let x = @#;");
+        let mut parser = Parser::new(lexer);
+        let _program = parser.parse_program();
+        assert_eq!(parser.errors(), vec!["illegal characters '@#' at line 1, column 9".to_string()]);
+
+        let lexer = Lexer::new("1 @ 2;\n3 + $");
+        let mut parser = Parser::new(lexer);
+        let _program = parser.parse_program();
+        assert_eq!(parser.errors(), vec![
+            "illegal character '@' at line 1, column 3".to_string(),
+            "illegal character '$' at line 2, column 5".to_string(),
+        ]);
+
+        let lexer = Lexer::new("let s = \"abc");
+        let mut parser = Parser::new(lexer);
+        let _program = parser.parse_program();
+        assert_eq!(parser.errors(), vec!["unterminated string at line 1, column 9".to_string()]);
+
+        let lexer = Lexer::new("puts(\"{\")");
+        let mut parser = Parser::new(lexer);
+        let _program = parser.parse_program();
+        assert_eq!(parser.errors()[0], "unterminated interpolation in string: expected }, or \\{ for a literal brace at line 1, column 6");
+    }
+
+    #[test]
+    fn test_parsing_type_annotations() {
+        let mut parser = Parser::new(Lexer::new("let x: int = 5; let f = fn(a: string, b, c: null) -> array { [a] }; let g: fn;"));
+        let program = parser.parse_program();
+        assert!(parser.errors().is_empty(), "parser errors: {:?}", parser.errors());
+        let let_statement = program.statements[0].as_any().downcast_ref::<ast::LetStatement>().unwrap();
+        assert_eq!(let_statement.type_annotation.as_ref().map(|t| t.name.as_str()), Some("int"));
+        let let_statement = program.statements[1].as_any().downcast_ref::<ast::LetStatement>().unwrap();
+        let function = let_statement.value.as_ref().unwrap().as_any().downcast_ref::<ast::FunctionLiteral>().unwrap();
+        let types: Vec<Option<&str>> = function.parameter_types.iter().map(|t| t.as_ref().map(|t| t.name.as_str())).collect();
+        assert_eq!(types, vec![Some("string"), None, Some("null")]);
+        assert_eq!(function.return_type.as_ref().map(|t| t.name.as_str()), Some("array"));
+        assert_eq!(program.to_string(), "let x: int = 5;let f = fn(a: string, b, c: null) -> array {[a]};let g: fn;");
+
+        for (input, error) in [
+            ("let x: = 5;", "expected a type, got ASSIGN instead"),
+            ("fn(a: 1) { a }", "expected a type, got INT instead"),
+            ("fn(a) -> { a }", "expected a type, got LBRACE instead"),
+            ("macro(a: int) { a }", "macro parameters cannot have type annotations"),
+        ] {
+            let mut parser = Parser::new(Lexer::new(input));
+            parser.parse_program();
+            assert_eq!(parser.errors().first().map(String::as_str), Some(error), "input: {}", input);
+        }
+    }
+
     #[test]
     fn test_catching_parsing_error() {
-       let lexer = Lexer::new("let x;"); 
+       let lexer = Lexer::new("let x 5;"); 
        let mut parser = Parser::new(lexer);
        let _program = parser.parse_program();
-       assert_eq!(parser.errors().len(), 2);
+       assert_eq!(parser.errors(), vec!["expected next token to be ASSIGN, got INT instead".to_string()]);
+    }
+
+    #[test]
+    fn test_let_without_initializer_and_null() {
+        let mut parser = Parser::new(Lexer::new("let x; if (x == null) { null }"));
+        let program = parser.parse_program();
+        assert!(parser.errors().is_empty(), "parser errors: {:?}", parser.errors());
+        let let_statement = program.statements[0].as_any().downcast_ref::<ast::LetStatement>().unwrap();
+        assert!(let_statement.value.is_none());
+        assert_eq!(program.to_string(), "let x;if(x == null) {null}");
+    }
+
+    #[test]
+    fn test_peek_nth() {
+        let mut parser = Parser::new(Lexer::new("let x = 5;"));
+        assert_eq!(parser.peek_nth(0).literal, "=");
+        assert_eq!(parser.peek_nth(1).literal, "5");
+        let program = parser.parse_program();
+        assert_eq!(program.to_string(), "let x = 5;");
+    }
+
+    #[test]
+    fn test_recovering_from_errors() {
+        let input = "let x 5;
+let ok = 1;
+let f = fn(a) {
+  let y = (a + ) * 2;
+  a
+};
+if (x +) { 1 } else { 2 };
+let g = fn() { let = 1 };
+return ok;";
+        let lexer = Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        assert_eq!(parser.errors(), vec![
+            "expected next token to be ASSIGN, got INT instead".to_string(),
+            "no prefix parse function for RPAREN found".to_string(),
+            "no prefix parse function for RPAREN found".to_string(),
+            "expected next token to be IDENT, got ASSIGN instead".to_string(),
+        ]);
+        let lines: Vec<usize> = parser.parse_errors().iter().map(|e| e.line).collect();
+        assert_eq!(lines, vec![1, 4, 7, 8]);
+        let statements: Vec<String> = program.statements.iter().map(|s| s.to_string()).collect();
+        assert_eq!(statements, vec!["let ok = 1;", "let f = fn(a) {a};", "let g = fn() {};", "return ok;"]);
+
+        for input in ["@ + 1", "if () { 1 }", "let x = fn(", "}; let y = 1;"] {
+            let lexer = Lexer::new(input);
+            let mut parser = Parser::new(lexer);
+            parser.parse_program();
+            assert_eq!(parser.errors().len(), 1, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_parse_next_statement() {
+        let input = "let x = 1;\nlet = 2;\nlet f = fn() { let y = (1 + ); 2 };\nx + 1\n";
+        let mut parser = Parser::new(Lexer::from_reader(std::io::Cursor::new(input.as_bytes().to_vec())));
+        let mut results = vec![];
+        while let Some(result) = parser.parse_next_statement() {
+            results.push(result.map(|s| s.to_string()).map_err(|errors| errors.iter().map(|e| (e.message.clone(), e.line)).collect::<Vec<_>>()));
+        }
+        assert_eq!(results, vec![
+            Ok("let x = 1;".to_string()),
+            Err(vec![("expected next token to be IDENT, got ASSIGN instead".to_string(), 2)]),
+            Err(vec![("no prefix parse function for RPAREN found".to_string(), 3)]),
+            Ok("(x + 1)".to_string()),
+        ]);
+        assert_eq!(parser.parse_errors().len(), 2);
+        assert!(parser.parse_next_statement().is_none());
+    }
+
+    #[test]
+    fn test_ast_json_round_trip() {
+        let input = "let add = fn(a, b) { return a + b; };
+let s = \"tab\\there\";
+x += -1.5;
+while (!done) { if (x <= 2) { add(x, 1) } else { false } }
+let twice = macro(x) { quote(unquote(x) + unquote(x)) };
+let utils = import \"lib/utils.monkey\";
+[1, \"two\"][0];
+let empty; empty == null;
+let n: int = fn(x: int, y) -> fn { x }(1, 2);";
+        let lexer = Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        assert!(parser.errors().is_empty(), "parser errors: {:?}", parser.errors());
+
+        let json = program.to_json();
+        assert_eq!(json["statements"][0]["node"], "LET_STATEMENT");
+        assert_eq!(json["statements"][0]["name"]["value"], "add");
+        assert_eq!(json["statements"][0]["token"]["line"], 1);
+        assert_eq!(json["statements"][2]["expression"]["node"], "ASSIGN_EXPRESSION");
+        assert_eq!(json["statements"][2]["expression"]["value"]["right"]["value"], 1.5);
+
+        let decoded = ast::Program::from_json(&json).unwrap();
+        assert_eq!(decoded.to_string(), program.to_string());
+        assert_eq!(decoded.to_json(), json);
+    }
+
+    #[test]
+    fn test_ast_from_invalid_json() {
+        let json = serde_json::json!({ "node": "PROGRAM", "statements": [{ "node": "LET_STATEMENT" }] });
+        assert_eq!(ast::Program::from_json(&json).err(), Some("missing field: token".to_string()));
+        let json = serde_json::json!({ "node": "IDENTIFIER" });
+        assert_eq!(ast::Program::from_json(&json).err(), Some("expected PROGRAM node, got IDENTIFIER".to_string()));
     }
 }
@@ -0,0 +1,230 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::{Cell, RefCell};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use evaluator::{Evaluator, Limits};
+use lexer::Lexer;
+use object::Object;
+
+use crate::evaluation::{self, Outcome};
+use crate::new_environment;
+
+// Counts the heap bytes each thread has live, so a program's peak can be
+// measured on the thread that evaluates it without other threads skewing it.
+pub struct CountingAllocator;
+
+thread_local! {
+    static CURRENT: Cell<usize> = const { Cell::new(0) };
+    static PEAK: Cell<usize> = const { Cell::new(0) };
+}
+
+fn record_alloc(size: usize) {
+    let _ = CURRENT.try_with(|current| {
+        current.set(current.get() + size);
+        let _ = PEAK.try_with(|peak| peak.set(peak.get().max(current.get())));
+    });
+}
+
+fn record_dealloc(size: usize) {
+    let _ = CURRENT.try_with(|current| current.set(current.get().saturating_sub(size)));
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        record_dealloc(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            record_dealloc(layout.size());
+            record_alloc(new_size);
+        }
+        new_ptr
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+pub struct Budgets {
+    pub time: Duration,
+    // Peak heap bytes a program may use on top of what was live before it
+    // started.
+    pub memory: usize,
+}
+
+impl Default for Budgets {
+    fn default() -> Self {
+        Budgets {
+            time: Duration::from_secs(1),
+            memory: 64 << 20,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Verdict {
+    Ok,
+    // Programs are stopped once they use up their time budget.
+    OverTime,
+    OverMemory,
+    Error(String),
+}
+
+struct Run {
+    verdict: Verdict,
+    duration: Duration,
+    peak_memory: usize,
+}
+
+// Runs every .monkey file in dir, in name order, and writes one line per
+// program plus a summary. Returns whether all of them finished within
+// budget without errors.
+pub fn run_corpus<W: Write>(dir: &Path, budgets: &Budgets, prelude: bool, writer: &mut W) -> io::Result<bool> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "monkey"))
+        .collect();
+    paths.sort();
+
+    let (mut over_time, mut over_memory, mut errors) = (0, 0, 0);
+    for path in &paths {
+        let name = path.strip_prefix(dir).unwrap_or(path).display();
+        let run = match std::fs::read_to_string(path) {
+            Ok(source) => run_program(path, &source, budgets, prelude),
+            Err(err) => Run { verdict: Verdict::Error(err.to_string()), duration: Duration::ZERO, peak_memory: 0 },
+        };
+        let measured = format!("{:.1} ms, {}", run.duration.as_secs_f64() * 1000.0, format_bytes(run.peak_memory));
+        match run.verdict {
+            Verdict::Ok => writeln!(writer, "{}: ok ({})", name, measured)?,
+            Verdict::OverTime => {
+                over_time += 1;
+                writeln!(writer, "{}: over time budget of {} ms, stopped ({})", name, budgets.time.as_millis(), measured)?;
+            }
+            Verdict::OverMemory => {
+                over_memory += 1;
+                writeln!(writer, "{}: over memory budget of {} ({})", name, format_bytes(budgets.memory), measured)?;
+            }
+            Verdict::Error(message) => {
+                errors += 1;
+                writeln!(writer, "{}: error: {} ({})", name, message, measured)?;
+            }
+        }
+    }
+    writeln!(
+        writer,
+        "{} programs: {} ok, {} over time, {} over memory, {} errors",
+        paths.len(),
+        paths.len() - over_time - over_memory - errors,
+        over_time,
+        over_memory,
+        errors,
+    )?;
+    Ok(over_time + over_memory + errors == 0)
+}
+
+fn run_program(path: &Path, source: &str, budgets: &Budgets, prelude: bool) -> Run {
+    let mut evaluator = Evaluator::with_limits(Limits::default().for_stack_size(crate::STACK_SIZE));
+    evaluator.output = Box::new(io::sink());
+    evaluator.source_path = Some(path.to_path_buf());
+    let environment = new_environment(&mut evaluator, prelude);
+    let macros = Rc::new(RefCell::new(object::Environment::new()));
+
+    let (done, finished) = mpsc::channel::<()>();
+    let token = evaluator.cancellation.clone();
+    let time = budgets.time;
+    let watchdog = thread::spawn(move || {
+        let timed_out = finished.recv_timeout(time) == Err(mpsc::RecvTimeoutError::Timeout);
+        if timed_out {
+            token.cancel();
+        }
+        timed_out
+    });
+
+    let baseline = CURRENT.with(Cell::get);
+    PEAK.with(|peak| peak.set(baseline));
+    let evaluation = evaluation::evaluate(Lexer::new(source), &environment, &macros, &mut evaluator);
+    let peak_memory = PEAK.with(Cell::get).saturating_sub(baseline);
+    let _ = done.send(());
+    let timed_out = watchdog.join().unwrap_or(false);
+
+    let verdict = match evaluation.outcome {
+        _ if timed_out => Verdict::OverTime,
+        _ if peak_memory > budgets.memory => Verdict::OverMemory,
+        Outcome::ParseErrors(errors) => Verdict::Error(format!("parser errors: {}", errors.iter().map(|e| e.message.as_str()).collect::<Vec<_>>().join("; "))),
+        Outcome::MacroError(message) => Verdict::Error(format!("macro expansion error: {}", message)),
+        Outcome::ValidationErrors(errors) => Verdict::Error(format!("validation errors: {}", errors.iter().map(|e| e.message.as_str()).collect::<Vec<_>>().join("; "))),
+        Outcome::Evaluated(Some(Object::Error(message))) => Verdict::Error(message),
+        Outcome::Evaluated(_) => Verdict::Ok,
+    };
+    Run { verdict, duration: evaluation.duration, peak_memory }
+}
+
+fn format_bytes(bytes: usize) -> String {
+    match bytes {
+        0..=1023 => format!("{} B", bytes),
+        1024..=0xFFFFF => format!("{:.1} KB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_corpus() {
+        let dir = std::env::temp_dir().join(format!("monkey_corpus_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a_fib.monkey"), "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; puts(fib(10));").unwrap();
+        std::fs::write(dir.join("b_loop.monkey"), "while (true) { 1 }").unwrap();
+        std::fs::write(dir.join("c_grow.monkey"), "let s = \"x\"; let i = 0; while (i < 22) { s = s + s; i += 1 }; len(s)").unwrap();
+        std::fs::write(dir.join("d_error.monkey"), "1 + true").unwrap();
+        std::fs::write(dir.join("notes.txt"), "not a program").unwrap();
+
+        let budgets = Budgets { time: Duration::from_millis(200), memory: 1 << 20 };
+        let mut output = Vec::new();
+        let passed = run_corpus(&dir, &budgets, false, &mut output).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert!(!passed);
+        assert_eq!(lines.len(), 5, "output: {:?}", output);
+        assert!(lines[0].starts_with("a_fib.monkey: ok ("), "output: {:?}", output);
+        assert!(lines[1].starts_with("b_loop.monkey: over time budget of 200 ms, stopped ("), "output: {:?}", output);
+        assert!(lines[2].starts_with("c_grow.monkey: over memory budget of 1.0 MB ("), "output: {:?}", output);
+        assert!(lines[3].starts_with("d_error.monkey: error: type mismatch: INTEGER + BOOLEAN ("), "output: {:?}", output);
+        assert_eq!(lines[4], "4 programs: 1 ok, 1 over time, 1 over memory, 1 errors");
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KB");
+        assert_eq!(format_bytes(64 << 20), "64.0 MB");
+    }
+}
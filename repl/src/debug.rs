@@ -0,0 +1,148 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+use evaluator::{Breakpoint, DebugConsole, Pause, Resume};
+use token::SourceMap;
+
+// A breakpoint as given to :break. Lines are kept by file name until an
+// evaluation starts, since the file may not have been loaded yet.
+#[derive(Debug, PartialEq)]
+pub enum Location {
+    Line { file: String, line: usize },
+    Function(String),
+}
+
+impl Location {
+    pub fn parse(spec: &str) -> Result<Location, String> {
+        if let Some((file, line)) = spec.rsplit_once(':') {
+            return match line.parse() {
+                Ok(line) if line > 0 && !file.is_empty() => Ok(Location::Line { file: file.to_string(), line }),
+                _ => Err(format!("expected FILE:LINE or a function name, got {}", spec)),
+            };
+        }
+        if spec.is_empty() || spec.contains(char::is_whitespace) {
+            return Err(format!("expected FILE:LINE or a function name, got {}", spec));
+        }
+        Ok(Location::Function(spec.to_string()))
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Location::Line { file, line } => write!(f, "{}:{}", file, line),
+            Location::Function(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+// The evaluator's breakpoints for the files loaded so far.
+pub fn resolve(locations: &[Location], sources: &SourceMap) -> Vec<Breakpoint> {
+    let mut breakpoints = vec![];
+    for location in locations {
+        match location {
+            Location::Line { file, line } => {
+                breakpoints.extend(sources.find(file).into_iter().map(|file| Breakpoint::Line { file: Some(file), line: *line }));
+            }
+            Location::Function(name) => breakpoints.push(Breakpoint::Function(name.clone())),
+        }
+    }
+    breakpoints
+}
+
+// Reads debugger commands from the REPL's own input while the evaluator is
+// paused. Running out of input stops the evaluation.
+pub struct Console<R> {
+    pub input: Rc<RefCell<R>>,
+}
+
+const HELP: &str = "expected :step, :next, :continue, :locals, :print NAME, :where or :quit";
+
+impl<R: BufRead> DebugConsole for Console<R> {
+    fn pause(&mut self, pause: &Pause, output: &mut dyn Write) -> Resume {
+        let reason = match pause.breakpoint {
+            Some(Breakpoint::Function(name)) => format!("breakpoint in {}", name),
+            Some(Breakpoint::Line { .. }) => "breakpoint".to_string(),
+            None => "step".to_string(),
+        };
+        let _ = writeln!(
+            output,
+            "paused ({}) at line {}, column {}: {}",
+            reason,
+            pause.token.line,
+            pause.token.column,
+            pause.statement.to_string(),
+        );
+        loop {
+            let _ = write!(output, "(debug) ");
+            let _ = output.flush();
+            let mut line = String::new();
+            match self.input.borrow_mut().read_line(&mut line) {
+                Ok(0) | Err(_) => return Resume::Stop,
+                Ok(_) => {}
+            }
+            let (command, argument) = match line.trim().split_once(char::is_whitespace) {
+                Some((command, argument)) => (command, argument.trim()),
+                None => (line.trim(), ""),
+            };
+            match command {
+                ":step" => return Resume::Step,
+                ":next" => return Resume::Next,
+                ":continue" => return Resume::Continue,
+                ":quit" => return Resume::Stop,
+                ":locals" => {
+                    let env = pause.environment.borrow();
+                    let mut names: Vec<&String> = env.scope.keys().collect();
+                    names.sort();
+                    for name in names {
+                        let _ = writeln!(output, "{} = {}", name, env.scope[name].inspect());
+                    }
+                }
+                ":print" => match pause.environment.borrow().get(argument) {
+                    Some(value) => {
+                        let _ = writeln!(output, "{}", value.inspect());
+                    }
+                    None => {
+                        let _ = writeln!(output, "identifier not found: {}", argument);
+                    }
+                },
+                ":where" => {
+                    for frame in pause.call_stack.frames().iter().rev() {
+                        let _ = writeln!(output, "in {} called at line {}, column {}", frame.function, frame.line, frame.column);
+                    }
+                }
+                _ => {
+                    let _ = writeln!(output, "{}", HELP);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locations() {
+        assert_eq!(Location::parse("lib.monkey:12"), Ok(Location::Line { file: "lib.monkey".to_string(), line: 12 }));
+        assert_eq!(Location::parse("<repl:3>:1"), Ok(Location::Line { file: "<repl:3>".to_string(), line: 1 }));
+        assert_eq!(Location::parse("fib"), Ok(Location::Function("fib".to_string())));
+        assert!(Location::parse("lib.monkey:0").is_err());
+        assert!(Location::parse(":3").is_err());
+        assert!(Location::parse("two words").is_err());
+
+        let mut sources = SourceMap::new();
+        let first = sources.add("lib.monkey", "");
+        sources.add("<repl:1>", "");
+        let second = sources.add("lib.monkey", "");
+        let locations = [Location::parse("lib.monkey:2").unwrap(), Location::parse("missing:1").unwrap(), Location::parse("fib").unwrap()];
+        assert_eq!(resolve(&locations, &sources), vec![
+            Breakpoint::Line { file: Some(first), line: 2 },
+            Breakpoint::Line { file: Some(second), line: 2 },
+            Breakpoint::Function("fib".to_string()),
+        ]);
+    }
+}
@@ -0,0 +1,253 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use evaluator::{CallStack, Evaluator, SharedBuffer};
+use lexer::Lexer;
+use object::Object;
+use parser::{ParseError, Parser};
+use serde_json::{json, Value};
+use token::{FileId, SourceMap, Token, TokenType};
+use validator::{Diagnostic, Severity};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputMode {
+    Text,
+    Json,
+}
+
+impl OutputMode {
+    pub fn parse(name: &str) -> Option<OutputMode> {
+        match name {
+            "text" => Some(OutputMode::Text),
+            "json" => Some(OutputMode::Json),
+            _ => None,
+        }
+    }
+}
+
+pub enum Outcome {
+    ParseErrors(Vec<Diagnostic>),
+    MacroError(String),
+    ValidationErrors(Vec<Diagnostic>),
+    Evaluated(Option<Object>),
+}
+
+pub struct Evaluation {
+    pub outcome: Outcome,
+    pub warnings: Vec<Diagnostic>,
+    pub duration: Duration,
+    // Where the innermost call was made when a runtime error occurred.
+    pub error_location: Option<(usize, usize)>,
+    // Where the operands start when an operator could not be applied.
+    pub error_operands: Vec<Rc<Token>>,
+    // The calls that were active when a runtime error occurred.
+    pub error_call_stack: Option<CallStack>,
+}
+
+impl Evaluation {
+    pub fn is_error(&self) -> bool {
+        match &self.outcome {
+            Outcome::Evaluated(Some(result)) => result.object_type() == object::ObjectType::ERROR,
+            Outcome::Evaluated(None) => false,
+            _ => true,
+        }
+    }
+
+    // One object per evaluation: value and type are null when evaluation
+    // did not produce a value, and every error carries the stage it came
+    // from and its position when one is known.
+    pub fn to_json(&self, output: &str) -> Value {
+        let mut errors = vec![];
+        let (value, value_type) = match &self.outcome {
+            Outcome::ParseErrors(diagnostics) => {
+                errors.extend(diagnostics.iter().map(|d| diagnostic_json("parse", d)));
+                (Value::Null, Value::Null)
+            }
+            Outcome::MacroError(message) => {
+                errors.push(json!({ "stage": "macro", "message": message }));
+                (Value::Null, Value::Null)
+            }
+            Outcome::ValidationErrors(diagnostics) => {
+                errors.extend(diagnostics.iter().map(|d| diagnostic_json("validate", d)));
+                (Value::Null, Value::Null)
+            }
+            Outcome::Evaluated(Some(result)) if result.object_type() == object::ObjectType::ERROR => {
+                let mut error = json!({ "stage": "runtime", "message": result.inspect() });
+                if let Some((line, column)) = self.error_location {
+                    error["line"] = json!(line);
+                    error["column"] = json!(column);
+                }
+                if !self.error_operands.is_empty() {
+                    error["operands"] = self.error_operands.iter()
+                        .map(|token| json!({ "line": token.line, "column": token.column, "length": token_width(token) }))
+                        .collect();
+                }
+                if let Some(stack) = &self.error_call_stack {
+                    error["trace"] = stack.frames().iter()
+                        .map(|frame| json!({ "function": frame.function, "line": frame.line, "column": frame.column }))
+                        .collect();
+                }
+                errors.push(error);
+                (Value::Null, Value::Null)
+            }
+            Outcome::Evaluated(Some(result)) => (json!(result.inspect()), json!(format!("{:?}", result.object_type()))),
+            Outcome::Evaluated(None) => (Value::Null, json!("NULL")),
+        };
+        json!({
+            "value": value,
+            "type": value_type,
+            "output": output,
+            "duration_ms": self.duration.as_secs_f64() * 1000.0,
+            "errors": errors,
+            "warnings": self.warnings.iter().map(|d| diagnostic_json("validate", d)).collect::<Vec<_>>(),
+        })
+    }
+}
+
+// Runs the lexer's input through the whole pipeline: parse, macro
+// expansion, validation and evaluation.
+pub fn evaluate(
+    lexer: Lexer,
+    environment: &Rc<RefCell<object::Environment>>,
+    macros: &Rc<RefCell<object::Environment>>,
+    evaluator: &mut Evaluator,
+) -> Evaluation {
+    let start = Instant::now();
+    let finish = |outcome, warnings, error_call_stack: Option<CallStack>, error_operands| Evaluation {
+        outcome,
+        warnings,
+        duration: start.elapsed(),
+        error_location: error_call_stack.as_ref()
+            .and_then(|stack| stack.innermost())
+            .map(|frame| (frame.line, frame.column)),
+        error_operands,
+        error_call_stack,
+    };
+
+    let mut p = Parser::new(lexer);
+    let mut program = p.parse_program();
+    if !p.parse_errors().is_empty() {
+        let diagnostics = p.parse_errors().iter().map(parse_error_diagnostic).collect();
+        return finish(Outcome::ParseErrors(diagnostics), vec![], None, vec![]);
+    }
+    evaluator.define_macros(&mut program, macros.clone());
+    let program = match evaluator.expand_macros(program, macros.clone()) {
+        Ok(program) => program,
+        Err(message) => return finish(Outcome::MacroError(message), vec![], None, vec![]),
+    };
+    let (errors, warnings): (Vec<_>, Vec<_>) = validator::validate(&program).into_iter().partition(|d| d.is_error());
+    if !errors.is_empty() {
+        return finish(Outcome::ValidationErrors(errors), warnings, None, vec![]);
+    }
+    let result = evaluator.evaluate_program(program, environment.clone());
+    let error_call_stack = evaluator.error_call_stack().cloned();
+    let error_operands = evaluator.error_operands().to_vec();
+    finish(Outcome::Evaluated(result), warnings, error_call_stack, error_operands)
+}
+
+// Like evaluate, but collects what puts writes instead of letting it reach
+// the evaluator's output, so it can be reported alongside the result.
+pub fn evaluate_captured(
+    lexer: Lexer,
+    environment: &Rc<RefCell<object::Environment>>,
+    macros: &Rc<RefCell<object::Environment>>,
+    evaluator: &mut Evaluator,
+) -> (Evaluation, String) {
+    let buffer = SharedBuffer::default();
+    let previous = std::mem::replace(&mut evaluator.output, Box::new(buffer.clone()));
+    let evaluation = evaluate(lexer, environment, macros, evaluator);
+    evaluator.output = previous;
+    (evaluation, buffer.contents())
+}
+
+// Errors for lexer tokens carry their position in the message, which the
+// diagnostic shows anyway.
+pub fn parse_error_diagnostic(error: &ParseError) -> Diagnostic {
+    let position = format!(" at line {}, column {}", error.line, error.column);
+    let message = error.message.strip_suffix(&position).unwrap_or(&error.message);
+    Diagnostic {
+        severity: Severity::ERROR,
+        message: message.to_string(),
+        line: error.line,
+        column: error.column,
+        length: error.length,
+        previous: None,
+        file: error.file,
+    }
+}
+
+// Underlines each operand of a failed operator on its source line:
+//
+//     let y = x + true;
+//             ^   ^^^^ type mismatch: INTEGER + BOOLEAN
+//
+// Returns None when the operands are not all on one line of a known file.
+pub fn render_operands(message: &str, operands: &[Rc<Token>], sources: &SourceMap, current: FileId) -> Option<String> {
+    let first = operands.first()?;
+    if operands.iter().any(|token| token.line != first.line || token.file != first.file) {
+        return None;
+    }
+    let file = first.file?;
+    let line = sources.line(file, first.line)?;
+    let mut underline = String::new();
+    for token in operands {
+        let skip = token.column.saturating_sub(1).checked_sub(underline.chars().count())?;
+        underline.extend(line.chars().skip(underline.chars().count()).take(skip).map(|c| if c == '\t' { '\t' } else { ' ' }));
+        underline.push_str(&"^".repeat(token_width(token)));
+    }
+    let rendered = format!("{}\n{} {}", line, underline, message);
+    match sources.get(file) {
+        Some(source) if file != current => Some(format!("in {}:\n{}", source.name, rendered)),
+        _ => Some(rendered),
+    }
+}
+
+// Lists the calls that led to a runtime error, most recent last, each with
+// where it was made:
+//
+//     stack trace (most recent call last):
+//       outer called at line 4, column 1
+//       check called at line 2, column 13 in lib.monkey
+//
+// Deep recursion is shortened to the outermost and innermost calls.
+pub fn render_trace(stack: &CallStack, sources: &SourceMap, current: FileId) -> String {
+    const SHOWN: usize = 10;
+    let frames = stack.frames();
+    let mut out = String::from("stack trace (most recent call last):");
+    for (i, frame) in frames.iter().enumerate() {
+        if frames.len() > 2 * SHOWN && i >= SHOWN && i < frames.len() - SHOWN {
+            if i == SHOWN {
+                out.push_str(&format!("\n  ... {} more calls", frames.len() - 2 * SHOWN));
+            }
+            continue;
+        }
+        out.push_str(&format!("\n  {} called at line {}, column {}", frame.function, frame.line, frame.column));
+        if let Some(source) = frame.file.filter(|file| *file != current).and_then(|file| sources.get(file)) {
+            out.push_str(&format!(" in {}", source.name));
+        }
+    }
+    out
+}
+
+// String tokens hold their value without the quotes around it.
+fn token_width(token: &Token) -> usize {
+    match token.token_type {
+        TokenType::STRING | TokenType::TEMPLATE => token.literal.chars().count() + 2,
+        _ => token.literal.chars().count().max(1),
+    }
+}
+
+fn diagnostic_json(stage: &str, diagnostic: &Diagnostic) -> Value {
+    let mut value = json!({
+        "stage": stage,
+        "message": diagnostic.message,
+        "line": diagnostic.line,
+        "column": diagnostic.column,
+        "length": diagnostic.length,
+    });
+    if let Some((line, column)) = diagnostic.previous {
+        value["previous"] = json!({ "line": line, "column": column });
+    }
+    value
+}
@@ -1,57 +1,1713 @@
-use std::io::{self, Write};
+use std::io::{self, BufRead, Read, Write};
 use lexer::Lexer;
+#[cfg(feature = "net")]
+use std::net::TcpListener;
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::thread;
 use parser::Parser;
 use std::env;
+use std::path::Path;
+use std::process;
+use ast::Node;
+use token::{FileId, SourceMap};
+use evaluator::{Evaluator, FileSystemLoader, Limits, MemoryLoader, ModuleLoader};
+use object::Object;
+use evaluation::{Evaluation, Outcome, OutputMode};
+
+mod corpus;
+mod debug;
+mod evaluation;
+#[cfg(feature = "net")]
+mod serve;
+mod testing;
+
+// The native stack the interpreter runs with. Limits are made with
+// Limits::for_stack_size, so recursion gets as deep as this has room for.
+const STACK_SIZE: usize = 64 << 20;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() > 1 {
-        run_file(&args[1]);
+    match thread::Builder::new().stack_size(STACK_SIZE).spawn(run) {
+        Ok(runner) => {
+            if runner.join().is_err() {
+                process::exit(101);
+            }
+        }
+        Err(err) => {
+            eprintln!("could not start interpreter thread: {}", err);
+            process::exit(1);
+        }
+    }
+}
+
+// Exit statuses. A program that fails before it starts running, as on a
+// parse error, exits like any other failed command; one that fails while
+// running exits with its own status so scripts can tell the two apart.
+const EXIT_FAILURE: i32 = 1;
+const EXIT_RUNTIME_ERROR: i32 = 2;
+
+const USAGE: &str = "usage: monkey [COMMAND] [OPTIONS] [FILE]
+
+commands:
+  run FILE [ARG...]    run a program, which args() gives the ARGs, and print
+                       its value; a FILE of - reads the program from stdin
+  repl [--listen ADDR] start an interactive session, or serve sandboxed
+                       sessions over TCP
+  fmt FILE...          format files in place
+  check FILE...        report errors without running; with --types, also
+                       check values against their type annotations
+  test [PATH...]       run the test_ functions of *_test.monkey files
+  bench-corpus DIR     run every program in a directory against budgets
+  listen ADDR          serve sandboxed REPL sessions over TCP
+  serve ADDR           serve JSON-RPC over HTTP, posted to /
+
+Without a command, a FILE is run and no FILE starts the REPL.
+
+options for run and repl:
+  --quiet              do not print the value a program ends with
+  --emit=STAGE         print tokens, ast, ast-json or resolved instead of
+                       running
+  --output=MODE        text or json
+  --group-digits[=C]   group the digits of results shown
+  --max-depth=N        nesting shown in results
+  --max-length=N       array elements and string characters shown in results
+  --optimize, --trace, --no-prelude, --sandbox, --allow-fs
+  --max-steps=N, --max-bindings=N, --timeout-ms=N";
+
+// The options run and repl take, and the files named among them.
+struct Options {
+    emit: String,
+    limits: Limits,
+    prelude: bool,
+    mode: OutputMode,
+    pretty: object::PrettyConfig,
+    optimize: bool,
+    trace: bool,
+    quiet: bool,
+    file: Option<String>,
+    // Everything after the file, passed to the program.
+    script_args: Vec<String>,
+    // Where repl --listen serves sessions instead of reading stdin.
+    listen: Option<String>,
+}
+
+fn run() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (command, args) = match args.first().map(String::as_str) {
+        Some(command @ ("run" | "repl" | "fmt" | "check" | "test" | "bench-corpus" | "listen" | "serve")) => (command, &args[1..]),
+        Some("--listen") => ("listen", &args[1..]),
+        Some("--serve") => ("serve", &args[1..]),
+        Some("help" | "--help" | "-h") => {
+            println!("{}", USAGE);
+            return;
+        }
+        _ if args.iter().any(|arg| !arg.starts_with("--")) => ("run", &args[..]),
+        _ => ("repl", &args[..]),
+    };
+    let code = match command {
+        "run" => {
+            let options = parse_options(args);
+            match &options.file {
+                Some(filename) => run_file(filename, &options, &mut io::stdout(), &mut io::stderr()),
+                None => usage_error("run takes a FILE"),
+            }
+        }
+        "repl" => {
+            let options = parse_options(args);
+            if options.file.is_some() {
+                usage_error("repl takes no FILE; use run to run one");
+            }
+            if let Some(addr) = &options.listen {
+                #[cfg(feature = "net")]
+                listen(addr);
+                #[cfg(not(feature = "net"))]
+                usage_error(&format!("cannot listen on {}: this build has no network support", addr));
+                return;
+            }
+            let mut evaluator = Evaluator::with_limits(options.limits);
+            evaluator.pretty = options.pretty;
+            evaluator.optimize = options.optimize;
+            if options.trace {
+                evaluator.trace = Some(Box::new(io::stderr()));
+            }
+            repl(evaluator, options.prelude, options.mode);
+            0
+        }
+        "fmt" if !args.is_empty() => {
+            args.iter().for_each(|filename| format_file(filename));
+            0
+        }
+        "check" => check_files(args),
+        "test" => run_tests(args),
+        "bench-corpus" => {
+            bench_corpus(args);
+            0
+        }
+        #[cfg(feature = "net")]
+        "listen" if args.len() == 1 => {
+            listen(&args[0]);
+            0
+        }
+        #[cfg(feature = "net")]
+        "serve" if args.len() == 1 => {
+            serve::serve(&args[0]);
+            0
+        }
+        #[cfg(not(feature = "net"))]
+        "listen" | "serve" => usage_error("this build has no network support"),
+        _ => usage_error(&format!("missing arguments to {}", command)),
+    };
+    if code != 0 {
+        process::exit(code);
+    }
+}
+
+fn usage_error(message: &str) -> i32 {
+    eprintln!("{}\n\n{}", message, USAGE);
+    process::exit(EXIT_FAILURE);
+}
+
+fn parse_options(args: &[String]) -> Options {
+    let mut options = Options {
+        emit: "result".to_string(),
+        limits: Limits::default().for_stack_size(STACK_SIZE),
+        prelude: true,
+        mode: OutputMode::Text,
+        pretty: object::PrettyConfig::default(),
+        optimize: false,
+        trace: false,
+        quiet: false,
+        file: None,
+        script_args: vec![],
+        listen: None,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if options.file.is_some() {
+            options.script_args.push(arg.clone());
+        } else if let Some(name) = arg.strip_prefix("--output=") {
+            options.mode = match OutputMode::parse(name) {
+                Some(mode) => mode,
+                None => {
+                    eprintln!("unsupported --output mode: {} (expected text or json)", name);
+                    process::exit(EXIT_FAILURE);
+                }
+            };
+        } else if let Some(stage) = arg.strip_prefix("--emit=") {
+            // json is what ast-json was first called.
+            options.emit = if stage == "json" { "ast-json" } else { stage }.to_string();
+        } else if arg == "--dump-ast" {
+            options.emit = "ast-json".to_string();
+        } else if arg == "--quiet" {
+            options.quiet = true;
+        } else if arg == "--optimize" {
+            options.optimize = true;
+        } else if arg == "--trace" {
+            options.trace = true;
+        } else if arg == "--group-digits" {
+            options.pretty.digit_grouping = Some(',');
+        } else if let Some(separator) = arg.strip_prefix("--group-digits=") {
+            let mut chars = separator.chars();
+            options.pretty.digit_grouping = match (chars.next(), chars.next()) {
+                (Some(separator), None) => Some(separator),
+                _ => {
+                    eprintln!("--group-digits separator must be a single character, got {:?}", separator);
+                    process::exit(EXIT_FAILURE);
+                }
+            };
+        } else if let Some(value) = arg.strip_prefix("--max-depth=") {
+            options.pretty.max_depth = parse_budget("--max-depth", value) as usize;
+        } else if let Some(value) = arg.strip_prefix("--max-length=") {
+            options.pretty.max_length = parse_budget("--max-length", value) as usize;
+        } else if arg == "--sandbox" {
+            options.limits = Limits::sandbox().for_stack_size(STACK_SIZE);
+        } else if arg == "--allow-fs" {
+            options.limits.allow_fs = true;
+        } else if let Some(value) = arg.strip_prefix("--max-steps=") {
+            options.limits.max_steps = Some(parse_budget("--max-steps", value) as usize);
+        } else if let Some(value) = arg.strip_prefix("--max-bindings=") {
+            options.limits.max_env_entries = Some(parse_budget("--max-bindings", value) as usize);
+        } else if let Some(value) = arg.strip_prefix("--timeout-ms=") {
+            options.limits.timeout = Some(std::time::Duration::from_millis(parse_budget("--timeout-ms", value)));
+        } else if arg == "--listen" {
+            if args.len() == 0 {
+                usage_error("--listen takes an ADDR");
+            }
+            options.listen = args.next().cloned();
+        } else if let Some(addr) = arg.strip_prefix("--listen=") {
+            options.listen = Some(addr.to_string());
+        } else if arg == "--no-prelude" {
+            options.prelude = false;
+        } else if arg.starts_with("--") {
+            usage_error(&format!("unknown option: {}", arg));
+        } else {
+            options.file = Some(arg.clone());
+        }
+    }
+    options
+}
+
+fn repl(evaluator: Evaluator, prelude: bool, mode: OutputMode) {
+    let stdin = io::stdin();
+    let token = evaluator.cancellation.clone();
+    let handler = ctrlc::set_handler(move || {
+        if token.is_cancelled() {
+            process::exit(130);
+        }
+        token.cancel();
+    });
+    if let Err(err) = handler {
+        eprintln!("could not install Ctrl-C handler: {}", err);
+    }
+    start(stdin.lock(), io::stdout(), evaluator, prelude, mode, false);
+}
+
+#[cfg(feature = "net")]
+fn listen(addr: &str) {
+    let listener = match TcpListener::bind(addr) {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("could not listen on {}: {}", addr, err);
+            return;
+        }
+    };
+    println!("Listening on {}", addr);
+    accept_sessions(listener, remote_limits());
+}
+
+// What a remote client may hold the server to: inputs are cut short, a
+// client that goes quiet is dropped, and no line may grow without end.
+#[cfg(feature = "net")]
+const REMOTE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+#[cfg(feature = "net")]
+const REMOTE_MAX_STEPS: usize = 10_000_000;
+#[cfg(feature = "net")]
+const REMOTE_IDLE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(600);
+#[cfg(feature = "net")]
+const REMOTE_MAX_LINE_LENGTH: usize = 64 << 10;
+#[cfg(feature = "net")]
+const MAX_REMOTE_SESSIONS: usize = 64;
+
+// Sandboxed, so a remote session's code cannot import files or use the file
+// builtins, and with a budget for each input.
+#[cfg(feature = "net")]
+fn remote_limits() -> Limits {
+    Limits {
+        max_steps: Some(REMOTE_MAX_STEPS),
+        timeout: Some(REMOTE_TIMEOUT),
+        ..Limits::sandbox().for_stack_size(STACK_SIZE)
+    }
+}
+
+// Each connection gets a session of its own, up to MAX_REMOTE_SESSIONS at
+// once; connections past that are told so and closed.
+#[cfg(feature = "net")]
+fn accept_sessions(listener: TcpListener, limits: Limits) {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let sessions = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        if sessions.fetch_add(1, Ordering::SeqCst) >= MAX_REMOTE_SESSIONS {
+            sessions.fetch_sub(1, Ordering::SeqCst);
+            let _ = writeln!(stream, "too many sessions, try again later");
+            continue;
+        }
+        let active = sessions.clone();
+        let spawned = thread::Builder::new().stack_size(STACK_SIZE).spawn(move || {
+            let _ = stream.set_read_timeout(Some(REMOTE_IDLE_TIMEOUT));
+            if let (Ok(reader), Ok(output)) = (stream.try_clone(), stream.try_clone()) {
+                let reader = LineLimit::new(io::BufReader::new(reader), REMOTE_MAX_LINE_LENGTH);
+                let mut evaluator = Evaluator::with_limits(limits);
+                evaluator.output = Box::new(output);
+                start(reader, stream, evaluator, true, OutputMode::Text, true);
+            }
+            active.fetch_sub(1, Ordering::SeqCst);
+        });
+        if let Err(err) = spawned {
+            sessions.fetch_sub(1, Ordering::SeqCst);
+            eprintln!("could not start connection thread: {}", err);
+        }
+    }
+}
+
+// Fails to read once a line runs past max bytes, so a client cannot make a
+// session buffer one endless line.
+#[cfg(feature = "net")]
+struct LineLimit<R> {
+    inner: R,
+    max: usize,
+    // Bytes read since the last newline.
+    line: usize,
+}
+
+#[cfg(feature = "net")]
+impl<R: BufRead> LineLimit<R> {
+    fn new(inner: R, max: usize) -> LineLimit<R> {
+        LineLimit { inner, max, line: 0 }
+    }
+}
+
+#[cfg(feature = "net")]
+impl<R: BufRead> Read for LineLimit<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+#[cfg(feature = "net")]
+impl<R: BufRead> BufRead for LineLimit<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.line > self.max {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("line longer than {} bytes", self.max)));
+        }
+        // No more than would take the line one byte past max.
+        let room = self.max + 1 - self.line;
+        let buf = self.inner.fill_buf()?;
+        Ok(&buf[..buf.len().min(room)])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if let Ok(buf) = self.inner.fill_buf() {
+            let consumed = &buf[..amt.min(buf.len())];
+            self.line = match consumed.iter().rposition(|&b| b == b'\n') {
+                Some(i) => consumed.len() - i - 1,
+                None => self.line + consumed.len(),
+            };
+        }
+        self.inner.consume(amt);
+    }
+}
+
+// What one input leaves behind for the next: bindings, macros, and the
+// source of every input so far, which diagnostics about code a macro
+// carried into a later input are rendered against.
+struct Session {
+    environment: Rc<RefCell<object::Environment>>,
+    macros: Rc<RefCell<object::Environment>>,
+    sources: SourceMap,
+    // Earlier inputs by name, for imports such as `import "<repl:1>"`.
+    inputs: MemoryLoader,
+    breakpoints: Vec<debug::Location>,
+}
+
+impl Session {
+    fn new(evaluator: &mut Evaluator, prelude: bool) -> Session {
+        let inputs = MemoryLoader::new();
+        evaluator.set_module_loader(Box::new(SessionLoader { inputs: inputs.clone() }));
+        Session {
+            environment: new_environment(evaluator, prelude),
+            macros: Rc::new(RefCell::new(object::Environment::new())),
+            sources: SourceMap::new(),
+            inputs,
+            breakpoints: vec![],
+        }
+    }
+}
+
+// Imports the session's earlier inputs by name, and anything else from disk.
+struct SessionLoader {
+    inputs: MemoryLoader,
+}
+
+impl ModuleLoader for SessionLoader {
+    fn resolve(&self, path: &str, importer: Option<&str>) -> Result<String, String> {
+        if self.inputs.contains(path) {
+            return Ok(path.to_string());
+        }
+        FileSystemLoader.resolve(path, importer)
+    }
+
+    fn load(&self, name: &str) -> Result<String, String> {
+        if self.inputs.contains(name) {
+            return self.inputs.load(name);
+        }
+        FileSystemLoader.load(name)
+    }
+}
+
+fn new_environment(evaluator: &mut Evaluator, prelude: bool) -> Rc<RefCell<object::Environment>> {
+    if prelude {
+        monkey::prelude::environment(evaluator)
     } else {
-        repl();
+        Rc::new(RefCell::new(object::Environment::new()))
     }
 }
 
-fn repl() {
+// In JSON mode the banner and prompts are left out, so every line written
+// is one JSON object.
+// The debugger reads its commands from the same input, so a script piped
+// in can drive it too.
+// A remote session is one served over a socket, which may not use the
+// server's files.
+fn start<R: BufRead + 'static, W: Write>(reader: R, mut writer: W, mut evaluator: Evaluator, prelude: bool, mode: OutputMode, remote: bool) {
     let msg = "This is monkey programming language!\nFeel free to type in commands";
-    let prompt = ">> ";
-    println!("{}", msg);
-    let environment = Rc::new(RefCell::new(object::Environment::new()));
+    let (prompt, continuation_prompt) = match mode {
+        OutputMode::Text => (">> ", ".. "),
+        OutputMode::Json => ("", ""),
+    };
+    if mode == OutputMode::Text {
+        let _ = writeln!(writer, "{}", msg);
+    }
+    let reader = Rc::new(RefCell::new(reader));
+    evaluator.debugger = Some(evaluator::Debugger::new(Box::new(debug::Console { input: reader.clone() })));
+    let mut session = Session::new(&mut evaluator, prelude);
     loop {
-        print!("{}", prompt);
-        let _ = io::stdout().flush();
-
         let mut input = String::new();
-        let _ = io::stdin().read_line(&mut input).unwrap();
+        // Input still open when the reader ends is run as it is, so what is
+        // wrong with it gets reported.
+        let mut ended = false;
+        loop {
+            let _ = write!(writer, "{}", if input.is_empty() { prompt } else { continuation_prompt });
+            let _ = writer.flush();
 
-        let l = Lexer::new(&input);
-        let mut p = Parser::new(l);
-        let program = p.parse_program();
-        if p.errors().len() != 0 {
-            println!(" parser errors:");
-            for msg in p.errors() {
-                println!("\t{}", msg);
+            let start = input.len();
+            match reader.borrow_mut().read_line(&mut input) {
+                Ok(0) if !input.trim().is_empty() => {
+                    ended = true;
+                    break;
+                }
+                Ok(0) | Err(_) => return,
+                Ok(_) => {}
             }
-            continue;
+            // A blank line submits input the parser is still waiting on, so
+            // a statement that can never be finished is not stuck.
+            let blank = start > 0 && input[start..].trim().is_empty();
+            match check_input(&input) {
+                InputState::Incomplete if blank && is_complete(&input) => break,
+                InputState::Incomplete => {}
+                InputState::Invalid | InputState::Complete => break,
+            }
+        }
+
+        match input.trim().strip_prefix(':') {
+            Some(command) => run_command(command, &mut writer, &mut session, &mut evaluator, prelude, mode, remote),
+            None => {
+                let name = format!("<repl:{}>", session.sources.len() + 1);
+                eval_input(&name, &input, &mut writer, &mut session, &mut evaluator, mode);
+                session.inputs.insert(&name, &input);
+            }
+        }
+        if ended {
+            return;
+        }
+    }
+}
+
+fn run_command<W: Write>(
+    command: &str,
+    writer: &mut W,
+    session: &mut Session,
+    evaluator: &mut Evaluator,
+    prelude: bool,
+    mode: OutputMode,
+    remote: bool,
+) {
+    let (name, argument) = match command.split_once(char::is_whitespace) {
+        Some((name, argument)) => (name, argument.trim()),
+        None => (command, ""),
+    };
+    if remote && matches!(name, "load" | "save" | "load-session") {
+        let _ = writeln!(writer, ":{} is not available in a remote session", name);
+        return;
+    }
+    match name {
+        "env" => {
+            let env = session.environment.borrow();
+            let mut names: Vec<&String> = env.scope.keys().collect();
+            names.sort();
+            for name in names {
+                let _ = writeln!(writer, "{} = {}", name, env.scope[name].inspect());
+            }
+        }
+        "ast" => {
+            if let Some(program) = parse_input(argument, writer) {
+                let _ = writeln!(writer, "{}", program.to_string());
+            }
+        }
+        "type" => {
+            if let Some(program) = parse_input(argument, writer) {
+                let _ = writeln!(writer, "{}", monkey::infer_type(&program, &session.environment));
+            }
+        }
+        "tokens" => write_tokens(writer, Lexer::new(argument)),
+        "break" if argument.is_empty() => {
+            for (i, location) in session.breakpoints.iter().enumerate() {
+                let _ = writeln!(writer, "{}: {}", i + 1, location);
+            }
+        }
+        "break" => match debug::Location::parse(argument) {
+            Ok(location) => {
+                let _ = writeln!(writer, "breakpoint {} at {}", session.breakpoints.len() + 1, location);
+                session.breakpoints.push(location);
+            }
+            Err(err) => {
+                let _ = writeln!(writer, "{}", err);
+            }
+        },
+        "clear" => {
+            let _ = writeln!(writer, "cleared {} breakpoints", session.breakpoints.len());
+            session.breakpoints.clear();
+        }
+        "stats" if argument == "reset" => evaluator.reset_stats(),
+        "stats" => {
+            let stats = evaluator.stats();
+            let _ = writeln!(writer, "expressions evaluated: {}", stats.expressions_evaluated);
+            let _ = writeln!(writer, "values allocated: {}", stats.values_allocated);
+            let _ = writeln!(
+                writer,
+                "environments allocated: {} (reused {}, released {}, collected {})",
+                stats.environments_allocated, stats.environments_reused, stats.environments_released, stats.environments_collected,
+            );
+            let _ = writeln!(writer, "max call depth: {}", stats.max_call_depth);
+        }
+        "reset" => *session = Session::new(evaluator, prelude),
+        // The trace goes to stderr, so it does not interleave with results
+        // written to a redirected stdout.
+        "trace" => match argument {
+            "on" => evaluator.trace = Some(Box::new(io::stderr())),
+            "off" => evaluator.trace = None,
+            "" => {
+                let _ = writeln!(writer, "tracing is {}", if evaluator.trace.is_some() { "on" } else { "off" });
+            }
+            _ => {
+                let _ = writeln!(writer, "expected :trace on or :trace off, got :trace {}", argument);
+            }
+        },
+        "load" => match std::fs::read_to_string(argument) {
+            Ok(source) => {
+                let previous = evaluator.source_path.replace(argument.into());
+                eval_input(argument, &source, writer, session, evaluator, mode);
+                evaluator.source_path = previous;
+            }
+            Err(err) => {
+                let _ = writeln!(writer, "could not read {}: {}", argument, err);
+            }
+        },
+        "save" => match save_session(session, argument) {
+            Ok(skipped) => {
+                if !skipped.is_empty() {
+                    let _ = writeln!(writer, "not saved: {}", skipped.join(", "));
+                }
+                let _ = writeln!(writer, "saved session to {}", argument);
+            }
+            Err(err) => {
+                let _ = writeln!(writer, "could not save {}: {}", argument, err);
+            }
+        },
+        "load-session" => match load_session(session, argument) {
+            Ok(count) => {
+                let _ = writeln!(writer, "restored {} bindings from {}", count, argument);
+            }
+            Err(err) => {
+                let _ = writeln!(writer, "could not load {}: {}", argument, err);
+            }
+        },
+        _ => {
+            let _ = writeln!(
+                writer,
+                "unknown command :{} (expected :env, :ast, :type, :tokens, :stats, :trace, :break, :clear, :reset, :load, :save or :load-session)",
+                name,
+            );
+        }
+    }
+}
+
+// A session file holds the bindings and macros of the session, but not its
+// inputs, so code from before the save cannot be imported by name.
+// Returns the names whose values could not be saved.
+fn save_session(session: &Session, path: &str) -> Result<Vec<String>, String> {
+    let environment = session.environment.borrow().snapshot();
+    let macros = session.macros.borrow().snapshot();
+    let file = serde_json::json!({ "environment": environment.value, "macros": macros.value });
+    let text = serde_json::to_string_pretty(&file).map_err(|err| err.to_string())?;
+    std::fs::write(path, text).map_err(|err| err.to_string())?;
+    Ok(environment.skipped)
+}
+
+// Bindings from the file are added to the session, replacing any of the
+// same name. Returns how many there were, not counting macros.
+fn load_session(session: &Session, path: &str) -> Result<usize, String> {
+    let text = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let file: serde_json::Value = serde_json::from_str(&text).map_err(|err| err.to_string())?;
+    let count = object::Environment::restore(&session.environment, &file["environment"])?;
+    object::Environment::restore(&session.macros, &file["macros"])?;
+    Ok(count)
+}
+
+fn eval_input<W: Write>(
+    name: &str,
+    input: &str,
+    writer: &mut W,
+    session: &mut Session,
+    evaluator: &mut Evaluator,
+    mode: OutputMode,
+) {
+    let file = session.sources.add(name, input);
+    if let Some(debugger) = evaluator.debugger.as_mut() {
+        debugger.breakpoints = debug::resolve(&session.breakpoints, &session.sources);
+    }
+    let lexer = Lexer::with_file(input, file);
+    match mode {
+        OutputMode::Text => {
+            let evaluation = evaluation::evaluate(lexer, &session.environment, &session.macros, evaluator);
+            write_evaluation(writer, &session.sources, file, evaluation, &evaluator.pretty);
+        }
+        OutputMode::Json => {
+            let (evaluation, output) = evaluation::evaluate_captured(lexer, &session.environment, &session.macros, evaluator);
+            let _ = writeln!(writer, "{}", evaluation.to_json(&output));
         }
-        println!("{}", evaluator::evaluate_program(program, environment.clone()).unwrap().inspect());
     }
 }
 
-fn run_file(filename: &str) {
-    let input = std::fs::read_to_string(filename).unwrap();
+// Results are laid out by pretty for people to read; JSON output always
+// keeps them canonical.
+fn write_evaluation<W: Write>(writer: &mut W, sources: &SourceMap, file: FileId, evaluation: Evaluation, pretty: &object::PrettyConfig) {
+    for warning in &evaluation.warnings {
+        let _ = writeln!(writer, "{}", warning.render_in(sources, file));
+    }
+    match evaluation.outcome {
+        Outcome::ParseErrors(errors) => {
+            let _ = writeln!(writer, " parser errors:");
+            for error in errors {
+                let _ = writeln!(writer, "{}", error.render_in(sources, file));
+            }
+        }
+        Outcome::MacroError(message) => {
+            let _ = writeln!(writer, " macro expansion error: {}", message);
+        }
+        Outcome::ValidationErrors(errors) => {
+            let _ = writeln!(writer, " validation errors:");
+            for error in errors {
+                let _ = writeln!(writer, "{}", error.render_in(sources, file));
+            }
+        }
+        Outcome::Evaluated(Some(Object::Error(message))) => {
+            let rendered = evaluation::render_operands(&message, &evaluation.error_operands, sources, file);
+            let _ = writeln!(writer, "{}", rendered.unwrap_or(message));
+            if let Some(stack) = &evaluation.error_call_stack {
+                let _ = writeln!(writer, "{}", evaluation::render_trace(stack, sources, file));
+            }
+        }
+        Outcome::Evaluated(Some(result)) => {
+            let _ = writeln!(writer, "{}", render_value(&result, pretty));
+        }
+        Outcome::Evaluated(None) => {}
+    }
+}
+
+// Functions and macros are laid out as monkey fmt would lay them out, other
+// values as pretty says. The formatter ends the statement it lays the
+// literal out in with a semicolon, which is not part of the value.
+fn render_value(value: &Object, pretty: &object::PrettyConfig) -> String {
+    let literal: Rc<dyn ast::Expression> = match value {
+        Object::Function(function) => Rc::new(function.literal()),
+        Object::Macro(macro_object) => Rc::new(macro_object.literal()),
+        _ => return value.inspect_pretty(pretty),
+    };
+    let program = ast::builder::program(vec![ast::builder::expr_stmt(literal)]);
+    let source = formatter::format(&program, &formatter::FormatConfig::default());
+    let source = source.trim_end();
+    source.strip_suffix(';').unwrap_or(source).to_string()
+}
+
+fn parse_input<W: Write>(input: &str, writer: &mut W) -> Option<ast::Program> {
+    let l = Lexer::new(input);
+    let mut p = Parser::new(l);
+    let program = p.parse_program();
+    if !p.parse_errors().is_empty() {
+        let _ = writeln!(writer, " parser errors:");
+        for error in p.parse_errors() {
+            let _ = writeln!(writer, "{}", evaluation::parse_error_diagnostic(error).render(input));
+        }
+        return None;
+    }
+    Some(program)
+}
+
+#[derive(Debug, PartialEq)]
+enum InputState {
+    // Typing more could still make the input parse.
+    Incomplete,
+    // The parser failed before reaching the end, which no further line
+    // can fix, so the input is submitted and the error reported at once.
+    Invalid,
+    Complete,
+}
+
+// Checks the input gathered so far before it is submitted: open brackets,
+// strings and comments ask for more, and so does a statement the parser
+// was still in the middle of when the input ran out, like `let x =`.
+fn check_input(input: &str) -> InputState {
+    if input.trim_start().starts_with(':') {
+        return InputState::Complete;
+    }
+    let depth = match bracket_depth(input) {
+        Some(depth) => depth,
+        None => return InputState::Incomplete,
+    };
+    let mut parser = Parser::new(Lexer::new(input));
+    parser.parse_program();
+    let end = end_of_input(input);
+    let errors = parser.parse_errors();
+    if errors.iter().any(|error| (error.line, error.column) < end) {
+        InputState::Invalid
+    } else if depth > 0 || !errors.is_empty() {
+        InputState::Incomplete
+    } else {
+        InputState::Complete
+    }
+}
+
+// Where the EOF token is, which is where errors about running out of
+// input are reported.
+fn end_of_input(input: &str) -> (usize, usize) {
+    let mut lexer = Lexer::new(input);
+    loop {
+        let token = lexer.next_token();
+        if token.token_type == token::TokenType::EOF {
+            return (token.line, token.column);
+        }
+    }
+}
+
+fn is_complete(input: &str) -> bool {
+    bracket_depth(input).is_some_and(|depth| depth <= 0)
+}
+
+// How many brackets are open at the end of input, or None when it ends
+// inside a string or a block comment.
+fn bracket_depth(input: &str) -> Option<i32> {
+    let mut depth = 0;
+    let mut chars = input.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '(' | '{' | '[' => depth += 1,
+            ')' | '}' | ']' => depth -= 1,
+            '"' => loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => {
+                        chars.next();
+                    }
+                    Some(_) => {}
+                    None => return None,
+                }
+            },
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.next_if(|&c| c != '\n').is_some() {}
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut comment_depth = 1;
+                while comment_depth > 0 {
+                    match chars.next() {
+                        Some('/') if chars.next_if_eq(&'*').is_some() => comment_depth += 1,
+                        Some('*') if chars.next_if_eq(&'/').is_some() => comment_depth -= 1,
+                        Some(_) => {}
+                        None => return None,
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Some(depth)
+}
+
+// Runs a file, or stdin for "-", writing what it emits or the value it ends
+// with to out and diagnostics to err. Returns the exit status. The input is
+// read as it is lexed, so it can be a pipe, but a program is only run once
+// all of it has parsed.
+fn run_file<W: Write, E: Write>(filename: &str, options: &Options, out: &mut W, err: &mut E) -> i32 {
+    let emit = options.emit.as_str();
+    if !matches!(emit, "tokens" | "ast" | "ast-json" | "resolved" | "result") {
+        let _ = writeln!(err, "unsupported --emit stage: {} (expected tokens, ast, ast-json, resolved or result)", emit);
+        return EXIT_FAILURE;
+    }
+    let reader: Box<dyn Read> = if filename == "-" {
+        Box::new(io::stdin())
+    } else {
+        match std::fs::File::open(filename) {
+            Ok(file) => Box::new(file),
+            Err(error) => {
+                let _ = writeln!(err, "could not read {}: {}", filename, error);
+                return EXIT_FAILURE;
+            }
+        }
+    };
+    let source = Rc::new(RefCell::new(Vec::new()));
+    let mut lexer = Lexer::from_reader(Recorder { reader, read: source.clone() });
+    if emit == "tokens" {
+        write_tokens(out, lexer);
+        return 0;
+    }
+
+    let mut evaluator = Evaluator::with_limits(options.limits);
+    if filename != "-" {
+        evaluator.source_path = Some(filename.into());
+    }
+    evaluator.script_args = options.script_args.clone();
+    evaluator.optimize = options.optimize;
+    if options.trace {
+        evaluator.trace = Some(Box::new(io::stderr()));
+    }
+    let environment = new_environment(&mut evaluator, options.prelude);
+    let macros = Rc::new(RefCell::new(object::Environment::new()));
+    if emit == "result" && options.mode == OutputMode::Json {
+        let (evaluation, output) = evaluation::evaluate_captured(lexer, &environment, &macros, &mut evaluator);
+        let _ = writeln!(out, "{}", evaluation.to_json(&output));
+        return match evaluation.outcome {
+            Outcome::Evaluated(_) if evaluation.is_error() => EXIT_RUNTIME_ERROR,
+            Outcome::Evaluated(_) => 0,
+            _ => EXIT_FAILURE,
+        };
+    }
+
+    let mut sources = SourceMap::new();
+    let file = sources.add(filename, "");
+    lexer.set_file(file);
+    let mut p = Parser::new(lexer);
+    let mut program = p.parse_program();
+    if !p.parse_errors().is_empty() {
+        write_parse_errors(err, filename, p.parse_errors());
+        return EXIT_FAILURE;
+    }
+    sources.append(file, &String::from_utf8_lossy(&source.borrow()));
+    if emit == "ast" {
+        let _ = writeln!(out, "{}", program.to_string());
+        return 0;
+    }
+    if emit == "ast-json" {
+        let _ = writeln!(out, "{}", program.to_json());
+        return 0;
+    }
+    evaluator.define_macros(&mut program, macros.clone());
+    let program = match evaluator.expand_macros(program, macros) {
+        Ok(program) => program,
+        Err(message) => {
+            let _ = writeln!(err, " macro expansion error: {}", message);
+            return EXIT_FAILURE;
+        }
+    };
+    if emit == "resolved" {
+        let is_defined = |name: &str| evaluator::builtins::lookup(name).is_some() || environment.borrow().get(name).is_some();
+        write_resolutions(out, &program, &is_defined);
+        return 0;
+    }
+
+    let (errors, warnings): (Vec<_>, Vec<_>) = validator::validate(&program).into_iter().partition(|d| d.is_error());
+    for warning in warnings {
+        let _ = writeln!(err, "{}", warning);
+    }
+    if !errors.is_empty() {
+        let _ = writeln!(err, " validation errors:");
+        for error in errors {
+            let _ = writeln!(err, "\t{}", error);
+        }
+        return EXIT_FAILURE;
+    }
+    match evaluator.evaluate_program(program, environment) {
+        Some(Object::Error(message)) => {
+            let rendered = evaluation::render_operands(&message, evaluator.error_operands(), &sources, file);
+            let _ = writeln!(err, "{}", rendered.unwrap_or(message));
+            if let Some(stack) = evaluator.error_call_stack() {
+                let _ = writeln!(err, "{}", evaluation::render_trace(stack, &sources, file));
+            }
+            EXIT_RUNTIME_ERROR
+        }
+        Some(result) if !options.quiet => {
+            let _ = writeln!(out, "{}", result.inspect());
+            0
+        }
+        _ => 0,
+    }
+}
+
+// Keeps a copy of what is read through it, so diagnostics can quote the
+// lines of a source that was streamed.
+struct Recorder {
+    reader: Box<dyn Read>,
+    read: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Read for Recorder {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.read.borrow_mut().extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+fn write_parse_errors<E: Write>(err: &mut E, filename: &str, errors: &[parser::ParseError]) {
+    let _ = writeln!(err, " parser errors:");
+    for error in errors {
+        let _ = writeln!(err, "{}", render_parse_error(error, filename));
+    }
+}
+
+fn bench_corpus(args: &[String]) {
+    let mut budgets = corpus::Budgets::default();
+    let mut prelude = true;
+    let mut dir = None;
+    for arg in args {
+        if let Some(value) = arg.strip_prefix("--time-ms=") {
+            budgets.time = std::time::Duration::from_millis(parse_budget("--time-ms", value));
+        } else if let Some(value) = arg.strip_prefix("--memory-mb=") {
+            budgets.memory = (parse_budget("--memory-mb", value) as usize) << 20;
+        } else if arg == "--no-prelude" {
+            prelude = false;
+        } else {
+            dir = Some(arg);
+        }
+    }
+    let dir = match dir {
+        Some(dir) => dir,
+        None => {
+            eprintln!("usage: bench-corpus DIR [--time-ms=N] [--memory-mb=N] [--no-prelude]");
+            process::exit(1);
+        }
+    };
+    match corpus::run_corpus(dir.as_ref(), &budgets, prelude, &mut io::stdout()) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(err) => {
+            eprintln!("could not run corpus {}: {}", dir, err);
+            process::exit(1);
+        }
+    }
+}
+
+// Tests the current directory when no paths are given.
+fn run_tests(args: &[String]) -> i32 {
+    let mut prelude = true;
+    let mut paths = vec![];
+    for arg in args {
+        if arg == "--no-prelude" {
+            prelude = false;
+        } else if arg.starts_with("--") {
+            usage_error(&format!("unknown option: {}", arg));
+        } else {
+            paths.push(arg.into());
+        }
+    }
+    if paths.is_empty() {
+        paths.push(".".into());
+    }
+    match testing::run_tests(&paths, prelude, &mut io::stdout()) {
+        Ok(true) => 0,
+        Ok(false) => EXIT_FAILURE,
+        Err(err) => {
+            eprintln!("could not run tests: {}", err);
+            EXIT_FAILURE
+        }
+    }
+}
+
+fn parse_budget(flag: &str, value: &str) -> u64 {
+    match value.parse() {
+        Ok(value) => value,
+        Err(_) => {
+            eprintln!("invalid {} budget: {} (expected a whole number)", flag, value);
+            process::exit(1);
+        }
+    }
+}
+
+fn check_files(args: &[String]) -> i32 {
+    let mut types = false;
+    let mut filenames = vec![];
+    for arg in args {
+        if arg == "--types" {
+            types = true;
+        } else if arg.starts_with("--") {
+            usage_error(&format!("unknown option: {}", arg));
+        } else {
+            filenames.push(arg);
+        }
+    }
+    if filenames.is_empty() {
+        usage_error("missing arguments to check");
+    }
+    let failed: Vec<bool> = filenames.iter().map(|filename| check_file(filename, types)).collect();
+    if failed.contains(&true) { EXIT_FAILURE } else { 0 }
+}
+
+// Reports what can be found without running the file: parse errors, the
+// validator's diagnostics and names the resolver finds unbound or
+// shadowing, and with types, values that do not match their annotations.
+// Returns whether there were errors; warnings alone pass.
+fn check_file(filename: &str, types: bool) -> bool {
+    let input = match std::fs::read_to_string(filename) {
+        Ok(input) => input,
+        Err(err) => {
+            eprintln!("could not read {}: {}", filename, err);
+            return true;
+        }
+    };
+    let mut output = io::stderr();
+    check(filename, &input, types, &mut output)
+}
+
+fn check<W: Write>(filename: &str, input: &str, types: bool, writer: &mut W) -> bool {
+    let mut p = Parser::new(Lexer::new(input));
+    let mut program = p.parse_program();
+    if !p.parse_errors().is_empty() {
+        for error in p.parse_errors() {
+            let _ = writeln!(writer, "in {}:\n{}", filename, evaluation::parse_error_diagnostic(error).render(input));
+        }
+        return true;
+    }
+
+    let mut evaluator = Evaluator::new();
+    evaluator.output = Box::new(io::sink());
+    let environment = new_environment(&mut evaluator, true);
+    let macros = Rc::new(RefCell::new(object::Environment::new()));
+    evaluator.define_macros(&mut program, macros.clone());
+    let program = match evaluator.expand_macros(program, macros) {
+        Ok(program) => program,
+        Err(message) => {
+            let _ = writeln!(writer, "in {}:\nmacro expansion error: {}", filename, message);
+            return true;
+        }
+    };
+
+    let is_defined = |name: &str| evaluator::builtins::lookup(name).is_some() || environment.borrow().get(name).is_some();
+    let mut diagnostics = validator::validate(&program);
+    diagnostics.extend(validator::scopes::resolve(&program, &is_defined));
+    if types {
+        diagnostics.extend(typechecker::check(&program));
+    }
+    diagnostics.sort_by_key(|d| (d.line, d.column));
+    for diagnostic in &diagnostics {
+        let _ = writeln!(writer, "in {}:\n{}", filename, diagnostic.render(input));
+    }
+    diagnostics.iter().any(|d| d.is_error())
+}
+
+fn format_file(filename: &str) {
+    let input = match std::fs::read_to_string(filename) {
+        Ok(input) => input,
+        Err(err) => {
+            eprintln!("could not read {}: {}", filename, err);
+            process::exit(1);
+        }
+    };
+    let config = match formatter::FormatConfig::discover(Path::new(filename)) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("invalid formatter configuration: {}", err);
+            process::exit(1);
+        }
+    };
+
     let l = Lexer::new(&input);
     let mut p = Parser::new(l);
     let program = p.parse_program();
-    if p.errors().len() != 0 {
-        println!(" parser errors:");
+    if !p.errors().is_empty() {
+        eprintln!(" parser errors in {}:", filename);
         for msg in p.errors() {
-            println!("\t{}", msg);
+            eprintln!("\t{}", msg);
         }
-        return;
+        process::exit(1);
+    }
+
+    let formatted = formatter::format_with_comments(&program, &Lexer::tokenize(&input, true), &config);
+    if formatted != input {
+        if let Err(err) = std::fs::write(filename, formatted) {
+            eprintln!("could not write {}: {}", filename, err);
+            process::exit(1);
+        }
+    }
+}
+
+fn write_tokens<W: Write>(writer: &mut W, lexer: Lexer) {
+    for tok in lexer {
+        let _ = writeln!(writer, "{}", serde_json::json!({ "type": tok.token_type.to_string(), "literal": tok.literal }));
+    }
+}
+
+// The error's position, then its line with the error underlined, as the
+// REPL shows it. The file is not kept while it runs, so the line is read
+// from it again; stdin cannot be read again, and its errors are shown
+// without their line.
+fn render_parse_error(error: &parser::ParseError, filename: &str) -> String {
+    let diagnostic = evaluation::parse_error_diagnostic(error);
+    let line = match filename {
+        "-" => None,
+        _ => std::fs::File::open(filename).ok()
+            .and_then(|file| io::BufReader::new(file).lines().nth(error.line.checked_sub(1)?))
+            .and_then(Result::ok),
+    };
+    match line {
+        Some(line) => {
+            let rendered = validator::Diagnostic { line: 1, ..diagnostic }.render(&line);
+            format!("at line {}, column {}:\n{}", error.line, error.column, rendered)
+        }
+        None => diagnostic.to_string(),
+    }
+}
+
+// One line per name used, with where it was bound: the line and column of
+// its declaration, "external" for a builtin or prelude name, or null.
+fn write_resolutions<W: Write>(writer: &mut W, program: &ast::Program, is_defined: &dyn Fn(&str) -> bool) {
+    use validator::scopes::Binding;
+
+    for resolution in validator::scopes::resolutions(program, is_defined) {
+        let binding = match resolution.binding {
+            Binding::DECLARED(line, column) => serde_json::json!({ "line": line, "column": column }),
+            Binding::EXTERNAL => serde_json::json!("external"),
+            Binding::UNDEFINED => serde_json::Value::Null,
+        };
+        let _ = writeln!(
+            writer,
+            "{}",
+            serde_json::json!({ "name": resolution.name, "line": resolution.line, "column": resolution.column, "binding": binding }),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_complete() {
+        let tests = vec![
+            ("let x = 5;\n", true),
+            ("let add = fn(a, b) {\n", false),
+            ("let add = fn(a, b) {\n  a + b\n", false),
+            ("let add = fn(a, b) {\n  a + b\n};\n", true),
+            ("add(1,\n", false),
+            ("\"unterminated\n", false),
+            ("\"{ \\\" (\";\n", true),
+            ("/* {\n", false),
+            ("/* { */ 1;\n", true),
+            ("1; // {\n", true),
+            ("}\n", true),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(is_complete(input), expected, "input: {:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_check_input() {
+        let tests = vec![
+            ("let x = 5;\n", InputState::Complete),
+            ("let add = fn(a, b) {\n", InputState::Incomplete),
+            ("let x =\n", InputState::Incomplete),
+            ("1 +\n", InputState::Incomplete),
+            ("add(1,\n", InputState::Incomplete),
+            ("\"unterminated {\n", InputState::Incomplete),
+            ("let add = fn(a, b) {\n  let = a + b;\n", InputState::Invalid),
+            ("}\n", InputState::Invalid),
+            (":ast let x =\n", InputState::Complete),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(check_input(input), expected, "input: {:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_continues_unfinished_statements() {
+        let input = "let x =\n5;\nx\nlet y\n\nlet f = fn() {\n\n  let = 1;\n2\n";
+        let mut output = Vec::new();
+        start(input.as_bytes(), &mut output, Evaluator::new(), true, OutputMode::Text, false);
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains(">> .. null\n>> 5\n"), "output: {:?}", output);
+        assert!(output.contains(">> ..  parser errors:\nexpected next token to be ASSIGN, got EOF instead"), "output: {:?}", output);
+        assert!(output.contains(">> .. ..  parser errors:\n  let = 1;\n      ^ expected next token to be IDENT, got ASSIGN instead\n"), "output: {:?}", output);
+        assert!(output.ends_with(">> 2\n>> "), "output: {:?}", output);
+    }
+
+    #[test]
+    fn test_multiline_input() {
+        let input = "let add = fn(a, b) {\n  a + b\n};\nadd(1,\n 2)\n";
+        let mut output = Vec::new();
+        start(input.as_bytes(), &mut output, Evaluator::new(), true, OutputMode::Text, false);
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.ends_with(">> .. .. null\n>> .. 3\n>> "), "output: {:?}", output);
+
+        // Input left open when the reader ends still gets run.
+        let mut output = Vec::new();
+        start("1 + 1\nlen([1, 2\n".as_bytes(), &mut output, Evaluator::new(), true, OutputMode::Text, false);
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.ends_with(">> 2\n>> ..  parser errors:\nexpected next token to be RBRACKET, got EOF instead at line 2, column 1\n"), "output: {:?}", output);
+    }
+
+    #[test]
+    fn test_macros_persist_between_inputs() {
+        let input = "let twice = macro(x) { quote(unquote(x) + unquote(x)) };\ntwice(2 * 3)\n:reset\ntwice(1)\n";
+        let mut output = Vec::new();
+        start(input.as_bytes(), &mut output, Evaluator::new(), true, OutputMode::Text, false);
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.ends_with(">> >> 12\n>> >> identifier not found: twice\n>> "), "output: {:?}", output);
+    }
+
+    #[test]
+    fn test_check() {
+        let mut output = Vec::new();
+        let source = "let total = 0;\nlet add = fn(total) { totl + len([]) + map([], abs) };";
+        assert!(!check("a.monkey", source, false, &mut output));
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output, concat!(
+            "in a.monkey:\nlet add = fn(total) { totl + len([]) + map([], abs) };\n",
+            "             ^^^^^ warning: variable `total` shadows outer binding (previously declared at line 1, column 5)\n",
+            "in a.monkey:\nlet add = fn(total) { totl + len([]) + map([], abs) };\n",
+            "                      ^^^^ warning: identifier `totl` is never defined\n",
+        ));
+
+        let mut output = Vec::new();
+        assert!(check("b.monkey", "return 1;", false, &mut output));
+        assert!(check("c.monkey", "let = 1;", false, &mut output));
+    }
+
+    #[test]
+    fn test_check_types() {
+        let source = "let double = fn(x: int) -> int { x * 2 };\ndouble(\"two\");";
+        let mut output = Vec::new();
+        assert!(!check("a.monkey", source, false, &mut output));
+        assert!(output.is_empty());
+        assert!(check("a.monkey", source, true, &mut output));
+        assert_eq!(String::from_utf8(output).unwrap(), "in a.monkey:\ndouble(\"two\");\n^^^^^^ cannot pass string as x: int\n");
+    }
+
+    #[test]
+    fn test_digit_grouping() {
+        let input = "832040\n[1234567, 0.5]\n";
+        let mut evaluator = Evaluator::new();
+        evaluator.pretty.digit_grouping = Some(',');
+        let mut output = Vec::new();
+        start(input.as_bytes(), &mut output, evaluator, true, OutputMode::Text, false);
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.ends_with(">> 832,040\n>> [1,234,567, 0.5]\n>> "), "output: {:?}", output);
+    }
+
+    #[test]
+    fn test_pretty_results() {
+        let input = "\"tab\\there\"\n[[[1]], \"a\"]\nlet rows = map([1, 2, 3], fn(i) { [\"row number {i}\", i * 1000] }); rows\n";
+        let mut evaluator = Evaluator::new();
+        evaluator.pretty.max_depth = 2;
+        evaluator.pretty.width = 40;
+        let mut output = Vec::new();
+        start(input.as_bytes(), &mut output, evaluator, true, OutputMode::Text, false);
+        let output = String::from_utf8(output).unwrap();
+        let expected = ">> \"tab\\there\"\n>> [[[...]], \"a\"]\n>> [\n    [\"row number 1\", 1000],\n    [\"row number 2\", 2000],\n    [\"row number 3\", 3000]\n]\n>> ";
+        assert!(output.ends_with(expected), "output: {:?}", output);
+
+        let mut output = Vec::new();
+        start("fn(a, b){let c = a + b; c * 2}\n".as_bytes(), &mut output, Evaluator::new(), true, OutputMode::Text, false);
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.ends_with(">> fn(a, b) {\n    let c = a + b;\n    c * 2;\n}\n>> "), "output: {:?}", output);
+    }
+
+    #[test]
+    fn test_run_file_exit_codes() {
+        let run_capturing = |name: &str, source: &str, quiet: bool| {
+            let path = env::temp_dir().join(name);
+            std::fs::write(&path, source).unwrap();
+            let mut options = parse_options(&[]);
+            options.quiet = quiet;
+            let mut output = Vec::new();
+            let mut errors = Vec::new();
+            let code = run_file(path.to_str().unwrap(), &options, &mut output, &mut errors);
+            let _ = std::fs::remove_file(&path);
+            (code, String::from_utf8(output).unwrap(), String::from_utf8(errors).unwrap())
+        };
+        let run = |name: &str, source: &str, quiet: bool| {
+            let (code, output, _) = run_capturing(name, source, quiet);
+            (code, output)
+        };
+        assert_eq!(run("monkey_run_ok.monkey", "puts(1); 1 + 1", false), (0, "2\n".to_string()));
+        assert_eq!(run("monkey_run_quiet.monkey", "1 + 1", true), (0, String::new()));
+        assert_eq!(run("monkey_run_parse_error.monkey", "let = 1;", false), (EXIT_FAILURE, String::new()));
+        assert_eq!(run("monkey_run_invalid.monkey", "return 1;", false), (EXIT_FAILURE, String::new()));
+        assert_eq!(run("monkey_run_runtime_error.monkey", "let f = fn() { 1 + true }; f()", false), (EXIT_RUNTIME_ERROR, String::new()));
+        assert_eq!(run("monkey_run_macro.monkey", "let twice = macro(x) { quote(unquote(x) * 2) };\ntwice(21)", false), (0, "42\n".to_string()));
+        assert_eq!(run("monkey_run_late_invalid.monkey", "let x = 1;\nreturn x;", false), (EXIT_FAILURE, String::new()));
+
+        // Nothing runs until the whole file has parsed.
+        let marker = env::temp_dir().join("monkey_run_late_parse_error.txt");
+        let _ = std::fs::remove_file(&marker);
+        let source = format!("write_file({:?}, \"ran\");\nputs(\"side effect\");\nlet x = ;", marker.to_str().unwrap());
+        let (code, output, errors) = run_capturing("monkey_run_late_parse_error.monkey", &source, false);
+        assert_eq!((code, output.as_str()), (EXIT_FAILURE, ""));
+        assert!(errors.starts_with(" parser errors:\nat line 3, column 9:"), "errors: {:?}", errors);
+        assert!(!marker.exists());
+
+        let (code, _, errors) = run_capturing("monkey_run_operands.monkey", "let a = 1;\nlet x = a + true;", false);
+        assert_eq!(code, EXIT_RUNTIME_ERROR);
+        assert_eq!(errors, "let x = a + true;\n        ^   ^^^^ type mismatch: INTEGER + BOOLEAN\n");
+
+        let options = parse_options(&["--quiet".to_string(), "script.monkey".to_string(), "a".to_string(), "--quiet".to_string()]);
+        assert_eq!(options.file.as_deref(), Some("script.monkey"));
+        assert_eq!(options.script_args, vec!["a", "--quiet"]);
+
+        let mut options = parse_options(&["--output=json".to_string()]);
+        options.quiet = true;
+        let path = env::temp_dir().join("monkey_run_json.monkey");
+        std::fs::write(&path, "len(1)").unwrap();
+        let mut output = Vec::new();
+        assert_eq!(run_file(path.to_str().unwrap(), &options, &mut output, &mut io::sink()), EXIT_RUNTIME_ERROR);
+        let _ = std::fs::remove_file(&path);
+        assert!(String::from_utf8(output).unwrap().contains("\"stage\":\"runtime\""));
+    }
+
+    #[test]
+    fn test_render_parse_error() {
+        let path = env::temp_dir().join("monkey_run_parse_errors.monkey");
+        std::fs::write(&path, "let x = 1;\nlet = 2;").unwrap();
+        let mut parser = Parser::new(Lexer::from_reader(std::fs::File::open(&path).unwrap()));
+        parser.parse_program();
+        let error = &parser.parse_errors()[0];
+        assert_eq!(
+            render_parse_error(error, path.to_str().unwrap()),
+            "at line 2, column 5:\nlet = 2;\n    ^ expected next token to be IDENT, got ASSIGN instead",
+        );
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(render_parse_error(error, "-"), "expected next token to be IDENT, got ASSIGN instead at line 2, column 5");
+
+        let mut parser = Parser::new(Lexer::new("1 @ 2"));
+        parser.parse_program();
+        assert_eq!(render_parse_error(&parser.parse_errors()[0], "-"), "illegal character '@' at line 1, column 3");
+    }
+
+    #[test]
+    fn test_emit_stages() {
+        let path = env::temp_dir().join("monkey_run_emit.monkey");
+        std::fs::write(&path, "let x = 1;\nputs(x + y)").unwrap();
+        let emit = |stage: &str| {
+            let options = parse_options(&[format!("--emit={}", stage)]);
+            let mut output = Vec::new();
+            let code = run_file(path.to_str().unwrap(), &options, &mut output, &mut io::sink());
+            (code, String::from_utf8(output).unwrap())
+        };
+        let (code, output) = emit("resolved");
+        assert_eq!(code, 0);
+        assert_eq!(output, [
+            r#"{"binding":"external","column":1,"line":2,"name":"puts"}"#,
+            r#"{"binding":{"column":5,"line":1},"column":6,"line":2,"name":"x"}"#,
+            r#"{"binding":null,"column":10,"line":2,"name":"y"}"#,
+            "",
+        ].join("\n"));
+        let (code, output) = emit("ast-json");
+        assert_eq!(code, 0);
+        assert!(output.starts_with("{\""), "output: {:?}", output);
+        assert_eq!(emit("json"), (0, output));
+        assert_eq!(emit("bytecode"), (EXIT_FAILURE, String::new()));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_json_output() {
+        let input = "puts(\"hi\"); 40 + 2\nlet = 1;\nfn(a, a) { a }\nlet f = fn() {\n  1 + true\n}; f()\nlet x = 1;\n";
+        let mut output = Vec::new();
+        start(input.as_bytes(), &mut output, Evaluator::new(), true, OutputMode::Json, false);
+        let output = String::from_utf8(output).unwrap();
+        let results: Vec<serde_json::Value> = output.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(results.len(), 5);
+
+        assert_eq!(results[0]["value"], "42");
+        assert_eq!(results[0]["type"], "INTEGER");
+        assert_eq!(results[0]["output"], "hi\n");
+        assert!(results[0]["duration_ms"].is_f64());
+        assert_eq!(results[0]["errors"], serde_json::json!([]));
+
+        assert_eq!(results[1]["value"], serde_json::Value::Null);
+        assert_eq!(results[1]["errors"][0]["stage"], "parse");
+        assert_eq!(results[1]["errors"][0]["message"], "expected next token to be IDENT, got ASSIGN instead");
+        assert_eq!((results[1]["errors"][0]["line"].as_u64(), results[1]["errors"][0]["column"].as_u64()), (Some(1), Some(5)));
+
+        assert_eq!(results[2]["errors"][0]["stage"], "validate");
+        assert_eq!(results[2]["errors"][0]["message"], "duplicate parameter a");
+        assert_eq!(results[2]["errors"][0]["previous"], serde_json::json!({ "line": 1, "column": 4 }));
+
+        assert_eq!(results[3]["errors"][0], serde_json::json!({
+            "stage": "runtime",
+            "message": "type mismatch: INTEGER + BOOLEAN",
+            "line": 3,
+            "column": 5,
+            "operands": [{ "line": 2, "column": 3, "length": 1 }, { "line": 2, "column": 7, "length": 4 }],
+            "trace": [{ "function": "f", "line": 3, "column": 5 }],
+        }));
+
+        assert_eq!(results[4]["value"], "null");
+        assert_eq!(results[4]["type"], "NULL");
+    }
+
+    #[test]
+    fn test_meta_commands() {
+        let path = env::temp_dir().join("monkey_repl_test_load.monkey");
+        std::fs::write(&path, "let double = fn(x) { x * 2 };\nlet y = double(21);").unwrap();
+        let input = format!(
+            "let b = \"hi\";\nlet a = 1;\n:env\n:ast 1 + 2 * 3\n:type a * 1.5\n:type b[0]\n:tokens a+1\n:load {}\ny\n:reset\n:env\na\n:nope\n",
+            path.display(),
+        );
+        let mut output = Vec::new();
+        start(io::Cursor::new(input), &mut output, Evaluator::new(), true, OutputMode::Text, false);
+        let _ = std::fs::remove_file(&path);
+        let output = String::from_utf8(output).unwrap();
+        let expected = [
+            ">> a = 1\nb = hi\n",
+            ">> (1 + (2 * 3))\n>> FLOAT\n>> STRING\n",
+            ">> {\"literal\":\"a\",\"type\":\"IDENT\"}\n{\"literal\":\"+\",\"type\":\"PLUS\"}\n{\"literal\":\"1\",\"type\":\"INT\"}\n",
+            ">> 42\n>> >> >> identifier not found: a\n",
+            ">> unknown command :nope (expected :env, :ast, :type, :tokens, :stats, :trace, :break, :clear, :reset, :load, :save or :load-session)\n",
+        ];
+        for part in expected {
+            assert!(output.contains(part), "missing {:?} in {:?}", part, output);
+        }
+    }
+
+    // Sends input to a session served over a socket and returns everything
+    // it wrote back.
+    #[cfg(feature = "net")]
+    fn remote_session(input: &str) -> String {
+        remote_session_with(input, remote_limits())
+    }
+
+    #[cfg(feature = "net")]
+    fn remote_session_with(input: &str, limits: Limits) -> String {
+        use std::io::Read;
+        use std::net::{Shutdown, TcpStream};
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || accept_sessions(listener, limits));
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(input.as_bytes()).unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let mut output = String::new();
+        stream.read_to_string(&mut output).unwrap();
+        output
+    }
+
+    #[test]
+    #[cfg(feature = "net")]
+    fn test_listen() {
+        let output = remote_session("let x = 2;\nx * 21\n");
+        assert_eq!(output, "This is monkey programming language!\nFeel free to type in commands\n>> null\n>> 42\n>> ");
+
+        let options = parse_options(&["--listen".to_string(), "127.0.0.1:4000".to_string(), "--quiet".to_string()]);
+        assert_eq!(options.listen.as_deref(), Some("127.0.0.1:4000"));
+        assert!(options.quiet);
+        assert_eq!(parse_options(&["--listen=[::1]:4000".to_string()]).listen.as_deref(), Some("[::1]:4000"));
+    }
+
+    #[test]
+    #[cfg(feature = "net")]
+    fn test_remote_session_limits() {
+        let limits = remote_limits();
+        assert_eq!(limits.max_steps, Some(REMOTE_MAX_STEPS));
+        assert_eq!(limits.timeout, Some(REMOTE_TIMEOUT));
+
+        // A runaway input is cut short, and the session goes on.
+        let limits = Limits { timeout: Some(std::time::Duration::from_millis(20)), ..remote_limits() };
+        let output = remote_session_with("while (true) {}\n1 + 1\n", limits);
+        assert!(output.ends_with(">> timed out after 20 ms\n>> 2\n>> "), "output: {:?}", output);
+
+        let input = format!("1\n{}\n", "a".repeat(100));
+        let mut reader = LineLimit::new(input.as_bytes(), 10);
+        let mut line = String::new();
+        assert_eq!(reader.read_line(&mut line).unwrap(), 2);
+        line.clear();
+        assert_eq!(reader.read_line(&mut line).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    #[cfg(feature = "net")]
+    fn test_remote_session_cannot_use_files() {
+        let path = env::temp_dir().join("monkey_repl_test_remote.json");
+        let _ = std::fs::remove_file(&path);
+        let input = format!(
+            "let x = 1;\n:save {}\n:load {}\n:load-session {}\nimport \"lib.monkey\"\nread_file(\"{}\")\nx + 1\n",
+            path.display(), path.display(), path.display(), path.display(),
+        );
+        let output = remote_session(&input);
+        let expected = [
+            ">> :save is not available in a remote session\n",
+            ">> :load is not available in a remote session\n",
+            ">> :load-session is not available in a remote session\n",
+            ">> cannot import lib.monkey: imports are disabled\n",
+            ">> cannot call read_file: file access is disabled\n",
+            ">> 2\n",
+        ];
+        for part in expected {
+            assert!(output.contains(part), "missing {:?} in {:?}", part, output);
+        }
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_save_and_load_session() {
+        let path = env::temp_dir().join("monkey_repl_test_session.json");
+        let input = format!(
+            "let total = 40;\nlet add = fn(x) {{ total + x }};\nlet twice = macro(x) {{ quote(unquote(x) + unquote(x)) }};\nlet log = [1.5, \"a\"];\n:save {}\n",
+            path.display(),
+        );
+        let mut output = Vec::new();
+        start(io::Cursor::new(input), &mut output, Evaluator::new(), true, OutputMode::Text, false);
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains(&format!(">> saved session to {}\n", path.display())), "output: {:?}", output);
+
+        let input = format!(":load-session {}\nadd(2)\ntotal = 0; add(twice(1))\nlog\n:load-session {}.missing\n", path.display(), path.display());
+        let mut output = Vec::new();
+        start(io::Cursor::new(input), &mut output, Evaluator::new(), true, OutputMode::Text, false);
+        let _ = std::fs::remove_file(&path);
+        let output = String::from_utf8(output).unwrap();
+        let expected = [
+            format!(">> restored 3 bindings from {}\n", path.display()),
+            ">> 42\n>> 2\n>> [1.5, \"a\"]\n".to_string(),
+            format!(">> could not load {}.missing: ", path.display()),
+        ];
+        for part in expected {
+            assert!(output.contains(&part), "missing {:?} in {:?}", part, output);
+        }
+    }
+
+    #[test]
+    fn test_imports_earlier_inputs() {
+        let input = "let double = fn(x) { x * 2 };\nlet double = 1;\nimport \"<repl:1>\"; double(4)\nimport \"<repl:9>\"\n";
+        let mut output = Vec::new();
+        start(input.as_bytes(), &mut output, Evaluator::new(), true, OutputMode::Text, false);
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains(">> 8\n"), "output: {:?}", output);
+        assert!(output.contains(">> cannot import <repl:9>: "), "output: {:?}", output);
+    }
+
+    #[test]
+    fn test_underlines_errors() {
+        let input = "let x 5;\nlet f = fn() {\n  fn(a, a) { a }\n};\n";
+        let mut output = Vec::new();
+        start(input.as_bytes(), &mut output, Evaluator::new(), true, OutputMode::Text, false);
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains(" parser errors:\nlet x 5;\n      ^ expected next token to be ASSIGN, got INT instead\n"), "output: {:?}", output);
+        assert!(output.contains(" validation errors:\n  fn(a, a) { a }\n        ^ duplicate parameter a (previously declared at line 2, column 6)\n"), "output: {:?}", output);
+    }
+
+    #[test]
+    fn test_underlines_operands_of_runtime_errors() {
+        let input = "let x = 1;\nlet f = fn() {\n\tx + true\n};\nf()\n-true\n\"a\" + \"b\" * 2\n";
+        let mut output = Vec::new();
+        start(input.as_bytes(), &mut output, Evaluator::new(), true, OutputMode::Text, false);
+        let output = String::from_utf8(output).unwrap();
+        let expected = [
+            ">> in <repl:2>:\n\tx + true\n\t^   ^^^^ type mismatch: INTEGER + BOOLEAN\n",
+            ">> -true\n ^^^^ unknown operator: -BOOLEAN\n",
+            ">> \"a\" + \"b\" * 2\n      ^^^   ^ type mismatch: STRING * INTEGER\n",
+        ];
+        for part in expected {
+            assert!(output.contains(part), "missing {:?} in {:?}", part, output);
+        }
+    }
+
+    #[test]
+    fn test_debugger() {
+        let input = [
+            "let add = fn(a, b) {\n  let sum = a + b;\n  sum\n};",
+            ":break add",
+            ":break <repl:3>:1",
+            ":break nowhere:",
+            ":break",
+            "add(1, 2)",
+            ":locals", ":print b", ":print c", ":where", ":next", ":continue",
+            "let y = add(3, 4); y",
+            ":step", ":quit",
+            "y",
+            ":clear",
+            "add(5, 6)",
+            "",
+        ].join("\n");
+        let mut output = Vec::new();
+        let debugger_output = evaluator::SharedBuffer::default();
+        let mut evaluator = Evaluator::new();
+        evaluator.output = Box::new(debugger_output.clone());
+        start(io::Cursor::new(input), &mut output, evaluator, true, OutputMode::Text, false);
+
+        let output = String::from_utf8(output).unwrap();
+        let expected = [
+            ">> breakpoint 1 at add\n>> breakpoint 2 at <repl:3>:1\n>> expected FILE:LINE or a function name, got nowhere:\n>> 1: add\n2: <repl:3>:1\n",
+            ">> 3\n>> evaluation interrupted\nstack trace (most recent call last):\n  add called at line 1, column 12\n>> identifier not found: y\n",
+            ">> cleared 2 breakpoints\n>> 11\n",
+        ];
+        for part in expected {
+            assert!(output.contains(part), "missing {:?} in {:?}", part, output);
+        }
+        assert_eq!(debugger_output.contents(), [
+            "paused (breakpoint in add) at line 2, column 3: let sum = (a + b);",
+            "(debug) a = 1",
+            "b = 2",
+            "(debug) 2",
+            "(debug) identifier not found: c",
+            "(debug) in add called at line 1, column 4",
+            "(debug) paused (step) at line 3, column 3: sum",
+            "(debug) paused (breakpoint) at line 1, column 1: let y = add(3, 4);",
+            "(debug) paused (breakpoint in add) at line 2, column 3: let sum = (a + b);",
+            "(debug) ",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn test_trace_command() {
+        let input = ":trace\n:trace on\n:trace\n:trace off\n:trace\n:trace loud\n";
+        let mut output = Vec::new();
+        start(input.as_bytes(), &mut output, Evaluator::new(), true, OutputMode::Text, false);
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.ends_with(">> tracing is off\n>> >> tracing is on\n>> >> tracing is off\n>> expected :trace on or :trace off, got :trace loud\n>> "),
+            "output: {:?}",
+            output,
+        );
+    }
+
+    #[test]
+    fn test_stats_command() {
+        let input = ":stats reset\nlet f = fn(n) { if (n > 0) { f(n - 1) } else { [n] } };\nf(3)\n:stats\n:stats reset\n:stats\n";
+        let mut output = Vec::new();
+        start(input.as_bytes(), &mut output, Evaluator::new(), true, OutputMode::Text, false);
+        let output = String::from_utf8(output).unwrap();
+        let expected = [
+            ">> expressions evaluated: 37\nvalues allocated: 2\nenvironments allocated: 4 (reused 0, released 4, collected 0)\nmax call depth: 4\n",
+            ">> expressions evaluated: 0\nvalues allocated: 0\nenvironments allocated: 0 (reused 0, released 0, collected 0)\nmax call depth: 0\n",
+        ];
+        for part in expected {
+            assert!(output.contains(part), "missing {:?} in {:?}", part, output);
+        }
+    }
+
+    #[test]
+    fn test_stack_traces() {
+        let input = "let check = fn(n) { n + true };\nlet outer = fn(x) {\n  check(x)\n};\nouter(1)\nfn() { outer(2) }()\nlet down = fn(n) { if (n > 0) { down(n - 1) } else { -true } };\ndown(25)\n";
+        let mut output = Vec::new();
+        start(input.as_bytes(), &mut output, Evaluator::new(), true, OutputMode::Text, false);
+        let output = String::from_utf8(output).unwrap();
+        let expected = [
+            "stack trace (most recent call last):\n  outer called at line 1, column 6\n  check called at line 2, column 8 in <repl:2>\n",
+            "  <fn at 1:1> called at line 1, column 18\n  outer called at line 1, column 13\n  check called at line 2, column 8 in <repl:2>\n",
+            "  down called at line 1, column 37 in <repl:5>\n  ... 6 more calls\n  down called at line 1, column 37 in <repl:5>\n",
+        ];
+        for part in expected {
+            assert!(output.contains(part), "missing {:?} in {:?}", part, output);
+        }
+        assert_eq!(output.matches("  down called").count(), 20);
+    }
+
+    #[test]
+    fn test_underlines_errors_in_earlier_inputs() {
+        let input = "let pair = macro() {\n  quote(fn(a, a) { a })\n};\n1 + 1;\npair()\n";
+        let mut output = Vec::new();
+        start(input.as_bytes(), &mut output, Evaluator::new(), true, OutputMode::Text, false);
+        let output = String::from_utf8(output).unwrap();
+        let expected = " validation errors:\nin <repl:1>:\n  quote(fn(a, a) { a })\n              ^ duplicate parameter a (previously declared at line 2, column 12)\n";
+        assert!(output.contains(expected), "output: {:?}", output);
     }
-    let environment = Rc::new(RefCell::new(object::Environment::new()));
-    println!("{}", evaluator::evaluate_program(program, environment).unwrap().inspect());
 }
@@ -0,0 +1,326 @@
+use std::cell::RefCell;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+
+use evaluator::{Evaluator, Limits, SharedBuffer};
+use lexer::Lexer;
+use parser::Parser;
+use serde_json::{json, Value};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SYNTAX_ERROR: i64 = 1;
+const RUNTIME_ERROR: i64 = 2;
+
+// Requests with a larger body are refused before it is read.
+const MAX_BODY_LENGTH: usize = 1 << 20;
+// The most the request line and headers together may take up.
+const MAX_HEADER_LENGTH: u64 = 8 << 10;
+// How long a client may leave the server waiting for the rest of a request.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+// How long one eval may run before it fails as timed out, so a request that
+// never finishes does not hold a connection thread forever.
+const EVAL_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub fn serve(addr: &str) {
+    let listener = match TcpListener::bind(addr) {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("could not listen on {}: {}", addr, err);
+            return;
+        }
+    };
+    println!("Serving JSON-RPC on http://{}", addr);
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        if let Err(err) = thread::Builder::new().stack_size(crate::STACK_SIZE).spawn(move || handle_connection(stream)) {
+            eprintln!("could not start connection thread: {}", err);
+        }
+    }
+}
+
+fn handle_connection(mut stream: TcpStream) {
+    let _ = stream.set_read_timeout(Some(READ_TIMEOUT));
+    let body = match read_request(&mut stream) {
+        Ok(body) => body,
+        Err(status) => {
+            write_response(&mut stream, status, "");
+            return;
+        }
+    };
+    let response = handle_body(&body);
+    write_response(&mut stream, "200 OK", &response.to_string());
+}
+
+// Requests are posted to /, the one endpoint.
+fn read_request<R: Read>(stream: R) -> Result<String, &'static str> {
+    let mut reader = BufReader::new(stream);
+    let mut head = (&mut reader).take(MAX_HEADER_LENGTH);
+    let request_line = read_head_line(&mut head)?;
+    let mut parts = request_line.split_whitespace();
+    if parts.next() != Some("POST") {
+        return Err("405 Method Not Allowed");
+    }
+    if parts.next() != Some("/") {
+        return Err("404 Not Found");
+    }
+
+    let mut content_length = 0;
+    loop {
+        let header = read_head_line(&mut head)?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().map_err(|_| "400 Bad Request")?;
+            }
+        }
+    }
+
+    if content_length > MAX_BODY_LENGTH {
+        return Err("413 Payload Too Large");
+    }
+    let mut body = vec![0; content_length];
+    if reader.read_exact(&mut body).is_err() {
+        return Err("400 Bad Request");
+    }
+    String::from_utf8(body).map_err(|_| "400 Bad Request")
+}
+
+// A line of the request line and headers. Running into the header limit
+// before its end means the head is too long.
+fn read_head_line<R: BufRead>(head: &mut R) -> Result<String, &'static str> {
+    let mut line = String::new();
+    match head.read_line(&mut line) {
+        Ok(_) if line.ends_with('\n') => Ok(line),
+        Ok(n) if n > 0 => Err("431 Request Header Fields Too Large"),
+        _ => Err("400 Bad Request"),
+    }
+}
+
+fn write_response(stream: &mut TcpStream, status: &str, body: &str) {
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body,
+    );
+    let _ = stream.flush();
+}
+
+pub fn handle_body(body: &str) -> Value {
+    let request: Value = match serde_json::from_str(body) {
+        Ok(request) => request,
+        Err(err) => return error_response(Value::Null, PARSE_ERROR, &err.to_string(), None),
+    };
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let method = match request.get("method").and_then(Value::as_str) {
+        Some(method) => method,
+        None => return error_response(id, INVALID_REQUEST, "missing method", None),
+    };
+    let source = match request.get("params").and_then(|params| params.get("source")).and_then(Value::as_str) {
+        Some(source) => source,
+        None => return error_response(id, INVALID_PARAMS, "missing string param: source", None),
+    };
+
+    match method {
+        "eval" => eval(id, source),
+        "parse" => parse(id, source),
+        "format" => format(id, source),
+        _ => error_response(id, METHOD_NOT_FOUND, &format!("method not found: {}", method), None),
+    }
+}
+
+fn parse_source(source: &str) -> Result<ast::Program, Vec<String>> {
+    let l = Lexer::new(source);
+    let mut p = Parser::new(l);
+    let program = p.parse_program();
+    if !p.errors().is_empty() {
+        return Err(p.errors());
+    }
+    Ok(program)
+}
+
+fn eval(id: Value, source: &str) -> Value {
+    let limits = Limits { timeout: Some(EVAL_TIMEOUT), ..Limits::sandbox().for_stack_size(crate::STACK_SIZE) };
+    eval_with_limits(id, source, limits)
+}
+
+fn eval_with_limits(id: Value, source: &str, limits: Limits) -> Value {
+    let mut program = match parse_source(source) {
+        Ok(program) => program,
+        Err(errors) => return error_response(id, SYNTAX_ERROR, "parser errors", Some(json!(errors))),
+    };
+    let mut evaluator = Evaluator::with_limits(limits);
+    let macros = Rc::new(RefCell::new(object::Environment::new()));
+    evaluator.define_macros(&mut program, macros.clone());
+    let program = match evaluator.expand_macros(program, macros) {
+        Ok(program) => program,
+        Err(message) => return error_response(id, SYNTAX_ERROR, "macro expansion error", Some(json!([message]))),
+    };
+    let (errors, warnings): (Vec<_>, Vec<_>) = validator::validate(&program).into_iter().partition(|d| d.is_error());
+    if !errors.is_empty() {
+        let messages: Vec<String> = errors.iter().map(|d| d.to_string()).collect();
+        return error_response(id, SYNTAX_ERROR, "validation errors", Some(json!(messages)));
+    }
+    let output = SharedBuffer::default();
+    evaluator.output = Box::new(output.clone());
+    let environment = monkey::prelude::environment(&mut evaluator);
+    let mut result = match evaluator.evaluate_program(program, environment) {
+        Some(result) if result.object_type() == object::ObjectType::ERROR => {
+            return error_response(id, RUNTIME_ERROR, &result.inspect(), None);
+        }
+        Some(result) => json!({
+            "value": result.inspect(),
+            "type": format!("{:?}", result.object_type()),
+        }),
+        None => json!({ "value": Value::Null, "type": "NULL" }),
+    };
+    result["output"] = json!(output.contents());
+    if !warnings.is_empty() {
+        result["warnings"] = json!(warnings.iter().map(|d| d.to_string()).collect::<Vec<String>>());
+    }
+    success_response(id, result)
+}
+
+fn parse(id: Value, source: &str) -> Value {
+    match parse_source(source) {
+        Ok(program) => success_response(id, json!({ "ast": program.to_json() })),
+        Err(errors) => error_response(id, SYNTAX_ERROR, "parser errors", Some(json!(errors))),
+    }
+}
+
+fn format(id: Value, source: &str) -> Value {
+    match parse_source(source) {
+        Ok(program) => {
+            let tokens = Lexer::tokenize(source, true);
+            success_response(id, json!({ "source": formatter::format_with_comments(&program, &tokens, &formatter::FormatConfig::default()) }))
+        }
+        Err(errors) => error_response(id, SYNTAX_ERROR, "parser errors", Some(json!(errors))),
+    }
+}
+
+fn success_response(id: Value, result: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "result": result, "id": id })
+}
+
+fn error_response(id: Value, code: i64, message: &str, data: Option<Value>) -> Value {
+    let mut error = json!({ "code": code, "message": message });
+    if let Some(data) = data {
+        error["data"] = data;
+    }
+    json!({ "jsonrpc": "2.0", "error": error, "id": id })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eval_method() {
+        let response = handle_body(r#"{"jsonrpc": "2.0", "method": "eval", "params": {"source": "let x = 2; x * 21"}, "id": 1}"#);
+        assert_eq!(response["result"]["value"], "42");
+        assert_eq!(response["result"]["type"], "INTEGER");
+        assert_eq!(response["id"], 1);
+        assert_eq!(response["result"]["output"], "");
+
+        let response = handle_body(r#"{"jsonrpc": "2.0", "method": "eval", "params": {"source": "puts(\"a\"); puts(1, true); 2"}, "id": 10}"#);
+        assert_eq!(response["result"]["value"], "2");
+        assert_eq!(response["result"]["output"], "a\n1\ntrue\n");
+    }
+
+    #[test]
+    fn test_eval_runtime_error() {
+        let response = handle_body(r#"{"jsonrpc": "2.0", "method": "eval", "params": {"source": "1 + true"}, "id": 2}"#);
+        assert_eq!(response["error"]["code"], RUNTIME_ERROR);
+        assert_eq!(response["error"]["message"], "type mismatch: INTEGER + BOOLEAN");
+    }
+
+    #[test]
+    fn test_eval_uses_sandbox_limits() {
+        let response = handle_body(r#"{"jsonrpc": "2.0", "method": "eval", "params": {"source": "let s = \"x\"; while (true) { s += s; }"}, "id": 7}"#);
+        assert_eq!(response["error"]["code"], RUNTIME_ERROR);
+        assert!(response["error"]["message"].as_str().unwrap().starts_with("resource limit exceeded"));
+    }
+
+    #[test]
+    fn test_eval_times_out() {
+        let limits = Limits { timeout: Some(Duration::from_millis(20)), ..Limits::sandbox() };
+        let response = eval_with_limits(json!(13), "while (true) {}", limits);
+        assert_eq!(response["error"]["code"], RUNTIME_ERROR);
+        assert_eq!(response["error"]["message"], "timed out after 20 ms");
+    }
+
+    #[test]
+    fn test_read_request() {
+        let body = r#"{"method": "parse", "params": {"source": "1"}, "id": 14}"#;
+        let request = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}", body.len(), body);
+        assert_eq!(read_request(request.as_bytes()), Ok(body.to_string()));
+
+        // Refused from the header alone, without waiting for the body.
+        let request = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_BODY_LENGTH + 1);
+        assert_eq!(read_request(request.as_bytes()), Err("413 Payload Too Large"));
+        assert_eq!(read_request("GET / HTTP/1.1\r\n\r\n".as_bytes()), Err("405 Method Not Allowed"));
+        assert_eq!(read_request("POST /anything HTTP/1.1\r\n\r\n".as_bytes()), Err("404 Not Found"));
+
+        // A head that never ends is cut off at the limit.
+        let request = format!("POST / HTTP/1.1\r\nX-Padding: {}", "a".repeat(MAX_HEADER_LENGTH as usize));
+        assert_eq!(read_request(request.as_bytes()), Err("431 Request Header Fields Too Large"));
+        let request = format!("POST /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_HEADER_LENGTH as usize));
+        assert_eq!(read_request(request.as_bytes()), Err("431 Request Header Fields Too Large"));
+    }
+
+    #[test]
+    fn test_eval_validation_error() {
+        let response = handle_body(r#"{"jsonrpc": "2.0", "method": "eval", "params": {"source": "fn(x, x) { x }"}, "id": 8}"#);
+        assert_eq!(response["error"]["code"], SYNTAX_ERROR);
+        assert_eq!(response["error"]["data"][0], "duplicate parameter x at line 1, column 7 (previously declared at line 1, column 4)");
+
+        let response = handle_body(r#"{"jsonrpc": "2.0", "method": "eval", "params": {"source": "let x = 1; let x = 2; x"}, "id": 9}"#);
+        assert_eq!(response["result"]["value"], "2");
+        assert_eq!(response["result"]["warnings"][0], "warning: duplicate let x at line 1, column 16 (previously declared at line 1, column 5)");
+    }
+
+    #[test]
+    fn test_parse_method() {
+        let response = handle_body(r#"{"jsonrpc": "2.0", "method": "parse", "params": {"source": "1 + 2 * 3"}, "id": 3}"#);
+        let ast = &response["result"]["ast"];
+        assert_eq!(ast["node"], "PROGRAM");
+        let expression = &ast["statements"][0]["expression"];
+        assert_eq!(expression["node"], "INFIX_EXPRESSION");
+        assert_eq!(expression["operator"], "+");
+        assert_eq!(expression["left"]["value"], 1);
+        assert_eq!(expression["right"]["operator"], "*");
+        assert_eq!(expression["right"]["right"]["token"]["column"], 9);
+
+        let response = handle_body(r#"{"jsonrpc": "2.0", "method": "parse", "params": {"source": "let = 1;"}, "id": 4}"#);
+        assert_eq!(response["error"]["code"], SYNTAX_ERROR);
+    }
+
+    #[test]
+    fn test_format_method() {
+        let response = handle_body(r#"{"jsonrpc": "2.0", "method": "format", "params": {"source": "let f=fn(x){x*2};"}, "id": 11}"#);
+        assert_eq!(response["result"]["source"], "let f = fn(x) {\n    x * 2;\n};\n");
+
+        let response = handle_body(r#"{"jsonrpc": "2.0", "method": "format", "params": {"source": "// sum\n1+2 // three"}, "id": 12}"#);
+        assert_eq!(response["result"]["source"], "// sum\n1 + 2; // three\n");
+    }
+
+    #[test]
+    fn test_invalid_requests() {
+        assert_eq!(handle_body("{").get("error").unwrap()["code"], PARSE_ERROR);
+        assert_eq!(handle_body(r#"{"method": "run", "params": {"source": ""}, "id": 5}"#)["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(handle_body(r#"{"method": "eval", "id": 6}"#)["error"]["code"], INVALID_PARAMS);
+    }
+}
@@ -0,0 +1,170 @@
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use ast::StatementKind;
+use evaluator::{Evaluator, Limits};
+use lexer::Lexer;
+use object::Object;
+use parser::Parser;
+
+use crate::evaluation::{self, Outcome};
+use crate::new_environment;
+
+const SUFFIX: &str = "_test.monkey";
+
+// Runs the tests in every *_test.monkey file under paths, in name order.
+// A test is a function bound at the top level of a file under a name
+// starting with test_; it passes when it returns without an error and
+// every assertion it made held. Each file runs in an interpreter of its
+// own. Writes one line per test plus a summary. Returns whether every test
+// passed, which finding none does not count as.
+pub fn run_tests<W: Write>(paths: &[PathBuf], prelude: bool, writer: &mut W) -> io::Result<bool> {
+    let mut files = vec![];
+    for path in paths {
+        if path.is_dir() {
+            find_tests(path, &mut files)?;
+        } else {
+            files.push(path.clone());
+        }
+    }
+    files.sort();
+
+    let (mut passed, mut failed, mut broken) = (0, 0, 0);
+    for file in &files {
+        let name = file.display();
+        let results = match std::fs::read_to_string(file) {
+            Ok(source) => run_file(file, &source, prelude),
+            Err(err) => Err(err.to_string()),
+        };
+        let results = match results {
+            Ok(results) => results,
+            Err(message) => {
+                broken += 1;
+                writeln!(writer, "{}: error: {}", name, message)?;
+                continue;
+            }
+        };
+        for (test, failures) in results {
+            if failures.is_empty() {
+                passed += 1;
+                writeln!(writer, "{} {}: ok", name, test)?;
+            } else {
+                failed += 1;
+                writeln!(writer, "{} {}: FAILED", name, test)?;
+                for failure in failures {
+                    writeln!(writer, "    {}", failure)?;
+                }
+            }
+        }
+    }
+    write!(writer, "{} tests: {} passed, {} failed", passed + failed, passed, failed)?;
+    if broken > 0 {
+        write!(writer, ", {} files could not be run", broken)?;
+    }
+    writeln!(writer)?;
+    Ok(passed > 0 && failed + broken == 0)
+}
+
+fn find_tests(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_tests(&path, files)?;
+        } else if path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.ends_with(SUFFIX)) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+// The failures of each test in the file, in the order the tests are
+// defined, or why the file itself could not be run.
+fn run_file(path: &Path, source: &str, prelude: bool) -> Result<Vec<(String, Vec<String>)>, String> {
+    let mut evaluator = Evaluator::with_limits(Limits::default().for_stack_size(crate::STACK_SIZE));
+    evaluator.source_path = Some(path.to_path_buf());
+    let environment = new_environment(&mut evaluator, prelude);
+    let macros = Rc::new(RefCell::new(object::Environment::new()));
+    let evaluation = evaluation::evaluate(Lexer::new(source), &environment, &macros, &mut evaluator);
+    match evaluation.outcome {
+        Outcome::ParseErrors(errors) | Outcome::ValidationErrors(errors) => {
+            return Err(errors.iter().map(|e| e.message.as_str()).collect::<Vec<_>>().join("; "));
+        }
+        Outcome::MacroError(message) | Outcome::Evaluated(Some(Object::Error(message))) => return Err(message),
+        Outcome::Evaluated(_) => {}
+    }
+
+    let program = Parser::new(Lexer::new(source)).parse_program();
+    let mut tests: Vec<String> = vec![];
+    for statement in &program.statements {
+        if let StatementKind::Let(let_statement) = statement.kind() {
+            let name = &let_statement.name.value;
+            if name.starts_with("test_") && !tests.contains(name) {
+                tests.push(name.clone());
+            }
+        }
+    }
+
+    let mut results = vec![];
+    for test in tests {
+        let function = match environment.borrow().get(&test) {
+            Some(Object::Function(function)) if function.parameters.is_empty() => Object::Function(function),
+            Some(Object::Function(_)) => {
+                results.push((test, vec!["test functions take no arguments".to_string()]));
+                continue;
+            }
+            _ => continue,
+        };
+        evaluator.assertion_failures = Some(vec![]);
+        evaluator.cancellation.reset();
+        evaluator.start_run();
+        let result = evaluator.call_function(function, vec![]);
+        let mut failures = evaluator.assertion_failures.take().unwrap_or_default();
+        if let Object::Error(message) = result {
+            failures.push(message);
+        }
+        results.push((test, failures));
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_tests() {
+        let dir = std::env::temp_dir().join(format!("monkey_tests_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        std::fs::write(dir.join("math_test.monkey"), "
+            let double = fn(x) { x * 2 };
+            let test_double = fn() { assert_eq(double(2), 4); assert(double(0) == 0, \"zero\") };
+            let test_wrong = fn() { assert_eq(double(2), 5); assert(false, \"keeps going\"); assert_eq(\"4\", 4) };
+            let test_error = fn() { double(true) };
+            let helper = fn() { assert(false) };
+        ").unwrap();
+        std::fs::write(dir.join("nested/strings_test.monkey"), "let test_upper = fn() { assert_eq(upper(\"a\"), \"A\") };").unwrap();
+        std::fs::write(dir.join("broken_test.monkey"), "let test_x = fn() { 1 }; let = 2;").unwrap();
+        std::fs::write(dir.join("helpers.monkey"), "let test_skipped = fn() { assert(false) };").unwrap();
+
+        let mut output = Vec::new();
+        let passed = run_tests(std::slice::from_ref(&dir), true, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap().replace(&format!("{}/", dir.display()), "");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert!(!passed);
+        assert_eq!(output, "\
+broken_test.monkey: error: expected next token to be IDENT, got ASSIGN instead
+math_test.monkey test_double: ok
+math_test.monkey test_wrong: FAILED
+    assertion failed: 4 != 5
+    assertion failed: keeps going
+    assertion failed: \"4\" != 4
+math_test.monkey test_error: FAILED
+    type mismatch: BOOLEAN * INTEGER
+nested/strings_test.monkey test_upper: ok
+4 tests: 2 passed, 2 failed, 1 files could not be run
+");
+    }
+}
@@ -1,9 +1,29 @@
+#![allow(non_camel_case_types)]
+
 use std::fmt;
 
+mod source_map;
+
+pub use source_map::{FileId, SourceFile, SourceMap};
+
 #[derive(Debug)]
 pub struct Token {
     pub token_type: TokenType,
     pub literal: String,
+    pub line: usize,
+    pub column: usize,
+    pub span: Span,
+    // None for tokens built in code or lexed from unregistered input.
+    pub file: Option<FileId>,
+}
+
+// The bytes of the source a token was lexed from, end exclusive, so
+// &source[span.start..span.end] is its text as written. Empty for tokens
+// built in code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
 }
 
 impl Token {
@@ -11,6 +31,10 @@ impl Token {
         Token {
             token_type,
             literal,
+            line: 0,
+            column: 0,
+            span: Span::default(),
+            file: None,
         }
     }
 }
@@ -21,9 +45,13 @@ pub fn lookup_ident(ident: &str) -> TokenType {
         "let" => TokenType::LET,
         "true" => TokenType::TRUE,
         "false" => TokenType::FALSE,
+        "null" => TokenType::NULL,
         "return" => TokenType::RETURN,
         "if" => TokenType::IF,
         "else" => TokenType::ELSE,
+        "while" => TokenType::WHILE,
+        "macro" => TokenType::MACRO,
+        "import" => TokenType::IMPORT,
         _ => TokenType::IDENT,
     }
 }
@@ -36,19 +64,28 @@ pub enum TokenType {
     // Identifiers + literals
     IDENT,
     INT,
+    FLOAT,
     STRING,
+    TEMPLATE,
 
     // Operators
     ASSIGN,
+    PLUS_ASSIGN,
+    MINUS_ASSIGN,
+    ASTERISK_ASSIGN,
+    SLASH_ASSIGN,
     PLUS,
     MINUS,
     ASTERISK,
+    POWER,
     SLASH,
     BANG,
     MODULO,
 
     LT,
     RT,
+    LT_EQ,
+    GT_EQ,
 
     EQ,
     NOT_EQ,
@@ -56,11 +93,15 @@ pub enum TokenType {
     // Delimiters
     COMMA,
     SEMICOLON,
+    COLON,
+    ARROW,
 
     LPAREN,
     RPAREN,
     LBRACE,
     RBRACE,
+    LBRACKET,
+    RBRACKET,
 
     DOUBLE_QUOTE,
 
@@ -69,10 +110,77 @@ pub enum TokenType {
     LET,
     TRUE,
     FALSE,
+    NULL,
     RETURN,
 
     IF,
     ELSE,
+    WHILE,
+    MACRO,
+    IMPORT,
+
+    // Trivia, only produced by lexers asked to keep it
+    WHITESPACE,
+    COMMENT,
+}
+
+impl std::str::FromStr for TokenType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ILLEGAL" => Ok(TokenType::ILLEGAL),
+            "EOF" => Ok(TokenType::EOF),
+            "IDENT" => Ok(TokenType::IDENT),
+            "INT" => Ok(TokenType::INT),
+            "FLOAT" => Ok(TokenType::FLOAT),
+            "STRING" => Ok(TokenType::STRING),
+            "TEMPLATE" => Ok(TokenType::TEMPLATE),
+            "ASSIGN" => Ok(TokenType::ASSIGN),
+            "PLUS_ASSIGN" => Ok(TokenType::PLUS_ASSIGN),
+            "MINUS_ASSIGN" => Ok(TokenType::MINUS_ASSIGN),
+            "ASTERISK_ASSIGN" => Ok(TokenType::ASTERISK_ASSIGN),
+            "SLASH_ASSIGN" => Ok(TokenType::SLASH_ASSIGN),
+            "PLUS" => Ok(TokenType::PLUS),
+            "MINUS" => Ok(TokenType::MINUS),
+            "ASTERISK" => Ok(TokenType::ASTERISK),
+            "POWER" => Ok(TokenType::POWER),
+            "SLASH" => Ok(TokenType::SLASH),
+            "BANG" => Ok(TokenType::BANG),
+            "MODULO" => Ok(TokenType::MODULO),
+            "LT" => Ok(TokenType::LT),
+            "RT" => Ok(TokenType::RT),
+            "LT_EQ" => Ok(TokenType::LT_EQ),
+            "GT_EQ" => Ok(TokenType::GT_EQ),
+            "EQ" => Ok(TokenType::EQ),
+            "NOT_EQ" => Ok(TokenType::NOT_EQ),
+            "COMMA" => Ok(TokenType::COMMA),
+            "SEMICOLON" => Ok(TokenType::SEMICOLON),
+            "COLON" => Ok(TokenType::COLON),
+            "ARROW" => Ok(TokenType::ARROW),
+            "LPAREN" => Ok(TokenType::LPAREN),
+            "RPAREN" => Ok(TokenType::RPAREN),
+            "LBRACE" => Ok(TokenType::LBRACE),
+            "RBRACE" => Ok(TokenType::RBRACE),
+            "LBRACKET" => Ok(TokenType::LBRACKET),
+            "RBRACKET" => Ok(TokenType::RBRACKET),
+            "DOUBLE_QUOTE" => Ok(TokenType::DOUBLE_QUOTE),
+            "FUNCTION" => Ok(TokenType::FUNCTION),
+            "LET" => Ok(TokenType::LET),
+            "TRUE" => Ok(TokenType::TRUE),
+            "FALSE" => Ok(TokenType::FALSE),
+            "NULL" => Ok(TokenType::NULL),
+            "RETURN" => Ok(TokenType::RETURN),
+            "IF" => Ok(TokenType::IF),
+            "ELSE" => Ok(TokenType::ELSE),
+            "WHILE" => Ok(TokenType::WHILE),
+            "MACRO" => Ok(TokenType::MACRO),
+            "IMPORT" => Ok(TokenType::IMPORT),
+            "WHITESPACE" => Ok(TokenType::WHITESPACE),
+            "COMMENT" => Ok(TokenType::COMMENT),
+            _ => Err(format!("unknown token type: {}", s)),
+        }
+    }
 }
 
 impl fmt::Display for TokenType {
@@ -0,0 +1,85 @@
+// Source text for every file a session has seen, including virtual ones
+// such as REPL inputs that never existed on disk. Tokens carry the FileId of
+// the file they were lexed from, so a diagnostic can always show the line it
+// points at, even when a macro moved the token into another program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileId(usize);
+
+#[derive(Debug)]
+pub struct SourceFile {
+    pub name: String,
+    pub contents: String,
+}
+
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> SourceMap {
+        SourceMap::default()
+    }
+
+    pub fn add(&mut self, name: &str, contents: &str) -> FileId {
+        self.files.push(SourceFile {
+            name: name.to_string(),
+            contents: contents.to_string(),
+        });
+        FileId(self.files.len() - 1)
+    }
+
+    // Adds text to the end of a file, for a source that is still being read.
+    pub fn append(&mut self, file: FileId, contents: &str) {
+        if let Some(source) = self.files.get_mut(file.0) {
+            source.contents.push_str(contents);
+        }
+    }
+
+    pub fn get(&self, file: FileId) -> Option<&SourceFile> {
+        self.files.get(file.0)
+    }
+
+    // A name can be added more than once, as when the REPL loads a file
+    // again after it changed.
+    pub fn find(&self, name: &str) -> Vec<FileId> {
+        (0..self.files.len()).filter(|&i| self.files[i].name == name).map(FileId).collect()
+    }
+
+    // Lines are numbered from 1, like token positions.
+    pub fn line(&self, file: FileId, line: usize) -> Option<&str> {
+        let source = self.get(file)?;
+        line.checked_sub(1).and_then(|i| source.contents.lines().nth(i))
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_map() {
+        let mut sources = SourceMap::new();
+        let first = sources.add("<repl:1>", "let a = 1;");
+        let second = sources.add("lib.monkey", "let b = 2;\nlet c = 3;");
+        assert_ne!(first, second);
+        assert_eq!(sources.len(), 2);
+        assert_eq!(sources.get(first).unwrap().name, "<repl:1>");
+        assert_eq!(sources.line(second, 2), Some("let c = 3;"));
+        assert_eq!(sources.line(second, 3), None);
+        sources.append(second, "\nlet d = 4;");
+        assert_eq!(sources.line(second, 3), Some("let d = 4;"));
+        assert_eq!(sources.line(second, 0), None);
+        let again = sources.add("lib.monkey", "");
+        assert_eq!(sources.find("lib.monkey"), vec![second, again]);
+        assert!(sources.find("other.monkey").is_empty());
+    }
+}
@@ -0,0 +1,384 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use ast::{Expression, ExpressionKind, FunctionLiteral, Statement, StatementKind, TypeAnnotation};
+use token::Token;
+use validator::types::{builtin_return_type, infix_type, is_builtin, join, Type};
+use validator::{Diagnostic, Severity};
+
+// Checks values against the types they are annotated with. The check is
+// best-effort: a value whose type cannot be told without running the
+// program is taken to be of any type, as are unannotated names, so only
+// obvious mismatches are reported and untyped code always passes.
+pub fn check(program: &ast::Program) -> Vec<Diagnostic> {
+    let mut checker = Checker {
+        diagnostics: vec![],
+        scopes: vec![HashMap::new()],
+        function_start: 0,
+        returns: vec![],
+    };
+    checker.check_statements(&program.statements);
+    checker.diagnostics
+}
+
+// The types annotations can name, spelled as they are written.
+pub fn annotated_type(name: &str) -> Option<Type> {
+    match name {
+        "int" => Some(Type::INTEGER),
+        "float" => Some(Type::FLOAT),
+        "bool" => Some(Type::BOOLEAN),
+        "string" => Some(Type::STRING),
+        "null" => Some(Type::NULL),
+        "array" => Some(Type::ARRAY),
+        "fn" => Some(Type::FUNCTION),
+        "any" => Some(Type::UNKNOWN),
+        _ => None,
+    }
+}
+
+fn type_name(ty: &Type) -> &'static str {
+    match ty {
+        Type::INTEGER => "int",
+        Type::FLOAT => "float",
+        Type::BOOLEAN => "bool",
+        Type::STRING => "string",
+        Type::NULL => "null",
+        Type::ARRAY => "array",
+        Type::FUNCTION => "fn",
+        Type::MACRO => "macro",
+        Type::UNKNOWN => "any",
+    }
+}
+
+// Integers are accepted where floats are expected, as arithmetic mixes the
+// two freely.
+fn accepts(expected: &Type, actual: &Type) -> bool {
+    matches!((expected, actual), (Type::UNKNOWN, _) | (_, Type::UNKNOWN) | (Type::FLOAT, Type::INTEGER)) || expected == actual
+}
+
+struct Signature {
+    parameters: Vec<(String, Option<Type>)>,
+    returns: Option<Type>,
+}
+
+#[derive(Clone)]
+struct Binding {
+    ty: Type,
+    declared: Option<Type>,
+    // Kept for names bound to a function literal, so calls to them can be
+    // checked against its annotations.
+    signature: Option<Rc<Signature>>,
+}
+
+struct Checker {
+    diagnostics: Vec<Diagnostic>,
+    // One scope per function call and block, as the evaluator makes them.
+    scopes: Vec<HashMap<String, Binding>>,
+    // The first scope of the function being checked. Unannotated names
+    // bound before it may have changed by the time the function is called.
+    function_start: usize,
+    // The declared return type of each function being checked, innermost
+    // last.
+    returns: Vec<Option<Type>>,
+}
+
+impl Checker {
+    // The type of the value the statements evaluate to.
+    fn check_statements(&mut self, statements: &[Rc<dyn Statement>]) -> Type {
+        let mut result = Type::NULL;
+        for statement in statements {
+            result = self.check_statement(statement);
+        }
+        result
+    }
+
+    fn check_statement(&mut self, statement: &Rc<dyn Statement>) -> Type {
+        match statement.kind() {
+            StatementKind::Let(let_statement) => {
+                let declared = let_statement.type_annotation.as_ref().and_then(|a| self.annotation(a));
+                let mut binding = Binding { ty: Type::NULL, declared: declared.clone(), signature: None };
+                // `let x: int;` declares x without giving it a value yet.
+                if let Some(value) = &let_statement.value {
+                    binding.ty = self.check_expression(value);
+                    if let ExpressionKind::FunctionLiteral(function) = value.kind() {
+                        binding.signature = Some(Rc::new(signature(function)));
+                    }
+                    if let Some(expected) = &declared {
+                        if !accepts(expected, &binding.ty) {
+                            let message = format!("cannot bind {} to {}: {}", type_name(&binding.ty), let_statement.name.value, type_name(expected));
+                            self.report(message, &let_statement.name.token);
+                        }
+                    }
+                }
+                if let Some(declared) = declared {
+                    binding.ty = declared;
+                }
+                self.scopes.last_mut().unwrap().insert(let_statement.name.value.clone(), binding);
+                Type::NULL
+            },
+            StatementKind::Return(return_statement) => {
+                let ty = match &return_statement.return_value {
+                    Some(value) => self.check_expression(value),
+                    None => Type::NULL,
+                };
+                if let Some(Some(expected)) = self.returns.last() {
+                    if !accepts(expected, &ty) {
+                        let message = format!("cannot return {} from a function returning {}", type_name(&ty), type_name(expected));
+                        self.report(message, &return_statement.token);
+                    }
+                }
+                // Nothing after a return sees its value fall through.
+                Type::UNKNOWN
+            },
+            StatementKind::Expression(expression_statement) => match &expression_statement.expression {
+                Some(expression) => self.check_expression(expression),
+                None => Type::NULL,
+            },
+            StatementKind::Block(block) => {
+                self.scopes.push(HashMap::new());
+                let ty = self.check_statements(&block.statements);
+                self.scopes.pop();
+                ty
+            },
+        }
+    }
+
+    fn check_expression(&mut self, exp: &Rc<dyn Expression>) -> Type {
+        match exp.kind() {
+            ExpressionKind::IntegerLiteral(_) => Type::INTEGER,
+            ExpressionKind::FloatLiteral(_) => Type::FLOAT,
+            ExpressionKind::StringLiteral(_) => Type::STRING,
+            ExpressionKind::InterpolatedString(interpolated) => {
+                interpolated.parts.iter().for_each(|part| { self.check_expression(part); });
+                Type::STRING
+            },
+            ExpressionKind::Boolean(_) => Type::BOOLEAN,
+            ExpressionKind::NullLiteral(_) => Type::NULL,
+            ExpressionKind::MacroLiteral(_) => Type::MACRO,
+            ExpressionKind::Import(_) => Type::UNKNOWN,
+            ExpressionKind::Array(array) => {
+                array.elements.iter().for_each(|element| { self.check_expression(element); });
+                Type::ARRAY
+            },
+            ExpressionKind::Identifier(identifier) => match self.lookup(&identifier.value) {
+                Some(binding) => binding.ty,
+                None if is_builtin(&identifier.value) => Type::FUNCTION,
+                None => Type::UNKNOWN,
+            },
+            ExpressionKind::Prefix(prefix) => {
+                let right = self.check_expression(&prefix.right);
+                match (prefix.operator.as_str(), right) {
+                    ("!", _) => Type::BOOLEAN,
+                    ("-", Type::INTEGER) => Type::INTEGER,
+                    ("-", Type::FLOAT) => Type::FLOAT,
+                    _ => Type::UNKNOWN,
+                }
+            },
+            ExpressionKind::Infix(infix) => {
+                let left = self.check_expression(&infix.left);
+                let right = self.check_expression(&infix.right);
+                infix_type(&infix.operator, left, right)
+            },
+            ExpressionKind::Assign(assign) => {
+                let value = self.check_expression(&assign.value);
+                let name = &assign.name.value;
+                let ty = match assign.operator.as_str() {
+                    "=" => value,
+                    operator => infix_type(operator.trim_end_matches('='), self.lookup(name).map_or(Type::UNKNOWN, |b| b.ty), value),
+                };
+                let declared = match self.scopes.iter_mut().rev().find_map(|scope| scope.get_mut(name)) {
+                    Some(Binding { declared: Some(declared), .. }) => declared.clone(),
+                    // Assignments may not run, so all an unannotated name
+                    // keeps is what every value it may hold agrees on.
+                    Some(binding) => {
+                        *binding = Binding { ty: join(binding.ty.clone(), ty.clone()), declared: None, signature: None };
+                        return ty;
+                    },
+                    None => return ty,
+                };
+                if !accepts(&declared, &ty) {
+                    let message = format!("cannot assign {} to {}: {}", type_name(&ty), name, type_name(&declared));
+                    self.report(message, &assign.name.token);
+                }
+                ty
+            },
+            ExpressionKind::If(if_expression) => {
+                self.check_expression(&if_expression.condition);
+                let consequence = self.check_statement(&if_expression.consequence);
+                let alternative = match &if_expression.alternative {
+                    Some(alternative) => self.check_statement(alternative),
+                    None => Type::NULL,
+                };
+                join(consequence, alternative)
+            },
+            ExpressionKind::While(while_expression) => {
+                self.check_expression(&while_expression.condition);
+                self.check_statement(&while_expression.body);
+                Type::UNKNOWN
+            },
+            ExpressionKind::Index(index) => {
+                let left = self.check_expression(&index.left);
+                self.check_expression(&index.index);
+                match left {
+                    Type::STRING => Type::STRING,
+                    _ => Type::UNKNOWN,
+                }
+            },
+            ExpressionKind::FunctionLiteral(function) => {
+                self.check_function(function);
+                Type::FUNCTION
+            },
+            ExpressionKind::Call(call) => {
+                self.check_expression(&call.function);
+                let arguments: Vec<Type> = call.arguments.iter().map(|a| self.check_expression(a)).collect();
+                let (signature, token) = match call.function.kind() {
+                    ExpressionKind::FunctionLiteral(function) => (Rc::new(signature(function)), &call.token),
+                    ExpressionKind::Identifier(identifier) => match self.lookup(&identifier.value) {
+                        Some(Binding { signature: Some(signature), .. }) => (signature, &identifier.token),
+                        Some(_) => return Type::UNKNOWN,
+                        None => return builtin_return_type(&identifier.value),
+                    },
+                    _ => return Type::UNKNOWN,
+                };
+                for ((parameter, expected), actual) in signature.parameters.iter().zip(&arguments) {
+                    if let Some(expected) = expected {
+                        if !accepts(expected, actual) {
+                            let message = format!("cannot pass {} as {}: {}", type_name(actual), parameter, type_name(expected));
+                            self.report(message, token);
+                        }
+                    }
+                }
+                signature.returns.clone().unwrap_or(Type::UNKNOWN)
+            },
+        }
+    }
+
+    fn check_function(&mut self, function: &FunctionLiteral) {
+        let mut scope = HashMap::new();
+        for (parameter, annotation) in function.parameters.iter().zip(&function.parameter_types) {
+            let declared = annotation.as_ref().and_then(|a| self.annotation(a));
+            let binding = Binding { ty: declared.clone().unwrap_or(Type::UNKNOWN), declared, signature: None };
+            scope.insert(parameter.value.clone(), binding);
+        }
+        let returns = function.return_type.as_ref().and_then(|a| self.annotation(a));
+
+        let function_start = std::mem::replace(&mut self.function_start, self.scopes.len());
+        self.scopes.push(scope);
+        self.returns.push(returns.clone());
+        let body = self.check_statement(&function.body);
+        self.returns.pop();
+        self.scopes.pop();
+        self.function_start = function_start;
+
+        let (Some(expected), Some(annotation)) = (returns, &function.return_type) else {
+            return;
+        };
+        if !accepts(&expected, &body) {
+            // Pointing at the expression whose value is returned, when the
+            // body ends in one.
+            let last = match function.body.kind() {
+                StatementKind::Block(block) => block.statements.last().and_then(|s| match s.kind() {
+                    StatementKind::Expression(expression_statement) => Some(expression_statement.token.clone()),
+                    _ => None,
+                }),
+                _ => None,
+            };
+            let message = format!("cannot return {} from a function returning {}", type_name(&body), type_name(&expected));
+            self.report(message, last.as_ref().unwrap_or(&annotation.token));
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<Binding> {
+        let (depth, binding) = self.scopes.iter().enumerate().rev().find_map(|(depth, scope)| scope.get(name).map(|b| (depth, b)))?;
+        let mut binding = binding.clone();
+        if depth < self.function_start && binding.declared.is_none() {
+            binding.ty = Type::UNKNOWN;
+        }
+        Some(binding)
+    }
+
+    // None for an annotation naming no type, which is reported.
+    fn annotation(&mut self, annotation: &TypeAnnotation) -> Option<Type> {
+        let ty = annotated_type(&annotation.name);
+        if ty.is_none() {
+            self.report(format!("unknown type {}", annotation.name), &annotation.token);
+        }
+        ty
+    }
+
+    fn report(&mut self, message: String, token: &Token) {
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::ERROR,
+            message,
+            line: token.line,
+            column: token.column,
+            length: token.literal.chars().count().max(1),
+            previous: None,
+            file: token.file,
+        });
+    }
+}
+
+// Unknown types are left unchecked here; check_function reports them.
+fn signature(function: &FunctionLiteral) -> Signature {
+    let resolve = |annotation: &Option<TypeAnnotation>| annotation.as_ref().and_then(|a| annotated_type(&a.name));
+    Signature {
+        parameters: function.parameters.iter().zip(&function.parameter_types).map(|(p, t)| (p.value.clone(), resolve(t))).collect(),
+        returns: resolve(&function.return_type),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::Lexer;
+    use parser::Parser;
+
+    fn check_source(input: &str) -> Vec<String> {
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program();
+        assert!(parser.errors().is_empty(), "parser errors: {:?}", parser.errors());
+        check(&program).iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn test_check() {
+        let tests = vec![
+            ("let x: int = \"five\";", vec!["cannot bind string to x: int at line 1, column 5"]),
+            ("let x: float = 1; let y: any = [1]; let z: fn = len; let w: int;", vec![]),
+            ("let x: int = 1; x = 2.5; x += 1;", vec!["cannot assign float to x: int at line 1, column 17"]),
+            ("let x: number = 1;", vec!["unknown type number at line 1, column 8"]),
+            (
+                "let greet = fn(name: string, times: int) -> string { name }; greet(\"a\", \"b\"); greet(1, 2);",
+                vec![
+                    "cannot pass string as times: int at line 1, column 62",
+                    "cannot pass int as name: string at line 1, column 79",
+                ],
+            ),
+            ("let f = fn(x) -> int { x }; let s: string = f(1);", vec!["cannot bind int to s: string at line 1, column 33"]),
+            ("fn() -> int { if (true) { return \"a\"; } 1 }", vec!["cannot return string from a function returning int at line 1, column 27"]),
+            ("fn(a: bool) -> bool { a; 1 }", vec!["cannot return int from a function returning bool at line 1, column 26"]),
+            ("fn() -> int { let x = 1; }", vec!["cannot return null from a function returning int at line 1, column 9"]),
+            ("fn(x: int) { x }(true)", vec!["cannot pass bool as x: int at line 1, column 17"]),
+            ("let n: int = len(\"abc\"); let s: string = upper(1);", vec![]),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(check_source(input), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_untyped_code_passes() {
+        let tests = vec![
+            "let x = 1; x = \"now a string\"; let y: int = x;",
+            "let x = 1; if (true) { x = \"s\" }; let y: string = x;",
+            "let x = \"s\"; let f = fn() { let y: int = x; }; x = 1; f();",
+            "let fib = fn(n: int) -> int { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(10);",
+            "let apply = fn(f, x) { f(x) }; let s: string = apply(fn(x) { x }, 1);",
+            "let f = fn(x: int) -> int { while (x > 0) { return x; } };",
+        ];
+        for input in tests {
+            assert_eq!(check_source(input), Vec::<String>::new(), "input: {}", input);
+        }
+    }
+}
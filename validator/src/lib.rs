@@ -0,0 +1,344 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use ast::{Expression, Statement};
+use token::{FileId, SourceMap};
+
+pub mod scopes;
+pub mod types;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Severity {
+    ERROR,
+    WARNING,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+    pub length: usize,
+    pub previous: Option<(usize, usize)>,
+    pub file: Option<FileId>,
+}
+
+impl Diagnostic {
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::ERROR
+    }
+
+    pub fn render(&self, source: &str) -> String {
+        let line = match self.line.checked_sub(1).and_then(|i| source.lines().nth(i)) {
+            Some(line) => line,
+            None => return self.to_string(),
+        };
+        let padding: String = line.chars()
+            .take(self.column.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        format!("{}\n{}{} {}{}{}", line, padding, "^".repeat(self.length.max(1)), self.prefix(), self.message, self.note())
+    }
+
+    // Renders against the file the diagnostic points into. When that is not
+    // `current`, as for code a macro defined in an earlier input, the file
+    // is named so its line is not mistaken for one of the current input.
+    pub fn render_in(&self, sources: &SourceMap, current: FileId) -> String {
+        match self.file.and_then(|file| sources.get(file)) {
+            Some(source) if self.file == Some(current) => self.render(&source.contents),
+            Some(source) => format!("in {}:\n{}", source.name, self.render(&source.contents)),
+            None => self.to_string(),
+        }
+    }
+
+    fn prefix(&self) -> &'static str {
+        match self.severity {
+            Severity::ERROR => "",
+            Severity::WARNING => "warning: ",
+        }
+    }
+
+    fn note(&self) -> String {
+        match self.previous {
+            Some((line, column)) => format!(" (previously declared at line {}, column {})", line, column),
+            None => String::new(),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{} at line {}, column {}{}", self.prefix(), self.message, self.line, self.column, self.note())
+    }
+}
+
+pub fn validate(program: &ast::Program) -> Vec<Diagnostic> {
+    Validator::new().validate(program)
+}
+
+// Validates a program handed over in parts, for a host that runs it as it
+// reads it. Each call reports what is wrong with that part, knowing the
+// parts before it, as validate would for the whole program.
+pub struct Validator {
+    diagnostics: Vec<Diagnostic>,
+    function_depth: usize,
+    scopes: Vec<HashMap<String, (usize, usize)>>,
+}
+
+impl Default for Validator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Validator {
+    pub fn new() -> Validator {
+        Validator {
+            diagnostics: vec![],
+            function_depth: 0,
+            scopes: vec![HashMap::new()],
+        }
+    }
+
+    pub fn validate(&mut self, program: &ast::Program) -> Vec<Diagnostic> {
+        for statement in &program.statements {
+            self.validate_statement(statement);
+        }
+        std::mem::take(&mut self.diagnostics)
+    }
+
+    fn validate_statement(&mut self, statement: &Rc<dyn Statement>) {
+        match statement.node_type() {
+            ast::NodeType::EXPRESSION_STATEMENT => {
+                let stmt = statement.as_any().downcast_ref::<ast::ExpressionStatement>().unwrap();
+                if let Some(expression) = &stmt.expression {
+                    self.validate_expression(expression);
+                }
+            },
+            ast::NodeType::LET_STATEMENT => {
+                let stmt = statement.as_any().downcast_ref::<ast::LetStatement>().unwrap();
+                if let Some(value) = &stmt.value {
+                    self.validate_expression(value);
+                }
+                let scope = self.scopes.last_mut().unwrap();
+                if let Some(&previous) = scope.get(&stmt.name.value) {
+                    self.report(Severity::WARNING, format!("duplicate let {}", stmt.name.value), &stmt.name.token, Some(previous));
+                } else {
+                    scope.insert(stmt.name.value.clone(), (stmt.name.token.line, stmt.name.token.column));
+                }
+            },
+            ast::NodeType::RETURN_STATEMENT => {
+                let stmt = statement.as_any().downcast_ref::<ast::ReturnStatement>().unwrap();
+                if self.function_depth == 0 {
+                    self.report(Severity::ERROR, "return outside function".to_string(), &stmt.token, None);
+                }
+                if let Some(value) = &stmt.return_value {
+                    self.validate_expression(value);
+                }
+            },
+            ast::NodeType::BLOCK_STATEMENT => {
+                let block = statement.as_any().downcast_ref::<ast::BlockStatement>().unwrap();
+                for statement in &block.statements {
+                    self.validate_statement(statement);
+                }
+            },
+            _ => {}
+        }
+    }
+
+    fn validate_expression(&mut self, exp: &Rc<dyn Expression>) {
+        match exp.node_type() {
+            ast::NodeType::INTERPOLATED_STRING => {
+                let interpolated = exp.as_any().downcast_ref::<ast::InterpolatedString>().unwrap();
+                for part in &interpolated.parts {
+                    self.validate_expression(part);
+                }
+            },
+            ast::NodeType::PREFIX_EXPRESSION => {
+                let prefix = exp.as_any().downcast_ref::<ast::PrefixExpression>().unwrap();
+                self.validate_expression(&prefix.right);
+            },
+            ast::NodeType::INFIX_EXPRESSION => {
+                let infix = exp.as_any().downcast_ref::<ast::InfixExpression>().unwrap();
+                self.validate_expression(&infix.left);
+                self.validate_expression(&infix.right);
+            },
+            ast::NodeType::ASSIGN_EXPRESSION => {
+                let assign = exp.as_any().downcast_ref::<ast::AssignExpression>().unwrap();
+                self.validate_expression(&assign.value);
+            },
+            ast::NodeType::IF_EXPRESSION => {
+                let if_expression = exp.as_any().downcast_ref::<ast::IfExpression>().unwrap();
+                self.validate_expression(&if_expression.condition);
+                self.validate_statement(&if_expression.consequence);
+                if let Some(alternative) = &if_expression.alternative {
+                    self.validate_statement(alternative);
+                }
+            },
+            ast::NodeType::WHILE_EXPRESSION => {
+                let while_expression = exp.as_any().downcast_ref::<ast::WhileExpression>().unwrap();
+                self.validate_expression(&while_expression.condition);
+                self.validate_statement(&while_expression.body);
+            },
+            ast::NodeType::FUNCTION_LITERAL => {
+                let function = exp.as_any().downcast_ref::<ast::FunctionLiteral>().unwrap();
+                let mut scope = HashMap::new();
+                for param in &function.parameters {
+                    if let Some(&previous) = scope.get(&param.value) {
+                        self.report(Severity::ERROR, format!("duplicate parameter {}", param.value), &param.token, Some(previous));
+                    } else {
+                        scope.insert(param.value.clone(), (param.token.line, param.token.column));
+                    }
+                }
+                self.scopes.push(scope);
+                self.function_depth += 1;
+                self.validate_statement(&function.body);
+                self.function_depth -= 1;
+                self.scopes.pop();
+            },
+            ast::NodeType::CALL_EXPRESSION => {
+                let call = exp.as_any().downcast_ref::<ast::CallExpression>().unwrap();
+                self.validate_expression(&call.function);
+                for argument in &call.arguments {
+                    self.validate_expression(argument);
+                }
+            },
+            ast::NodeType::ARRAY_LITERAL => {
+                let array = exp.as_any().downcast_ref::<ast::ArrayLiteral>().unwrap();
+                for element in &array.elements {
+                    self.validate_expression(element);
+                }
+            },
+            ast::NodeType::INDEX_EXPRESSION => {
+                let index = exp.as_any().downcast_ref::<ast::IndexExpression>().unwrap();
+                self.validate_expression(&index.left);
+                self.validate_expression(&index.index);
+            },
+            _ => {}
+        }
+    }
+
+    fn report(&mut self, severity: Severity, message: String, token: &token::Token, previous: Option<(usize, usize)>) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            message,
+            line: token.line,
+            column: token.column,
+            length: token.literal.chars().count().max(1),
+            previous,
+            file: token.file,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::Lexer;
+    use parser::Parser;
+
+    fn test_validate(input: &str) -> Vec<String> {
+        let lexer = Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        assert!(parser.errors().is_empty(), "parser errors: {:?}", parser.errors());
+        validate(&program).iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn test_valid_program() {
+        let input = "
+        let add = fn(a, b) { if (a > b) { return a; } while (true) { return b; } };
+        add(1, 2);";
+        assert!(test_validate(input).is_empty());
+    }
+
+    #[test]
+    fn test_return_outside_function() {
+        assert_eq!(test_validate("return 5;"), vec!["return outside function at line 1, column 1"]);
+        assert_eq!(test_validate("if (true) {\n  return 1;\n}"), vec!["return outside function at line 2, column 3"]);
+        assert_eq!(test_validate("let f = fn() { fn() { 1 } }; return f;"), vec!["return outside function at line 1, column 30"]);
+        assert!(test_validate("let fs = [fn() { return 1; }]; fs[0]").is_empty());
+    }
+
+    #[test]
+    fn test_duplicate_parameters() {
+        assert_eq!(test_validate("[fn(x, x) { x }]"), vec![
+            "duplicate parameter x at line 1, column 8 (previously declared at line 1, column 5)",
+        ]);
+        assert_eq!(test_validate("fn(x, y, x) { x }"), vec![
+            "duplicate parameter x at line 1, column 10 (previously declared at line 1, column 4)",
+        ]);
+        assert_eq!(test_validate("fn(a) { fn(b, b, b) { b } }"), vec![
+            "duplicate parameter b at line 1, column 15 (previously declared at line 1, column 12)",
+            "duplicate parameter b at line 1, column 18 (previously declared at line 1, column 12)",
+        ]);
+    }
+
+    #[test]
+    fn test_duplicate_let() {
+        assert_eq!(test_validate("let x = 1;\nlet x = 2;"), vec![
+            "warning: duplicate let x at line 2, column 5 (previously declared at line 1, column 5)",
+        ]);
+        assert_eq!(test_validate("fn(x) { let x = 1; }"), vec![
+            "warning: duplicate let x at line 1, column 13 (previously declared at line 1, column 4)",
+        ]);
+        assert_eq!(test_validate("let x = 1; if (x) { let x = 2; }"), vec![
+            "warning: duplicate let x at line 1, column 25 (previously declared at line 1, column 5)",
+        ]);
+        assert!(test_validate("let x = 1; let f = fn() { let x = 2; let y = 3; }; let y = 4;").is_empty());
+        assert!(test_validate("let i = 0; while (i < 3) { let j = i; i += 1; }").is_empty());
+    }
+
+    #[test]
+    fn test_validate_in_parts() {
+        let mut validator = Validator::new();
+        let mut validate_part = |input: &str| -> Vec<String> {
+            validator.validate(&Parser::new(Lexer::new(input)).parse_program()).iter().map(|d| d.to_string()).collect()
+        };
+        assert!(validate_part("let x = 1;").is_empty());
+        assert_eq!(validate_part("\nlet x = 2; return x;"), vec![
+            "warning: duplicate let x at line 2, column 5 (previously declared at line 1, column 5)",
+            "return outside function at line 2, column 12",
+        ]);
+        assert!(validate_part("let y = x;").is_empty());
+    }
+
+    #[test]
+    fn test_render() {
+        let source = "let f = fn(a, b) {\n\tfn(x, x) { return x; }\n};";
+        let lexer = Lexer::new(source);
+        let mut parser = Parser::new(lexer);
+        let diagnostics = validate(&parser.parse_program());
+        assert_eq!(diagnostics[0].render(source), "\tfn(x, x) { return x; }\n\t      ^ duplicate parameter x (previously declared at line 2, column 5)");
+
+        let diagnostic = Diagnostic {
+            severity: Severity::WARNING,
+            message: "unused".to_string(),
+            line: 3,
+            column: 1,
+            length: 6,
+            previous: None,
+            file: None,
+        };
+        assert_eq!(diagnostic.render("let x = 1;"), "warning: unused at line 3, column 1");
+        assert_eq!(diagnostic.render("a\nb\nreturn"), "return\n^^^^^^ warning: unused");
+    }
+
+    #[test]
+    fn test_render_in() {
+        let mut sources = SourceMap::new();
+        let library = sources.add("<repl:1>", "let f = fn(a) {\n  fn(x, x) { x }\n};");
+        let current = sources.add("<repl:2>", "f(1)");
+        let mut parser = Parser::new(Lexer::for_file(&sources, library).unwrap());
+        let diagnostics = validate(&parser.parse_program());
+        assert_eq!(diagnostics[0].render_in(&sources, library), "  fn(x, x) { x }\n        ^ duplicate parameter x (previously declared at line 2, column 6)");
+        assert_eq!(diagnostics[0].render_in(&sources, current), "in <repl:1>:\n  fn(x, x) { x }\n        ^ duplicate parameter x (previously declared at line 2, column 6)");
+
+        let mut parser = Parser::new(Lexer::new("fn(y, y) { y }"));
+        let diagnostics = validate(&parser.parse_program());
+        assert_eq!(diagnostics[0].render_in(&sources, current), "duplicate parameter y at line 1, column 7 (previously declared at line 1, column 4)");
+    }
+}
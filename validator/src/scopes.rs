@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use ast::{Expression, ExpressionKind, Identifier, Statement, StatementKind};
+use token::Token;
+
+use crate::{Diagnostic, Severity};
+
+// Warns about names no scope binds and about bindings that shadow one in an
+// enclosing scope. As in the evaluator, functions and block statements open
+// a scope, while the blocks of if and while share the one they are in.
+// `is_defined` tells whether a name the program does not bind exists
+// anyway, such as a builtin or a name from the prelude.
+pub fn resolve(program: &ast::Program, is_defined: &dyn Fn(&str) -> bool) -> Vec<Diagnostic> {
+    let mut resolver = Resolver::run(program, is_defined);
+    resolver.diagnostics.sort_by_key(|d| (d.line, d.column));
+    resolver.diagnostics
+}
+
+// What each name the program uses refers to, in the order they appear.
+pub fn resolutions(program: &ast::Program, is_defined: &dyn Fn(&str) -> bool) -> Vec<Resolution> {
+    let mut resolver = Resolver::run(program, is_defined);
+    resolver.resolutions.sort_by_key(|r| (r.line, r.column));
+    resolver.resolutions
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resolution {
+    pub name: String,
+    pub line: usize,
+    pub column: usize,
+    pub binding: Binding,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Binding {
+    // The line and column of the name in the let or parameter list.
+    DECLARED(usize, usize),
+    // Not bound by the program, but is_defined knows it.
+    EXTERNAL,
+    UNDEFINED,
+}
+
+struct Scope {
+    bindings: HashMap<String, (usize, usize)>,
+    // Names not bound when they were used. A function may use a name bound
+    // after it, so they are only reported once no enclosing scope has
+    // bound them by its end.
+    unresolved: Vec<Rc<Token>>,
+}
+
+struct Resolver<'a> {
+    scopes: Vec<Scope>,
+    is_defined: &'a dyn Fn(&str) -> bool,
+    diagnostics: Vec<Diagnostic>,
+    resolutions: Vec<Resolution>,
+}
+
+impl<'a> Resolver<'a> {
+    fn run(program: &ast::Program, is_defined: &'a dyn Fn(&str) -> bool) -> Resolver<'a> {
+        let mut resolver = Resolver {
+            scopes: vec![],
+            is_defined,
+            diagnostics: vec![],
+            resolutions: vec![],
+        };
+        resolver.scope(&[], &program.statements);
+        resolver
+    }
+
+    fn scope(&mut self, parameters: &[Rc<Identifier>], statements: &[Rc<dyn Statement>]) {
+        self.scopes.push(Scope {
+            bindings: HashMap::new(),
+            unresolved: vec![],
+        });
+        for parameter in parameters {
+            self.bind(parameter);
+        }
+        self.statements(statements);
+
+        let scope = self.scopes.pop().unwrap();
+        for token in scope.unresolved {
+            if let Some(&(line, column)) = scope.bindings.get(&token.literal) {
+                self.resolved(&token, Binding::DECLARED(line, column));
+                continue;
+            }
+            match self.scopes.last_mut() {
+                Some(outer) => outer.unresolved.push(token),
+                None if (self.is_defined)(&token.literal) => self.resolved(&token, Binding::EXTERNAL),
+                None => {
+                    self.report(format!("identifier `{}` is never defined", token.literal), &token, None);
+                    self.resolved(&token, Binding::UNDEFINED);
+                },
+            }
+        }
+    }
+
+    fn statements(&mut self, statements: &[Rc<dyn Statement>]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, statement: &Rc<dyn Statement>) {
+        match statement.kind() {
+            StatementKind::Let(let_statement) => {
+                if let Some(value) = &let_statement.value {
+                    self.expression(value);
+                }
+                self.bind(&let_statement.name);
+            },
+            StatementKind::Return(return_statement) => {
+                if let Some(value) = &return_statement.return_value {
+                    self.expression(value);
+                }
+            },
+            StatementKind::Expression(expression_statement) => {
+                if let Some(expression) = &expression_statement.expression {
+                    self.expression(expression);
+                }
+            },
+            StatementKind::Block(block) => self.scope(&[], &block.statements),
+        }
+    }
+
+    // The blocks of if and while run in the scope around them.
+    fn branch(&mut self, branch: &Rc<dyn Statement>) {
+        match branch.kind() {
+            StatementKind::Block(block) => self.statements(&block.statements),
+            _ => self.statement(branch),
+        }
+    }
+
+    fn expression(&mut self, exp: &Rc<dyn Expression>) {
+        match exp.kind() {
+            ExpressionKind::Identifier(identifier) => self.reference(&identifier.token),
+            ExpressionKind::InterpolatedString(interpolated) => interpolated.parts.iter().for_each(|p| self.expression(p)),
+            ExpressionKind::Prefix(prefix) => self.expression(&prefix.right),
+            ExpressionKind::Infix(infix) => {
+                self.expression(&infix.left);
+                self.expression(&infix.right);
+            },
+            ExpressionKind::Assign(assign) => {
+                self.reference(&assign.name.token);
+                self.expression(&assign.value);
+            },
+            ExpressionKind::If(if_expression) => {
+                self.expression(&if_expression.condition);
+                self.branch(&if_expression.consequence);
+                if let Some(alternative) = &if_expression.alternative {
+                    self.branch(alternative);
+                }
+            },
+            ExpressionKind::While(while_expression) => {
+                self.expression(&while_expression.condition);
+                self.branch(&while_expression.body);
+            },
+            ExpressionKind::FunctionLiteral(function) => {
+                let body = match function.body.kind() {
+                    StatementKind::Block(block) => block.statements.as_slice(),
+                    _ => std::slice::from_ref(&function.body),
+                };
+                self.scope(&function.parameters, body);
+            },
+            // What a quote holds is code to be spliced elsewhere, and the
+            // names in it are resolved there.
+            ExpressionKind::Call(call) if call.function.to_string() == "quote" => {},
+            ExpressionKind::Call(call) => {
+                self.expression(&call.function);
+                call.arguments.iter().for_each(|a| self.expression(a));
+            },
+            ExpressionKind::Array(array) => array.elements.iter().for_each(|e| self.expression(e)),
+            ExpressionKind::Index(index) => {
+                self.expression(&index.left);
+                self.expression(&index.index);
+            },
+            ExpressionKind::MacroLiteral(_)
+            | ExpressionKind::IntegerLiteral(_)
+            | ExpressionKind::FloatLiteral(_)
+            | ExpressionKind::StringLiteral(_)
+            | ExpressionKind::Boolean(_)
+            | ExpressionKind::NullLiteral(_)
+            | ExpressionKind::Import(_) => {},
+        }
+    }
+
+    // A name bound twice in one scope is left to validate, which reports it
+    // as a duplicate.
+    fn bind(&mut self, name: &Identifier) {
+        let (current, outer) = self.scopes.split_last_mut().unwrap();
+        if current.bindings.contains_key(&name.value) {
+            return;
+        }
+        current.bindings.insert(name.value.clone(), (name.token.line, name.token.column));
+        if let Some(&previous) = outer.iter().rev().find_map(|scope| scope.bindings.get(&name.value)) {
+            self.report(format!("variable `{}` shadows outer binding", name.value), &name.token, Some(previous));
+        }
+    }
+
+    fn reference(&mut self, token: &Rc<Token>) {
+        match self.scopes.iter().rev().find_map(|scope| scope.bindings.get(&token.literal)) {
+            Some(&(line, column)) => self.resolved(token, Binding::DECLARED(line, column)),
+            None => self.scopes.last_mut().unwrap().unresolved.push(token.clone()),
+        }
+    }
+
+    fn resolved(&mut self, token: &Token, binding: Binding) {
+        self.resolutions.push(Resolution {
+            name: token.literal.clone(),
+            line: token.line,
+            column: token.column,
+            binding,
+        });
+    }
+
+    fn report(&mut self, message: String, token: &Token, previous: Option<(usize, usize)>) {
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::WARNING,
+            message,
+            line: token.line,
+            column: token.column,
+            length: token.literal.chars().count().max(1),
+            previous,
+            file: token.file,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::Lexer;
+    use parser::Parser;
+
+    fn test_resolve(input: &str) -> Vec<String> {
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program();
+        assert!(parser.errors().is_empty(), "parser errors: {:?}", parser.errors());
+        resolve(&program, &|name| name == "puts").iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn test_undefined_identifiers() {
+        assert_eq!(test_resolve("let count = 1;\nif (count > 0) { puts(cuont) }"), vec![
+            "warning: identifier `cuont` is never defined at line 2, column 23",
+        ]);
+        assert_eq!(test_resolve("let f = fn() { g(y) };\nlet g = fn(x) { x };\ntotal = 1;"), vec![
+            "warning: identifier `y` is never defined at line 1, column 18",
+            "warning: identifier `total` is never defined at line 3, column 1",
+        ]);
+        assert_eq!(test_resolve("let f = fn(n) { if (n > 0) { let m = n - 1; f(m) } else { m } };"), Vec::<String>::new());
+        assert_eq!(test_resolve("{ let inner = 1; }\ninner"), vec!["warning: identifier `inner` is never defined at line 2, column 1"]);
+        assert!(test_resolve("let m = macro(a) { quote(unquote(a) + b) }; puts(\"{m}\")").is_empty());
+    }
+
+    #[test]
+    fn test_resolutions() {
+        let input = "let x = 1;\nlet f = fn(x) { x + y + z };\nlet y = 2;\nputs(f(x))";
+        let program = Parser::new(Lexer::new(input)).parse_program();
+        let resolutions: Vec<(String, usize, usize, Binding)> = resolutions(&program, &|name| name == "puts")
+            .into_iter()
+            .map(|r| (r.name, r.line, r.column, r.binding))
+            .collect();
+        assert_eq!(resolutions, vec![
+            ("x".to_string(), 2, 17, Binding::DECLARED(2, 12)),
+            ("y".to_string(), 2, 21, Binding::DECLARED(3, 5)),
+            ("z".to_string(), 2, 25, Binding::UNDEFINED),
+            ("puts".to_string(), 4, 1, Binding::EXTERNAL),
+            ("f".to_string(), 4, 6, Binding::DECLARED(2, 5)),
+            ("x".to_string(), 4, 8, Binding::DECLARED(1, 5)),
+        ]);
+    }
+
+    #[test]
+    fn test_shadowing() {
+        assert_eq!(test_resolve("let x = 1;\nlet f = fn(x) { let y = x; fn() { let y = 2; y } };"), vec![
+            "warning: variable `x` shadows outer binding at line 2, column 12 (previously declared at line 1, column 5)",
+            "warning: variable `y` shadows outer binding at line 2, column 39 (previously declared at line 2, column 21)",
+        ]);
+        assert!(test_resolve("let x = 1; if (true) { let x = 2; } while (false) { let x = 3; }").is_empty());
+        assert!(test_resolve("let f = fn(a) { a }; let g = fn(a) { a };").is_empty());
+    }
+}
@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use ast::{Expression, ExpressionKind, Statement, StatementKind};
+
+// Calls into functions bound in the source are inferred by inferring the
+// body with the argument types; this bounds how deep that goes, so
+// recursive functions come out as UNKNOWN instead of looping.
+const MAX_CALL_DEPTH: usize = 16;
+
+// The static counterpart of object::ObjectType. UNKNOWN is used wherever
+// the type depends on values only known at runtime.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    INTEGER,
+    FLOAT,
+    BOOLEAN,
+    STRING,
+    NULL,
+    ARRAY,
+    FUNCTION,
+    MACRO,
+    UNKNOWN,
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+// Infers the type of the program's value without evaluating it. `lookup`
+// gives the type of names the program does not bind itself, such as those
+// bound by earlier REPL inputs.
+pub fn infer(program: &ast::Program, lookup: &dyn Fn(&str) -> Option<Type>) -> Type {
+    let mut inference = Inference {
+        scopes: vec![HashMap::new()],
+        lookup,
+        returns: vec![vec![]],
+    };
+    inference.infer_statements(&program.statements)
+}
+
+#[derive(Clone)]
+struct Binding {
+    ty: Type,
+    // Kept for names bound to a function literal, so calls to them can be
+    // inferred from its body.
+    function: Option<Rc<dyn Expression>>,
+}
+
+struct Inference<'a> {
+    scopes: Vec<HashMap<String, Binding>>,
+    lookup: &'a dyn Fn(&str) -> Option<Type>,
+    // Types of the return statements seen in each function call being
+    // inferred, innermost last.
+    returns: Vec<Vec<Type>>,
+}
+
+impl Inference<'_> {
+    fn infer_statements(&mut self, statements: &[Rc<dyn Statement>]) -> Type {
+        let mut result = Type::NULL;
+        for statement in statements {
+            result = self.infer_statement(statement);
+            if let StatementKind::Return(_) = statement.kind() {
+                break;
+            }
+        }
+        result
+    }
+
+    fn infer_statement(&mut self, statement: &Rc<dyn Statement>) -> Type {
+        match statement.kind() {
+            StatementKind::Let(let_statement) => {
+                let value = let_statement.value.as_ref();
+                let binding = Binding {
+                    ty: value.map(|v| self.infer_expression(v)).unwrap_or(Type::NULL),
+                    function: value.filter(|v| matches!(v.kind(), ExpressionKind::FunctionLiteral(_))).cloned(),
+                };
+                self.scopes.last_mut().unwrap().insert(let_statement.name.value.clone(), binding);
+                Type::NULL
+            },
+            StatementKind::Return(return_statement) => {
+                let ty = match &return_statement.return_value {
+                    Some(value) => self.infer_expression(value),
+                    None => Type::NULL,
+                };
+                self.returns.last_mut().unwrap().push(ty.clone());
+                ty
+            },
+            StatementKind::Expression(expression_statement) => match &expression_statement.expression {
+                Some(expression) => self.infer_expression(expression),
+                None => Type::NULL,
+            },
+            StatementKind::Block(block) => self.infer_statements(&block.statements),
+        }
+    }
+
+    fn infer_expression(&mut self, exp: &Rc<dyn Expression>) -> Type {
+        match exp.kind() {
+            ExpressionKind::IntegerLiteral(_) => Type::INTEGER,
+            ExpressionKind::FloatLiteral(_) => Type::FLOAT,
+            ExpressionKind::StringLiteral(_) | ExpressionKind::InterpolatedString(_) => Type::STRING,
+            ExpressionKind::Boolean(_) => Type::BOOLEAN,
+            ExpressionKind::NullLiteral(_) => Type::NULL,
+            ExpressionKind::Array(_) => Type::ARRAY,
+            ExpressionKind::FunctionLiteral(_) => Type::FUNCTION,
+            ExpressionKind::MacroLiteral(_) => Type::MACRO,
+            ExpressionKind::Import(_) | ExpressionKind::While(_) => Type::UNKNOWN,
+            ExpressionKind::Identifier(identifier) => self.identifier_type(&identifier.value),
+            ExpressionKind::Prefix(prefix) => {
+                let right = self.infer_expression(&prefix.right);
+                match (prefix.operator.as_str(), right) {
+                    ("!", _) => Type::BOOLEAN,
+                    ("-", Type::INTEGER) => Type::INTEGER,
+                    ("-", Type::FLOAT) => Type::FLOAT,
+                    _ => Type::UNKNOWN,
+                }
+            },
+            ExpressionKind::Infix(infix) => {
+                let left = self.infer_expression(&infix.left);
+                let right = self.infer_expression(&infix.right);
+                infix_type(&infix.operator, left, right)
+            },
+            ExpressionKind::Assign(assign) => {
+                let value = self.infer_expression(&assign.value);
+                let ty = match assign.operator.as_str() {
+                    "=" => value,
+                    operator => infix_type(operator.trim_end_matches('='), self.identifier_type(&assign.name.value), value),
+                };
+                if let Some(binding) = self.scopes.iter_mut().rev().find_map(|scope| scope.get_mut(&assign.name.value)) {
+                    *binding = Binding { ty: ty.clone(), function: None };
+                }
+                ty
+            },
+            ExpressionKind::If(if_expression) => {
+                self.infer_expression(&if_expression.condition);
+                let consequence = self.infer_statement(&if_expression.consequence);
+                let alternative = match &if_expression.alternative {
+                    Some(alternative) => self.infer_statement(alternative),
+                    None => Type::NULL,
+                };
+                join(consequence, alternative)
+            },
+            ExpressionKind::Index(index) => {
+                let left = self.infer_expression(&index.left);
+                self.infer_expression(&index.index);
+                match left {
+                    // Ignoring the null an out-of-range index evaluates to.
+                    Type::STRING => Type::STRING,
+                    _ => Type::UNKNOWN,
+                }
+            },
+            ExpressionKind::Call(call) => {
+                let arguments: Vec<Type> = call.arguments.iter().map(|a| self.infer_expression(a)).collect();
+                match call.function.kind() {
+                    ExpressionKind::FunctionLiteral(_) => self.infer_call(&call.function, arguments),
+                    ExpressionKind::Identifier(identifier) => match self.binding(&identifier.value) {
+                        Some(Binding { function: Some(function), .. }) => self.infer_call(&function, arguments),
+                        Some(_) => Type::UNKNOWN,
+                        None if (self.lookup)(&identifier.value).is_some() => Type::UNKNOWN,
+                        None => builtin_return_type(&identifier.value),
+                    },
+                    _ => Type::UNKNOWN,
+                }
+            },
+        }
+    }
+
+    fn infer_call(&mut self, function: &Rc<dyn Expression>, arguments: Vec<Type>) -> Type {
+        let function = match function.kind() {
+            ExpressionKind::FunctionLiteral(function) => function,
+            _ => return Type::UNKNOWN,
+        };
+        if self.returns.len() > MAX_CALL_DEPTH || arguments.len() != function.parameters.len() {
+            return Type::UNKNOWN;
+        }
+        let scope = function.parameters.iter()
+            .zip(arguments)
+            .map(|(param, ty)| (param.value.clone(), Binding { ty, function: None }))
+            .collect();
+        self.scopes.push(scope);
+        self.returns.push(vec![]);
+        let body = self.infer_statement(&function.body);
+        let returns = self.returns.pop().unwrap();
+        self.scopes.pop();
+        returns.into_iter().fold(body, join)
+    }
+
+    fn binding(&self, name: &str) -> Option<Binding> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name)).cloned()
+    }
+
+    fn identifier_type(&self, name: &str) -> Type {
+        if let Some(binding) = self.binding(name) {
+            return binding.ty;
+        }
+        if let Some(ty) = (self.lookup)(name) {
+            return ty;
+        }
+        match builtin_return_type(name) {
+            Type::UNKNOWN if !is_builtin(name) => Type::UNKNOWN,
+            _ => Type::FUNCTION,
+        }
+    }
+}
+
+pub fn infix_type(operator: &str, left: Type, right: Type) -> Type {
+    match (operator, left, right) {
+        ("==" | "!=" | "<" | ">" | "<=" | ">=", _, _) => Type::BOOLEAN,
+        ("+", Type::STRING, Type::STRING) => Type::STRING,
+        ("+" | "-" | "*" | "/" | "%" | "**", Type::INTEGER, Type::INTEGER) => Type::INTEGER,
+        ("+" | "-" | "*" | "/" | "%" | "**", Type::INTEGER | Type::FLOAT, Type::INTEGER | Type::FLOAT) => Type::FLOAT,
+        _ => Type::UNKNOWN,
+    }
+}
+
+pub fn join(a: Type, b: Type) -> Type {
+    if a == b {
+        a
+    } else {
+        Type::UNKNOWN
+    }
+}
+
+pub fn is_builtin(name: &str) -> bool {
+    matches!(name, "string_builder" | "append" | "call" | "reduce" | "with_timeout" | "env")
+        || builtin_return_type(name) != Type::UNKNOWN
+}
+
+// What calling a builtin evaluates to when it succeeds.
+pub fn builtin_return_type(name: &str) -> Type {
+    match name {
+        "len" | "arity" => Type::INTEGER,
+        "type" | "build" | "join" | "upper" | "lower" | "trim" | "replace" | "substr" | "inspect_pretty" | "read_file" => Type::STRING,
+        "split" | "map" | "filter" | "params" | "args" | "push" | "pop" | "insert" | "remove" => Type::ARRAY,
+        "contains" | "file_exists" => Type::BOOLEAN,
+        "puts" | "each" | "write_file" | "append_file" | "assert" | "assert_eq" => Type::NULL,
+        _ => Type::UNKNOWN,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::Lexer;
+    use parser::Parser;
+
+    fn infer_source(input: &str) -> Type {
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program();
+        assert!(parser.errors().is_empty(), "parser errors: {:?}", parser.errors());
+        infer(&program, &|name| (name == "outer").then_some(Type::FLOAT))
+    }
+
+    #[test]
+    fn test_infer() {
+        let tests = vec![
+            ("1 + 2 * 3", Type::INTEGER),
+            ("1 + 2.5", Type::FLOAT),
+            ("2 ** 3", Type::INTEGER),
+            ("2 ** 0.5", Type::FLOAT),
+            ("\"a\" + \"b\"", Type::STRING),
+            ("\"a\" + 1", Type::UNKNOWN),
+            ("!5", Type::BOOLEAN),
+            ("1 < 2", Type::BOOLEAN),
+            ("let x = 1;", Type::NULL),
+            ("let x = 1; x * 2", Type::INTEGER),
+            ("let x = 1; x = \"s\"; x", Type::STRING),
+            ("outer - 1", Type::FLOAT),
+            ("missing", Type::UNKNOWN),
+            ("if (true) { 1 } else { 2 }", Type::INTEGER),
+            ("if (true) { 1 } else { \"a\" }", Type::UNKNOWN),
+            ("if (true) { 1 }", Type::UNKNOWN),
+            ("let double = fn(x) { x * 2 }; double(2)", Type::INTEGER),
+            ("let double = fn(x) { x * 2 }; double(2.5)", Type::FLOAT),
+            ("fn(x) { if (x) { return 1; } 2 }(true)", Type::INTEGER),
+            ("fn(x) { if (x) { return \"a\"; } 2 }(true)", Type::UNKNOWN),
+            ("let f = fn(n) { f(n - 1) }; f(3)", Type::UNKNOWN),
+            ("len(\"abc\")", Type::INTEGER),
+            ("map([1], fn(x) { x })", Type::ARRAY),
+            ("\"abc\"[0]", Type::STRING),
+            ("[1, 2][0]", Type::UNKNOWN),
+            ("puts", Type::FUNCTION),
+            ("let len = fn(x) { \"shadowed\" }; len(1)", Type::STRING),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(infer_source(input), expected, "input: {}", input);
+        }
+    }
+}
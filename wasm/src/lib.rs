@@ -0,0 +1,79 @@
+use std::io::{self, Write};
+
+use evaluator::SharedBuffer;
+use monkey::{Interpreter, Limits};
+use wasm_bindgen::prelude::*;
+
+// A browser tab has no other thread to cancel a runaway program from, so
+// each evaluation gets a step budget instead.
+const MAX_STEPS: usize = 1_000_000;
+
+fn limits() -> Limits {
+    Limits { max_steps: Some(MAX_STEPS), ..Limits::sandbox() }
+}
+
+#[wasm_bindgen]
+pub fn eval(source: &str) -> String {
+    let output = SharedBuffer::default();
+    let mut interpreter = Interpreter::with_limits(limits());
+    interpreter.set_output(Box::new(output.clone()));
+    let result = render(interpreter.eval(source));
+    format!("{}{}", output.contents(), result)
+}
+
+#[wasm_bindgen]
+pub struct Playground {
+    interpreter: Interpreter,
+}
+
+#[wasm_bindgen]
+impl Playground {
+    #[wasm_bindgen(constructor)]
+    pub fn new(on_output: js_sys::Function) -> Playground {
+        let mut interpreter = Interpreter::with_limits(limits());
+        interpreter.set_output(Box::new(CallbackOutput { callback: on_output }));
+        Playground { interpreter }
+    }
+
+    pub fn eval(&mut self, source: &str) -> String {
+        render(self.interpreter.eval(source))
+    }
+}
+
+struct CallbackOutput {
+    callback: js_sys::Function,
+}
+
+impl Write for CallbackOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let chunk = JsValue::from_str(&String::from_utf8_lossy(buf));
+        self.callback.call1(&JsValue::NULL, &chunk)
+            .map_err(|_| io::Error::other("output callback failed"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn render(result: Result<monkey::Value, monkey::MonkeyError>) -> String {
+    match result {
+        Ok(value) => value.to_string(),
+        Err(err) => err.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eval() {
+        assert_eq!(eval("let x = 2; x * 21"), "42");
+        assert_eq!(eval("puts(\"hello\"); 1 + 1"), "hello\n2");
+        assert_eq!(eval("1 + true"), "type mismatch: INTEGER + BOOLEAN");
+        assert_eq!(eval("let = 1;"), "parser errors: expected next token to be IDENT, got ASSIGN instead");
+        assert_eq!(eval("while (true) {}"), "resource limit exceeded: more than 1000000 evaluation steps");
+    }
+}